@@ -0,0 +1,517 @@
+use crate::expand;
+use crate::vars::Variables;
+
+// arithmetic expressions with C operators on 64-bit integers, used for
+// integer variables; variables are referenced by name without $
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    // variable name with optional subscript
+    Name(String, Option<String>),
+    Op(&'static str),
+}
+
+// longer operators go first
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", "(", ")", ",",
+];
+
+// nested variable values are evaluated too, this stops a=a from looping
+const MAX_DEPTH: usize = 64;
+
+// number in C notation (0x1f, 017) or base#digits like bash (2#101)
+fn number(text: &str) -> Result<i64, String> {
+    let invalid = || format!("{}: value too great for base", text);
+    let (base, digits) = match text.split_once('#') {
+        Some((base, digits)) => match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => return Err(format!("{}: invalid arithmetic base", text)),
+        },
+        None if text.starts_with("0x") || text.starts_with("0X") => (16, &text[2..]),
+        None if text.len() > 1 && text.starts_with('0') => (8, &text[1..]),
+        None => (10, text),
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        // bases over 36 use lower case, upper case, @ and _ like bash
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+
+    Ok(value)
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_digit() {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric() || matches!(chars[index], '#' | '@' | '_'))
+            {
+                index += 1;
+            }
+            let text: String = chars[start..index].iter().collect();
+            tokens.push(Token::Number(number(&text)?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric() || chars[index] == '_')
+            {
+                index += 1;
+            }
+            let name: String = chars[start..index].iter().collect();
+
+            // subscript is an expression itself, keep it as text
+            let mut subscript = None;
+            if chars.get(index) == Some(&'[') {
+                let mut depth = 0;
+                let start = index + 1;
+                for (end, &c) in chars.iter().enumerate().skip(index) {
+                    match c {
+                        '[' => depth += 1,
+                        ']' if depth == 1 => {
+                            subscript = Some(chars[start..end].iter().collect());
+                            index = end + 1;
+                            break;
+                        }
+                        ']' => depth -= 1,
+                        _ => {}
+                    }
+                }
+                if subscript.is_none() {
+                    return Err(format!("{}: missing `]'", expression));
+                }
+            }
+            tokens.push(Token::Name(name, subscript));
+        } else {
+            let rest: String = chars[index..].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    index += op.chars().count();
+                }
+                None => {
+                    return Err(format!(
+                        "{}: syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        expression, rest
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Evaluator<'a> {
+    expression: &'a str,
+    tokens: Vec<Token>,
+    index: usize,
+    vars: &'a mut Variables,
+    depth: usize,
+    // inside the branch which is not taken: a && b, a ? b : c, side effects
+    // like assignments are skipped there
+    skipping: usize,
+}
+
+const ASSIGNMENTS: [&str; 11] = [
+    "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+// binary operators from the lowest precedence
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Evaluator<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{}: {}", self.expression.trim(), message)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek_op() {
+            Some(next) if next == op => {
+                self.index += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("syntax error: `{}' expected", op))),
+        }
+    }
+
+    // subscript of indexed array is an expression, of associative one a string
+    fn variable(&mut self, name: &str, key: &Option<String>) -> Result<i64, String> {
+        if self.skipping > 0 {
+            return Ok(0);
+        }
+        let text = match key {
+            Some(key) if !self.vars.is_assoc(name) => {
+                let index = evaluate_nested(key, self.vars, self.depth + 1)?;
+                self.vars.element(name, &index.to_string())
+            }
+            Some(key) => self.vars.element(name, key),
+            None => Some(expand::lookup(name, self.vars)),
+        };
+        match text {
+            Some(text) if !text.trim().is_empty() => {
+                evaluate_nested(&text, self.vars, self.depth + 1)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn assign(&mut self, name: &str, key: &Option<String>, value: i64) -> Result<i64, String> {
+        if self.skipping > 0 {
+            return Ok(value);
+        }
+        match key {
+            Some(key) if !self.vars.is_assoc(name) => {
+                let index = evaluate_nested(key, self.vars, self.depth + 1)?;
+                self.vars
+                    .set_element(name, &index.to_string(), &value.to_string())?
+            }
+            Some(key) => self.vars.set_element(name, key, &value.to_string())?,
+            None => self.vars.set(name, &value.to_string())?,
+        }
+        Ok(value)
+    }
+
+    // a, b evaluates both and gives b
+    fn comma(&mut self) -> Result<i64, String> {
+        let mut value = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.index += 1;
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64, String> {
+        if let (Some(Token::Name(name, subscript)), Some(Token::Op(op))) = (
+            self.peek().cloned(),
+            self.tokens.get(self.index + 1).cloned(),
+        ) {
+            if ASSIGNMENTS.contains(&op) {
+                self.index += 2;
+                let value = self.assignment()?;
+                let value = match op {
+                    "=" => value,
+                    _ => {
+                        let current = self.variable(&name, &subscript)?;
+                        self.binary(&op[..op.len() - 1], current, value)?
+                    }
+                };
+                return self.assign(&name, &subscript, value);
+            }
+        }
+
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<i64, String> {
+        let condition = self.binary_level(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.index += 1;
+
+        if condition == 0 {
+            self.skipping += 1;
+        }
+        let first = self.assignment()?;
+        if condition == 0 {
+            self.skipping -= 1;
+        }
+        self.expect(":")?;
+        if condition != 0 {
+            self.skipping += 1;
+        }
+        let second = self.conditional()?;
+        if condition != 0 {
+            self.skipping -= 1;
+        }
+
+        Ok(if condition != 0 { first } else { second })
+    }
+
+    fn binary_level(&mut self, level: usize) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.power();
+        }
+
+        let mut left = self.binary_level(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| LEVELS[level].contains(op)) {
+            self.index += 1;
+            // the right side of && and || is evaluated only when needed
+            let skip = (op == "&&" && left == 0) || (op == "||" && left != 0);
+            if skip {
+                self.skipping += 1;
+            }
+            let right = self.binary_level(level + 1)?;
+            if skip {
+                self.skipping -= 1;
+            }
+            left = self.binary(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn binary(&self, op: &str, left: i64, right: i64) -> Result<i64, String> {
+        if matches!(op, "/" | "%") && right == 0 {
+            if self.skipping > 0 {
+                return Ok(0);
+            }
+            return Err(self.error("division by 0"));
+        }
+
+        Ok(match op {
+            "||" => (left != 0 || right != 0) as i64,
+            "&&" => (left != 0 && right != 0) as i64,
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" if right < 0 => return Err(self.error("exponent less than 0")),
+            "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+            _ => return Err(self.error(&format!("unknown operator `{}'", op))),
+        })
+    }
+
+    // ** is right associative and binds tighter than * but looser than unary -
+    fn power(&mut self) -> Result<i64, String> {
+        let base = self.unary()?;
+        if self.peek_op() == Some("**") {
+            self.index += 1;
+            let exponent = self.power()?;
+            return self.binary("**", base, exponent);
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.index += 1;
+                match self.peek().cloned() {
+                    Some(Token::Name(name, subscript)) => {
+                        self.index += 1;
+                        let value = self.variable(&name, &subscript)?;
+                        let value = if op == "++" { value + 1 } else { value - 1 };
+                        self.assign(&name, &subscript, value)
+                    }
+                    _ => Err(self.error(&format!(
+                        "syntax error: operand expected (error token is \"{}\")",
+                        op
+                    ))),
+                }
+            }
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.index += 1;
+                let value = self.unary()?;
+                Ok(match op {
+                    "-" => value.wrapping_neg(),
+                    "+" => value,
+                    "!" => (value == 0) as i64,
+                    _ => !value,
+                })
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64, String> {
+        match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.index += 1;
+                Ok(value)
+            }
+            Some(Token::Name(name, subscript)) => {
+                self.index += 1;
+                let value = self.variable(&name, &subscript)?;
+                match self.peek_op() {
+                    Some(op @ ("++" | "--")) => {
+                        self.index += 1;
+                        let updated = if op == "++" { value + 1 } else { value - 1 };
+                        self.assign(&name, &subscript, updated)?;
+                        Ok(value)
+                    }
+                    _ => Ok(value),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.index += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(Token::Op(op)) => Err(self.error(&format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                op
+            ))),
+            None => Err(self.error("syntax error: operand expected")),
+        }
+    }
+}
+
+fn evaluate_nested(expression: &str, vars: &mut Variables, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "{}: expression recursion level exceeded",
+            expression.trim()
+        ));
+    }
+
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut evaluator = Evaluator {
+        expression,
+        tokens,
+        index: 0,
+        vars,
+        depth,
+        skipping: 0,
+    };
+    let value = evaluator.comma()?;
+    if let Some(token) = evaluator.peek() {
+        let token = match token {
+            Token::Number(value) => value.to_string(),
+            Token::Name(name, _) => name.clone(),
+            Token::Op(op) => String::from(*op),
+        };
+        return Err(evaluator.error(&format!(
+            "syntax error in expression (error token is \"{}\")",
+            token
+        )));
+    }
+
+    Ok(value)
+}
+
+// evaluate the expression, empty one gives 0
+pub fn evaluate(expression: &str, vars: &mut Variables) -> Result<i64, String> {
+    evaluate_nested(expression, vars, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    fn value(expression: &str) -> i64 {
+        evaluate(expression, &mut Variables::new(&Options::new())).unwrap()
+    }
+
+    fn error(expression: &str) -> String {
+        evaluate(expression, &mut Variables::new(&Options::new())).unwrap_err()
+    }
+
+    #[test]
+    fn operators_and_precedence() {
+        assert_eq!(value("1 + 2 * 3"), 7);
+        assert_eq!(value("(1 + 2) * 3"), 9);
+        assert_eq!(value("2 ** 3 ** 2"), 512);
+        assert_eq!(value("-2 ** 2"), 4);
+        assert_eq!(value("7 / 2 + 7 % 2"), 4);
+        assert_eq!(value("1 << 4 | 1"), 17);
+        assert_eq!(value("3 > 2 && 2 > 3 || !0"), 1);
+        assert_eq!(value("0 ? 1 : 2"), 2);
+        assert_eq!(value("1, 2"), 2);
+        assert_eq!(value(""), 0);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(value("0x1f + 017 + 2#101"), 31 + 15 + 5);
+        assert_eq!(value("64#_"), 63);
+        assert_eq!(error("08"), "08: value too great for base");
+        assert_eq!(error("1#1"), "1#1: invalid arithmetic base");
+    }
+
+    #[test]
+    fn variables() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("ARITH_TEST_A", "5").unwrap();
+        vars.set("ARITH_TEST_B", "ARITH_TEST_A * 2").unwrap();
+        assert_eq!(evaluate("ARITH_TEST_B + 1", &mut vars), Ok(11));
+        assert_eq!(evaluate("ARITH_TEST_C = ARITH_TEST_A++", &mut vars), Ok(5));
+        assert_eq!(vars.get("ARITH_TEST_A").as_deref(), Some("6"));
+        assert_eq!(vars.get("ARITH_TEST_C").as_deref(), Some("5"));
+        assert_eq!(evaluate("ARITH_TEST_C *= 3", &mut vars), Ok(15));
+        assert_eq!(evaluate("ARITH_TEST_UNSET + 1", &mut vars), Ok(1));
+        vars.set("ARITH_TEST_LOOP", "ARITH_TEST_LOOP").unwrap();
+        assert!(evaluate("ARITH_TEST_LOOP", &mut vars)
+            .unwrap_err()
+            .ends_with("expression recursion level exceeded"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("1 / 0"), "1 / 0: division by 0");
+        assert_eq!(value("0 && 1 / 0"), 0);
+        assert_eq!(error("2 ** -1"), "2 ** -1: exponent less than 0");
+        assert_eq!(error("1 +"), "1 +: syntax error: operand expected");
+        assert_eq!(
+            error("1 2"),
+            "1 2: syntax error in expression (error token is \"2\")"
+        );
+        assert_eq!(error("(1"), "(1: syntax error: `)' expected");
+    }
+}
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::shell::CANCELLED;
+use crate::{CancelToken, ExecResult, Shell};
+
+// what the commands given to AsyncShell::eval_async produce, Exit with the
+// status of the last command is the last event
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit { status: i32, duration: Duration },
+}
+
+struct State {
+    events: VecDeque<Event>,
+    done: bool,
+    running: bool,
+    // the Eval was dropped, its commands aren't needed anymore
+    dropped: bool,
+    // and it cancelled them
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+type Channel = Arc<Mutex<State>>;
+
+fn send(channel: &Channel, event: Event) {
+    let mut state = channel.lock().unwrap();
+    if let Event::Exit { .. } = event {
+        state.done = true;
+    }
+    state.events.push_back(event);
+    if let Some(waker) = state.waker.take() {
+        waker.wake();
+    }
+}
+
+fn exit(channel: &Channel, status: i32, duration: Duration) {
+    send(channel, Event::Exit { status, duration });
+}
+
+// output of the commands goes to the Eval being run
+struct Output {
+    current: Rc<RefCell<Option<Channel>>>,
+    stderr: bool,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(channel) = &*self.current.borrow() {
+            let bytes = buf.to_vec();
+            send(
+                channel,
+                match self.stderr {
+                    true => Event::Stderr(bytes),
+                    false => Event::Stdout(bytes),
+                },
+            );
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// the shell for async programs: it lives in its own thread, so the commands
+// and the programs they run don't block the threads of the runtime. Any
+// executor can poll the returned futures, they don't need a reactor
+pub struct AsyncShell {
+    jobs: Option<mpsc::Sender<(String, Channel)>>,
+    cancel: CancelToken,
+    // the commands which haven't started are skipped
+    closed: Arc<AtomicBool>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl AsyncShell {
+    // the shell is built in its thread by the function; output of the commands
+    // goes to the events instead of the writers given to the builder
+    pub fn spawn<F>(build: F) -> Result<AsyncShell, String>
+    where
+        F: FnOnce() -> Result<Shell, String> + Send + 'static,
+    {
+        let (jobs, received) = mpsc::channel::<(String, Channel)>();
+        let (built, result) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let skipped = Arc::clone(&closed);
+        let worker = thread::spawn(move || {
+            let mut shell = match build() {
+                Ok(shell) => shell,
+                Err(err) => {
+                    let _ = built.send(Err(err));
+                    return;
+                }
+            };
+            let current = Rc::new(RefCell::new(None));
+            shell.set_handles(
+                Box::new(Output {
+                    current: Rc::clone(&current),
+                    stderr: false,
+                }),
+                Box::new(Output {
+                    current: Rc::clone(&current),
+                    stderr: true,
+                }),
+            );
+            let _ = built.send(Ok(shell.cancel_token()));
+
+            for (input, channel) in received {
+                {
+                    let mut state = channel.lock().unwrap();
+                    if state.dropped || skipped.load(Ordering::SeqCst) {
+                        drop(state);
+                        exit(&channel, CANCELLED, Duration::ZERO);
+                        continue;
+                    }
+                    state.running = true;
+                }
+                *current.borrow_mut() = Some(Arc::clone(&channel));
+                let started = Instant::now();
+                let status = shell.run(&input);
+                let duration = started.elapsed();
+                *current.borrow_mut() = None;
+                let mut state = channel.lock().unwrap();
+                state.running = false;
+                // it may have been cancelled after the commands finished, the
+                // next ones must still run
+                if state.cancelled {
+                    shell.cancel_token().reset();
+                }
+                drop(state);
+                exit(&channel, status, duration);
+            }
+        });
+
+        let cancel = match result.recv() {
+            Ok(built) => built?,
+            Err(_) => return Err(String::from("the shell thread panicked")),
+        };
+        Ok(AsyncShell {
+            jobs: Some(jobs),
+            cancel,
+            closed,
+            worker: Some(worker),
+        })
+    }
+
+    // the text is run like by Shell::run after the ones given before;
+    // dropping the Eval before its end cancels the commands
+    pub fn eval_async(&self, input: impl Into<String>) -> Eval {
+        let channel = Arc::new(Mutex::new(State {
+            events: VecDeque::new(),
+            done: false,
+            running: false,
+            dropped: false,
+            cancelled: false,
+            waker: None,
+        }));
+        let sent = match &self.jobs {
+            Some(jobs) => jobs.send((input.into(), Arc::clone(&channel))).is_ok(),
+            None => false,
+        };
+        // the shell thread is gone, the commands can't run
+        if !sent {
+            exit(&channel, 1, Duration::ZERO);
+        }
+        Eval {
+            channel,
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+// the running commands are cancelled and the shell is dropped in its thread,
+// which may save the history
+impl Drop for AsyncShell {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.cancel.cancel();
+        self.jobs.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+// events of the commands: read them with next_event() like a stream
+pub struct Eval {
+    channel: Channel,
+    cancel: CancelToken,
+}
+
+impl Eval {
+    // None after the Exit event
+    pub fn next_event(&mut self) -> NextEvent<'_> {
+        NextEvent { eval: self }
+    }
+
+    // the events collected like Shell::eval returns them
+    pub async fn result(mut self) -> ExecResult {
+        let mut result = ExecResult {
+            status: 0,
+            stdout: vec![],
+            stderr: vec![],
+            duration: Duration::ZERO,
+        };
+        while let Some(event) = self.next_event().await {
+            match event {
+                Event::Stdout(bytes) => result.stdout.extend(bytes),
+                Event::Stderr(bytes) => result.stderr.extend(bytes),
+                Event::Exit { status, duration } => {
+                    result.status = status;
+                    result.duration = duration;
+                }
+            }
+        }
+        result
+    }
+
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let mut state = self.channel.lock().unwrap();
+        match state.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if state.done => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Eval {
+    fn drop(&mut self) {
+        let mut state = self.channel.lock().unwrap();
+        state.dropped = true;
+        if state.running {
+            state.cancelled = true;
+            self.cancel.cancel();
+        }
+    }
+}
+
+pub struct NextEvent<'a> {
+    eval: &'a mut Eval,
+}
+
+impl Future for NextEvent<'_> {
+    type Output = Option<Event>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.eval.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+    use std::time::{Duration, Instant};
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // the smallest executor: the thread sleeps until the future is woken
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    // the output events and the status of the exit one
+    fn events(mut eval: Eval) -> (Vec<Event>, i32) {
+        let mut events = vec![];
+        while let Some(event) = block_on(eval.next_event()) {
+            match event {
+                Event::Exit { status, .. } => return (events, status),
+                event => events.push(event),
+            }
+        }
+        panic!("no exit event");
+    }
+
+    fn spawn() -> AsyncShell {
+        AsyncShell::spawn(|| Shell::builder().build()).unwrap()
+    }
+
+    #[test]
+    fn output_is_streamed() {
+        let shell = spawn();
+        let (events, status) = events(shell.eval_async("echo out; cd /nonexistent/directory"));
+        assert_eq!(events[0], Event::Stdout(b"out\n".to_vec()));
+        assert!(matches!(&events[1], Event::Stderr(error) if error.starts_with(b"cd: ")));
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn texts_are_run_in_order() {
+        let shell = spawn();
+        let first = shell.eval_async("ASYNC_TEST_VALUE=1");
+        let second = shell.eval_async("echo $ASYNC_TEST_VALUE");
+        assert_eq!(events(second), (vec![Event::Stdout(b"1\n".to_vec())], 0));
+        assert_eq!(events(first), (vec![], 0));
+    }
+
+    #[test]
+    fn dropped_evals_are_cancelled() {
+        let shell = spawn();
+        let started = Instant::now();
+        let mut eval = shell.eval_async("echo started; sleep 5");
+        assert_eq!(
+            block_on(eval.next_event()),
+            Some(Event::Stdout(b"started\n".to_vec()))
+        );
+        drop(eval);
+        assert_eq!(events(shell.eval_async("true")), (vec![], 0));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn errors_of_the_builder_are_returned() {
+        let error = AsyncShell::spawn(|| Shell::builder().without_builtin("fly").build()).err();
+        assert_eq!(error.as_deref(), Some("fly: no such builtin"));
+    }
+
+    #[test]
+    fn results_are_collected() {
+        let shell = spawn();
+        let result = block_on(shell.eval_async("echo a; echo b; false").result());
+        assert_eq!(result.status, 1);
+        assert_eq!(result.stdout, b"a\nb\n");
+        assert!(result.stderr.is_empty());
+    }
+
+    #[test]
+    fn program_output_comes_while_it_runs() {
+        let shell = spawn();
+        let started = Instant::now();
+        let mut eval = shell.eval_async("sh -c 'echo first; sleep 5'");
+        assert_eq!(
+            block_on(eval.next_event()),
+            Some(Event::Stdout(b"first\n".to_vec()))
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
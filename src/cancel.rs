@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+
+use crate::signals;
+
+struct State {
+    cancelled: AtomicBool,
+    // pid of the program the shell waits for, 0 if there is no such program
+    program: AtomicI32,
+    // the token was given to the embedding program
+    shared: AtomicBool,
+}
+
+// stops the commands run by Shell::eval, run_file or run_stdin from another
+// thread: the program the shell waits for is killed with the programs it
+// started and the remaining commands are skipped. Once the token is taken
+// programs run in their own process group, so they don't get the signals of
+// the terminal. Background jobs keep running. The token is reset when the
+// shell returns
+#[derive(Clone)]
+pub struct CancelToken {
+    state: Arc<State>,
+}
+
+impl CancelToken {
+    pub(crate) fn new() -> Self {
+        CancelToken {
+            state: Arc::new(State {
+                cancelled: AtomicBool::new(false),
+                program: AtomicI32::new(0),
+                shared: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        let pid = self.state.program.load(Ordering::SeqCst);
+        if pid > 0 {
+            signals::send_group(pid as u32, signals::SIGKILL);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    // the program is killed if the commands were cancelled before it started
+    pub(crate) fn watch(&self, pid: u32) {
+        self.state.program.store(pid as i32, Ordering::SeqCst);
+        if self.is_cancelled() {
+            signals::send_group(pid, signals::SIGKILL);
+        }
+    }
+
+    // the token for the embedding program
+    pub(crate) fn share(&self) -> Self {
+        self.state.shared.store(true, Ordering::SeqCst);
+        self.clone()
+    }
+
+    // programs are started in their own process group, which is killed on
+    // cancel
+    pub(crate) fn is_shared(&self) -> bool {
+        self.state.shared.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn unwatch(&self) {
+        self.state.program.store(0, Ordering::SeqCst);
+    }
+
+    // whether the commands were cancelled
+    pub(crate) fn reset(&self) -> bool {
+        self.state.cancelled.swap(false, Ordering::SeqCst)
+    }
+}
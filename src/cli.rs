@@ -0,0 +1,154 @@
+// command line of the shell binary itself
+
+pub const USAGE: &str = "usage: shell [--help] [--version] [--norc] [--login] [--dry-run] [-ils] [-c <command> | <script>] [arguments...]";
+
+pub const HELP: &str = "\
+options:
+    --help          print this help and exit
+    --version       print version and exit
+    --norc          don't read ~/.shellrc in interactive mode
+    --login, -l     act as a login shell, read ~/.shell_profile
+    --dry-run       print programs with their arguments instead of running them
+    -c <command>    run commands from the string and exit
+    -i              force interactive mode
+    -s              read commands from the standard input";
+
+// where commands are read from
+pub enum Input {
+    Stdin,
+    Command(String),
+    Script(String),
+}
+
+pub struct Args {
+    pub help: bool,
+    pub version: bool,
+    pub norc: bool,
+    pub login: bool,
+    pub interactive: bool,
+    pub dry_run: bool,
+    pub input: Input,
+    // $0 and positional parameters
+    pub name: Option<String>,
+    pub positional: Vec<String>,
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        help: false,
+        version: false,
+        norc: false,
+        login: false,
+        interactive: false,
+        dry_run: false,
+        input: Input::Stdin,
+        name: None,
+        positional: vec![],
+    };
+    let mut command = false;
+    let mut stdin = false;
+    let mut operands = vec![];
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--help" => parsed.help = true,
+            "--version" => parsed.version = true,
+            "--norc" => parsed.norc = true,
+            "--login" => parsed.login = true,
+            "--dry-run" => parsed.dry_run = true,
+            "--" => {
+                operands.extend(args.by_ref());
+            }
+            flags if flags.len() > 1 && flags.starts_with('-') && !flags.starts_with("--") => {
+                for flag in flags[1..].chars() {
+                    match flag {
+                        'c' => command = true,
+                        'i' => parsed.interactive = true,
+                        'l' => parsed.login = true,
+                        's' => stdin = true,
+                        _ => return Err(format!("invalid option: -{}", flag)),
+                    }
+                }
+            }
+            option if option.starts_with("--") => {
+                return Err(format!("invalid option: {}", option));
+            }
+            _ => {
+                operands.push(arg);
+                operands.extend(args.by_ref());
+            }
+        }
+    }
+
+    // the rest operands are arguments of the command string or script,
+    // the first argument of the command string is $0
+    let mut operands = operands.into_iter();
+    if command {
+        match operands.next() {
+            Some(command) => parsed.input = Input::Command(command),
+            None => return Err(String::from("option -c requires an argument")),
+        }
+        parsed.name = operands.next();
+    } else if !stdin {
+        if let Some(script) = operands.next() {
+            parsed.name = Some(script.clone());
+            parsed.input = Input::Script(script);
+        }
+    }
+    parsed.positional = operands.collect();
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Result<Args, String> {
+        parse(words.iter().map(|word| String::from(*word)))
+    }
+
+    #[test]
+    fn command_string() {
+        let parsed = args(&["-c", "echo $1", "name", "one", "two"]).unwrap();
+        assert!(matches!(parsed.input, Input::Command(ref command) if command == "echo $1"));
+        assert_eq!(parsed.name.as_deref(), Some("name"));
+        assert_eq!(parsed.positional, ["one", "two"]);
+    }
+
+    #[test]
+    fn script() {
+        let parsed = args(&["--norc", "script.sh", "-x"]).unwrap();
+        assert!(parsed.norc);
+        assert!(matches!(parsed.input, Input::Script(ref script) if script == "script.sh"));
+        assert_eq!(parsed.name.as_deref(), Some("script.sh"));
+        assert_eq!(parsed.positional, ["-x"]);
+    }
+
+    #[test]
+    fn standard_input() {
+        let parsed = args(&["-is", "a", "b"]).unwrap();
+        assert!(parsed.interactive);
+        assert!(matches!(parsed.input, Input::Stdin));
+        assert_eq!(parsed.name, None);
+        assert_eq!(parsed.positional, ["a", "b"]);
+    }
+
+    #[test]
+    fn flags() {
+        let parsed = args(&["--login", "--dry-run", "--", "-c"]).unwrap();
+        assert!(parsed.login && parsed.dry_run);
+        assert!(!parsed.help && !parsed.version);
+        assert!(matches!(parsed.input, Input::Script(ref script) if script == "-c"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(args(&["-x"]).err().unwrap(), "invalid option: -x");
+        assert_eq!(args(&["--nope"]).err().unwrap(), "invalid option: --nope");
+        assert_eq!(
+            args(&["-c"]).err().unwrap(),
+            "option -c requires an argument"
+        );
+    }
+}
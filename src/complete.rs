@@ -0,0 +1,286 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::frequency::Frequency;
+use crate::osstr;
+
+// characters escaped with backslash when a name is put on the line
+const SPECIAL: &str = " \t\n'\"\\$&;|<>()*?[]#`{}!";
+
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn unescape(word: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// the word ends at unescaped blanks and operators
+fn word_start(line: &[char], cursor: usize) -> usize {
+    let mut start = cursor;
+    while start > 0 {
+        let escaped = start >= 2 && line[start - 2] == '\\';
+        let c = line[start - 1];
+        if !escaped && (c.is_whitespace() || ";&|<>(){}".contains(c)) {
+            break;
+        }
+        start -= 1;
+    }
+    start
+}
+
+// what the names are completed in: PATH, HOME and the working directory of
+// the shell, which updates them before reading a line
+#[derive(Default)]
+pub struct Context {
+    pub path: Option<String>,
+    pub home: Option<String>,
+    pub cwd: PathBuf,
+}
+
+fn is_dir(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+// executable files in the PATH directories starting with the prefix
+fn programs(prefix: &str, context: &Context) -> Vec<String> {
+    let mut names = vec![];
+    let paths = osstr::to_os(context.path.as_deref().unwrap_or_default());
+    for directory in env::split_paths(&paths) {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = osstr::from_os(&entry.file_name());
+            if !name.starts_with(prefix) {
+                continue;
+            }
+            let executable = fs::metadata(entry.path()).is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            });
+            if executable {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+// entries of the directory in the word starting with its last component,
+// directories end with /; hidden ones only if the component starts with a dot
+fn files(word: &str, context: &Context) -> Vec<String> {
+    let (directory, prefix) = match word.rfind('/') {
+        Some(index) => (&word[..=index], &word[index + 1..]),
+        None => ("", word),
+    };
+    let path = match directory {
+        "" => context.cwd.clone(),
+        _ => match (directory.strip_prefix("~/"), &context.home) {
+            (Some(rest), Some(home)) => PathBuf::from(osstr::to_os(&format!("{}/{}", home, rest))),
+            _ => context.cwd.join(osstr::to_os(directory)),
+        },
+    };
+    let entries = match fs::read_dir(&path) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut names = vec![];
+    for entry in entries.flatten() {
+        let name = osstr::from_os(&entry.file_name());
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        let mut candidate = escape(&format!("{}{}", directory, name));
+        if is_dir(&path.join(entry.file_name())) {
+            candidate.push('/');
+        }
+        names.push(candidate);
+    }
+    names.sort();
+    names
+}
+
+// candidates for Tab in the line editor: builtins and programs for the first
+// word of a command, the ones run most in the current directory first; file
+// names for the other words and paths
+pub struct Completer {
+    builtins: Vec<String>,
+    frequency: Rc<RefCell<Frequency>>,
+    context: Rc<RefCell<Context>>,
+}
+
+impl Completer {
+    pub fn new(
+        builtins: Vec<String>,
+        frequency: Rc<RefCell<Frequency>>,
+        context: Rc<RefCell<Context>>,
+    ) -> Self {
+        Completer {
+            builtins,
+            frequency,
+            context,
+        }
+    }
+
+    // the start of the word before the cursor and the words which replace it
+    pub fn complete(&self, line: &[char], cursor: usize) -> (usize, Vec<String>) {
+        let start = word_start(line, cursor);
+        let word = unescape(&line[start..cursor].iter().collect::<String>());
+        let before: String = line[..start].iter().collect();
+        let command = match before.trim_end().chars().last() {
+            None => true,
+            Some(c) => ";&|({".contains(c),
+        };
+
+        let context = self.context.borrow();
+        if !command || word.contains('/') {
+            return (start, files(&word, &context));
+        }
+        let mut names: Vec<String> = self
+            .builtins
+            .iter()
+            .filter(|name| name.starts_with(&word))
+            .cloned()
+            .chain(programs(&word, &context))
+            .collect();
+        names.sort();
+        names.dedup();
+        let directory = osstr::from_os(context.cwd.as_os_str());
+        self.frequency.borrow().rank(&directory, &mut names);
+        (start, names.iter().map(|name| escape(name)).collect())
+    }
+}
+
+// the longest start shared by all the words
+pub fn common_prefix(words: &[String]) -> String {
+    let mut prefix: Vec<char> = match words.first() {
+        Some(word) => word.chars().collect(),
+        None => return String::new(),
+    };
+    for word in &words[1..] {
+        let same = prefix
+            .iter()
+            .zip(word.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(same);
+    }
+    prefix.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn completer(builtins: &[&str]) -> Completer {
+        let builtins = builtins.iter().map(|name| name.to_string()).collect();
+        let context = Context {
+            cwd: env::temp_dir(),
+            ..Context::default()
+        };
+        Completer::new(
+            builtins,
+            Rc::new(RefCell::new(Frequency::new())),
+            Rc::new(RefCell::new(context)),
+        )
+    }
+
+    fn completed(completer: &Completer, line: &str) -> (usize, Vec<String>) {
+        let line: Vec<char> = line.chars().collect();
+        completer.complete(&line, line.len())
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        assert_eq!(escape("a b$(c)"), "a\\ b\\$\\(c\\)");
+        assert_eq!(unescape("a\\ b\\\\c\\"), "a b\\c");
+        let line: Vec<char> = "ls a\\ b|cat x;y".chars().collect();
+        assert_eq!(word_start(&line, 7), 3);
+        assert_eq!(word_start(&line, 11), 8);
+        assert_eq!(word_start(&line, 15), 14);
+    }
+
+    #[test]
+    fn common_prefixes() {
+        let words = |list: &[&str]| list.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&words(&["echo", "eval", "exit"])), "e");
+        assert_eq!(
+            common_prefix(&words(&["\u{e9}t\u{e9}", "\u{e9}t\u{e0}"])),
+            "\u{e9}t"
+        );
+        assert_eq!(common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn builtins_are_completed_in_command_position() {
+        let completer = completer(&["zzcompletetest"]);
+        assert!(completed(&completer, "zzcomp")
+            .1
+            .contains(&String::from("zzcompletetest")));
+        assert!(completed(&completer, "true; zzcomp")
+            .1
+            .contains(&String::from("zzcompletetest")));
+        assert!(completed(&completer, "echo zzcomp").1.is_empty());
+    }
+
+    #[test]
+    fn files_are_completed() {
+        let directory = env::temp_dir().join(format!("complete-test-{}", process::id()));
+        fs::create_dir_all(directory.join("sub dir")).unwrap();
+        fs::write(directory.join("subfile"), "").unwrap();
+        fs::write(directory.join(".subhidden"), "").unwrap();
+        let path = directory.to_str().unwrap();
+
+        let completer = completer(&[]);
+        let line = format!("cat {}/su", path);
+        let (start, names) = completed(&completer, &line);
+        assert_eq!(start, 4);
+        assert_eq!(
+            names,
+            [format!("{}/sub\\ dir/", path), format!("{}/subfile", path)]
+        );
+        let (_, names) = completed(&completer, &format!("cat {}/.su", path));
+        assert_eq!(names, [format!("{}/.subhidden", path)]);
+        // relative names are in the working directory of the shell
+        let name = directory.file_name().unwrap().to_str().unwrap();
+        let (_, names) = completed(&completer, &format!("cat {}/subf", name));
+        assert_eq!(names, [format!("{}/subfile", name)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn frequent_commands_come_first() {
+        let completer = completer(&["zzrankeda", "zzrankedb", "zzrankedc"]);
+        let directory = osstr::from_os(completer.context.borrow().cwd.as_os_str());
+        completer
+            .frequency
+            .borrow_mut()
+            .add(&directory, "zzrankedc");
+        assert_eq!(
+            completed(&completer, "zzranked").1,
+            ["zzrankedc", "zzrankeda", "zzrankedb"]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::vec::IntoIter;
+
+use crate::lexer::{self, Token};
+use crate::vars;
+
+// error of the command line which needs more lines, like select without done
+pub const INCOMPLETE: &str = "syntax error: unexpected end of file";
+
+// simple command or compound command with its body
+pub enum Item {
+    Simple(Vec<Token>, bool),
+    // the second command runs only if the first one succeeds or fails
+    And(Box<Item>, Box<Item>),
+    Or(Box<Item>, Box<Item>),
+    // ! command inverts the status
+    Not(Box<Item>),
+    // select NAME [in WORDS]; do BODY; done, without in it uses "$@"
+    Select {
+        name: String,
+        words: Option<Vec<Token>>,
+        body: Vec<Item>,
+    },
+    // for NAME [in WORDS]; do BODY; done, the same words as select
+    For {
+        name: String,
+        words: Option<Vec<Token>>,
+        body: Vec<Item>,
+    },
+    // coproc [NAME] command or coproc [NAME] { commands; }, NAME is COPROC
+    // by default
+    Coproc {
+        name: String,
+        commands: Vec<(Vec<Token>, bool)>,
+    },
+}
+
+fn is_word(token: Option<&Token>, word: &str) -> bool {
+    matches!(token, Some(Token::Word(token)) if token == word)
+}
+
+// reserved words like do are recognised only at the beginning of a command,
+// split them off to make their own commands: do echo a -> do; echo a
+fn split_reserved(commands: Vec<(Vec<Token>, bool)>) -> Vec<(Vec<Token>, bool)> {
+    let mut result = vec![];
+    for (mut tokens, background) in commands {
+        if is_word(tokens.first(), "do") && tokens.len() > 1 {
+            let rest = tokens.split_off(1);
+            result.push((tokens, false));
+            result.push((rest, background));
+        } else {
+            result.push((tokens, background));
+        }
+    }
+
+    result
+}
+
+// select or for loop, both are keyword NAME [in WORDS]; do BODY; done
+fn parse_loop(
+    keyword: &str,
+    tokens: Vec<Token>,
+    commands: &mut IntoIter<(Vec<Token>, bool)>,
+) -> Result<Item, String> {
+    let mut tokens = tokens.into_iter().skip(1);
+    let name = match tokens.next() {
+        Some(Token::Word(name)) if vars::is_name(&name) => name,
+        Some(Token::Word(name)) => {
+            return Err(format!("{}: `{}': not a valid identifier", keyword, name))
+        }
+        _ => return Err(format!("syntax error: {} without variable name", keyword)),
+    };
+    let words = match tokens.next() {
+        Some(Token::Word(word)) if word == "in" => Some(tokens.collect()),
+        None => None,
+        Some(token) => {
+            let token = match token {
+                Token::Word(word) | Token::Op(word) => word,
+            };
+            return Err(format!("syntax error near unexpected token `{}'", token));
+        }
+    };
+
+    match commands.next() {
+        Some((tokens, _)) if tokens.len() == 1 && is_word(tokens.first(), "do") => {}
+        Some(_) => return Err(format!("syntax error: {} without do", keyword)),
+        None => return Err(String::from(INCOMPLETE)),
+    }
+    let body = parse_list(commands, true)?;
+
+    Ok(match keyword {
+        "for" => Item::For { name, words, body },
+        _ => Item::Select { name, words, body },
+    })
+}
+
+fn parse_coproc(
+    tokens: Vec<Token>,
+    background: bool,
+    commands: &mut IntoIter<(Vec<Token>, bool)>,
+) -> Result<Item, String> {
+    let brace = if is_word(tokens.get(1), "{") {
+        1
+    } else if is_word(tokens.get(2), "{") {
+        2
+    } else {
+        // a simple command can't be named, the name would be the program
+        let command = tokens.into_iter().skip(1).collect::<Vec<_>>();
+        if command.is_empty() {
+            return Err(String::from("syntax error: coproc without command"));
+        }
+        return Ok(Item::Coproc {
+            name: String::from("COPROC"),
+            commands: vec![(command, background)],
+        });
+    };
+
+    let mut tokens = tokens.into_iter().skip(1);
+    let name = match brace {
+        2 => match tokens.next() {
+            Some(Token::Word(name)) if vars::is_name(&name) => name,
+            Some(Token::Word(name)) => {
+                return Err(format!("coproc: `{}': not a valid identifier", name))
+            }
+            _ => return Err(String::from("syntax error: coproc without name")),
+        },
+        _ => String::from("COPROC"),
+    };
+
+    // the body is everything up to the closing brace command
+    let first: Vec<Token> = tokens.skip(1).collect();
+    let mut body = vec![];
+    if !first.is_empty() {
+        body.push((first, background));
+    }
+    loop {
+        match commands.next() {
+            Some((tokens, _)) if tokens.len() == 1 && is_word(tokens.first(), "}") => break,
+            Some(command) => body.push(command),
+            None => return Err(String::from(INCOMPLETE)),
+        }
+    }
+    if body.is_empty() {
+        return Err(String::from("syntax error near unexpected token `}'"));
+    }
+
+    Ok(Item::Coproc {
+        name,
+        commands: body,
+    })
+}
+
+// simple command or the command negated by ! before it
+fn simple(mut tokens: Vec<Token>, background: bool) -> Result<Item, String> {
+    if is_word(tokens.first(), "!") {
+        tokens.remove(0);
+        if tokens.is_empty() {
+            return Err(String::from("syntax error: command expected after `!'"));
+        }
+        return Ok(Item::Not(Box::new(simple(tokens, background)?)));
+    }
+    Ok(Item::Simple(tokens, background))
+}
+
+// split the command at && and || outside of [[ ... ]], they have the same
+// precedence: a && b || c runs c if a or b fails
+fn and_or(tokens: Vec<Token>, background: bool) -> Result<Item, String> {
+    let mut item: Option<Item> = None;
+    let mut operator: Option<String> = None;
+    let mut command = vec![];
+    let mut conditional = false;
+
+    for token in tokens.into_iter().chain([Token::Op(String::new())]) {
+        match token {
+            Token::Word(ref word)
+                if word == "[[" && command.iter().all(|token| is_word(Some(token), "!")) =>
+            {
+                conditional = true;
+                command.push(token);
+            }
+            Token::Word(ref word) if word == "]]" && conditional => {
+                conditional = false;
+                command.push(token);
+            }
+            Token::Op(op) if !conditional && (op == "&&" || op == "||" || op.is_empty()) => {
+                if command.is_empty() {
+                    return Err(match op.is_empty() {
+                        true => String::from(INCOMPLETE),
+                        false => format!("syntax error near unexpected token `{}'", op),
+                    });
+                }
+                let simple = simple(std::mem::take(&mut command), op.is_empty() && background)?;
+                item = Some(match (item, operator.as_deref()) {
+                    (Some(left), Some("&&")) => Item::And(Box::new(left), Box::new(simple)),
+                    (Some(left), _) => Item::Or(Box::new(left), Box::new(simple)),
+                    (None, _) => simple,
+                });
+                operator = Some(op);
+            }
+            Token::Op(op) if op == "|" && !conditional => {
+                return Err(String::from("syntax error: pipelines are not supported"))
+            }
+            _ => command.push(token),
+        }
+    }
+
+    Ok(item.unwrap())
+}
+
+// parse commands up to done if it's the body of a loop
+fn parse_list(
+    commands: &mut IntoIter<(Vec<Token>, bool)>,
+    body: bool,
+) -> Result<Vec<Item>, String> {
+    let mut items = vec![];
+    while let Some((tokens, background)) = commands.next() {
+        if is_word(tokens.first(), "done") {
+            if !body {
+                return Err(String::from("syntax error near unexpected token `done'"));
+            }
+            if tokens.len() > 1 || background {
+                return Err(String::from("syntax error: unexpected words after done"));
+            }
+            return Ok(items);
+        }
+
+        if is_word(tokens.first(), "select") {
+            items.push(parse_loop("select", tokens, commands)?);
+        } else if is_word(tokens.first(), "for") {
+            items.push(parse_loop("for", tokens, commands)?);
+        } else if is_word(tokens.first(), "coproc") {
+            items.push(parse_coproc(tokens, background, commands)?);
+        } else if is_word(tokens.first(), "do") {
+            return Err(String::from("syntax error near unexpected token `do'"));
+        } else {
+            items.push(and_or(tokens, background)?);
+        }
+    }
+
+    if body {
+        Err(String::from(INCOMPLETE))
+    } else {
+        Ok(items)
+    }
+}
+
+// split the command line into simple and compound commands
+pub fn parse(input: &str) -> Result<Vec<Item>, String> {
+    let commands = split_reserved(lexer::split_commands(lexer::tokenize(input)?)?);
+    parse_list(&mut commands.into_iter(), false)
+}
+
+// the command line continues on the next line, like select before its done
+pub fn is_incomplete(input: &str) -> bool {
+    matches!(parse(input), Err(err) if err == INCOMPLETE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_is_parsed() {
+        let items = parse("select x in a b; do echo $x; break; done; echo after").unwrap();
+        assert_eq!(items.len(), 2);
+        match &items[0] {
+            Item::Select { name, words, body } => {
+                assert_eq!(name, "x");
+                assert_eq!(words.as_ref().map(Vec::len), Some(2));
+                assert_eq!(body.len(), 2);
+            }
+            _ => panic!("select is not parsed"),
+        }
+        assert!(matches!(&items[1], Item::Simple(tokens, false) if tokens.len() == 2));
+        assert!(matches!(
+            parse("select x\ndo\necho\ndone").unwrap()[0],
+            Item::Select { words: None, .. }
+        ));
+    }
+
+    #[test]
+    fn for_is_parsed() {
+        match &parse("for x in a 'b c'; do echo $x; done").unwrap()[0] {
+            Item::For { name, words, body } => {
+                assert_eq!(name, "x");
+                assert_eq!(words.as_ref().map(Vec::len), Some(2));
+                assert_eq!(body.len(), 1);
+            }
+            _ => panic!("for is not parsed"),
+        }
+        assert!(matches!(
+            parse("for x\ndo\n:\ndone").unwrap()[0],
+            Item::For { words: None, .. }
+        ));
+        assert!(is_incomplete("for x in a; do"));
+        assert_eq!(
+            parse("for 1x; do :; done").err().unwrap(),
+            "for: `1x': not a valid identifier"
+        );
+        assert_eq!(
+            parse("for x in a; echo; done").err().unwrap(),
+            "syntax error: for without do"
+        );
+    }
+
+    #[test]
+    fn and_or_lists() {
+        let items = parse("a && b || c").unwrap();
+        match &items[0] {
+            Item::Or(left, right) => {
+                assert!(matches!(**left, Item::And(..)));
+                assert!(matches!(**right, Item::Simple(..)));
+            }
+            _ => panic!("list is not parsed"),
+        }
+        assert!(parse("a &&").is_err());
+    }
+
+    #[test]
+    fn coproc_is_parsed() {
+        let name_and_commands = |input| match parse(input).unwrap().remove(0) {
+            Item::Coproc { name, commands } => (name, commands.len()),
+            _ => panic!("coproc is not parsed"),
+        };
+        assert_eq!(
+            name_and_commands("coproc cat -n"),
+            (String::from("COPROC"), 1)
+        );
+        assert_eq!(
+            name_and_commands("coproc worker { read x; echo $x; }"),
+            (String::from("worker"), 2)
+        );
+        // the brace isn't a part of the first command
+        for input in ["coproc { echo a; }", "coproc worker { echo a; }"] {
+            match parse(input).unwrap().remove(0) {
+                Item::Coproc { commands, .. } => {
+                    assert_eq!(commands.len(), 1);
+                    assert!(is_word(commands[0].0.first(), "echo"), "{}", input);
+                }
+                _ => panic!("coproc is not parsed"),
+            }
+        }
+        assert!(is_incomplete("coproc { echo a"));
+        assert_eq!(
+            parse("coproc").err().unwrap(),
+            "syntax error: coproc without command"
+        );
+        assert_eq!(
+            parse("coproc 1x { :; }").err().unwrap(),
+            "coproc: `1x': not a valid identifier"
+        );
+    }
+
+    #[test]
+    fn negation() {
+        let items = parse("! ! a && ! [[ b ]]").unwrap();
+        match &items[0] {
+            Item::And(left, right) => {
+                assert!(matches!(&**left, Item::Not(inner) if matches!(**inner, Item::Not(_))));
+                assert!(
+                    matches!(&**right, Item::Not(inner) if matches!(**inner, Item::Simple(..)))
+                );
+            }
+            _ => panic!("list is not parsed"),
+        }
+        assert_eq!(
+            parse("!").err().unwrap(),
+            "syntax error: command expected after `!'"
+        );
+    }
+
+    #[test]
+    fn incomplete_commands() {
+        assert!(is_incomplete("select x in a"));
+        assert!(is_incomplete("select x in a; do echo $x"));
+        assert!(!is_incomplete("select x in a; do echo $x; done"));
+        assert!(!is_incomplete("echo"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |input| parse(input).err().unwrap();
+        assert_eq!(
+            error("select 1x; do :; done"),
+            "select: `1x': not a valid identifier"
+        );
+        assert_eq!(
+            error("select x; echo; done"),
+            "syntax error: select without do"
+        );
+        assert_eq!(error("done"), "syntax error near unexpected token `done'");
+        assert_eq!(
+            error("select x; do :; done x"),
+            "syntax error: unexpected words after done"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::expand;
+use crate::glob;
+use crate::lexer::Token;
+use crate::options::Options;
+use crate::regex;
+use crate::vars::Variables;
+
+extern "C" {
+    fn access(path: *const c_char, mode: c_int) -> c_int;
+}
+
+const R_OK: c_int = 4;
+const W_OK: c_int = 2;
+const X_OK: c_int = 1;
+
+// [[ ... ]] expression, operands are kept unexpanded until they are needed,
+// so a && b doesn't expand b if a is false
+enum Expression {
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Unary(String, String),
+    Binary(String, String, String),
+    Word(String),
+}
+
+const UNARY: [&str; 14] = [
+    "-n", "-z", "-e", "-a", "-f", "-d", "-s", "-L", "-h", "-r", "-w", "-x", "-v", "-o",
+];
+
+const BINARY: [&str; 14] = [
+    "==", "=", "!=", "=~", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot",
+];
+
+fn text(token: &Token) -> &str {
+    match token {
+        Token::Word(word) | Token::Op(word) => word,
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    index: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.index).map(text)
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.tokens.get(self.index) {
+            Some(Token::Word(word)) => {
+                self.index += 1;
+                Ok(word.clone())
+            }
+            Some(Token::Op(op)) => Err(format!(
+                "syntax error in conditional expression: unexpected token `{}'",
+                op
+            )),
+            None => Err(String::from(
+                "syntax error in conditional expression: unexpected end",
+            )),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some("||") {
+            self.index += 1;
+            left = Expression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut left = self.not()?;
+        while self.peek() == Some("&&") {
+            self.index += 1;
+            left = Expression::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        if self.peek() == Some("!") {
+            self.index += 1;
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        if self.peek() == Some("(") {
+            self.index += 1;
+            let expression = self.or()?;
+            if self.peek() != Some(")") {
+                return Err(String::from(
+                    "syntax error in conditional expression: expected `)'",
+                ));
+            }
+            self.index += 1;
+            return Ok(expression);
+        }
+
+        let word = self.word()?;
+        let operand_follows = self
+            .peek()
+            .is_some_and(|next| !matches!(next, "&&" | "||" | ")"));
+        if UNARY.contains(&&word[..]) && operand_follows && !BINARY.contains(&self.peek().unwrap())
+        {
+            return Ok(Expression::Unary(word, self.word()?));
+        }
+
+        match self.peek() {
+            Some(op) if BINARY.contains(&op) => {
+                let op = String::from(op);
+                self.index += 1;
+                Ok(Expression::Binary(word, op, self.word()?))
+            }
+            _ => Ok(Expression::Word(word)),
+        }
+    }
+}
+
+fn accessible(path: &Path, mode: c_int) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn unary(op: &str, operand: &str, vars: &Variables) -> bool {
+    // relative paths are in the working directory of the shell
+    let path = vars.path(operand);
+    match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" | "-a" => fs::metadata(&path).is_ok(),
+        "-f" => fs::metadata(&path).is_ok_and(|metadata| metadata.is_file()),
+        "-d" => fs::metadata(&path).is_ok_and(|metadata| metadata.is_dir()),
+        "-s" => fs::metadata(&path).is_ok_and(|metadata| metadata.len() > 0),
+        "-L" | "-h" => fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink()),
+        "-r" => accessible(&path, R_OK),
+        "-w" => accessible(&path, W_OK),
+        "-x" => accessible(&path, X_OK),
+        "-v" => vars.get(operand).is_some(),
+        // -o option is checked by the caller
+        _ => false,
+    }
+}
+
+fn integer(text: &str) -> Result<i64, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", text))
+}
+
+fn modified(path: &str, vars: &Variables) -> Option<std::time::SystemTime> {
+    fs::metadata(vars.path(path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+struct Evaluator<'a> {
+    vars: &'a mut Variables,
+    options: &'a Options,
+}
+
+impl Evaluator<'_> {
+    fn evaluate(&mut self, expression: &Expression) -> Result<bool, String> {
+        match expression {
+            Expression::Not(inner) => Ok(!self.evaluate(inner)?),
+            Expression::And(left, right) => Ok(self.evaluate(left)? && self.evaluate(right)?),
+            Expression::Or(left, right) => Ok(self.evaluate(left)? || self.evaluate(right)?),
+            Expression::Word(word) => Ok(!expand::expand_string(word, self.vars)?.is_empty()),
+            Expression::Unary(op, operand) => {
+                let operand = expand::expand_string(operand, self.vars)?;
+                if op == "-o" {
+                    return Ok(self.options.lookup(&operand).unwrap_or(false));
+                }
+                Ok(unary(op, &operand, self.vars))
+            }
+            Expression::Binary(left, op, right) => self.binary(left, op, right),
+        }
+    }
+
+    fn binary(&mut self, left: &str, op: &str, right: &str) -> Result<bool, String> {
+        let left = expand::expand_string(left, self.vars)?;
+        match op {
+            // like in bash, extended patterns always work here
+            "==" | "=" | "!=" => {
+                let pattern = expand::expand_pattern(right, self.vars)?;
+                Ok(glob::matches(&pattern, &left, true) != (op == "!="))
+            }
+            "=~" => {
+                let regex = expand::expand_regex(right, self.vars)?;
+                match regex::captures(&regex, &left) {
+                    Ok(Some(captures)) => {
+                        self.vars.set_array("BASH_REMATCH", captures);
+                        Ok(true)
+                    }
+                    Ok(None) => {
+                        self.vars.set_array("BASH_REMATCH", vec![]);
+                        Ok(false)
+                    }
+                    Err(err) => Err(format!("{}: {}", regex, err)),
+                }
+            }
+            _ => {
+                let right = expand::expand_string(right, self.vars)?;
+                match op {
+                    "<" => Ok(left < right),
+                    ">" => Ok(left > right),
+                    // missing file is older than any existing one
+                    "-nt" => Ok(modified(&left, self.vars) > modified(&right, self.vars)),
+                    "-ot" => Ok(modified(&left, self.vars) < modified(&right, self.vars)),
+                    _ => {
+                        let (left, right) = (integer(&left)?, integer(&right)?);
+                        Ok(match op {
+                            "-eq" => left == right,
+                            "-ne" => left != right,
+                            "-lt" => left < right,
+                            "-le" => left <= right,
+                            "-gt" => left > right,
+                            _ => left >= right,
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+// evaluate [[ ... ]] given its tokens with the brackets, operands are neither
+// split nor globbed
+pub fn evaluate(tokens: &[Token], vars: &mut Variables, options: &Options) -> Result<bool, String> {
+    let inner = match tokens {
+        [_, inner @ .., Token::Word(close)] if close == "]]" => inner,
+        _ => return Err(String::from("syntax error: expected `]]'")),
+    };
+    if inner.is_empty() {
+        return Err(String::from(
+            "syntax error in conditional expression: unexpected `]]'",
+        ));
+    }
+
+    let mut parser = Parser {
+        tokens: inner,
+        index: 0,
+    };
+    let expression = parser.or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!(
+            "syntax error in conditional expression: unexpected token `{}'",
+            token
+        ));
+    }
+
+    Evaluator { vars, options }.evaluate(&expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn test(expression: &str, vars: &mut Variables) -> Result<bool, String> {
+        let tokens = lexer::tokenize(&format!("[[ {} ]]", expression)).unwrap();
+        evaluate(&tokens, vars, &Options::new())
+    }
+
+    fn holds(expression: &str) -> bool {
+        test(expression, &mut Variables::new(&Options::new())).unwrap()
+    }
+
+    #[test]
+    fn strings_and_patterns() {
+        assert!(holds("-n a && -z ''"));
+        assert!(holds("abc == a*"));
+        assert!(!holds("abc == 'a*'"));
+        assert!(holds("abc != b*"));
+        assert!(holds("ab == @(ab|cd)"));
+        assert!(holds("a < b"));
+        assert!(holds("! a == b"));
+        assert!(holds("( a == b || c ) && d"));
+    }
+
+    #[test]
+    fn integers() {
+        assert!(holds("10 -gt 9"));
+        assert!(holds("-1 -lt 0 && 3 -eq 3 && 3 -le 3"));
+        assert_eq!(
+            test("a -eq 1", &mut Variables::new(&Options::new())).unwrap_err(),
+            "a: integer expression expected"
+        );
+    }
+
+    #[test]
+    fn files() {
+        assert!(holds("-d / && -e / && ! -f /"));
+        assert!(!holds("-e /nonexistent/file"));
+        assert!(holds("/ -nt /nonexistent/file"));
+    }
+
+    #[test]
+    fn regex_captures() {
+        let mut vars = Variables::new(&Options::new());
+        assert!(test("key=value =~ ^([a-z]+)=(.*)$", &mut vars).unwrap());
+        assert_eq!(vars.elements("BASH_REMATCH"), ["key=value", "key", "value"]);
+        assert!(!test("x =~ y", &mut vars).unwrap());
+        assert!(vars.elements("BASH_REMATCH").is_empty());
+        assert!(test("'(' =~ '('", &mut vars).unwrap());
+        assert_eq!(
+            test("x =~ [a", &mut vars).unwrap_err(),
+            "[a: brackets ([ ]) not balanced"
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut vars = Variables::new(&Options::new());
+        assert_eq!(
+            test("", &mut vars).unwrap_err(),
+            "syntax error in conditional expression: unexpected `]]'"
+        );
+        assert_eq!(
+            test("a b", &mut vars).unwrap_err(),
+            "syntax error in conditional expression: unexpected token `b'"
+        );
+        assert_eq!(
+            test("( a", &mut vars).unwrap_err(),
+            "syntax error in conditional expression: expected `)'"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::osstr;
+use crate::vars::Variables;
+
+// values of the TOML subset the configuration is written in: strings,
+// integers, booleans and arrays of them, in [tables] and [dotted.tables]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // strings of the array, other values are skipped
+    pub fn strings(&self) -> Option<Vec<String>> {
+        match self {
+            Value::Array(values) => Some(
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(String::from))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+pub struct Config {
+    // keys before the first table are in the "" one
+    tables: HashMap<String, HashMap<String, Value>>,
+}
+
+impl Config {
+    pub fn get(&self, table: &str, key: &str) -> Option<&Value> {
+        self.tables.get(table)?.get(key)
+    }
+
+    pub fn has_table(&self, table: &str) -> bool {
+        self.tables.contains_key(table)
+    }
+}
+
+// $XDG_CONFIG_HOME/shell/config.toml or ~/.config/shell/config.toml
+pub fn path(vars: &Variables) -> Option<String> {
+    match (vars.get("XDG_CONFIG_HOME"), vars.get("HOME")) {
+        (Some(config), _) if !config.is_empty() => Some(format!("{}/shell/config.toml", config)),
+        (_, Some(home)) => Some(format!(
+            "{}/.config/shell/config.toml",
+            home.trim_end_matches('/')
+        )),
+        _ => None,
+    }
+}
+
+// the file read last with its modification time and the parsed configuration
+type Loaded = (String, SystemTime, Result<Arc<Config>, String>);
+
+static LOADED: Mutex<Option<Loaded>> = Mutex::new(None);
+
+// None if there is no configuration file; it's read again only when it
+// changes, the prompt loads it every time it's shown
+pub fn load(vars: &Variables) -> Result<Option<Arc<Config>>, String> {
+    let path = match path(vars) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let modified = match fs::metadata(osstr::to_os(&path)).and_then(|metadata| metadata.modified())
+    {
+        Ok(modified) => modified,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("{}: {}", path, err)),
+    };
+    let mut loaded = LOADED.lock().unwrap();
+    if let Some((loaded_path, time, config)) = &*loaded {
+        if *loaded_path == path && *time == modified {
+            return config.clone().map(Some);
+        }
+    }
+    let config = match fs::read(osstr::to_os(&path)) {
+        Ok(bytes) => parse(&osstr::from_bytes(&bytes))
+            .map(Arc::new)
+            .map_err(|err| format!("{}:{}", path, err)),
+        Err(err) => Err(format!("{}: {}", path, err)),
+    };
+    *loaded = Some((path, modified, config.clone()));
+    config.map(Some)
+}
+
+// a basic "string" with escapes or a 'literal' one, the index after it
+fn string(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let quote = chars[start];
+    let mut text = String::new();
+    let mut index = start + 1;
+    while index < chars.len() {
+        match chars[index] {
+            c if c == quote => return Ok((text, index + 1)),
+            '\\' if quote == '"' => {
+                let escaped = chars.get(index + 1).copied();
+                index += 2;
+                match escaped {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('e') => text.push('\x1b'),
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    Some(kind @ ('u' | 'U')) => {
+                        let digits = if kind == 'u' { 4 } else { 8 };
+                        let code: String = chars.iter().skip(index).take(digits).collect();
+                        match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                            Some(c) if code.len() == digits => text.push(c),
+                            _ => return Err(format!("invalid escape \\{}{}", kind, code)),
+                        }
+                        index += digits;
+                    }
+                    Some(c) => return Err(format!("invalid escape \\{}", c)),
+                    None => break,
+                }
+            }
+            c => {
+                text.push(c);
+                index += 1;
+            }
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+fn skip_blanks(chars: &[char], mut index: usize) -> usize {
+    while index < chars.len() && chars[index].is_whitespace() {
+        index += 1;
+    }
+    // comments end the line
+    if chars.get(index) == Some(&'#') {
+        while index < chars.len() && chars[index] != '\n' {
+            index += 1;
+        }
+        return skip_blanks(chars, index);
+    }
+    index
+}
+
+fn value(chars: &[char], start: usize) -> Result<(Value, usize), String> {
+    match chars.get(start) {
+        Some('"') | Some('\'') => {
+            string(chars, start).map(|(text, end)| (Value::String(text), end))
+        }
+        Some('[') => {
+            let mut values = vec![];
+            let mut index = skip_blanks(chars, start + 1);
+            while chars.get(index) != Some(&']') {
+                let (item, end) = value(chars, index)?;
+                values.push(item);
+                index = skip_blanks(chars, end);
+                match chars.get(index) {
+                    Some(',') => index = skip_blanks(chars, index + 1),
+                    Some(']') => {}
+                    _ => return Err(String::from("expected , or ] in the array")),
+                }
+            }
+            Ok((Value::Array(values), index + 1))
+        }
+        Some(_) => {
+            let mut end = start;
+            while end < chars.len() && !",]#".contains(chars[end]) && !chars[end].is_whitespace() {
+                end += 1;
+            }
+            let word: String = chars[start..end].iter().collect();
+            let value = match &word[..] {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                _ => match word.replace('_', "").parse() {
+                    Ok(number) => Value::Integer(number),
+                    Err(_) => return Err(format!("invalid value: {}", word)),
+                },
+            };
+            Ok((value, end))
+        }
+        None => Err(String::from("value expected")),
+    }
+}
+
+// the errors start with the line number
+pub fn parse(text: &str) -> Result<Config, String> {
+    let chars: Vec<char> = text.chars().collect();
+    // the line of an index is found among the indexes of the newlines
+    let newlines: Vec<usize> = (0..chars.len())
+        .filter(|&index| chars[index] == '\n')
+        .collect();
+    let line = |index: usize| newlines.partition_point(|&newline| newline < index) + 1;
+    let mut tables: HashMap<String, HashMap<String, Value>> = HashMap::new();
+    let mut table = String::new();
+    tables.insert(table.clone(), HashMap::new());
+
+    let mut index = skip_blanks(&chars, 0);
+    while index < chars.len() {
+        let start = index;
+        let rest_of_line = |from: usize| {
+            let end = skip_blanks(&chars, from);
+            match line(end) == line(from) && end < chars.len() {
+                true => Err(format!("{}: unexpected text after the value", line(from))),
+                false => Ok(end),
+            }
+        };
+        if chars[index] == '[' {
+            let end = match chars[index..].iter().position(|&c| c == ']' || c == '\n') {
+                Some(offset) if chars[index + offset] == ']' => index + offset,
+                _ => return Err(format!("{}: unterminated table name", line(start))),
+            };
+            table = chars[index + 1..end]
+                .iter()
+                .collect::<String>()
+                .trim()
+                .to_string();
+            tables.entry(table.clone()).or_default();
+            index = rest_of_line(end + 1)?;
+            continue;
+        }
+
+        let (key, end) = match chars[index] {
+            '"' | '\'' => {
+                string(&chars, index).map_err(|err| format!("{}: {}", line(start), err))?
+            }
+            _ => {
+                let mut end = index;
+                while end < chars.len()
+                    && (chars[end].is_ascii_alphanumeric() || "_-".contains(chars[end]))
+                {
+                    end += 1;
+                }
+                (chars[index..end].iter().collect(), end)
+            }
+        };
+        let equals = skip_blanks(&chars, end);
+        if key.is_empty() || chars.get(equals) != Some(&'=') || line(equals) != line(start) {
+            return Err(format!("{}: expected key = value", line(start)));
+        }
+        let value_start = skip_blanks(&chars, equals + 1);
+        if line(value_start) != line(start) {
+            return Err(format!("{}: value expected", line(start)));
+        }
+        let (value, end) =
+            value(&chars, value_start).map_err(|err| format!("{}: {}", line(start), err))?;
+        tables.entry(table.clone()).or_default().insert(key, value);
+        index = rest_of_line(end)?;
+    }
+    Ok(Config { tables })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn tables_and_values() {
+        let config = parse(
+            "top = 1\n\
+             # comment\n\
+             [prompt]\n\
+             theme = \"powerline\" # after the value\n\
+             right = ['time', \"jobs\"]\n\
+             [segments.git]\n\
+             enabled = false\n\
+             timeout = 1_000\n",
+        )
+        .unwrap();
+        assert_eq!(config.get("", "top"), Some(&Value::Integer(1)));
+        assert_eq!(
+            config.get("prompt", "theme").and_then(Value::as_str),
+            Some("powerline")
+        );
+        assert_eq!(
+            config.get("prompt", "right").and_then(Value::strings),
+            Some(vec![String::from("time"), String::from("jobs")])
+        );
+        assert_eq!(
+            config.get("segments.git", "enabled"),
+            Some(&Value::Boolean(false))
+        );
+        assert_eq!(
+            config.get("segments.git", "timeout"),
+            Some(&Value::Integer(1000))
+        );
+        assert!(config.has_table("segments.git"));
+        assert!(!config.has_table("segments"));
+    }
+
+    #[test]
+    fn string_escapes() {
+        let config = parse("a = \"x\\ty\\u00e9\"\nb = 'c:\\path'\n").unwrap();
+        assert_eq!(
+            config.get("", "a").and_then(Value::as_str),
+            Some("x\ty\u{e9}")
+        );
+        assert_eq!(
+            config.get("", "b").and_then(Value::as_str),
+            Some("c:\\path")
+        );
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = |text| parse(text).err().unwrap();
+        assert_eq!(error("a = 1\n\nb = \n"), "3: value expected");
+        assert_eq!(error("[prompt\n"), "1: unterminated table name");
+        assert_eq!(error("a = 1\nb\n"), "2: expected key = value");
+        assert_eq!(error("a = 1 2\n"), "1: unexpected text after the value");
+        assert_eq!(error("\n\na = \"x\n"), "3: unterminated string");
+        assert_eq!(error("a = yes\n"), "1: invalid value: yes");
+    }
+
+    #[test]
+    fn files_are_read_again_when_they_change() {
+        let root = env::temp_dir().join(format!("config-test-{}", process::id()));
+        let mut vars = Variables::new(&Options::new());
+        vars.set("XDG_CONFIG_HOME", root.to_str().unwrap()).unwrap();
+        assert!(load(&vars).unwrap().is_none());
+
+        let file = root.join("shell/config.toml");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "a = 1\n").unwrap();
+        let first = load(&vars).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&first, &load(&vars).unwrap().unwrap()));
+
+        fs::write(&file, "a = 2\n").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let second = load(&vars).unwrap().unwrap();
+        assert_eq!(second.get("", "a"), Some(&Value::Integer(2)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,1117 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::os::raw::{c_int, c_short, c_uint, c_ulong, c_ushort};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::complete::{self, Completer};
+use crate::history::History;
+use crate::jobs::Jobs;
+use crate::keymap::{Action, Binding, Bindings, Keymap};
+use crate::osstr;
+use crate::prompt::{self, Prompt};
+use crate::signals;
+
+#[cfg(not(target_os = "linux"))]
+compile_error!("the terminal settings are only written for Linux");
+
+// linux struct termios
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Termios {
+    iflag: c_uint,
+    oflag: c_uint,
+    cflag: c_uint,
+    lflag: c_uint,
+    line: u8,
+    cc: [u8; 32],
+    ispeed: c_uint,
+    ospeed: c_uint,
+}
+
+#[repr(C)]
+struct WinSize {
+    rows: c_ushort,
+    cols: c_ushort,
+    xpixel: c_ushort,
+    ypixel: c_ushort,
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn read(fd: c_int, buf: *mut u8, count: usize) -> isize;
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, actions: c_int, termios: *const Termios) -> c_int;
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+const TCSADRAIN: c_int = 1;
+const POLLIN: c_short = 1;
+// milliseconds to wait for the rest of a key sequence
+const KEYSEQ_TIMEOUT: c_int = 50;
+const TIOCGWINSZ: c_ulong = 0x5413;
+
+const ISIG: c_uint = 0o1;
+const ICANON: c_uint = 0o2;
+const ECHO: c_uint = 0o10;
+const IEXTEN: c_uint = 0o100000;
+const ICRNL: c_uint = 0o400;
+const IXON: c_uint = 0o2000;
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+
+// the terminal reads keys one by one without echo while the line is edited,
+// the previous mode is restored when it's dropped, so commands get it back
+struct RawMode(Termios);
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        let mut termios = Termios {
+            iflag: 0,
+            oflag: 0,
+            cflag: 0,
+            lflag: 0,
+            line: 0,
+            cc: [0; 32],
+            ispeed: 0,
+            ospeed: 0,
+        };
+        if unsafe { tcgetattr(0, &mut termios) } != 0 {
+            return None;
+        }
+
+        let mut raw = termios;
+        raw.iflag &= !(ICRNL | IXON);
+        raw.lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
+        raw.cc[VMIN] = 1;
+        raw.cc[VTIME] = 0;
+        if unsafe { tcsetattr(0, TCSADRAIN, &raw) } != 0 {
+            return None;
+        }
+        Some(RawMode(termios))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            tcsetattr(0, TCSADRAIN, &self.0);
+        }
+    }
+}
+
+fn window_size() -> Option<WinSize> {
+    let mut size = WinSize {
+        rows: 0,
+        cols: 0,
+        xpixel: 0,
+        ypixel: 0,
+    };
+    match unsafe { ioctl(1, TIOCGWINSZ, &mut size) } {
+        0 => Some(size),
+        _ => None,
+    }
+}
+
+// width of the terminal, 80 if it's unknown
+pub fn columns() -> usize {
+    match window_size() {
+        Some(size) if size.cols > 0 => size.cols as usize,
+        _ => 80,
+    }
+}
+
+// height of the terminal, 24 if it's unknown
+fn rows() -> usize {
+    match window_size() {
+        Some(size) if size.rows > 0 => size.rows as usize,
+        _ => 24,
+    }
+}
+
+// candidates shown below the line when Tab doesn't complete the word, the
+// selected one is put on the line with Enter
+struct Menu {
+    // the completed word is the part of the line from start to the cursor
+    start: usize,
+    candidates: Vec<String>,
+    selected: Option<usize>,
+}
+
+impl Menu {
+    // paths are shown by their last component
+    fn label(candidate: &str) -> String {
+        let name = candidate.trim_end_matches('/');
+        complete::unescape(&candidate[name.rfind('/').map_or(0, |index| index + 1)..])
+    }
+
+    fn width(&self, columns: usize) -> usize {
+        let longest = self
+            .candidates
+            .iter()
+            .map(|candidate| Menu::label(candidate).chars().count())
+            .max()
+            .unwrap_or_default();
+        (longest + 2).min(columns.saturating_sub(1)).max(3)
+    }
+
+    fn per_row(&self, columns: usize) -> usize {
+        (columns.saturating_sub(1) / self.width(columns)).max(1)
+    }
+
+    fn step(&mut self, offset: isize) {
+        let count = self.candidates.len() as isize;
+        self.selected = Some(match self.selected {
+            None if offset > 0 => 0,
+            None => count as usize - 1,
+            Some(selected) => (selected as isize + offset).rem_euclid(count) as usize,
+        });
+    }
+
+    // rows of candidates with the selected one in reverse video; when there
+    // are more than the limit, the ones around the selection are shown with a
+    // line telling which they are
+    fn render(&self, columns: usize, limit: usize) -> Vec<String> {
+        let width = self.width(columns);
+        let per_row = self.per_row(columns);
+        let rows = self.candidates.len().div_ceil(per_row);
+        let (first, shown) = match limit.max(2) {
+            limit if rows <= limit => (0, rows),
+            limit => {
+                let row = self.selected.unwrap_or_default() / per_row;
+                (row.saturating_sub(limit - 2), limit - 1)
+            }
+        };
+
+        let mut lines = vec![];
+        for row in first..first + shown {
+            let mut line = String::new();
+            let end = ((row + 1) * per_row).min(self.candidates.len());
+            for index in row * per_row..end {
+                let label: String = Menu::label(&self.candidates[index])
+                    .chars()
+                    .take(width - 2)
+                    .collect();
+                let label = format!("{:<1$}", label, width - 2);
+                match self.selected == Some(index) {
+                    true => line.push_str(&format!("\x1b[7m{}\x1b[0m  ", label)),
+                    false => line.push_str(&format!("{}  ", label)),
+                }
+            }
+            lines.push(line);
+        }
+        if shown < rows {
+            lines.push(format!("rows {}-{} of {}", first + 1, first + shown, rows));
+        }
+        lines
+    }
+}
+
+enum Outcome {
+    Continue,
+    Accept,
+    Eof,
+    Interrupt,
+}
+
+// one byte from the terminal, not buffered, so poll sees what's left; None at
+// the end of input, Interrupted error if the terminal is resized before it
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0;
+    match unsafe { read(0, &mut byte, 1) } {
+        0 => Ok(None),
+        1 => Ok(Some(byte)),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// more input arrives within the timeout in milliseconds
+fn pending(timeout: c_int) -> bool {
+    let mut fd = PollFd {
+        fd: 0,
+        events: POLLIN,
+        revents: 0,
+    };
+    unsafe { poll(&mut fd, 1, timeout) > 0 }
+}
+
+// lines of a multiline command joined so it's edited in one line
+fn one_line(entry: &str) -> String {
+    let mut joined = String::new();
+    for line in entry.lines() {
+        let opened = ["{", "(", "&&", "||", "|"]
+            .iter()
+            .any(|end| joined.trim_end().ends_with(end));
+        match joined.is_empty() {
+            true => {}
+            false if opened => joined.push(' '),
+            false => joined.push_str("; "),
+        }
+        joined.push_str(line.trim());
+    }
+    joined
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// vi words are runs of word characters or of other non-blank ones
+fn class(c: char) -> u8 {
+    match c {
+        c if c.is_whitespace() => 0,
+        c if is_word(c) => 1,
+        _ => 2,
+    }
+}
+
+// editing of the input line with the keys bound in keymap: emacs-like by
+// default, vi-like with insert and command modes after set -o vi
+pub struct Editor {
+    bindings: Rc<RefCell<Bindings>>,
+    vi: Arc<AtomicBool>,
+    keymap: Keymap,
+    buffer: Vec<char>,
+    cursor: usize,
+    // row of the cursor below the last line of the prompt
+    row: usize,
+    // the line and the cursor before each change and the undone changes
+    undo: Vec<(Vec<char>, usize)>,
+    redo: Vec<(Vec<char>, usize)>,
+    // characters typed one after another are undone together
+    typing: bool,
+    // the last killed text for yank
+    killed: Vec<char>,
+    // bytes of macros and the ones read after a bound sequence
+    queued: VecDeque<u8>,
+    completer: Completer,
+    // finished jobs are printed while the line is edited with set -b
+    jobs: Jobs,
+    menu: Option<Menu>,
+    history: Rc<RefCell<History>>,
+    // the shown entry of the history, its length for the line being entered,
+    // which is kept while other entries are shown
+    position: usize,
+    draft: Vec<char>,
+}
+
+impl Editor {
+    pub fn new(
+        bindings: Rc<RefCell<Bindings>>,
+        vi: Arc<AtomicBool>,
+        completer: Completer,
+        jobs: Jobs,
+        history: Rc<RefCell<History>>,
+    ) -> Self {
+        Editor {
+            bindings,
+            vi,
+            keymap: Keymap::Emacs,
+            buffer: vec![],
+            cursor: 0,
+            row: 0,
+            undo: vec![],
+            redo: vec![],
+            typing: false,
+            killed: vec![],
+            queued: VecDeque::new(),
+            completer,
+            jobs,
+            menu: None,
+            history,
+            position: 0,
+            draft: vec![],
+        }
+    }
+
+    // redraw the prompt and the line from its first row; the right prompt is
+    // shown only while everything fits in one row with a space before it, the
+    // completion menu goes below the line
+    fn refresh(&mut self, prompt: &str, right: &str, out: &mut impl Write) -> io::Result<()> {
+        let columns = columns();
+        let mut screen = String::new();
+        if self.row > 0 {
+            screen.push_str(&format!("\x1b[{}A", self.row));
+        }
+        screen.push_str("\r\x1b[J");
+        screen.push_str(&prompt::printable(prompt));
+        screen.extend(&self.buffer);
+
+        let start = prompt::width(prompt);
+        let end = start + self.buffer.len();
+        let right_width = prompt::width(right);
+        let end_row = if right_width > 0 && end + 1 + right_width < columns {
+            screen.push_str(&format!("\x1b[{}G", columns - right_width));
+            screen.push_str(&prompt::printable(right));
+            0
+        } else {
+            // the cursor stays at the last column of the full row, moved to
+            // the next one like the following text would be
+            if end > 0 && end % columns == 0 {
+                screen.push_str("\r\n");
+            }
+            end / columns
+        };
+
+        let mut bottom = end_row;
+        if let Some(menu) = &self.menu {
+            for line in menu.render(columns, rows().saturating_sub(end_row + 2)) {
+                screen.push_str("\r\n");
+                screen.push_str(&line);
+                bottom += 1;
+            }
+        }
+
+        let target = start + self.cursor;
+        let (row, column) = (target / columns, target % columns);
+        if bottom > row {
+            screen.push_str(&format!("\x1b[{}A", bottom - row));
+        }
+        screen.push('\r');
+        if column > 0 {
+            screen.push_str(&format!("\x1b[{}C", column));
+        }
+        self.row = row;
+
+        out.write_all(screen.as_bytes())?;
+        out.flush()
+    }
+
+    fn undo(&mut self) {
+        if let Some((buffer, cursor)) = self.undo.pop() {
+            let current = std::mem::replace(&mut self.buffer, buffer);
+            self.redo.push((current, self.cursor));
+            self.cursor = cursor;
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some((buffer, cursor)) = self.redo.pop() {
+            let current = std::mem::replace(&mut self.buffer, buffer);
+            self.undo.push((current, self.cursor));
+            self.cursor = cursor;
+        }
+    }
+
+    fn word_start(&self) -> usize {
+        let mut index = self.cursor;
+        while index > 0 && !is_word(self.buffer[index - 1]) {
+            index -= 1;
+        }
+        while index > 0 && is_word(self.buffer[index - 1]) {
+            index -= 1;
+        }
+        index
+    }
+
+    fn word_end(&self) -> usize {
+        let mut index = self.cursor;
+        while index < self.buffer.len() && !is_word(self.buffer[index]) {
+            index += 1;
+        }
+        while index < self.buffer.len() && is_word(self.buffer[index]) {
+            index += 1;
+        }
+        index
+    }
+
+    // start of the whitespace-delimited word before the cursor
+    fn field_start(&self) -> usize {
+        let mut index = self.cursor;
+        while index > 0 && self.buffer[index - 1].is_whitespace() {
+            index -= 1;
+        }
+        while index > 0 && !self.buffer[index - 1].is_whitespace() {
+            index -= 1;
+        }
+        index
+    }
+
+    fn vi_word_forward(&self) -> usize {
+        let mut index = self.cursor;
+        if let Some(&c) = self.buffer.get(index) {
+            let start = class(c);
+            while start != 0 && index < self.buffer.len() && class(self.buffer[index]) == start {
+                index += 1;
+            }
+        }
+        while index < self.buffer.len() && class(self.buffer[index]) == 0 {
+            index += 1;
+        }
+        index
+    }
+
+    fn vi_word_end(&self) -> usize {
+        let mut index = self.cursor + 1;
+        while index < self.buffer.len() && class(self.buffer[index]) == 0 {
+            index += 1;
+        }
+        if index >= self.buffer.len() {
+            return self.buffer.len();
+        }
+        let start = class(self.buffer[index]);
+        while index + 1 < self.buffer.len() && class(self.buffer[index + 1]) == start {
+            index += 1;
+        }
+        index
+    }
+
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.killed = self.buffer.drain(start..end).collect();
+        }
+        self.cursor = start;
+    }
+
+    // the part of the line from start to the cursor is replaced with the text
+    fn replace(&mut self, start: usize, text: &str) {
+        let text: Vec<char> = text.chars().collect();
+        self.buffer.splice(start..self.cursor, text.iter().copied());
+        self.cursor = start + text.len();
+    }
+
+    // a single candidate is put on the line with a space after it unless it's
+    // a directory, several ones are completed to their common start, the menu
+    // is opened when that doesn't add anything
+    fn complete(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (start, candidates) = self.completer.complete(&self.buffer, self.cursor);
+        match &candidates[..] {
+            [] => out.write_all(b"\x07")?,
+            [candidate] => {
+                let mut text = candidate.clone();
+                if !text.ends_with('/') {
+                    text.push(' ');
+                }
+                self.replace(start, &text);
+            }
+            _ => {
+                let common = complete::common_prefix(&candidates);
+                if common.chars().count() > self.cursor - start {
+                    self.replace(start, &common);
+                } else {
+                    self.menu = Some(Menu {
+                        start,
+                        candidates,
+                        selected: None,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // keys of the open menu: Tab, Shift-Tab and arrows move the selection,
+    // Enter puts the selected candidate on the line, escape and Ctrl-G close
+    // the menu; false for other keys, which close it and do what they do
+    fn menu_key(&mut self, sequence: &[u8], binding: &Option<Binding>) -> bool {
+        let menu = match &mut self.menu {
+            Some(menu) => menu,
+            None => return false,
+        };
+        let actions: &[Action] = match binding {
+            Some(Binding::Actions(actions)) => actions,
+            _ => &[],
+        };
+        let per_row = menu.per_row(columns()) as isize;
+        let offset = match (sequence, actions) {
+            (_, [Action::Complete | Action::ForwardChar]) => Some(1),
+            (_, [Action::MenuCompleteBackward | Action::BackwardChar]) => Some(-1),
+            (_, [Action::NextHistory]) => Some(per_row),
+            (_, [Action::PreviousHistory]) => Some(-per_row),
+            _ => None,
+        };
+
+        match (offset, actions, menu.selected) {
+            (Some(offset), _, _) => {
+                menu.step(offset);
+                return true;
+            }
+            (None, [Action::AcceptLine], Some(selected)) => {
+                let start = menu.start;
+                let mut text = menu.candidates[selected].clone();
+                if !text.ends_with('/') {
+                    text.push(' ');
+                }
+                self.menu = None;
+                self.undo.push((self.buffer.clone(), self.cursor));
+                self.redo.clear();
+                self.typing = false;
+                self.replace(start, &text);
+                return true;
+            }
+            _ => {}
+        }
+        self.menu = None;
+        sequence == [0x1b] || sequence == [0x07]
+    }
+
+    // the entry of the history at the position is shown with the cursor at
+    // its end, past the last one is the line being entered
+    fn show_history(&mut self, position: usize) {
+        let history = Rc::clone(&self.history);
+        let history = history.borrow();
+        let entries = history.entries();
+        if position > entries.len() || position == self.position {
+            return;
+        }
+        if self.position >= entries.len() {
+            self.draft = self.buffer.clone();
+        }
+        self.buffer = match entries.get(position) {
+            Some(entry) => one_line(entry).chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.cursor = self.buffer.len();
+        self.position = position;
+    }
+
+    fn insert(&mut self, text: &[char]) {
+        for &c in text {
+            self.buffer.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    // bytes after the first one of a sequence, the resize doesn't split it
+    fn next_byte(&mut self, first: bool) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.queued.pop_front() {
+            return Ok(Some(byte));
+        }
+        loop {
+            match read_byte() {
+                Err(err) if !first && err.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // the longest bound sequence of the read keys; when a bound one is the
+    // start of longer ones, or it starts with escape, the next byte is waited
+    // for a moment only. Unbound escape sequences are read to their end,
+    // other unbound bytes are read to the end of the UTF-8 character
+    fn read_binding(&mut self) -> io::Result<Option<(Vec<u8>, Option<Binding>)>> {
+        let mut sequence = match self.next_byte(true)? {
+            Some(byte) => vec![byte],
+            None => return Ok(None),
+        };
+        let bindings = Rc::clone(&self.bindings);
+        let bindings = bindings.borrow();
+        let mut found = None;
+        loop {
+            let (exact, longer) = bindings.lookup(self.keymap, &sequence);
+            if let Some(binding) = exact {
+                found = Some((sequence.len(), binding));
+            }
+            if !longer {
+                break;
+            }
+            let waited = found.is_some() || sequence[0] == 0x1b;
+            if waited && self.queued.is_empty() && !pending(KEYSEQ_TIMEOUT) {
+                break;
+            }
+            match self.next_byte(false)? {
+                Some(byte) => sequence.push(byte),
+                None => break,
+            }
+        }
+
+        if let Some((len, binding)) = found {
+            for &byte in sequence[len..].iter().rev() {
+                self.queued.push_front(byte);
+            }
+            sequence.truncate(len);
+            return Ok(Some((sequence, Some(binding))));
+        }
+
+        if sequence[0] == 0x1b {
+            let introduced = matches!(sequence.get(1), Some(b'[') | Some(b'O'));
+            while introduced
+                && (sequence.len() == 2 || !(0x40..=0x7e).contains(&sequence[sequence.len() - 1]))
+            {
+                match self.next_byte(false)? {
+                    Some(byte) => sequence.push(byte),
+                    None => break,
+                }
+            }
+            return Ok(Some((sequence, None)));
+        }
+
+        for &byte in sequence[1..].iter().rev() {
+            self.queued.push_front(byte);
+        }
+        sequence.truncate(1);
+        let len = match sequence[0] {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        while sequence.len() < len {
+            match self.next_byte(false)? {
+                Some(byte) => sequence.push(byte),
+                None => break,
+            }
+        }
+        Ok(Some((sequence, None)))
+    }
+
+    fn perform(
+        &mut self,
+        action: Action,
+        sequence: &[u8],
+        out: &mut impl Write,
+    ) -> io::Result<Outcome> {
+        use Action::*;
+
+        match action {
+            AcceptLine => return Ok(Outcome::Accept),
+            Interrupt => return Ok(Outcome::Interrupt),
+            SelfInsert => {
+                let text: Vec<char> = osstr::from_bytes(sequence).chars().collect();
+                self.insert(&text);
+            }
+            BeginningOfLine => self.cursor = 0,
+            EndOfLine => self.cursor = self.buffer.len(),
+            ForwardChar => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            BackwardChar => self.cursor = self.cursor.saturating_sub(1),
+            ForwardWord => self.cursor = self.word_end(),
+            BackwardWord => self.cursor = self.word_start(),
+            // only Ctrl-D itself ends the input, not x of vi
+            DeleteChar if self.buffer.is_empty() && sequence == [0x04] => return Ok(Outcome::Eof),
+            DeleteChar if self.cursor < self.buffer.len() => {
+                self.buffer.remove(self.cursor);
+            }
+            BackwardDeleteChar if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buffer.remove(self.cursor);
+            }
+            KillLine => self.kill(self.cursor, self.buffer.len()),
+            BackwardKillLine => self.kill(0, self.cursor),
+            KillWholeLine => self.kill(0, self.buffer.len()),
+            KillWord => self.kill(self.cursor, self.word_end()),
+            BackwardKillWord => self.kill(self.word_start(), self.cursor),
+            UnixWordRubout => self.kill(self.field_start(), self.cursor),
+            Yank => {
+                let killed = self.killed.clone();
+                self.insert(&killed);
+            }
+            TransposeChars if self.cursor > 0 && self.buffer.len() >= 2 => {
+                if self.cursor == self.buffer.len() {
+                    self.cursor -= 1;
+                }
+                self.buffer.swap(self.cursor - 1, self.cursor);
+                self.cursor += 1;
+            }
+            Complete | MenuCompleteBackward => self.complete(out)?,
+            PreviousHistory if self.position > 0 => self.show_history(self.position - 1),
+            NextHistory => self.show_history(self.position + 1),
+            ClearScreen => {
+                write!(out, "\x1b[H\x1b[2J")?;
+                self.row = 0;
+            }
+            Undo => self.undo(),
+            Redo => self.redo(),
+            // the cursor moves back onto the last inserted character
+            ViMovementMode => {
+                self.keymap = Keymap::ViCommand;
+                self.cursor = self.cursor.saturating_sub(1);
+            }
+            ViInsertionMode => self.keymap = Keymap::ViInsert,
+            ViAppendMode => {
+                self.keymap = Keymap::ViInsert;
+                self.cursor = (self.cursor + 1).min(self.buffer.len());
+            }
+            ViAppendEol => {
+                self.keymap = Keymap::ViInsert;
+                self.cursor = self.buffer.len();
+            }
+            ViInsertBeg => {
+                self.keymap = Keymap::ViInsert;
+                self.cursor = 0;
+            }
+            ViForwardWord => self.cursor = self.vi_word_forward(),
+            ViEndWord => self.cursor = self.vi_word_end(),
+            ViKillWord => self.kill(self.cursor, self.vi_word_forward()),
+            _ => {}
+        }
+        Ok(Outcome::Continue)
+    }
+
+    // finished jobs are printed in place of the prompt and the line, which are
+    // drawn again below them; false if there are no such jobs
+    fn print_finished(&mut self, above: Option<&str>, out: &mut impl Write) -> io::Result<bool> {
+        let lines = self.jobs.finished();
+        if lines.is_empty() {
+            return Ok(false);
+        }
+        if self.row > 0 {
+            write!(out, "\x1b[{}A", self.row)?;
+        }
+        write!(out, "\r\x1b[J")?;
+        for line in lines {
+            write!(out, "{}\r\n", line)?;
+        }
+        if let Some(above) = above {
+            write!(
+                out,
+                "{}\r\n",
+                prompt::printable(above).replace('\n', "\r\n")
+            )?;
+        }
+        self.row = 0;
+        Ok(true)
+    }
+
+    // read a line from the terminal after the prompt, the line with \n is
+    // appended like by read_line; 0 at the end of input, Ctrl-C gives
+    // Interrupted error
+    pub fn read_line(&mut self, prompt: &Prompt, line: &mut String) -> io::Result<usize> {
+        signals::catch_resize();
+        let result = self.edit(prompt, line);
+        signals::release_resize();
+        result
+    }
+
+    fn edit(&mut self, prompt: &Prompt, line: &mut String) -> io::Result<usize> {
+        let mut out = io::stdout();
+        let raw = match RawMode::enable() {
+            Some(raw) => raw,
+            None => {
+                write!(out, "{}", prompt::printable(&prompt.left))?;
+                out.flush()?;
+                return io::stdin().read_line(line);
+            }
+        };
+
+        // lines of the prompt before the last one are printed once
+        let (above, left) = match prompt.left.rsplit_once('\n') {
+            Some((above, left)) => (Some(above), left),
+            None => (None, &prompt.left[..]),
+        };
+        if let Some(above) = above {
+            write!(
+                out,
+                "{}\r\n",
+                prompt::printable(above).replace('\n', "\r\n")
+            )?;
+        }
+
+        self.keymap = match self.vi.load(Ordering::Relaxed) {
+            true => Keymap::ViInsert,
+            false => Keymap::Emacs,
+        };
+        self.buffer.clear();
+        self.cursor = 0;
+        self.row = 0;
+        self.undo.clear();
+        self.redo.clear();
+        self.typing = false;
+        self.menu = None;
+        self.position = self.history.borrow().entries().len();
+        self.draft.clear();
+        self.refresh(left, &prompt.right, &mut out)?;
+
+        loop {
+            let (sequence, binding) = match self.read_binding() {
+                Ok(Some(read)) => read,
+                Ok(None) if self.buffer.is_empty() => return Ok(0),
+                Ok(None) => break,
+                // terminals rewrap the drawn lines for the new width, so the
+                // row of the cursor is found again before the line is redrawn
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    let resized = signals::resized();
+                    if resized {
+                        self.row = (prompt::width(left) + self.cursor) / columns();
+                    }
+                    let notified = signals::notified() && self.print_finished(above, &mut out)?;
+                    if resized || notified {
+                        self.refresh(left, &prompt.right, &mut out)?;
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if self.menu_key(&sequence, &binding) {
+                self.refresh(left, &prompt.right, &mut out)?;
+                continue;
+            }
+            let text = String::from_utf8_lossy(&sequence).into_owned();
+            let actions = match binding {
+                // the text of a macro is read like typed keys
+                Some(Binding::Macro(text)) => {
+                    for &byte in text.iter().rev() {
+                        self.queued.push_front(byte);
+                    }
+                    continue;
+                }
+                Some(Binding::Actions(actions)) => actions,
+                None if sequence[0] != 0x1b
+                    && self.keymap.inserts()
+                    && !text.chars().any(|c| c.is_control()) =>
+                {
+                    vec![Action::SelfInsert]
+                }
+                None => continue,
+            };
+
+            let before = (self.buffer.clone(), self.cursor);
+            let typed = actions == [Action::SelfInsert] && !text.chars().any(|c| c.is_whitespace());
+            // undo and redo move the line between the stacks themselves
+            let undoing = actions
+                .iter()
+                .any(|action| matches!(action, Action::Undo | Action::Redo));
+            let mut outcome = Outcome::Continue;
+            for action in actions {
+                outcome = self.perform(action, &sequence, &mut out)?;
+                if !matches!(outcome, Outcome::Continue) {
+                    break;
+                }
+            }
+            // in vi command mode the cursor stays on a character
+            if self.keymap == Keymap::ViCommand && self.cursor >= self.buffer.len() {
+                self.cursor = self.buffer.len().saturating_sub(1);
+            }
+
+            match outcome {
+                Outcome::Continue => {}
+                Outcome::Accept => break,
+                Outcome::Eof => {
+                    drop(raw);
+                    return Ok(0);
+                }
+                Outcome::Interrupt => {
+                    self.cursor = self.buffer.len();
+                    self.refresh(left, &prompt.right, &mut out)?;
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Err(io::Error::from(io::ErrorKind::Interrupted));
+                }
+            }
+
+            if before.0 != self.buffer && !undoing {
+                if !(typed && self.typing) {
+                    self.undo.push(before);
+                }
+                self.redo.clear();
+            }
+            self.typing = typed;
+            self.refresh(left, &prompt.right, &mut out)?;
+        }
+
+        self.cursor = self.buffer.len();
+        match &prompt.transient {
+            // the accepted line is drawn again from the first line of the
+            // prompt, so the rows above the input are replaced too
+            Some(transient) => {
+                let columns = columns();
+                self.row += above.map_or(0, |above| {
+                    above
+                        .split('\n')
+                        .map(|line| prompt::width(line).max(1).div_ceil(columns))
+                        .sum()
+                });
+                self.menu = None;
+                self.refresh(transient, "", &mut out)?;
+            }
+            None => self.refresh(left, &prompt.right, &mut out)?,
+        }
+        write!(out, "\r\n")?;
+        out.flush()?;
+
+        let text: String = self.buffer.iter().collect();
+        line.push_str(&text);
+        line.push('\n');
+        Ok(text.len() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frequency::Frequency;
+    use crate::options::Options;
+    use std::process;
+    use std::time::{Duration, Instant};
+
+    fn editor(line: &str, cursor: usize) -> Editor {
+        let bindings = Rc::new(RefCell::new(Bindings::new()));
+        let vi = Arc::new(AtomicBool::new(false));
+        let history = Rc::new(RefCell::new(History::new()));
+        let frequency = Rc::new(RefCell::new(Frequency::new()));
+        let context = Rc::new(RefCell::new(complete::Context::default()));
+        let completer = Completer::new(vec![], frequency, context);
+        let jobs = Jobs::new(&Options::new());
+        let mut editor = Editor::new(bindings, vi, completer, jobs, history);
+        editor.buffer = line.chars().collect();
+        editor.cursor = cursor;
+        editor
+    }
+
+    fn performed(editor: &mut Editor, actions: &[Action]) -> String {
+        for &action in actions {
+            let outcome = editor.perform(action, b"", &mut Vec::new()).unwrap();
+            assert!(matches!(outcome, Outcome::Continue));
+        }
+        editor.buffer.iter().collect()
+    }
+
+    #[test]
+    fn bound_sequences_are_read() {
+        let mut editor = editor("", 0);
+        editor.queued.extend(b"\x01\x1b[3~\xc3\xa9x");
+        let (sequence, binding) = editor.read_binding().unwrap().unwrap();
+        assert_eq!(sequence, b"\x01");
+        assert!(
+            matches!(binding, Some(Binding::Actions(actions)) if actions == [Action::BeginningOfLine])
+        );
+        let (sequence, binding) = editor.read_binding().unwrap().unwrap();
+        assert_eq!(sequence, b"\x1b[3~");
+        assert!(
+            matches!(binding, Some(Binding::Actions(actions)) if actions == [Action::DeleteChar])
+        );
+        // unbound characters are read whole
+        let (sequence, binding) = editor.read_binding().unwrap().unwrap();
+        assert_eq!(sequence, "\u{e9}".as_bytes());
+        assert!(binding.is_none());
+        assert_eq!(editor.queued, b"x");
+    }
+
+    #[test]
+    fn words_are_found() {
+        let line = "echo foo_bar  baz";
+        assert_eq!(editor(line, 17).word_start(), 14);
+        assert_eq!(editor(line, 14).word_start(), 5);
+        assert_eq!(editor(line, 7).word_start(), 5);
+        assert_eq!(editor(line, 0).word_start(), 0);
+        assert_eq!(editor(line, 0).word_end(), 4);
+        assert_eq!(editor(line, 4).word_end(), 12);
+        assert_eq!(editor(line, 17).word_end(), 17);
+        assert_eq!(editor("a b.c/d", 7).field_start(), 2);
+        assert_eq!(editor("ab.c d", 0).vi_word_forward(), 2);
+        assert_eq!(editor("ab.c d", 2).vi_word_forward(), 3);
+        assert_eq!(editor("ab  cd", 1).vi_word_end(), 5);
+    }
+
+    #[test]
+    fn text_is_killed_and_yanked() {
+        use Action::*;
+
+        let mut editor = editor("echo foo bar", 8);
+        assert_eq!(performed(&mut editor, &[BackwardKillWord]), "echo  bar");
+        assert_eq!(performed(&mut editor, &[EndOfLine, Yank]), "echo  barfoo");
+        assert_eq!(
+            performed(&mut editor, &[BackwardChar, BackwardChar, TransposeChars]),
+            "echo  barofo"
+        );
+        assert_eq!(performed(&mut editor, &[BeginningOfLine, KillLine]), "");
+        assert_eq!(
+            performed(&mut editor, &[Yank, BackwardChar, BackwardChar]),
+            "echo  barofo"
+        );
+        assert_eq!(editor.cursor, 10);
+        assert_eq!(performed(&mut editor, &[UnixWordRubout]), "echo  fo");
+        assert_eq!(performed(&mut editor, &[KillWholeLine]), "");
+    }
+
+    #[test]
+    fn vi_modes_move_the_cursor() {
+        use Action::*;
+
+        let mut editor = editor("abc", 3);
+        performed(&mut editor, &[ViMovementMode]);
+        assert!(editor.keymap == Keymap::ViCommand && editor.cursor == 2);
+        performed(&mut editor, &[ViAppendMode]);
+        assert!(editor.keymap == Keymap::ViInsert && editor.cursor == 3);
+        performed(&mut editor, &[ViMovementMode, ViInsertBeg]);
+        assert!(editor.keymap == Keymap::ViInsert && editor.cursor == 0);
+    }
+
+    #[test]
+    fn empty_lines_end_with_ctrl_d() {
+        let mut editor = editor("", 0);
+        let outcome = editor.perform(Action::DeleteChar, b"\x04", &mut Vec::new());
+        assert!(matches!(outcome.unwrap(), Outcome::Eof));
+        let outcome = editor.perform(Action::DeleteChar, b"x", &mut Vec::new());
+        assert!(matches!(outcome.unwrap(), Outcome::Continue));
+    }
+
+    #[test]
+    fn inserted_bytes_are_kept() {
+        let mut editor = editor("", 0);
+        for sequence in [&b"a"[..], b"\xff", "\u{10ff41}".as_bytes()] {
+            let outcome = editor.perform(Action::SelfInsert, sequence, &mut Vec::new());
+            assert!(matches!(outcome.unwrap(), Outcome::Continue));
+        }
+        let line: String = editor.buffer.iter().collect();
+        assert_eq!(osstr::to_bytes(&line), b"a\xff\xf4\x8f\xbd\x81");
+    }
+
+    #[test]
+    fn changes_are_undone_and_redone() {
+        let mut editor = editor("ab", 2);
+        editor.undo.push((vec!['a'], 1));
+        editor.undo.push((Vec::new(), 0));
+        editor.undo();
+        assert_eq!((editor.buffer.len(), editor.cursor), (0, 0));
+        editor.undo();
+        editor.undo();
+        assert_eq!((&editor.buffer[..], editor.cursor), (&['a'][..], 1));
+        editor.redo();
+        editor.redo();
+        assert_eq!((&editor.buffer[..], editor.cursor), (&['a', 'b'][..], 2));
+        editor.redo();
+        assert_eq!(editor.undo.len(), 2);
+    }
+
+    #[test]
+    fn history_entries_are_shown() {
+        use Action::*;
+
+        let mut editor = editor("draft", 5);
+        let mut history = History::new();
+        history.add("echo one", 10);
+        history.add("if true\nthen\necho\nfi", 10);
+        editor.history = Rc::new(RefCell::new(history));
+        editor.position = 2;
+
+        assert_eq!(
+            performed(&mut editor, &[PreviousHistory]),
+            "if true; then; echo; fi"
+        );
+        assert_eq!(
+            performed(&mut editor, &[PreviousHistory, PreviousHistory]),
+            "echo one"
+        );
+        assert_eq!(editor.cursor, 8);
+        assert_eq!(performed(&mut editor, &[NextHistory, NextHistory]), "draft");
+        assert_eq!(performed(&mut editor, &[NextHistory]), "draft");
+        assert_eq!(one_line("a |\nb {\nc\n}"), "a | b { c; }");
+    }
+
+    #[test]
+    fn finished_jobs_are_printed_above_the_prompt() {
+        let mut editor = editor("ls", 2);
+        let mut out = vec![];
+        assert!(!editor.print_finished(None, &mut out).unwrap());
+        editor
+            .jobs
+            .spawn(process::Command::new("/bin/true"), &[], "true", None)
+            .unwrap();
+        let started = Instant::now();
+        while !editor.print_finished(Some("above"), &mut out).unwrap() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+        }
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\r\x1b[J[1]+  Done"), "{:?}", out);
+        assert!(out.ends_with("true\r\nabove\r\n"), "{:?}", out);
+    }
+}
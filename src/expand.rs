@@ -0,0 +1,1099 @@
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+use std::process;
+
+use crate::arith;
+use crate::glob;
+use crate::options::Options;
+use crate::osstr;
+use crate::vars::{self, Variables};
+
+extern "C" {
+    fn gethostname(name: *mut c_char, len: usize) -> c_int;
+}
+
+pub fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    if unsafe { gethostname(buffer.as_mut_ptr() as *mut c_char, buffer.len()) } != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..len]).into_owned())
+}
+
+// variables computed on every expansion, they take precedence over stored ones
+fn special_variable(name: &str, vars: &mut Variables) -> Option<String> {
+    match name {
+        "RANDOM" => Some(vars.random().to_string()),
+        "SECONDS" => Some(vars.seconds().to_string()),
+        "PWD" => Some(osstr::from_os(vars.cwd.as_os_str())),
+        "HOSTNAME" => hostname(),
+        "$" => Some(process::id().to_string()),
+        "?" => Some(vars.status.to_string()),
+        "#" => Some(vars.positional.len().to_string()),
+        "0" => Some(vars.name.clone()),
+        _ if name.chars().all(|c| c.is_ascii_digit()) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| vars.positional.get(index.wrapping_sub(1)).cloned()),
+        _ => None,
+    }
+}
+
+// None if the variable is not set
+fn lookup_set(name: &str, vars: &mut Variables) -> Option<String> {
+    special_variable(name, vars).or_else(|| vars.get(name))
+}
+
+pub fn lookup(name: &str, vars: &mut Variables) -> String {
+    lookup_set(name, vars).unwrap_or_default()
+}
+
+// where a character of the expanded word comes from: only results of unquoted
+// expansions are split by IFS, quoted characters are never globbed
+#[derive(Clone, Copy, PartialEq)]
+enum Origin {
+    Literal,
+    Expanded,
+    Quoted,
+    // boundary between positional parameters of $@, its character is ignored
+    Break,
+    // the same for "$@", fields are kept even if they are empty
+    QuotedBreak,
+}
+
+// expanded word ready for filename expansion, pattern has quoted special
+// characters escaped by backslash
+pub struct Field {
+    pub text: String,
+    pub pattern: String,
+}
+
+impl Field {
+    // word taken as is, it's never globbed
+    pub fn literal(text: &str) -> Self {
+        let chars: Vec<(char, Origin)> = text.chars().map(|c| (c, Origin::Quoted)).collect();
+        field(&chars)
+    }
+}
+
+const DEFAULT_IFS: &str = " \t\n";
+
+enum Value {
+    Scalar(String),
+    // "$@" and unquoted $@ and $* give a separate field for each parameter
+    Fields(Vec<String>),
+    // expanded word of ${NAME:-word} keeps its quotes, the flag tells whether
+    // it had any
+    Word(Vec<(char, Origin)>, bool),
+}
+
+fn is_special_parameter(c: char) -> bool {
+    matches!(c, '$' | '#' | '@' | '*' | '?')
+}
+
+// list of words expanded by $@ or $*, also used for ${map[@]} and ${!map[@]}
+fn list(words: Vec<String>, star: bool, quoted: bool, vars: &mut Variables) -> Value {
+    if star && quoted {
+        // "$*" joins words with the first character of IFS
+        let ifs = vars.get("IFS").unwrap_or_else(|| String::from(DEFAULT_IFS));
+        let separator: String = ifs.chars().take(1).collect();
+        Value::Scalar(words.join(&separator))
+    } else {
+        Value::Fields(words)
+    }
+}
+
+fn value(name: &str, quoted: bool, vars: &mut Variables) -> Value {
+    match name {
+        "@" | "*" => {
+            let positional = vars.positional.clone();
+            list(positional, name == "*", quoted, vars)
+        }
+        _ => Value::Scalar(lookup(name, vars)),
+    }
+}
+
+// split ${...} contents into the parameter, optionally with [subscript],
+// and the rest which is an operator with its word
+fn split_parameter(inner: &str) -> (&str, &str) {
+    let len = match inner.chars().next() {
+        Some(c) if is_special_parameter(c) => 1,
+        Some(c) if c.is_ascii_digit() => inner
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(inner.len()),
+        _ => inner
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(inner.len()),
+    };
+    let len = match inner[len..]
+        .strip_prefix('[')
+        .and_then(|rest| rest.find(']'))
+    {
+        Some(close) => len + close + 2,
+        None => len,
+    };
+
+    inner.split_at(len)
+}
+
+// value of NAME, NAME[key] or NAME[@], None if it's not set
+fn parameter_value(
+    parameter: &str,
+    quoted: bool,
+    vars: &mut Variables,
+) -> Result<Option<Value>, String> {
+    let (name, subscript) = match parameter
+        .strip_suffix(']')
+        .and_then(|parameter| parameter.split_once('['))
+    {
+        Some(split) => split,
+        None if parameter == "@" || parameter == "*" => {
+            return Ok(Some(value(parameter, quoted, vars)))
+        }
+        None => return Ok(lookup_set(parameter, vars).map(Value::Scalar)),
+    };
+
+    Ok(match subscript {
+        "@" | "*" => {
+            let elements = vars.elements(name);
+            Some(list(elements, subscript == "*", quoted, vars))
+        }
+        _ => {
+            let key = expand_string(subscript, vars)?;
+            // subscripts of indexed arrays are arithmetic expressions
+            let key = match vars.is_assoc(name) {
+                true => key,
+                false => arith::evaluate(&key, vars)?.to_string(),
+            };
+            vars.element(name, &key).map(Value::Scalar)
+        }
+    })
+}
+
+fn is_null(value: &Option<Value>) -> bool {
+    match value {
+        Some(Value::Scalar(value)) => value.is_empty(),
+        Some(Value::Fields(fields)) => fields.iter().all(|field| field.is_empty()),
+        Some(Value::Word(chars, _)) => chars.is_empty(),
+        None => true,
+    }
+}
+
+// ${NAME:-word}, ${NAME:=word}, ${NAME:?word} and ${NAME:+word}, without
+// the colon only unset parameter is replaced, with it also the empty one
+fn default_value(
+    parameter: &str,
+    operator: &str,
+    word: &str,
+    value: Option<Value>,
+    vars: &mut Variables,
+) -> Result<Value, String> {
+    let (check_null, kind) = match operator.strip_prefix(':') {
+        Some(kind) => (true, kind),
+        None => (false, operator),
+    };
+    let missing = if check_null {
+        is_null(&value)
+    } else {
+        value.is_none()
+    };
+
+    match kind {
+        "-" if missing => word_value(word, vars),
+        "=" if missing => {
+            if !vars::is_name(parameter) {
+                return Err(format!("{}: cannot assign in this way", parameter));
+            }
+            let word = expand_string(word, vars)?;
+            vars.set(parameter, &word)?;
+            Ok(Value::Scalar(word))
+        }
+        "?" if missing => {
+            let message = match expand_string(word, vars)? {
+                message if message.is_empty() && check_null => {
+                    String::from("parameter null or not set")
+                }
+                message if message.is_empty() => String::from("parameter not set"),
+                message => message,
+            };
+            vars.fatal = true;
+            Err(format!("{}: {}", parameter, message))
+        }
+        "+" if missing => Ok(Value::Scalar(String::new())),
+        "+" => word_value(word, vars),
+        _ => Ok(value.unwrap_or(Value::Scalar(String::new()))),
+    }
+}
+
+fn word_value(word: &str, vars: &mut Variables) -> Result<Value, String> {
+    let (chars, quoted) = expand_chars(word, vars)?;
+    Ok(Value::Word(chars, quoted))
+}
+
+// apply the operation to the value or to each of its fields
+fn transform(value: Option<Value>, operation: impl Fn(&str) -> String) -> Value {
+    match value {
+        Some(Value::Scalar(value)) => Value::Scalar(operation(&value)),
+        Some(Value::Fields(fields)) => {
+            Value::Fields(fields.iter().map(|field| operation(field)).collect())
+        }
+        Some(Value::Word(chars, _)) => Value::Scalar(operation(
+            &chars.iter().map(|&(c, _)| c).collect::<String>(),
+        )),
+        None => Value::Scalar(operation("")),
+    }
+}
+
+// expand the word into a glob pattern, quoted characters are escaped
+pub fn expand_pattern(word: &str, vars: &mut Variables) -> Result<String, String> {
+    let (chars, _) = expand_chars(word, vars)?;
+    Ok(field(&chars).pattern)
+}
+
+// expand the word into a regular expression, quoted characters match literally
+pub fn expand_regex(word: &str, vars: &mut Variables) -> Result<String, String> {
+    let (chars, _) = expand_chars(word, vars)?;
+    let mut regex = String::new();
+    for (c, origin) in chars {
+        if origin == Origin::Quoted && ".[]()*+?{}|^$\\".contains(c) {
+            regex.push('\\');
+        }
+        regex.push(c);
+    }
+
+    Ok(regex)
+}
+
+// ${NAME#pattern} and ${NAME##pattern} remove the shortest or the longest
+// matching prefix, ${NAME%pattern} and ${NAME%%pattern} the same for suffix
+fn remove_matching(
+    text: &str,
+    pattern: &str,
+    suffix: bool,
+    longest: bool,
+    extglob: bool,
+) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut lengths: Vec<usize> = (0..=chars.len()).collect();
+    if longest {
+        lengths.reverse();
+    }
+
+    for len in lengths {
+        let (part, rest) = if suffix {
+            (&chars[chars.len() - len..], &chars[..chars.len() - len])
+        } else {
+            (&chars[..len], &chars[len..])
+        };
+        if glob::matches(pattern, &part.iter().collect::<String>(), extglob) {
+            return rest.iter().collect();
+        }
+    }
+
+    String::from(text)
+}
+
+// ${NAME/pattern/string} replaces the first longest match, // all of them,
+// /# and /% only a match at the beginning or at the end
+fn replace_matching(
+    text: &str,
+    kind: &str,
+    pattern: &str,
+    replacement: &str,
+    extglob: bool,
+) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let matches = |start: usize, end: usize| {
+        glob::matches(
+            pattern,
+            &chars[start..end].iter().collect::<String>(),
+            extglob,
+        )
+    };
+    let collect = |chars: &[char]| chars.iter().collect::<String>();
+
+    match kind {
+        "#" => match (0..=chars.len()).rev().find(|&end| matches(0, end)) {
+            Some(end) => format!("{}{}", replacement, collect(&chars[end..])),
+            None => String::from(text),
+        },
+        "%" => match (0..=chars.len()).find(|&start| matches(start, chars.len())) {
+            Some(start) => format!("{}{}", collect(&chars[..start]), replacement),
+            None => String::from(text),
+        },
+        _ => {
+            let mut result = String::new();
+            let mut start = 0;
+            while start < chars.len() {
+                // empty matches are skipped, otherwise * would never advance
+                match (start + 1..=chars.len())
+                    .rev()
+                    .find(|&end| matches(start, end))
+                {
+                    Some(end) => {
+                        result.push_str(replacement);
+                        start = end;
+                        if kind != "/" {
+                            break;
+                        }
+                    }
+                    None => {
+                        result.push(chars[start]);
+                        start += 1;
+                    }
+                }
+            }
+            result.push_str(&collect(&chars[start..]));
+            result
+        }
+    }
+}
+
+// ${NAME:offset} and ${NAME:offset:length}, negative offset counts from
+// the end and gives nothing before the start, negative length is the
+// position from the end where to stop
+fn substring(count: usize, offset: &str, length: Option<&str>) -> Result<(usize, usize), String> {
+    let number = |text: &str| {
+        text.trim()
+            .parse::<i64>()
+            .map_err(|_| format!("{}: arithmetic syntax error", text.trim()))
+    };
+    let count = count as i64;
+
+    let mut start = number(offset)?;
+    if start < 0 {
+        start += count;
+        if start < 0 {
+            return Ok((0, 0));
+        }
+    }
+    let start = start.clamp(0, count);
+    let end = match length.map(number).transpose()? {
+        Some(length) if length < 0 => {
+            if count + length < start {
+                return Err(format!("{}: substring expression < 0", length));
+            }
+            count + length
+        }
+        Some(length) => (start + length).min(count),
+        None => count,
+    };
+
+    Ok((start as usize, end as usize))
+}
+
+// contents of ${...}: NAME, NAME[key], NAME[@], ${!NAME[@]} for the keys,
+// ${#NAME} for the length and NAME followed by an operator
+fn braced(inner: &str, quoted: bool, vars: &mut Variables) -> Result<Value, String> {
+    if let Some(name) = inner
+        .strip_prefix('!')
+        .and_then(|inner| inner.strip_suffix("[@]").or(inner.strip_suffix("[*]")))
+    {
+        let keys = vars.keys(name);
+        return Ok(list(keys, inner.ends_with("*]"), quoted, vars));
+    }
+
+    // ${#} alone is the number of positional parameters
+    if let Some(parameter) = inner.strip_prefix('#').filter(|name| !name.is_empty()) {
+        let (parameter, rest) = split_parameter(parameter);
+        if parameter.is_empty() || !rest.is_empty() {
+            return Err(format!("${{{}}}: bad substitution", inner));
+        }
+        let length = match parameter_value(parameter, quoted, vars)? {
+            Some(Value::Scalar(value)) => value.chars().count(),
+            Some(Value::Fields(fields)) => fields.len(),
+            Some(Value::Word(chars, _)) => chars.len(),
+            None => 0,
+        };
+        return Ok(Value::Scalar(length.to_string()));
+    }
+
+    let (parameter, rest) = split_parameter(inner);
+    if parameter.is_empty() {
+        return Err(format!("${{{}}}: bad substitution", inner));
+    }
+    let value = parameter_value(parameter, quoted, vars)?;
+
+    for operator in [":-", ":=", ":?", ":+", "-", "=", "?", "+"] {
+        if let Some(word) = rest.strip_prefix(operator) {
+            return default_value(parameter, operator, word, value, vars);
+        }
+    }
+    for operator in ["##", "#", "%%", "%"] {
+        if let Some(word) = rest.strip_prefix(operator) {
+            let pattern = expand_pattern(word, vars)?;
+            let suffix = operator.starts_with('%');
+            let longest = operator.len() == 2;
+            let extglob = vars.extglob();
+            return Ok(transform(value, |text| {
+                remove_matching(text, &pattern, suffix, longest, extglob)
+            }));
+        }
+    }
+    if let Some(word) = rest.strip_prefix('/') {
+        let (kind, word) = match word.chars().next() {
+            Some('/' | '#' | '%') => (&word[..1], &word[1..]),
+            _ => ("", word),
+        };
+        let (pattern, replacement) = split_replacement(word);
+        let pattern = expand_pattern(pattern, vars)?;
+        let replacement = expand_string(replacement, vars)?;
+        let extglob = vars.extglob();
+        return Ok(transform(value, |text| {
+            replace_matching(text, kind, &pattern, &replacement, extglob)
+        }));
+    }
+    if let Some(word) = rest.strip_prefix(':') {
+        let (offset, length) = match word.split_once(':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (word, None),
+        };
+        let offset = expand_string(offset, vars)?;
+        let length = length
+            .map(|length| expand_string(length, vars))
+            .transpose()?;
+        return match value {
+            // ${@:1} starts with the first positional parameter, $0 is at 0
+            Some(Value::Fields(mut fields)) => {
+                if parameter == "@" || parameter == "*" {
+                    fields.insert(0, vars.name.clone());
+                }
+                let (start, end) = substring(fields.len(), &offset, length.as_deref())?;
+                Ok(list(
+                    fields[start..end].to_vec(),
+                    parameter.ends_with('*'),
+                    quoted,
+                    vars,
+                ))
+            }
+            value => {
+                let text = match value {
+                    Some(Value::Scalar(text)) => text,
+                    _ => String::new(),
+                };
+                let chars: Vec<char> = text.chars().collect();
+                let (start, end) = substring(chars.len(), &offset, length.as_deref())?;
+                Ok(Value::Scalar(chars[start..end].iter().collect()))
+            }
+        };
+    }
+    if !rest.is_empty() {
+        return Err(format!("${{{}}}: bad substitution", inner));
+    }
+
+    Ok(value.unwrap_or(Value::Scalar(String::new())))
+}
+
+// split pattern/string at the first unescaped slash, the string may be missing
+fn split_replacement(word: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (index, c) in word.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => return (&word[..index], &word[index + 1..]),
+            _ => {}
+        }
+    }
+
+    (word, "")
+}
+
+// index of the } closing ${ which starts right before the index, braces
+// inside quotes or escaped don't count
+fn closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut index = start;
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            '\\' if quote != Some('\'') => index += 1,
+            _ if quote == Some(c) => quote = None,
+            _ if quote.is_some() => {}
+            '\'' | '"' => quote = Some(c),
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+
+    None
+}
+
+// index of the parenthesis closing the one before the start
+fn closing_parenthesis(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(index),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// evaluate the arithmetic expression after expanding parameters in it, the
+// same for $((...)), ((...)) and let
+pub fn arithmetic(expression: &str, vars: &mut Variables) -> Result<i64, String> {
+    let expression = expand_string(expression, vars)?;
+    arith::evaluate(&expression, vars)
+}
+
+// expand $$, $NAME, ${NAME}, ${NAME[key]}, $1, $@, $((...)) starting right after the $
+// sign, None means that $ is not followed by an expansion and stays as is
+fn parameter(
+    chars: &[char],
+    index: &mut usize,
+    quoted: bool,
+    vars: &mut Variables,
+) -> Result<Option<Value>, String> {
+    Ok(match chars.get(*index) {
+        Some(&c) if is_special_parameter(c) || c.is_ascii_digit() => {
+            *index += 1;
+            Some(value(&c.to_string(), quoted, vars))
+        }
+        Some('(') if chars.get(*index + 1) == Some(&'(') => {
+            let start = *index + 2;
+            let end = match closing_parenthesis(chars, start) {
+                Some(end) if chars.get(end + 1) == Some(&')') => end,
+                _ => return Err(String::from("syntax error: unterminated $((")),
+            };
+            *index = end + 2;
+            let inner: String = chars[start..end].iter().collect();
+            Some(Value::Scalar(arithmetic(&inner, vars)?.to_string()))
+        }
+        Some('{') => {
+            let start = *index + 1;
+            let end = match closing_brace(chars, start) {
+                Some(end) => end,
+                None => return Ok(None),
+            };
+            *index = end + 1;
+            let inner: String = chars[start..end].iter().collect();
+            Some(braced(&inner, quoted, vars)?)
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            let start = *index;
+            while chars
+                .get(*index)
+                .is_some_and(|&c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                *index += 1;
+            }
+            let name: String = chars[start..*index].iter().collect();
+            Some(value(&name, quoted, vars))
+        }
+        _ => None,
+    })
+}
+
+fn push_value(result: &mut Vec<(char, Origin)>, value: Value, origin: Origin) {
+    match value {
+        Value::Scalar(value) => result.extend(value.chars().map(|c| (c, origin))),
+        // inside double quotes the whole word is quoted, outside only its
+        // quoted parts are
+        Value::Word(chars, _) => {
+            result.extend(chars.into_iter().map(|(c, kind)| match (origin, kind) {
+                (Origin::Quoted, Origin::Break) => (c, Origin::QuotedBreak),
+                (Origin::Quoted, _) => (c, Origin::Quoted),
+                (_, Origin::Literal) => (c, Origin::Expanded),
+                _ => (c, kind),
+            }))
+        }
+        Value::Fields(fields) => {
+            let separator = if origin == Origin::Quoted {
+                Origin::QuotedBreak
+            } else {
+                Origin::Break
+            };
+            for (index, field) in fields.iter().enumerate() {
+                if index > 0 {
+                    result.push(('\0', separator));
+                }
+                result.extend(field.chars().map(|c| (c, origin)));
+            }
+        }
+    }
+}
+
+// expand leading ~ and parameters, remove quotes; the flag tells whether
+// the word had any quotes, "" must produce an empty field while "$@" without
+// positional parameters produces nothing
+fn expand_chars(word: &str, vars: &mut Variables) -> Result<(Vec<(char, Origin)>, bool), String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut result = vec![];
+    let mut quoted = false;
+    let mut index = 0;
+
+    if word == "~" || word.starts_with("~/") {
+        index = 1;
+        result.extend(lookup("HOME", vars).chars().map(|c| (c, Origin::Quoted)));
+    }
+
+    while index < chars.len() {
+        let c = chars[index];
+        index += 1;
+        match c {
+            '\'' => {
+                quoted = true;
+                while index < chars.len() && chars[index] != '\'' {
+                    result.push((chars[index], Origin::Quoted));
+                    index += 1;
+                }
+                index += 1;
+            }
+            '"' => {
+                let len = result.len();
+                let mut empty_fields = false;
+                while index < chars.len() && chars[index] != '"' {
+                    let c = chars[index];
+                    index += 1;
+                    match c {
+                        '\\' if index < chars.len() && "$`\"\\\n".contains(chars[index]) => {
+                            result.push((chars[index], Origin::Quoted));
+                            index += 1;
+                        }
+                        '$' => match parameter(&chars, &mut index, true, vars)? {
+                            Some(Value::Fields(fields)) if fields.is_empty() => empty_fields = true,
+                            Some(value) => push_value(&mut result, value, Origin::Quoted),
+                            None => result.push(('$', Origin::Quoted)),
+                        },
+                        _ => result.push((c, Origin::Quoted)),
+                    }
+                }
+                index += 1;
+                quoted |= result.len() > len || !empty_fields;
+            }
+            '\\' if index < chars.len() => {
+                result.push((chars[index], Origin::Quoted));
+                index += 1;
+            }
+            '$' => match parameter(&chars, &mut index, false, vars)? {
+                Some(value) => {
+                    quoted |= matches!(value, Value::Word(_, true));
+                    push_value(&mut result, value, Origin::Expanded)
+                }
+                None => result.push(('$', Origin::Literal)),
+            },
+            _ => result.push((c, Origin::Literal)),
+        }
+    }
+
+    Ok((result, quoted))
+}
+
+fn field(chars: &[(char, Origin)]) -> Field {
+    let mut text = String::new();
+    let mut pattern = String::new();
+    for &(c, origin) in chars {
+        text.push(c);
+        if origin == Origin::Quoted && "*?[]\\@!+()|".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+
+    Field { text, pattern }
+}
+
+// split results of unquoted expansions by IFS characters: IFS whitespace
+// sequences delimit fields and are trimmed, other IFS characters delimit
+// a field each, so a::b gives a, empty field and b
+fn split_fields(chars: Vec<(char, Origin)>, quoted: bool, ifs: &str) -> Vec<Field> {
+    let mut fields = vec![];
+    let mut current = vec![];
+    // the current field has quoted part, so it's kept even if it's empty
+    let mut started = quoted;
+    let mut after_whitespace = false;
+
+    for (c, origin) in chars {
+        if origin == Origin::QuotedBreak || (origin == Origin::Break && started) {
+            fields.push(field(&current));
+            current.clear();
+            started = origin == Origin::QuotedBreak;
+            after_whitespace = false;
+            continue;
+        }
+        if origin == Origin::Break {
+            if !current.is_empty() {
+                fields.push(field(&current));
+                current.clear();
+            }
+            continue;
+        }
+
+        if origin != Origin::Expanded || !ifs.contains(c) {
+            current.push((c, origin));
+            started = true;
+            after_whitespace = false;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            if started || !current.is_empty() {
+                fields.push(field(&current));
+                current.clear();
+                started = false;
+                after_whitespace = true;
+            }
+        } else {
+            if started || !current.is_empty() || !after_whitespace {
+                fields.push(field(&current));
+                current.clear();
+                started = false;
+            }
+            after_whitespace = false;
+        }
+    }
+    if started || !current.is_empty() {
+        fields.push(field(&current));
+    }
+
+    fields
+}
+
+// split the line of the read builtin, characters are paired with the flag
+// telling they were escaped by backslash and don't separate fields. With the
+// count of fields the last one gets the rest of the line, without it the line
+// is split into all its fields
+pub fn split_read(chars: &[(char, bool)], ifs: &str, count: Option<usize>) -> Vec<String> {
+    let separator = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let whitespace = |pair: &(char, bool)| separator(pair) && pair.0.is_whitespace();
+    let text = |chars: &[(char, bool)]| chars.iter().map(|(c, _)| c).collect::<String>();
+
+    let start = chars.iter().position(|pair| !whitespace(pair));
+    let end = chars.iter().rposition(|pair| !whitespace(pair));
+    let chars = match (start, end) {
+        (Some(start), Some(end)) => &chars[start..=end],
+        _ => &[],
+    };
+
+    let mut fields = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        if count == Some(fields.len() + 1) {
+            fields.push(text(&chars[index..]));
+            break;
+        }
+
+        let start = index;
+        while index < chars.len() && !separator(&chars[index]) {
+            index += 1;
+        }
+        fields.push(text(&chars[start..index]));
+
+        // whitespace around one other separator delimits the field
+        while index < chars.len() && whitespace(&chars[index]) {
+            index += 1;
+        }
+        if index < chars.len() && separator(&chars[index]) {
+            index += 1;
+            while index < chars.len() && whitespace(&chars[index]) {
+                index += 1;
+            }
+        }
+    }
+
+    fields
+}
+
+pub fn ifs(vars: &Variables) -> String {
+    vars.get("IFS").unwrap_or_else(|| String::from(DEFAULT_IFS))
+}
+
+// expand the word into fields: unquoted expansions are split by IFS
+pub fn expand_word(word: &str, vars: &mut Variables) -> Result<Vec<Field>, String> {
+    let (chars, quoted) = expand_chars(word, vars)?;
+    let ifs = vars.get("IFS").unwrap_or_else(|| String::from(DEFAULT_IFS));
+    Ok(split_fields(chars, quoted, &ifs))
+}
+
+// expand the word into a single string without splitting, like in assignments
+pub fn expand_string(word: &str, vars: &mut Variables) -> Result<String, String> {
+    let (chars, _) = expand_chars(word, vars)?;
+    Ok(chars
+        .into_iter()
+        .map(|(c, origin)| match origin {
+            Origin::Break | Origin::QuotedBreak => ' ',
+            _ => c,
+        })
+        .collect())
+}
+
+// replace patterns by matching paths, patterns without matches are kept as is,
+// removed with nullglob option or make the command fail with failglob option
+// relative patterns match in cwd
+pub fn expand_patterns(
+    fields: Vec<Field>,
+    cwd: &Path,
+    options: &Options,
+) -> Result<Vec<String>, String> {
+    let globstar = options.get("globstar");
+    let extglob = options.get("extglob");
+
+    let mut expanded = vec![];
+    for field in fields {
+        if !glob::is_pattern(&field.pattern, extglob) {
+            expanded.push(field.text);
+            continue;
+        }
+
+        let paths = glob::expand(&field.pattern, cwd, globstar, extglob);
+        if !paths.is_empty() {
+            expanded.extend(paths);
+        } else if options.get("failglob") {
+            return Err(format!("no match: {}", field.text));
+        } else if !options.get("nullglob") {
+            expanded.push(field.text);
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("x", "value").unwrap();
+        vars.set("words", "a  b c").unwrap();
+        vars
+    }
+
+    fn string(word: &str, vars: &mut Variables) -> String {
+        expand_string(word, vars).unwrap()
+    }
+
+    fn fields(word: &str, vars: &mut Variables) -> Vec<String> {
+        expand_word(word, vars)
+            .unwrap()
+            .into_iter()
+            .map(|field| field.text)
+            .collect()
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        let mut vars = variables();
+        assert_eq!(string("a$x.${x}b", &mut vars), "avalue.valueb");
+        assert_eq!(string("$unset-", &mut vars), "-");
+        assert_eq!(string("$$", &mut vars), process::id().to_string());
+    }
+
+    #[test]
+    fn home_directory() {
+        let mut vars = variables();
+        let home = vars.get("HOME").unwrap_or_default();
+        assert_eq!(string("~/src", &mut vars), format!("{}/src", home));
+        assert_eq!(string("a~", &mut vars), "a~");
+    }
+
+    #[test]
+    fn quotes_are_removed() {
+        let mut vars = variables();
+        assert_eq!(fields("'$x'\"$x\"\\$x", &mut vars), ["$xvalue$x"]);
+        assert_eq!(fields("\"\"", &mut vars), [""]);
+        assert!(fields("$unset", &mut vars).is_empty());
+    }
+
+    #[test]
+    fn unquoted_expansions_are_split() {
+        let mut vars = variables();
+        assert_eq!(fields("$words", &mut vars), ["a", "b", "c"]);
+        assert_eq!(fields("\"$words\"", &mut vars), ["a  b c"]);
+        assert_eq!(fields("$words\"$words\"", &mut vars), ["a", "b", "ca  b c"]);
+        vars.set("IFS", ":").unwrap();
+        vars.set("path", "/bin::/usr/bin").unwrap();
+        assert_eq!(fields("$path", &mut vars), ["/bin", "", "/usr/bin"]);
+    }
+
+    #[test]
+    fn positional_parameters() {
+        let mut vars = variables();
+        vars.name = String::from("script");
+        vars.positional = vec![String::from("a b"), String::from("c")];
+        assert_eq!(string("$0:$#:$2:$3", &mut vars), "script:2:c:");
+        assert_eq!(fields("\"$@\"", &mut vars), ["a b", "c"]);
+        assert_eq!(fields("\"x$@y\"", &mut vars), ["xa b", "cy"]);
+        assert_eq!(fields("\"$*\"", &mut vars), ["a b c"]);
+        assert_eq!(fields("$@", &mut vars), ["a", "b", "c"]);
+        vars.positional.clear();
+        assert!(fields("\"$@\"", &mut vars).is_empty());
+    }
+
+    #[test]
+    fn array_elements_and_keys() {
+        let mut vars = variables();
+        vars.declare_assoc("map").unwrap();
+        vars.set_element("map", "a b", "1").unwrap();
+        vars.set_element("map", "c", "2 3").unwrap();
+        assert_eq!(string("${map[c]}", &mut vars), "2 3");
+        assert_eq!(string("${map[$unset]}", &mut vars), "");
+        assert_eq!(fields("\"${!map[@]}\"", &mut vars), ["a b", "c"]);
+        assert_eq!(fields("\"${map[@]}\"", &mut vars), ["1", "2 3"]);
+        assert_eq!(fields("${map[@]}", &mut vars), ["1", "2", "3"]);
+        assert_eq!(fields("\"${map[*]}\"", &mut vars), ["1 2 3"]);
+    }
+
+    #[test]
+    fn default_values() {
+        let mut vars = variables();
+        vars.set("empty", "").unwrap();
+        assert_eq!(string("${unset-a b}", &mut vars), "a b");
+        assert_eq!(string("${empty-a}.${empty:-b}", &mut vars), ".b");
+        assert_eq!(string("${x:-$words}", &mut vars), "value");
+        assert_eq!(string("${x+set}${unset+set}", &mut vars), "set");
+        assert_eq!(string("${empty:+set}${empty+set}", &mut vars), "set");
+        assert_eq!(string("${EXPAND_TEST_ASSIGN:=new}", &mut vars), "new");
+        assert_eq!(vars.get("EXPAND_TEST_ASSIGN").as_deref(), Some("new"));
+        assert_eq!(
+            expand_string("${1:=a}", &mut vars).unwrap_err(),
+            "1: cannot assign in this way"
+        );
+    }
+
+    #[test]
+    fn quotes_inside_braces() {
+        let mut vars = variables();
+        vars.set("x", "banana").unwrap();
+        assert_eq!(fields("\"${unset:-\"q r\"}\"", &mut vars), ["q r"]);
+        assert_eq!(fields("\"${x/a/\"z z\"}\"", &mut vars), ["bz znana"]);
+        assert_eq!(fields("${unset:-\"a b\"}", &mut vars), ["a b"]);
+        assert_eq!(fields("${unset:-\"a b\" c}", &mut vars), ["a b", "c"]);
+        assert_eq!(fields("${unset:-\"}\"}", &mut vars), ["}"]);
+        assert_eq!(fields("${unset:-\"\"}", &mut vars), [""]);
+        assert!(fields("${unset:-}", &mut vars).is_empty());
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        let mut vars = variables();
+        vars.set("empty", "").unwrap();
+        assert_eq!(
+            expand_string("${unset?}", &mut vars).unwrap_err(),
+            "unset: parameter not set"
+        );
+        assert_eq!(
+            expand_string("${empty:?}", &mut vars).unwrap_err(),
+            "empty: parameter null or not set"
+        );
+        assert_eq!(
+            expand_string("${unset:?custom $x}", &mut vars).unwrap_err(),
+            "unset: custom value"
+        );
+        assert_eq!(string("${empty?}", &mut vars), "");
+    }
+
+    #[test]
+    fn lengths() {
+        let mut vars = variables();
+        vars.positional = vec![String::from("a"), String::from("b")];
+        assert_eq!(string("${#x}.${#unset}.${#}.${#@}", &mut vars), "5.0.2.2");
+    }
+
+    #[test]
+    fn prefixes_and_suffixes_are_removed() {
+        let mut vars = variables();
+        vars.set("file", "dir/archive.tar.gz").unwrap();
+        assert_eq!(string("${file#*/}", &mut vars), "archive.tar.gz");
+        assert_eq!(string("${file%.*}", &mut vars), "dir/archive.tar");
+        assert_eq!(string("${file%%.*}", &mut vars), "dir/archive");
+        assert_eq!(string("${file##*.}", &mut vars), "gz");
+        assert_eq!(string("${file#'*'}", &mut vars), "dir/archive.tar.gz");
+    }
+
+    #[test]
+    fn patterns_are_replaced() {
+        let mut vars = variables();
+        vars.set("text", "a-b-c").unwrap();
+        assert_eq!(string("${text/-/+}", &mut vars), "a+b-c");
+        assert_eq!(string("${text//-/+}", &mut vars), "a+b+c");
+        assert_eq!(
+            string("${text/#a/x}.${text/%c/x}", &mut vars),
+            "x-b-c.a-b-x"
+        );
+        assert_eq!(string("${text//-}", &mut vars), "abc");
+        assert_eq!(string("${text//[ac]/\\/}", &mut vars), "/-b-/");
+    }
+
+    #[test]
+    fn extglob_patterns_with_the_option() {
+        let options = Options::new();
+        let mut vars = Variables::new(&options);
+        vars.set("x", "abc").unwrap();
+        assert_eq!(string("${x#@(a|b)}", &mut vars), "abc");
+        options.set("extglob", true).unwrap();
+        assert_eq!(string("${x#@(a|b)}", &mut vars), "bc");
+        assert_eq!(string("${x##+(a|b)}", &mut vars), "c");
+        assert_eq!(string("${x/@(b|c)/-}", &mut vars), "a-c");
+        assert_eq!(string("${x//@(b|c)/-}", &mut vars), "a--");
+    }
+
+    #[test]
+    fn substrings() {
+        let mut vars = variables();
+        assert_eq!(string("${x:1}.${x:1:2}", &mut vars), "alue.al");
+        assert_eq!(string("${x: -2}.${x:1:-1}", &mut vars), "ue.alu");
+        assert_eq!(string("${x:10}", &mut vars), "");
+        assert_eq!(string("${x: -10}.${x: -10:2}.${x: -6:-1}", &mut vars), "..");
+        vars.positional = vec![String::from("a"), String::from("b")];
+        vars.name = String::from("script");
+        assert_eq!(string("${@:0:2}", &mut vars), "script a");
+        assert_eq!(string("${@:2}", &mut vars), "b");
+        assert_eq!(string("${@: -5}", &mut vars), "");
+    }
+
+    #[test]
+    fn read_lines_are_split() {
+        let chars =
+            |text: &str| -> Vec<(char, bool)> { text.chars().map(|c| (c, false)).collect() };
+        assert_eq!(
+            split_read(&chars("  a  b c  "), " \t\n", None),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            split_read(&chars(" a  b c "), " \t\n", Some(2)),
+            ["a", "b c"]
+        );
+        assert_eq!(
+            split_read(&chars("a : b::c"), ": ", None),
+            ["a", "b", "", "c"]
+        );
+        let mut escaped = chars("a b");
+        escaped[1].1 = true;
+        assert_eq!(split_read(&escaped, " ", None), ["a b"]);
+        assert!(split_read(&chars("   "), " ", None).is_empty());
+    }
+
+    #[test]
+    fn arithmetic_expansion() {
+        let mut vars = variables();
+        vars.set("n", "4").unwrap();
+        assert_eq!(string("$((n * 2 + $n))", &mut vars), "12");
+        assert_eq!(string("x$(( (1 + 2) * 3 ))y", &mut vars), "x9y");
+        assert_eq!(string("$((EXPAND_TEST_COUNT += 2))", &mut vars), "2");
+        assert_eq!(vars.get("EXPAND_TEST_COUNT").as_deref(), Some("2"));
+        assert_eq!(
+            expand_string("$((1 + 2)", &mut vars).unwrap_err(),
+            "syntax error: unterminated $(("
+        );
+        assert!(expand_string("$((1 / 0))", &mut vars).is_err());
+    }
+
+    #[test]
+    fn quoted_characters_are_escaped_in_patterns() {
+        let mut vars = variables();
+        let field = expand_word("'*'?", &mut vars).unwrap().remove(0);
+        assert_eq!(field.text, "*?");
+        assert_eq!(field.pattern, "\\*?");
+    }
+}
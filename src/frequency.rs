@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history::{lock, LOCK_EX, LOCK_SH};
+use crate::osstr;
+
+// entries kept in the file, the least recently used ones are dropped
+const LIMIT: usize = 5000;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    count: u64,
+    // seconds since the epoch
+    last: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+// one entry per line: count, last use, directory and command separated by
+// tabs, which are escaped in the names like newlines and backslashes
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut name = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(escaped @ ('t' | 'n' | '\\'))) => {
+                chars.next();
+                name.push(match escaped {
+                    't' => '\t',
+                    'n' => '\n',
+                    _ => '\\',
+                });
+            }
+            (c, _) => name.push(c),
+        }
+    }
+    name
+}
+
+type Entries = HashMap<(String, String), Entry>;
+
+fn parse(bytes: &[u8]) -> Entries {
+    let mut entries = HashMap::new();
+    for line in osstr::from_bytes(bytes).lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let [count, last, directory, command] = fields[..] {
+            if let (Ok(count), Ok(last)) = (count.parse(), last.parse()) {
+                let key = (unescape(directory), unescape(command));
+                entries.insert(key, Entry { count, last });
+            }
+        }
+    }
+    entries
+}
+
+// how often commands are run in each directory, shared by the sessions in a
+// file; Tab completion offers the frequent ones first
+pub struct Frequency {
+    entries: Entries,
+    // counts of this session which are not in the file yet
+    unsaved: Entries,
+}
+
+impl Frequency {
+    pub fn new() -> Self {
+        Frequency {
+            entries: HashMap::new(),
+            unsaved: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, directory: &str, command: &str) {
+        let last = now();
+        let key = (String::from(directory), String::from(command));
+        for entries in [&mut self.entries, &mut self.unsaved] {
+            let entry = entries
+                .entry(key.clone())
+                .or_insert(Entry { count: 0, last });
+            entry.count += 1;
+            entry.last = last;
+        }
+    }
+
+    // counts in the directory and the ones in its parents, which count half
+    // as much for each level up
+    pub fn score(&self, directory: &str, command: &str) -> f64 {
+        let mut score = 0.0;
+        let mut weight = 1.0;
+        for ancestor in Path::new(&osstr::to_os(directory)).ancestors() {
+            let key = (osstr::from_os(ancestor.as_os_str()), String::from(command));
+            if let Some(entry) = self.entries.get(&key) {
+                score += entry.count as f64 * weight;
+            }
+            weight /= 2.0;
+        }
+        score
+    }
+
+    // the most frequent names first, others keep their order
+    pub fn rank(&self, directory: &str, names: &mut [String]) {
+        let mut scored: Vec<(f64, String)> = names
+            .iter()
+            .map(|name| (self.score(directory, name), name.clone()))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (name, (_, ranked)) in names.iter_mut().zip(scored) {
+            *name = ranked;
+        }
+    }
+
+    pub fn read(&mut self, path: &str) -> Result<(), String> {
+        let error = |err: io::Error| format!("frequency: {}: {}", path, err);
+        let mut file = match File::open(osstr::to_os(path)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(error(err)),
+        };
+        lock(&file, LOCK_SH).map_err(error)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).map_err(error)?;
+        self.entries = parse(&bytes);
+        Ok(())
+    }
+
+    // counts of this session are added to the ones in the file, which other
+    // sessions may have changed since it was read
+    pub fn save(&mut self, path: &str) -> Result<(), String> {
+        if self.unsaved.is_empty() {
+            return Ok(());
+        }
+        let error = |err: io::Error| format!("frequency: {}: {}", path, err);
+        let path = osstr::to_os(path);
+        if let Some(directory) = Path::new(&path).parent() {
+            fs::create_dir_all(directory).map_err(error)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(error)?;
+        lock(&file, LOCK_EX).map_err(error)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).map_err(error)?;
+
+        let mut entries = parse(&bytes);
+        for (key, added) in self.unsaved.drain() {
+            let entry = entries.entry(key).or_insert(Entry {
+                count: 0,
+                last: added.last,
+            });
+            entry.count += added.count;
+            entry.last = entry.last.max(added.last);
+        }
+        let mut sorted: Vec<_> = entries.into_iter().collect();
+        sorted.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last));
+        sorted.truncate(LIMIT);
+
+        let mut text = String::new();
+        for ((directory, command), entry) in &sorted {
+            text.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                entry.count,
+                entry.last,
+                escape(directory),
+                escape(command)
+            ));
+        }
+        file.set_len(0).map_err(error)?;
+        file.seek(SeekFrom::Start(0)).map_err(error)?;
+        file.write_all(&osstr::to_bytes(&text)).map_err(error)?;
+        self.entries = sorted.into_iter().collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn parents_count_half_as_much() {
+        let mut frequency = Frequency::new();
+        frequency.add("/a/b", "make");
+        frequency.add("/a", "make");
+        frequency.add("/", "make");
+        frequency.add("/a/b", "ls");
+        assert_eq!(frequency.score("/a/b", "make"), 1.75);
+        assert_eq!(frequency.score("/a/b/c", "ls"), 0.5);
+        assert_eq!(frequency.score("/x", "ls"), 0.0);
+
+        let mut names = vec![String::from("cd"), String::from("ls"), String::from("make")];
+        frequency.rank("/a/b", &mut names);
+        assert_eq!(names, ["make", "ls", "cd"]);
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let name = "a\tb\\c\nd";
+        assert_eq!(escape(name), "a\\tb\\\\c\\nd");
+        assert_eq!(unescape(&escape(name)), name);
+    }
+
+    #[test]
+    fn sessions_add_their_counts() {
+        let directory = env::temp_dir().join(format!("frequency-test-{}", process::id()));
+        let path = directory.join("state").join("frequency");
+        let path = path.to_str().unwrap();
+
+        let mut first = Frequency::new();
+        first.add("/tmp", "ls");
+        first.save(path).unwrap();
+        let mut second = Frequency::new();
+        second.add("/tmp", "ls");
+        second.add("/tmp", "a\tb");
+        second.save(path).unwrap();
+        // nothing new to save
+        second.save(path).unwrap();
+
+        let mut third = Frequency::new();
+        third.read(path).unwrap();
+        assert_eq!(third.score("/tmp", "ls"), 2.0);
+        assert_eq!(third.score("/tmp", "a\tb"), 1.0);
+        assert_eq!(parse(b"x\ty\n1\t2\t/\tls\textra\n").len(), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::osstr;
+
+// characters escaped by backslash are not special
+pub fn is_pattern(word: &str, extglob: bool) -> bool {
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            '@' | '!' | '+' if extglob && chars.peek() == Some(&'(') => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+// remove escaping backslashes
+pub fn unescape(word: &str) -> String {
+    let mut result = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+// split ksh-style extended pattern like @(a|b) at the beginning of the pattern
+// into alternatives and the rest of the pattern, None if it's not closed
+fn split_extended(pattern: &[char]) -> Option<(Vec<&[char]>, &[char])> {
+    let mut alternatives = vec![];
+    let mut depth = 0;
+    let mut start = 2;
+    let mut escaped = false;
+    for (index, &c) in pattern.iter().enumerate().skip(2) {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' => {
+                alternatives.push(&pattern[start..index]);
+                return Some((alternatives, &pattern[index + 1..]));
+            }
+            '|' if depth == 0 => {
+                alternatives.push(&pattern[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn match_any(alternatives: &[&[char]], text: &[char]) -> bool {
+    alternatives
+        .iter()
+        .any(|alternative| match_chars(alternative, text, true))
+}
+
+// zero or more repetitions of the alternatives followed by the rest of the pattern
+fn match_repeated(alternatives: &[&[char]], rest: &[char], text: &[char]) -> bool {
+    match_chars(rest, text, true)
+        || (1..=text.len()).any(|len| {
+            match_any(alternatives, &text[..len])
+                && match_repeated(alternatives, rest, &text[len..])
+        })
+}
+
+fn match_extended(kind: char, alternatives: &[&[char]], rest: &[char], text: &[char]) -> bool {
+    match kind {
+        '@' => (0..=text.len()).any(|len| {
+            match_any(alternatives, &text[..len]) && match_chars(rest, &text[len..], true)
+        }),
+        '?' => {
+            match_chars(rest, text, true)
+                || (0..=text.len()).any(|len| {
+                    match_any(alternatives, &text[..len]) && match_chars(rest, &text[len..], true)
+                })
+        }
+        '*' => match_repeated(alternatives, rest, text),
+        '+' => (1..=text.len()).any(|len| {
+            match_any(alternatives, &text[..len])
+                && match_repeated(alternatives, rest, &text[len..])
+        }),
+        '!' => (0..=text.len()).any(|len| {
+            !match_any(alternatives, &text[..len]) && match_chars(rest, &text[len..], true)
+        }),
+        _ => false,
+    }
+}
+
+// match [...] class at the beginning of the pattern, returns matched flag and
+// the rest of the pattern, None if the class is not closed
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let mut index = 1;
+    let negate = matches!(pattern.get(index), Some('!') | Some('^'));
+    if negate {
+        index += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while let Some(&start) = pattern.get(index) {
+        if start == ']' && !first {
+            return Some((matched != negate, &pattern[index + 1..]));
+        }
+        first = false;
+
+        match (pattern.get(index + 1), pattern.get(index + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                matched |= start <= c && c <= end;
+                index += 3;
+            }
+            _ => {
+                matched |= start == c;
+                index += 1;
+            }
+        }
+    }
+
+    None
+}
+
+fn match_chars(pattern: &[char], text: &[char], extglob: bool) -> bool {
+    if extglob && pattern.len() > 1 && "@!+?*".contains(pattern[0]) && pattern[1] == '(' {
+        if let Some((alternatives, rest)) = split_extended(pattern) {
+            return match_extended(pattern[0], &alternatives, rest, text);
+        }
+    }
+
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            (0..=text.len()).any(|skip| match_chars(&pattern[1..], &text[skip..], extglob))
+        }
+        Some('?') => !text.is_empty() && match_chars(&pattern[1..], &text[1..], extglob),
+        Some('[') => match (
+            text.first(),
+            match_class(pattern, *text.first().unwrap_or(&'\0')),
+        ) {
+            (Some(_), Some((matched, rest))) => matched && match_chars(rest, &text[1..], extglob),
+            // not closed class matches '[' literally
+            (Some('['), None) => match_chars(&pattern[1..], &text[1..], extglob),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && match_chars(&pattern[2..], &text[1..], extglob)
+        }
+        Some(&c) => text.first() == Some(&c) && match_chars(&pattern[1..], &text[1..], extglob),
+    }
+}
+
+// match the whole text against the pattern with *, ? and [...] wildcards and \\ escapes,
+// with extglob also @(a|b), !(a|b), +(a|b), ?(a|b) and *(a|b)
+pub fn matches(pattern: &str, text: &str, extglob: bool) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_chars(&pattern, &text, extglob)
+}
+
+struct Walker {
+    cwd: PathBuf,
+    globstar: bool,
+    extglob: bool,
+    results: Vec<String>,
+    // directories of the current ** descent, protects from symlink loops
+    visited: HashSet<(u64, u64)>,
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    match prefix {
+        "" => String::from(name),
+        "/" => format!("/{}", name),
+        _ => format!("{}/{}", prefix, name),
+    }
+}
+
+fn is_dir(cwd: &Path, path: &str) -> bool {
+    fs::metadata(cwd.join(osstr::to_os(path))).is_ok_and(|metadata| metadata.is_dir())
+}
+
+impl Walker {
+    // relative paths are in the directory of the walk
+    fn path(&self, path: &str) -> PathBuf {
+        self.cwd.join(osstr::to_os(path))
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        is_dir(&self.cwd, path)
+    }
+
+    // names in the directory, hidden ones only if the pattern starts with a dot
+    fn entries(&self, prefix: &str, hidden: bool) -> Vec<String> {
+        let directory = if prefix.is_empty() { "." } else { prefix };
+        let mut names: Vec<String> = match fs::read_dir(self.path(directory)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| osstr::from_os(&entry.file_name()))
+                .filter(|name| hidden || !name.starts_with('.'))
+                .collect(),
+            Err(_) => vec![],
+        };
+        names.sort();
+        names
+    }
+
+    fn walk(&mut self, prefix: &str, components: &[&str]) {
+        let (component, rest) = match components.split_first() {
+            Some(split) => split,
+            None => {
+                self.results.push(String::from(prefix));
+                return;
+            }
+        };
+
+        if *component == "**" && self.globstar {
+            self.walk_recursive(prefix, rest);
+        } else if !is_pattern(component, self.extglob) {
+            let path = join(prefix, &unescape(component));
+            if rest.is_empty() {
+                if fs::symlink_metadata(self.path(&path)).is_ok() {
+                    self.results.push(path);
+                }
+            } else if self.is_dir(&path) {
+                self.walk(&path, rest);
+            }
+        } else {
+            for name in self.entries(prefix, component.starts_with('.')) {
+                if matches(component, &name, self.extglob) {
+                    let path = join(prefix, &name);
+                    if rest.is_empty() {
+                        self.results.push(path);
+                    } else if self.is_dir(&path) {
+                        self.walk(&path, rest);
+                    }
+                }
+            }
+        }
+    }
+
+    // ** matches any files and zero or more directories
+    fn walk_recursive(&mut self, prefix: &str, rest: &[&str]) {
+        let directory = if prefix.is_empty() { "." } else { prefix };
+        let key = match fs::metadata(self.path(directory)) {
+            Ok(metadata) => (metadata.dev(), metadata.ino()),
+            Err(_) => return,
+        };
+        if !self.visited.insert(key) {
+            return;
+        }
+
+        if !rest.is_empty() {
+            self.walk(prefix, rest);
+        }
+        for name in self.entries(prefix, false) {
+            let path = join(prefix, &name);
+            if rest.is_empty() {
+                self.results.push(path.clone());
+            }
+            if self.is_dir(&path) {
+                self.walk_recursive(&path, rest);
+            }
+        }
+
+        self.visited.remove(&key);
+    }
+}
+
+// expand the pattern to sorted list of matching paths, ** matches directories
+// recursively if globstar is set, otherwise it's the same as *; relative
+// patterns match in cwd
+pub fn expand(pattern: &str, cwd: &Path, globstar: bool, extglob: bool) -> Vec<String> {
+    let mut walker = Walker {
+        cwd: cwd.to_path_buf(),
+        globstar,
+        extglob,
+        results: vec![],
+        visited: HashSet::new(),
+    };
+
+    let prefix = if pattern.starts_with('/') { "/" } else { "" };
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    walker.walk(prefix, &components);
+
+    // trailing slash matches only directories
+    if pattern.ends_with('/') {
+        walker.results.retain(|path| is_dir(cwd, path));
+        for path in walker.results.iter_mut() {
+            path.push('/');
+        }
+    }
+
+    walker.results.sort();
+    walker.results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.rs", "main.rs", false));
+        assert!(!matches("*.rs", "main.rs.bak", false));
+        assert!(matches("?ain.rs", "main.rs", false));
+        assert!(matches("[a-c]x", "bx", false));
+        assert!(!matches("[!a-c]x", "bx", false));
+        assert!(matches("\\*", "*", false));
+        assert!(!matches("\\*", "a", false));
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(is_pattern("*.rs", false));
+        assert!(is_pattern("a?", false));
+        assert!(is_pattern("[ab]", false));
+        assert!(!is_pattern("main.rs", false));
+    }
+
+    #[test]
+    fn extglob_patterns() {
+        assert!(matches("@(a|b).txt", "b.txt", true));
+        assert!(!matches("@(a|b).txt", "c.txt", true));
+        assert!(matches("!(*.o)", "main.c", true));
+        assert!(!matches("!(*.o)", "main.o", true));
+        assert!(matches("+(ab)", "abab", true));
+        assert!(matches("x*(y)", "x", true));
+        assert!(matches("x?(y)", "xy", true));
+        assert!(!matches("x?(y)", "xyy", true));
+        assert!(is_pattern("@(a|b)", true));
+    }
+
+    #[test]
+    fn extglob_is_off() {
+        assert!(!matches("@(a|b)", "a", false));
+        assert!(matches("@(a|b)", "@(a|b)", false));
+        assert!(!is_pattern("@(a|b)", false));
+    }
+
+    #[test]
+    fn paths_are_expanded() {
+        let root = env::temp_dir().join(format!("glob-{}", std::process::id()));
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        for file in ["a.rs", "b.txt", "src/c.rs", "src/nested/d.rs"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let expanded = |pattern: &str, globstar| expand(pattern, &root, globstar, false);
+
+        assert_eq!(expanded("*.rs", false), ["a.rs"]);
+        assert_eq!(expanded("*/", false), ["src/"]);
+        assert_eq!(expanded("**/*.rs", false), ["src/c.rs"]);
+        assert_eq!(
+            expanded("**/*.rs", true),
+            ["a.rs", "src/c.rs", "src/nested/d.rs"]
+        );
+        assert!(expanded("*.none", false).is_empty());
+        // absolute patterns don't depend on the directory
+        let absolute = format!("{}/src/*.rs", root.display());
+        assert_eq!(
+            expand(&absolute, Path::new("/"), false, false),
+            [format!("{}/src/c.rs", root.display())]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::raw::c_int;
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::glob;
+use crate::osstr;
+
+// the history may have passwords typed by mistake, only the user reads it
+const MODE: u32 = 0o600;
+
+// the flock operations have the values of Linux
+#[cfg(not(target_os = "linux"))]
+compile_error!("locking the history file is only written for Linux");
+
+extern "C" {
+    fn flock(fd: c_int, operation: c_int) -> c_int;
+}
+
+pub(crate) const LOCK_SH: c_int = 1;
+pub(crate) const LOCK_EX: c_int = 2;
+
+// words which look like secrets when HISTREDACT isn't set; after a word
+// matching the name of a NAME=* pattern the next word is masked, like the
+// password in --password hunter2
+pub const SECRETS: &str = "--password=*:--passwd=*:--token=*:--secret=*:--api-key=*:\
+*PASSWORD=*:*PASSWD=*:*TOKEN=*:*SECRET=*:*API_KEY=*:*Bearer *:\
+ghp_*:gho_*:github_pat_*:glpat-*:xox[abprs]-*:sk-*:AKIA*";
+
+const MASK: &str = "***";
+
+// sessions sharing the file take turns, the lock is released when the file is
+// closed
+pub(crate) fn lock(file: &File, operation: c_int) -> io::Result<()> {
+    loop {
+        if unsafe { flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+// spans of the words of the command, quoted blanks don't end them
+fn words(chars: &[char]) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, &c) in chars.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote != Some('\'') => escaped = true,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            c if c.is_whitespace() && quote.is_none() => {
+                if let Some(start) = start.take() {
+                    spans.push((start, index));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(index);
+    }
+    if let Some(start) = start {
+        spans.push((start, chars.len()));
+    }
+    spans
+}
+
+// words of the command matching the colon-separated patterns are replaced
+// with ***, only the value is for the ones with =
+pub fn redact(entry: &str, patterns: &str, extglob: bool) -> String {
+    let patterns: Vec<&str> = patterns.split(':').filter(|p| !p.is_empty()).collect();
+    let chars: Vec<char> = entry.chars().collect();
+    let mut redacted = String::new();
+    let mut end = 0;
+    let mut mask_next = false;
+    for (start, word_end) in words(&chars) {
+        redacted.extend(&chars[end..start]);
+        end = word_end;
+        let word: String = chars[start..word_end].iter().collect();
+        if mask_next {
+            mask_next = false;
+            redacted.push_str(MASK);
+            continue;
+        }
+        if patterns.iter().any(|p| glob::matches(p, &word, extglob)) {
+            match word.split_once('=') {
+                Some((name, _)) => redacted.push_str(&format!("{}={}", name, MASK)),
+                None => redacted.push_str(MASK),
+            }
+            continue;
+        }
+        mask_next = patterns.iter().any(|pattern| {
+            pattern
+                .strip_suffix("=*")
+                .is_some_and(|name| glob::matches(name, &word, extglob))
+        });
+        redacted.push_str(&word);
+    }
+    redacted.extend(&chars[end..]);
+    redacted
+}
+
+// the command isn't saved: HISTCONTROL has ignorespace and it starts with a
+// space, ignoredups and it's the same as the previous one (ignoreboth is
+// both), or it matches a colon-separated pattern of HISTIGNORE where & is
+// the previous command
+pub fn ignored(
+    entry: &str,
+    previous: Option<&str>,
+    ignore: &str,
+    control: &str,
+    extglob: bool,
+) -> bool {
+    let control: Vec<&str> = control.split(':').collect();
+    let space = control.contains(&"ignorespace") || control.contains(&"ignoreboth");
+    let dups = control.contains(&"ignoredups") || control.contains(&"ignoreboth");
+    if (space && entry.starts_with(' ')) || (dups && previous == Some(entry)) {
+        return true;
+    }
+    ignore
+        .split(':')
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| match pattern {
+            "&" => previous == Some(entry),
+            _ => glob::matches(pattern, entry, extglob),
+        })
+}
+
+// one entry per line of the file, newlines of multiline commands are escaped
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                entry.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                entry.push('\\');
+            }
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
+
+fn parse(bytes: &[u8]) -> Vec<String> {
+    osstr::from_bytes(bytes)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(unescape)
+        .collect()
+}
+
+// commands entered in the interactive shell. Several sessions may share the
+// file: each of them appends only its new entries and remembers how much of
+// the file it has seen, so the entries added by others can be read later
+pub struct History {
+    entries: Vec<String>,
+    // entries of this session which are not in the file yet
+    unsaved: Vec<String>,
+    // the end of the file when this session last read or wrote it
+    offset: u64,
+    // entries of other sessions found in the file while appending
+    unread: Vec<String>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            entries: vec![],
+            unsaved: vec![],
+            offset: 0,
+            unread: vec![],
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // the oldest entries are dropped when there are more than the limit
+    fn truncate(&mut self, limit: usize) {
+        if self.entries.len() > limit {
+            let extra = self.entries.len() - limit;
+            self.entries.drain(..extra);
+        }
+    }
+
+    pub fn add(&mut self, entry: &str, limit: usize) {
+        let entry = entry.trim_end_matches('\n');
+        if entry.trim().is_empty() {
+            return;
+        }
+        self.entries.push(String::from(entry));
+        self.unsaved.push(String::from(entry));
+        self.truncate(limit);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.unsaved.clear();
+    }
+
+    // the whole file is added to the entries, like at the start of the shell
+    pub fn read(&mut self, path: &str, limit: usize) -> Result<(), String> {
+        let error = |err: io::Error| format!("history: {}: {}", path, err);
+        let mut file = match File::open(osstr::to_os(path)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(error(err)),
+        };
+        lock(&file, LOCK_SH).map_err(error)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).map_err(error)?;
+
+        self.entries.extend(parse(&bytes));
+        self.truncate(limit);
+        self.offset = bytes.len() as u64;
+        self.unread.clear();
+        Ok(())
+    }
+
+    // entries added to the file by other sessions since this one last saw it
+    pub fn read_new(&mut self, path: &str, limit: usize) -> Result<(), String> {
+        let error = |err: io::Error| format!("history: {}: {}", path, err);
+        let mut file = match File::open(osstr::to_os(path)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(error(err)),
+        };
+        lock(&file, LOCK_SH).map_err(error)?;
+        // the file was rewritten by history -w of another session
+        let len = file.metadata().map_err(error)?.len();
+        if len < self.offset {
+            self.offset = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset)).map_err(error)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).map_err(error)?;
+
+        self.entries.append(&mut self.unread);
+        self.entries.extend(parse(&bytes));
+        self.truncate(limit);
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    // new entries of this session are added to the end of the file
+    pub fn append(&mut self, path: &str) -> Result<(), String> {
+        let error = |err: io::Error| format!("history: {}: {}", path, err);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(MODE)
+            .open(osstr::to_os(path))
+            .map_err(error)?;
+        lock(&file, LOCK_EX).map_err(error)?;
+
+        let len = file.metadata().map_err(error)?.len();
+        if len < self.offset {
+            self.offset = 0;
+        }
+        if len > self.offset {
+            file.seek(SeekFrom::Start(self.offset)).map_err(error)?;
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes).map_err(error)?;
+            self.unread.extend(parse(&bytes));
+        }
+
+        let mut text = String::new();
+        for entry in self.unsaved.drain(..) {
+            text.push_str(&escape(&entry));
+            text.push('\n');
+        }
+        let bytes = osstr::to_bytes(&text);
+        file.write_all(&bytes).map_err(error)?;
+        self.offset = len + bytes.len() as u64;
+        Ok(())
+    }
+
+    // the file is replaced with the entries of this session
+    pub fn write(&mut self, path: &str) -> Result<(), String> {
+        let error = |err: io::Error| format!("history: {}: {}", path, err);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(MODE)
+            .open(osstr::to_os(path))
+            .map_err(error)?;
+        lock(&file, LOCK_EX).map_err(error)?;
+
+        let mut text = String::new();
+        for entry in &self.entries {
+            text.push_str(&escape(entry));
+            text.push('\n');
+        }
+        let bytes = osstr::to_bytes(&text);
+        file.set_len(0).map_err(error)?;
+        file.write_all(&bytes).map_err(error)?;
+        self.offset = bytes.len() as u64;
+        self.unsaved.clear();
+        self.unread.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn temporary(name: &str) -> String {
+        let path = env::temp_dir().join(format!("history-test-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn entries_are_limited() {
+        let mut history = History::new();
+        for entry in ["a", "", "  ", "b\n", "c"] {
+            history.add(entry, 2);
+        }
+        assert_eq!(history.entries(), ["b", "c"]);
+        history.clear();
+        assert!(history.entries().is_empty());
+    }
+
+    #[test]
+    fn multiline_entries_are_escaped() {
+        let entry = "echo 'a\\n'\necho b";
+        assert_eq!(escape(entry), "echo 'a\\\\n'\\necho b");
+        assert_eq!(unescape(&escape(entry)), entry);
+        assert_eq!(parse(b"a\n\nb\\nc\n"), ["a", "b\nc"]);
+    }
+
+    #[test]
+    fn sessions_share_the_file() {
+        let path = temporary("shared");
+        let mut first = History::new();
+        let mut second = History::new();
+        first.read(&path, 10).unwrap();
+        second.read(&path, 10).unwrap();
+
+        first.add("one", 10);
+        first.append(&path).unwrap();
+        second.add("two", 10);
+        second.append(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        // entries of the other session are added only when asked for
+        assert_eq!(second.entries(), ["two"]);
+        second.read_new(&path, 10).unwrap();
+        assert_eq!(second.entries(), ["two", "one"]);
+        first.read_new(&path, 10).unwrap();
+        assert_eq!(first.entries(), ["one", "two"]);
+
+        let mut third = History::new();
+        third.read(&path, 1).unwrap();
+        assert_eq!(third.entries(), ["two"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_file_is_rewritten() {
+        let path = temporary("rewritten");
+        fs::write(&path, "old\n").unwrap();
+        let mut history = History::new();
+        history.add("new", 10);
+        history.write(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+
+        // a shorter file is read again from its start
+        let mut other = History::new();
+        other.read(&path, 10).unwrap();
+        fs::write(&path, "x\n").unwrap();
+        other.read_new(&path, 10).unwrap();
+        assert_eq!(other.entries(), ["new", "x"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn new_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        for (name, write) in [("appended", false), ("written", true)] {
+            let path = temporary(name);
+            let mut history = History::new();
+            history.add("secret", 10);
+            match write {
+                true => history.write(&path).unwrap(),
+                false => history.append(&path).unwrap(),
+            }
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", name);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn secrets_are_masked() {
+        let redacted = |entry| redact(entry, SECRETS, false);
+        assert_eq!(
+            redacted("curl --token=abc -H 'Bearer x y' ok"),
+            "curl --token=*** -H *** ok"
+        );
+        assert_eq!(
+            redacted("login --password hunter2 now"),
+            "login --password *** now"
+        );
+        assert_eq!(redacted("DB_PASSWORD=\"a b\" run"), "DB_PASSWORD=*** run");
+        assert_eq!(redacted("git push ghp_abc  x"), "git push ***  x");
+        assert_eq!(redact("echo secret", "", false), "echo secret");
+    }
+
+    #[test]
+    fn commands_are_ignored() {
+        assert!(ignored(" ls", None, "", "ignorespace", false));
+        assert!(!ignored(" ls", None, "", "ignoredups", false));
+        assert!(ignored("ls", Some("ls"), "", "ignoreboth", false));
+        assert!(ignored("ls", Some("ls"), "&", "", false));
+        assert!(!ignored("ls", Some("pwd"), "&", "", false));
+        assert!(ignored("exit 1", None, "ls*:exit*", "", false));
+        assert!(!ignored("echo", None, "ls*:exit*", "", false));
+    }
+}
@@ -0,0 +1,501 @@
+use std::fs::File;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{self, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::options::Options;
+use crate::osstr;
+use crate::rusage::{self, Stats};
+use crate::signals;
+
+// state of a job shared between the shell and the thread waiting for the job
+struct JobState {
+    status: Option<ExitStatus>,
+    reported: bool,
+    // resources used by the finished job
+    stats: Option<Stats>,
+}
+
+struct Job {
+    id: usize,
+    pid: u32,
+    command: String,
+    state: Arc<Mutex<JobState>>,
+    // disown -h keeps the job in the table but it's not hung up
+    nohup: bool,
+}
+
+// job table, it's shared with the signal handling thread to hang up jobs
+#[derive(Clone)]
+pub struct Jobs {
+    jobs: Arc<Mutex<Vec<Job>>>,
+    // report finished jobs immediately instead of waiting for the next prompt (set -b)
+    notify: Arc<AtomicBool>,
+    huponexit: Arc<AtomicBool>,
+}
+
+impl Jobs {
+    pub fn new(options: &Options) -> Self {
+        Jobs {
+            jobs: Arc::new(Mutex::new(vec![])),
+            notify: options.flag("notify"),
+            huponexit: options.flag("huponexit"),
+        }
+    }
+
+    // spawn program in background and return line to print: [1] 12345
+    // without redirected input jobs don't read the terminal
+    pub fn spawn(
+        &self,
+        mut program: process::Command,
+        args: &[&str],
+        command: &str,
+        stdin: Option<File>,
+    ) -> Result<String, String> {
+        let threshold = notify_time(&program);
+        let child = signals::unblock_in_child(&mut program)
+            .args(args.iter().map(|arg| osstr::to_os(arg)))
+            .stdin(stdin.map_or_else(Stdio::null, Stdio::from))
+            .spawn()
+            .map_err(|err| format!("failed to execute program: {}", err))?;
+
+        Ok(self.add(child, command, threshold))
+    }
+
+    // run the program as a job connected to the shell by pipes, returns the job
+    // line, its pid and the file descriptors to read its output from and to
+    // write its input to
+    pub fn coproc(
+        &self,
+        mut program: process::Command,
+        args: &[&str],
+        command: &str,
+    ) -> Result<(String, u32, RawFd, RawFd), String> {
+        let threshold = notify_time(&program);
+        let mut child = signals::unblock_in_child(&mut program)
+            .args(args.iter().map(|arg| osstr::to_os(arg)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("failed to execute program: {}", err))?;
+
+        // the descriptors are closed on exec, so other programs don't keep
+        // the pipes open
+        let read = child.stdout.take().unwrap().into_raw_fd();
+        let write = child.stdin.take().unwrap().into_raw_fd();
+        let pid = child.id();
+        Ok((self.add(child, command, threshold), pid, read, write))
+    }
+
+    fn add(&self, child: process::Child, command: &str, threshold: Option<f64>) -> String {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let pid = child.id();
+        let state = Arc::new(Mutex::new(JobState {
+            status: None,
+            reported: false,
+            stats: None,
+        }));
+        jobs.push(Job {
+            id,
+            pid,
+            command: String::from(command),
+            state: Arc::clone(&state),
+            nohup: false,
+        });
+
+        let notify = Arc::clone(&self.notify);
+        let command = String::from(command);
+        thread::spawn(move || wait_job(child, id, command, state, notify, threshold));
+
+        format!("[{}] {}", id, pid)
+    }
+
+    // collect notifications of finished jobs which were not reported yet,
+    // reported jobs are removed from the table
+    pub fn finished(&self) -> Vec<String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let count = jobs.len();
+        let mut lines = vec![];
+        for (index, job) in jobs.iter().enumerate() {
+            let mut state = job.state.lock().unwrap();
+            if let Some(status) = state.status {
+                if !state.reported {
+                    state.reported = true;
+                    lines.push(job_line(
+                        job.id,
+                        job_mark(index, count),
+                        &status_name(status),
+                        &job.command,
+                    ));
+                }
+            }
+        }
+        prune(&mut jobs);
+
+        lines
+    }
+
+    // lines of the jobs builtin, finished jobs are reported by it too; the
+    // long ones have the pids and the resources used by finished jobs
+    pub fn list(&self, long: bool) -> Vec<String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let count = jobs.len();
+        let mut lines = vec![];
+        for (index, job) in jobs.iter().enumerate() {
+            let mut state = job.state.lock().unwrap();
+            let status = match state.status {
+                Some(status) => {
+                    state.reported = true;
+                    status_name(status)
+                }
+                None => String::from("Running"),
+            };
+            let mut line = job_line(job.id, job_mark(index, count), &status, &job.command);
+            if long {
+                line = line.replacen("  ", &format!(" {} ", job.pid), 1);
+                if let Some(stats) = &state.stats {
+                    line.push_str(&format!("  ({})", rusage::summary(stats)));
+                }
+            }
+            lines.push(line);
+        }
+        prune(&mut jobs);
+
+        lines
+    }
+
+    // jobs which haven't finished yet
+    pub fn running(&self) -> usize {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .filter(|job| job.state.lock().unwrap().status.is_none())
+            .count()
+    }
+
+    // send SIGHUP to running jobs if huponexit option is set
+    pub fn hangup(&self) {
+        if !self.huponexit.load(Ordering::Relaxed) {
+            return;
+        }
+
+        for job in self.jobs.lock().unwrap().iter() {
+            if !job.nohup && job.state.lock().unwrap().status.is_none() {
+                signals::send(job.pid, signals::SIGHUP);
+            }
+        }
+    }
+
+    // remove job from the table, so it won't be hung up and reported: %n, %+,
+    // %%, %- or pid, without job specification the current job is removed;
+    // with keep flag (disown -h) the job stays in the table
+    pub fn disown(&self, spec: Option<&str>, keep: bool) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let count = jobs.len();
+        let index = match spec {
+            None | Some("%+") | Some("%%") => count.checked_sub(1),
+            Some("%-") => count.checked_sub(2),
+            Some(spec) => match spec.strip_prefix('%') {
+                Some(id) => id
+                    .parse()
+                    .ok()
+                    .and_then(|id: usize| jobs.iter().position(|job| job.id == id)),
+                None => spec
+                    .parse()
+                    .ok()
+                    .and_then(|pid: u32| jobs.iter().position(|job| job.pid == pid)),
+            },
+        };
+
+        match index {
+            Some(index) if keep => {
+                jobs[index].nohup = true;
+                Ok(())
+            }
+            Some(index) => {
+                jobs.remove(index);
+                Ok(())
+            }
+            None => Err(format!(
+                "disown: {}: no such job",
+                spec.unwrap_or("current")
+            )),
+        }
+    }
+
+    pub fn disown_all(&self, keep: bool) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if keep {
+            for job in jobs.iter_mut() {
+                job.nohup = true;
+            }
+        } else {
+            jobs.clear();
+        }
+    }
+}
+
+// finished jobs are kept in the table only until they are reported once
+fn prune(jobs: &mut Vec<Job>) {
+    jobs.retain(|job| {
+        let state = job.state.lock().unwrap();
+        state.status.is_none() || !state.reported
+    });
+}
+
+// the most recent job is marked by '+', the previous one by '-'
+fn job_mark(index: usize, count: usize) -> char {
+    if index + 1 == count {
+        '+'
+    } else if index + 2 == count {
+        '-'
+    } else {
+        ' '
+    }
+}
+
+fn status_name(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(0), _) => String::from("Done"),
+        (Some(code), _) => format!("Exit {}", code),
+        (None, Some(9)) => String::from("Killed"),
+        (None, Some(15)) => String::from("Terminated"),
+        (None, Some(signal)) => format!("Signal {}", signal),
+        (None, None) => String::from("Done"),
+    }
+}
+
+// format job line in bash style: [1]+  Done                    sleep 30
+fn job_line(id: usize, mark: char, status: &str, command: &str) -> String {
+    format!("[{}]{}  {:<24}{}", id, mark, status, command)
+}
+
+fn wait_job(
+    child: process::Child,
+    id: usize,
+    command: String,
+    state: Arc<Mutex<JobState>>,
+    notify: Arc<AtomicBool>,
+    threshold: Option<f64>,
+) {
+    let started = Instant::now();
+    let (status, stats) = match rusage::wait(&child, started) {
+        Ok((status, stats)) => (status, Some(stats)),
+        Err(_) => (ExitStatus::from_raw(1 << 8), None),
+    };
+
+    let mut state = state.lock().unwrap();
+    state.status = Some(status);
+    state.stats = stats;
+    drop(state);
+    // the line editor reports the job and redraws the prompt and the line,
+    // when it isn't reading the job is reported before the next prompt
+    if notify.load(Ordering::Relaxed) {
+        signals::notify_reader();
+    }
+
+    if let Some(threshold) = threshold {
+        desktop_notify(id, &command, started.elapsed(), threshold);
+    }
+}
+
+// NOTIFYTIME seconds from the environment the job is run with
+fn notify_time(program: &process::Command) -> Option<f64> {
+    program
+        .get_envs()
+        .find(|(name, _)| *name == "NOTIFYTIME")
+        .and_then(|(_, value)| value?.to_str()?.trim().parse().ok())
+}
+
+// send desktop notification for jobs running longer than NOTIFYTIME seconds
+fn desktop_notify(id: usize, command: &str, duration: Duration, threshold: f64) {
+    if duration.as_secs_f64() >= threshold {
+        let _ = process::Command::new("notify-send")
+            .arg("Job finished")
+            .arg(format!(
+                "[{}] {} ({})",
+                id,
+                command,
+                crate::format_duration(duration)
+            ))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+
+    // lines of the finished jobs once there are some
+    fn wait_finished(jobs: &Jobs) -> Vec<String> {
+        let started = Instant::now();
+        loop {
+            let lines = jobs.finished();
+            if !lines.is_empty() || started.elapsed() > Duration::from_secs(5) {
+                return lines;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn marks_of_the_last_jobs() {
+        assert_eq!(job_mark(2, 3), '+');
+        assert_eq!(job_mark(1, 3), '-');
+        assert_eq!(job_mark(0, 3), ' ');
+    }
+
+    #[test]
+    fn status_names() {
+        assert_eq!(status_name(ExitStatus::from_raw(0)), "Done");
+        assert_eq!(status_name(ExitStatus::from_raw(3 << 8)), "Exit 3");
+        assert_eq!(status_name(ExitStatus::from_raw(9)), "Killed");
+        assert_eq!(status_name(ExitStatus::from_raw(15)), "Terminated");
+        assert_eq!(status_name(ExitStatus::from_raw(2)), "Signal 2");
+    }
+
+    #[test]
+    fn finished_job_is_reported_once() {
+        let jobs = Jobs::new(&Options::new());
+        let line = jobs
+            .spawn(
+                process::Command::new("/bin/sh"),
+                &["-c", "exit 2"],
+                "sh -c 'exit 2'",
+                None,
+            )
+            .unwrap();
+        assert!(line.starts_with("[1] "));
+        assert_eq!(
+            wait_finished(&jobs),
+            [format!("[1]+  {:<24}sh -c 'exit 2'", "Exit 2")]
+        );
+        assert!(jobs.finished().is_empty());
+    }
+
+    #[test]
+    fn huponexit_hangs_up_running_jobs() {
+        let options = Options::new();
+        let jobs = Jobs::new(&options);
+        jobs.spawn(
+            process::Command::new("/bin/sleep"),
+            &["30"],
+            "sleep 30",
+            None,
+        )
+        .unwrap();
+        jobs.hangup();
+        thread::sleep(Duration::from_millis(100));
+        assert!(jobs.finished().is_empty());
+
+        options.apply(&["-o", "huponexit"]).unwrap();
+        jobs.hangup();
+        assert_eq!(
+            wait_finished(&jobs),
+            [format!("[1]+  {:<24}sleep 30", "Signal 1")]
+        );
+    }
+
+    #[test]
+    fn disowned_jobs_are_not_hung_up() {
+        let options = Options::new();
+        options.apply(&["-o", "huponexit"]).unwrap();
+        let jobs = Jobs::new(&options);
+        let line = jobs
+            .spawn(
+                process::Command::new("/bin/sleep"),
+                &["0.3"],
+                "sleep 0.3",
+                None,
+            )
+            .unwrap();
+        let pid = line.split(' ').nth(1).unwrap();
+        assert_eq!(
+            jobs.disown(Some("%2"), false).unwrap_err(),
+            "disown: %2: no such job"
+        );
+        jobs.disown(Some(pid), false).unwrap();
+        jobs.hangup();
+        assert!(jobs.disown(None, false).is_err());
+    }
+
+    #[test]
+    fn disown_h_keeps_the_job() {
+        let options = Options::new();
+        options.apply(&["-o", "huponexit"]).unwrap();
+        let jobs = Jobs::new(&options);
+        jobs.spawn(
+            process::Command::new("/bin/sleep"),
+            &["0.3"],
+            "sleep 0.3",
+            None,
+        )
+        .unwrap();
+        jobs.disown(None, true).unwrap();
+        jobs.hangup();
+        assert_eq!(
+            jobs.list(false),
+            [format!("[1]+  {:<24}sleep 0.3", "Running")]
+        );
+        assert_eq!(
+            wait_finished(&jobs),
+            [format!("[1]+  {:<24}sleep 0.3", "Done")]
+        );
+        assert!(jobs.list(false).is_empty());
+    }
+
+    #[test]
+    fn long_lines_have_pids_and_resources() {
+        let jobs = Jobs::new(&Options::new());
+        let line = jobs
+            .spawn(
+                process::Command::new("/bin/sleep"),
+                &["0.1"],
+                "sleep 0.1",
+                None,
+            )
+            .unwrap();
+        let pid = line.split(' ').nth(1).unwrap();
+        let prefix = format!("[1]+ {} ", pid);
+        assert_eq!(
+            jobs.list(true),
+            [format!("{}{:<24}sleep 0.1", prefix, "Running")]
+        );
+
+        // the finished job is shown once
+        let started = Instant::now();
+        let mut lines = jobs.list(true);
+        while lines[0].contains("Running") && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+            lines = jobs.list(true);
+        }
+        let line = format!("{}{:<24}sleep 0.1  (real ", prefix, "Done");
+        assert!(lines[0].starts_with(&line), "{:?}", lines);
+        assert!(jobs.list(true).is_empty());
+    }
+
+    #[test]
+    fn coproc_is_connected_by_pipes() {
+        let jobs = Jobs::new(&Options::new());
+        let (line, pid, read, write) = jobs
+            .coproc(process::Command::new("/bin/cat"), &[], "cat")
+            .unwrap();
+        assert_eq!(line, format!("[1] {}", pid));
+        let mut input = unsafe { File::from_raw_fd(write) };
+        let mut output = unsafe { File::from_raw_fd(read) };
+        input.write_all(b"line\n").unwrap();
+        drop(input);
+        let mut text = String::new();
+        output.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "line\n");
+        assert_eq!(wait_finished(&jobs), [format!("[1]+  {:<24}cat", "Done")]);
+    }
+}
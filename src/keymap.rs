@@ -0,0 +1,657 @@
+use std::fs;
+
+use crate::options::Options;
+
+// editor actions named like in readline, so bindings from ~/.inputrc work
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    AcceptLine,
+    Interrupt,
+    SelfInsert,
+    BeginningOfLine,
+    EndOfLine,
+    ForwardChar,
+    BackwardChar,
+    ForwardWord,
+    BackwardWord,
+    DeleteChar,
+    BackwardDeleteChar,
+    KillLine,
+    BackwardKillLine,
+    KillWholeLine,
+    KillWord,
+    BackwardKillWord,
+    UnixWordRubout,
+    Yank,
+    TransposeChars,
+    ClearScreen,
+    Complete,
+    PreviousHistory,
+    NextHistory,
+    MenuCompleteBackward,
+    Undo,
+    Redo,
+    ViMovementMode,
+    ViInsertionMode,
+    ViAppendMode,
+    ViAppendEol,
+    ViInsertBeg,
+    ViForwardWord,
+    ViEndWord,
+    ViKillWord,
+}
+
+const ACTIONS: &[(&str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("interrupt", Action::Interrupt),
+    ("self-insert", Action::SelfInsert),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("forward-char", Action::ForwardChar),
+    ("backward-char", Action::BackwardChar),
+    ("forward-word", Action::ForwardWord),
+    ("backward-word", Action::BackwardWord),
+    ("delete-char", Action::DeleteChar),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("kill-line", Action::KillLine),
+    ("backward-kill-line", Action::BackwardKillLine),
+    ("unix-line-discard", Action::BackwardKillLine),
+    ("kill-whole-line", Action::KillWholeLine),
+    ("kill-word", Action::KillWord),
+    ("backward-kill-word", Action::BackwardKillWord),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("yank", Action::Yank),
+    ("transpose-chars", Action::TransposeChars),
+    ("clear-screen", Action::ClearScreen),
+    ("complete", Action::Complete),
+    ("previous-history", Action::PreviousHistory),
+    ("next-history", Action::NextHistory),
+    ("menu-complete-backward", Action::MenuCompleteBackward),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("vi-movement-mode", Action::ViMovementMode),
+    ("vi-insertion-mode", Action::ViInsertionMode),
+    ("vi-append-mode", Action::ViAppendMode),
+    ("vi-append-eol", Action::ViAppendEol),
+    ("vi-insert-beg", Action::ViInsertBeg),
+    ("vi-forward-word", Action::ViForwardWord),
+    ("vi-end-word", Action::ViEndWord),
+    ("vi-kill-word", Action::ViKillWord),
+];
+
+fn action(name: &str) -> Option<Action> {
+    ACTIONS
+        .iter()
+        .find(|(action, _)| *action == name)
+        .map(|(_, action)| *action)
+}
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS
+        .iter()
+        .find(|(_, other)| *other == action)
+        .map(|(name, _)| *name)
+        .unwrap_or_default()
+}
+
+// names for bind -l
+pub fn action_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = ACTIONS.iter().map(|(name, _)| *name).collect();
+    names.sort();
+    names
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Keymap {
+    Emacs,
+    ViInsert,
+    ViCommand,
+}
+
+impl Keymap {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "emacs" | "emacs-standard" => Ok(Keymap::Emacs),
+            "vi-insert" => Ok(Keymap::ViInsert),
+            "vi" | "vi-command" | "vi-move" => Ok(Keymap::ViCommand),
+            _ => Err(format!("{}: invalid keymap name", name)),
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    // printable characters without bindings are inserted, in vi command mode
+    // they are ignored
+    pub fn inserts(self) -> bool {
+        self != Keymap::ViCommand
+    }
+}
+
+// what a key sequence does: editor actions run one after another or text which
+// is read again as if it was typed
+#[derive(Clone)]
+pub enum Binding {
+    Actions(Vec<Action>),
+    Macro(Vec<u8>),
+}
+
+pub struct Bindings([Vec<(Vec<u8>, Binding)>; 3]);
+
+fn bind(map: &mut Vec<(Vec<u8>, Binding)>, sequence: &str, actions: &[Action]) {
+    let sequence = parse_sequence(sequence).unwrap();
+    map.push((sequence, Binding::Actions(actions.to_vec())));
+}
+
+// arrows, Home, End and Delete in the forms sent by terminals; up and down
+// go through the history
+fn bind_cursor_keys(map: &mut Vec<(Vec<u8>, Binding)>) {
+    use Action::*;
+
+    for sequence in ["\\e[A", "\\eOA"] {
+        bind(map, sequence, &[PreviousHistory]);
+    }
+    for sequence in ["\\e[B", "\\eOB"] {
+        bind(map, sequence, &[NextHistory]);
+    }
+    for sequence in ["\\e[C", "\\eOC"] {
+        bind(map, sequence, &[ForwardChar]);
+    }
+    for sequence in ["\\e[D", "\\eOD"] {
+        bind(map, sequence, &[BackwardChar]);
+    }
+    for sequence in ["\\e[H", "\\eOH", "\\e[1~", "\\e[7~"] {
+        bind(map, sequence, &[BeginningOfLine]);
+    }
+    for sequence in ["\\e[F", "\\eOF", "\\e[4~", "\\e[8~"] {
+        bind(map, sequence, &[EndOfLine]);
+    }
+    bind(map, "\\e[3~", &[DeleteChar]);
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        use Action::*;
+
+        let mut emacs = vec![];
+        for (sequence, action) in [
+            ("\\C-m", AcceptLine),
+            ("\\C-j", AcceptLine),
+            ("\\C-c", Interrupt),
+            ("\\C-a", BeginningOfLine),
+            ("\\C-e", EndOfLine),
+            ("\\C-f", ForwardChar),
+            ("\\C-b", BackwardChar),
+            ("\\ef", ForwardWord),
+            ("\\eb", BackwardWord),
+            ("\\C-d", DeleteChar),
+            ("\\C-?", BackwardDeleteChar),
+            ("\\C-h", BackwardDeleteChar),
+            ("\\C-k", KillLine),
+            ("\\C-u", BackwardKillLine),
+            ("\\ed", KillWord),
+            ("\\e\\C-?", BackwardKillWord),
+            ("\\C-w", UnixWordRubout),
+            ("\\C-y", Yank),
+            ("\\C-t", TransposeChars),
+            ("\\C-l", ClearScreen),
+            ("\\C-i", Complete),
+            ("\\C-p", PreviousHistory),
+            ("\\C-n", NextHistory),
+            ("\\e[Z", MenuCompleteBackward),
+            ("\\C-_", Undo),
+            ("\\C-xu", Undo),
+            ("\\C-x\\C-u", Undo),
+            ("\\e_", Redo),
+        ] {
+            bind(&mut emacs, sequence, &[action]);
+        }
+        bind_cursor_keys(&mut emacs);
+
+        let mut insert = vec![];
+        for (sequence, action) in [
+            ("\\e", ViMovementMode),
+            ("\\C-m", AcceptLine),
+            ("\\C-j", AcceptLine),
+            ("\\C-c", Interrupt),
+            ("\\C-d", DeleteChar),
+            ("\\C-?", BackwardDeleteChar),
+            ("\\C-h", BackwardDeleteChar),
+            ("\\C-u", BackwardKillLine),
+            ("\\C-w", UnixWordRubout),
+            ("\\C-y", Yank),
+            ("\\C-t", TransposeChars),
+            ("\\C-l", ClearScreen),
+            ("\\C-i", Complete),
+            ("\\C-p", PreviousHistory),
+            ("\\C-n", NextHistory),
+            ("\\e[Z", MenuCompleteBackward),
+            ("\\C-_", Undo),
+        ] {
+            bind(&mut insert, sequence, &[action]);
+        }
+        bind_cursor_keys(&mut insert);
+
+        let mut command = vec![];
+        for (sequence, actions) in [
+            ("\\C-m", &[AcceptLine][..]),
+            ("\\C-j", &[AcceptLine]),
+            ("\\C-c", &[Interrupt]),
+            ("\\C-d", &[DeleteChar]),
+            ("\\C-l", &[ClearScreen]),
+            ("\\C-r", &[Redo]),
+            ("\\C-?", &[BackwardChar]),
+            ("k", &[PreviousHistory]),
+            ("j", &[NextHistory]),
+            ("h", &[BackwardChar]),
+            ("l", &[ForwardChar]),
+            (" ", &[ForwardChar]),
+            ("0", &[BeginningOfLine]),
+            ("^", &[BeginningOfLine]),
+            ("$", &[EndOfLine]),
+            ("w", &[ViForwardWord]),
+            ("W", &[ViForwardWord]),
+            ("b", &[BackwardWord]),
+            ("B", &[BackwardWord]),
+            ("e", &[ViEndWord]),
+            ("E", &[ViEndWord]),
+            ("x", &[DeleteChar]),
+            ("X", &[BackwardDeleteChar]),
+            ("i", &[ViInsertionMode]),
+            ("a", &[ViAppendMode]),
+            ("A", &[ViAppendEol]),
+            ("I", &[ViInsertBeg]),
+            ("D", &[KillLine]),
+            ("d$", &[KillLine]),
+            ("d0", &[BackwardKillLine]),
+            ("dd", &[KillWholeLine]),
+            ("dw", &[ViKillWord]),
+            ("db", &[BackwardKillWord]),
+            ("C", &[KillLine, ViAppendEol]),
+            ("c$", &[KillLine, ViAppendEol]),
+            ("c0", &[BackwardKillLine, ViInsertionMode]),
+            ("cc", &[KillWholeLine, ViInsertionMode]),
+            ("S", &[KillWholeLine, ViInsertionMode]),
+            ("cw", &[KillWord, ViInsertionMode]),
+            ("cb", &[BackwardKillWord, ViInsertionMode]),
+            ("s", &[DeleteChar, ViInsertionMode]),
+            ("p", &[ForwardChar, Yank, BackwardChar]),
+            ("P", &[Yank, BackwardChar]),
+            ("u", &[Undo]),
+        ] {
+            bind(&mut command, sequence, actions);
+        }
+        bind_cursor_keys(&mut command);
+
+        Bindings([emacs, insert, command])
+    }
+
+    fn set(&mut self, keymap: Keymap, sequence: Vec<u8>, binding: Binding) {
+        let map = &mut self.0[keymap.index()];
+        match map.iter_mut().find(|(other, _)| *other == sequence) {
+            Some((_, existing)) => *existing = binding,
+            None => map.push((sequence, binding)),
+        }
+    }
+
+    pub fn remove(&mut self, keymap: Keymap, sequence: &str) -> Result<(), String> {
+        let sequence = parse_sequence(sequence)?;
+        self.0[keymap.index()].retain(|(other, _)| *other != sequence);
+        Ok(())
+    }
+
+    // the binding of the sequence and whether longer sequences start with it
+    pub fn lookup(&self, keymap: Keymap, sequence: &[u8]) -> (Option<Binding>, bool) {
+        let map = &self.0[keymap.index()];
+        let exact = map
+            .iter()
+            .find(|(other, _)| other == sequence)
+            .map(|(_, binding)| binding.clone());
+        let longer = map
+            .iter()
+            .any(|(other, _)| other.len() > sequence.len() && other.starts_with(sequence));
+        (exact, longer)
+    }
+
+    // bindings in the form read by bind: "\C-a": beginning-of-line
+    pub fn list(&self, keymap: Keymap) -> Vec<String> {
+        let mut lines: Vec<String> = self.0[keymap.index()]
+            .iter()
+            .map(|(sequence, binding)| {
+                let target = match binding {
+                    Binding::Actions(actions) => actions
+                        .iter()
+                        .map(|action| action_name(*action))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    Binding::Macro(text) => format!("\"{}\"", format_sequence(text)),
+                };
+                format!("\"{}\": {}", format_sequence(sequence), target)
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings::new()
+    }
+}
+
+// readline key sequence escapes: \C-x, \M-x, \e, \\, \", \', \a, \b, \d, \f,
+// \n, \r, \t, \v, \NNN and \xHH
+pub fn parse_sequence(text: &str) -> Result<Vec<u8>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut bytes = vec![];
+    let mut index = 0;
+    // \C- and \M- apply to the next character
+    let mut control = false;
+    while index < chars.len() {
+        let byte = match chars[index] {
+            '\\' if chars[index + 1..].starts_with(&['C', '-']) => {
+                control = true;
+                index += 3;
+                continue;
+            }
+            '\\' if chars[index + 1..].starts_with(&['M', '-']) => {
+                bytes.push(0x1b);
+                index += 3;
+                continue;
+            }
+            '\\' if index + 1 < chars.len() => {
+                index += 2;
+                match chars[index - 1] {
+                    'e' => 0x1b,
+                    'a' => 0x07,
+                    'b' => 0x08,
+                    'd' => 0x7f,
+                    'f' => 0x0c,
+                    'n' => b'\n',
+                    'r' => b'\r',
+                    't' => b'\t',
+                    'v' => 0x0b,
+                    'x' => {
+                        let digits: String = chars[index..]
+                            .iter()
+                            .take(2)
+                            .take_while(|c| c.is_ascii_hexdigit())
+                            .collect();
+                        index += digits.len();
+                        u8::from_str_radix(&digits, 16).unwrap_or(b'x')
+                    }
+                    c @ '0'..='7' => {
+                        let digits: String = std::iter::once(c)
+                            .chain(chars[index..].iter().take(2).copied())
+                            .take_while(|c| ('0'..='7').contains(c))
+                            .collect();
+                        index += digits.len() - 1;
+                        u8::from_str_radix(&digits, 8).unwrap_or(0)
+                    }
+                    c => c as u8,
+                }
+            }
+            c => {
+                index += 1;
+                let mut buffer = [0; 4];
+                let encoded = c.encode_utf8(&mut buffer).as_bytes();
+                if encoded.len() > 1 {
+                    bytes.extend_from_slice(encoded);
+                    continue;
+                }
+                encoded[0]
+            }
+        };
+
+        bytes.push(match control {
+            true if byte == b'?' => 0x7f,
+            true => byte & 0x1f,
+            false => byte,
+        });
+        control = false;
+    }
+
+    if control {
+        return Err(format!("{}: incomplete key sequence", text));
+    }
+    Ok(bytes)
+}
+
+fn format_sequence(sequence: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in sequence {
+        match byte {
+            0x1b => text.push_str("\\e"),
+            0x7f => text.push_str("\\C-?"),
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            0..=0x1f => {
+                text.push_str("\\C-");
+                text.push((byte | 0x40).to_ascii_lowercase() as char);
+            }
+            0x20..=0x7e => text.push(byte as char),
+            _ => text.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    text
+}
+
+// Control-u, C-u, Meta-f, M-f and names of keys like in inputrc
+fn parse_key_name(name: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut control = false;
+    let mut rest = name;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        if let Some(prefix) = ["control-", "c-"].iter().find(|p| lower.starts_with(**p)) {
+            control = true;
+            rest = &rest[prefix.len()..];
+        } else if let Some(prefix) = ["meta-", "m-"].iter().find(|p| lower.starts_with(**p)) {
+            bytes.push(0x1b);
+            rest = &rest[prefix.len()..];
+        } else {
+            break;
+        }
+    }
+
+    let byte = match rest.to_ascii_lowercase().as_str() {
+        "rubout" | "del" => 0x7f,
+        "escape" | "esc" => 0x1b,
+        "newline" | "lfd" => b'\n',
+        "return" | "ret" => b'\r',
+        "space" | "spc" => b' ',
+        "tab" => b'\t',
+        _ if rest.len() == 1 => rest.as_bytes()[0],
+        _ => return Err(format!("{}: unknown key name", name)),
+    };
+    bytes.push(match control {
+        true if byte == b'?' => 0x7f,
+        true => byte.to_ascii_lowercase() & 0x1f,
+        false => byte,
+    });
+    Ok(bytes)
+}
+
+// text between quotes at the start and the rest after the closing one
+fn quoted(text: &str) -> Option<(&str, &str)> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return Some((&text[1..index], &text[index + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+// one line of inputrc or of the bind arguments: "keyseq": action-name,
+// "keyseq": "macro text", keyname: action-name, set editing-mode vi and
+// set keymap name; the returned keymap is used for the following lines
+pub fn parse_line(
+    bindings: &mut Bindings,
+    line: &str,
+    keymap: Keymap,
+    options: &Options,
+) -> Result<Keymap, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(keymap);
+    }
+
+    if let Some(setting) = line.strip_prefix("set ") {
+        let mut words = setting.split_whitespace();
+        return match (words.next(), words.next()) {
+            (Some("editing-mode"), Some(mode @ ("vi" | "emacs"))) => {
+                options.set(mode, true)?;
+                Ok(match mode {
+                    "vi" => Keymap::ViInsert,
+                    _ => Keymap::Emacs,
+                })
+            }
+            (Some("keymap"), Some(name)) => Keymap::parse(name),
+            // other readline variables are not supported, they are ignored
+            _ => Ok(keymap),
+        };
+    }
+    // conditional and include directives are ignored
+    if line.starts_with('$') {
+        return Ok(keymap);
+    }
+
+    let (sequence, rest) = match quoted(line) {
+        Some((sequence, rest)) => (parse_sequence(sequence)?, rest),
+        None => match line.split_once(':') {
+            Some((name, rest)) => (parse_key_name(name.trim())?, rest),
+            None => return Err(format!("{}: no key sequence terminator", line)),
+        },
+    };
+    let target = rest.trim_start().strip_prefix(':').unwrap_or(rest).trim();
+    if sequence.is_empty() {
+        return Err(format!("{}: empty key sequence", line));
+    }
+
+    let binding = match quoted(target) {
+        Some((text, _)) => Binding::Macro(parse_sequence(text)?),
+        None => {
+            let actions = target
+                .split_whitespace()
+                .map(|name| action(name).ok_or_else(|| format!("{}: unknown function name", name)))
+                .collect::<Result<Vec<_>, _>>()?;
+            if actions.is_empty() {
+                return Err(format!("{}: function name expected", line));
+            }
+            Binding::Actions(actions)
+        }
+    };
+    bindings.set(keymap, sequence, binding);
+    Ok(keymap)
+}
+
+// bindings from the file, like ~/.inputrc for readline
+pub fn read_file(
+    bindings: &mut Bindings,
+    path: &str,
+    keymap: Keymap,
+    options: &Options,
+) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut keymap = keymap;
+    for (number, line) in text.lines().enumerate() {
+        match parse_line(bindings, line, keymap, options) {
+            Ok(next) => keymap = next,
+            Err(err) => eprintln!("{}: line {}: {}", path, number + 1, err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(bindings: &Bindings, keymap: Keymap, sequence: &[u8]) -> Option<String> {
+        match bindings.lookup(keymap, sequence).0? {
+            Binding::Actions(actions) => Some(
+                actions
+                    .iter()
+                    .map(|action| action_name(*action))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Binding::Macro(text) => Some(format!("\"{}\"", format_sequence(&text))),
+        }
+    }
+
+    #[test]
+    fn sequences_are_parsed() {
+        assert_eq!(
+            parse_sequence("\\C-a\\M-b\\e\\C-?").unwrap(),
+            b"\x01\x1bb\x1b\x7f"
+        );
+        assert_eq!(parse_sequence("\\t\\x41\\101\\\"x").unwrap(), b"\tAA\"x");
+        assert_eq!(parse_sequence("\u{e9}").unwrap(), "\u{e9}".as_bytes());
+        assert_eq!(
+            parse_sequence("a\\C-").unwrap_err(),
+            "a\\C-: incomplete key sequence"
+        );
+        assert_eq!(
+            format_sequence(b"\x01\x1b[\x7f\"\\\xff"),
+            "\\C-a\\e[\\C-?\\\"\\\\\\377"
+        );
+    }
+
+    #[test]
+    fn lines_bind_sequences() {
+        let options = Options::new();
+        let mut bindings = Bindings::new();
+        let keymap = Keymap::Emacs;
+        parse_line(&mut bindings, "\"\\C-t\": kill-line yank", keymap, &options).unwrap();
+        parse_line(&mut bindings, "Meta-x: \"ls\\r\"", keymap, &options).unwrap();
+        parse_line(&mut bindings, "# comment", keymap, &options).unwrap();
+        assert_eq!(bound(&bindings, keymap, b"\x14").unwrap(), "kill-line yank");
+        assert_eq!(bound(&bindings, keymap, b"\x1bx").unwrap(), "\"ls\\C-m\"");
+        assert!(bindings.lookup(keymap, b"\x1b").1);
+        assert!(bindings
+            .list(keymap)
+            .contains(&String::from("\"\\C-t\": kill-line yank")));
+
+        bindings.remove(keymap, "\\C-t").unwrap();
+        assert!(bound(&bindings, keymap, b"\x14").is_none());
+    }
+
+    #[test]
+    fn keymaps_are_chosen() {
+        let options = Options::new();
+        let mut bindings = Bindings::new();
+        let keymap = parse_line(&mut bindings, "set keymap vi", Keymap::Emacs, &options).unwrap();
+        assert!(keymap == Keymap::ViCommand);
+        parse_line(&mut bindings, "\"q\": undo", keymap, &options).unwrap();
+        assert_eq!(bound(&bindings, Keymap::ViCommand, b"q").unwrap(), "undo");
+        assert!(bound(&bindings, Keymap::Emacs, b"q").is_none());
+        assert!(!Keymap::ViCommand.inserts() && Keymap::ViInsert.inserts());
+    }
+
+    #[test]
+    fn errors() {
+        let options = Options::new();
+        let mut bindings = Bindings::new();
+        let keymap = Keymap::Emacs;
+        let error = |line| {
+            parse_line(&mut Bindings::new(), line, keymap, &options)
+                .err()
+                .unwrap()
+        };
+        assert_eq!(error("\"a\": fly"), "fly: unknown function name");
+        assert_eq!(error("\"a\":"), "\"a\":: function name expected");
+        assert_eq!(error("abc"), "abc: no key sequence terminator");
+        assert_eq!(error("Hyper-a: yank"), "Hyper-a: unknown key name");
+        assert_eq!(error("\"\": yank"), "\"\": yank: empty key sequence");
+        assert_eq!(Keymap::parse("x").err().unwrap(), "x: invalid keymap name");
+        parse_line(&mut bindings, "$if mode=emacs", keymap, &options).unwrap();
+    }
+}
@@ -0,0 +1,596 @@
+use crate::osstr;
+use crate::vars;
+
+// split the command line into words and operators, words keep their quotes,
+// they are removed by the expander which needs to know what was quoted
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Word(String),
+    // ; & && || | newline and redirections, optionally prefixed by file
+    // descriptor: 2>, 1>>
+    Op(String),
+}
+
+fn is_operator_start(c: char) -> bool {
+    matches!(c, ';' | '&' | '>' | '<' | '|')
+}
+
+// $(...) and `...` at the index, $((...)) is arithmetic
+fn is_substitution(chars: &[char], index: usize) -> bool {
+    match chars[index] {
+        '`' => true,
+        '$' => chars.get(index + 1) == Some(&'(') && chars.get(index + 2) != Some(&'('),
+        _ => false,
+    }
+}
+
+fn substitution_error() -> String {
+    String::from("syntax error: command substitution is not supported")
+}
+
+// read quoted part of the word starting at the opening quote
+fn read_quoted(chars: &[char], start: usize, word: &mut String) -> Result<usize, String> {
+    let quote = chars[start];
+    word.push(quote);
+    let mut index = start + 1;
+    while index < chars.len() {
+        let c = chars[index];
+        // quotes inside ${...} don't end the double quoted string
+        if quote == '"' && c == '$' && chars.get(index + 1) == Some(&'{') {
+            index = read_braced(chars, index, word)?;
+            continue;
+        }
+        if quote == '"' && is_substitution(chars, index) {
+            return Err(substitution_error());
+        }
+        word.push(c);
+        if c == quote {
+            return Ok(index + 1);
+        }
+        if c == '\\' && quote == '"' && index + 1 < chars.len() {
+            word.push(chars[index + 1]);
+            index += 1;
+        }
+        index += 1;
+    }
+
+    Err(String::from("syntax error: unterminated quote"))
+}
+
+// read ${...} expansion, it may contain spaces and quotes
+fn read_braced(chars: &[char], start: usize, word: &mut String) -> Result<usize, String> {
+    word.push_str("${");
+    let mut depth = 1;
+    let mut index = start + 2;
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            '\'' | '"' => {
+                index = read_quoted(chars, index, word)?;
+                continue;
+            }
+            '\\' if index + 1 < chars.len() => {
+                word.push(c);
+                index += 1;
+                word.push(chars[index]);
+            }
+            _ if is_substitution(chars, index) => return Err(substitution_error()),
+            '{' => {
+                depth += 1;
+                word.push(c);
+            }
+            '}' => {
+                depth -= 1;
+                word.push(c);
+                if depth == 0 {
+                    return Ok(index + 1);
+                }
+            }
+            _ => word.push(c),
+        }
+        index += 1;
+    }
+
+    Err(String::from("syntax error: unterminated ${"))
+}
+
+// up to max digits of the number in escape sequence
+fn number(chars: &[char], index: &mut usize, radix: u32, max: usize) -> Option<u32> {
+    let mut number = 0;
+    let mut count = 0;
+    while count < max && chars.get(*index).is_some_and(|c| c.is_digit(radix)) {
+        number = number * radix + chars[*index].to_digit(radix).unwrap();
+        *index += 1;
+        count += 1;
+    }
+    (count > 0).then_some(number)
+}
+
+// append the value of the escape sequence of $'...' at the index right after
+// the backslash, false for NUL, which ends the string like in bash
+fn ansi_c_escape(chars: &[char], index: &mut usize, value: &mut String) -> bool {
+    let c = chars[*index];
+    *index += 1;
+
+    let escaped = match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        'a' => Some('\x07'),
+        'b' => Some('\x08'),
+        'f' => Some('\x0c'),
+        'v' => Some('\x0b'),
+        'e' | 'E' => Some('\x1b'),
+        '\\' | '\'' | '"' | '?' => Some(c),
+        '0'..='7' => {
+            *index -= 1;
+            number(chars, index, 8, 3).map(|number| osstr::from_byte(number as u8))
+        }
+        'x' => number(chars, index, 16, 2).map(|number| osstr::from_byte(number as u8)),
+        'u' => number(chars, index, 16, 4).and_then(char::from_u32),
+        'U' => number(chars, index, 16, 8).and_then(char::from_u32),
+        // \cX is the control character of X
+        'c' if *index < chars.len() => {
+            *index += 1;
+            char::from_u32(chars[*index - 1].to_ascii_uppercase() as u32 ^ 0x40)
+        }
+        _ => None,
+    };
+
+    match escaped {
+        Some('\0') => false,
+        // \u and \U give characters, the other escapes bytes
+        Some(escaped) if matches!(c, 'u' | 'U') => {
+            osstr::push_char(value, escaped);
+            true
+        }
+        Some(escaped) => {
+            value.push(escaped);
+            true
+        }
+        // unknown escapes are kept as is
+        None => {
+            value.push('\\');
+            value.push(c);
+            true
+        }
+    }
+}
+
+// read $'...' starting at the $ sign, the escapes are replaced and the result
+// is written as single quoted string, so it's taken literally later
+fn read_ansi_c(chars: &[char], start: usize, word: &mut String) -> Result<usize, String> {
+    let mut index = start + 2;
+    let mut value = String::new();
+    let mut ended = false;
+    loop {
+        match chars.get(index) {
+            None => return Err(String::from("syntax error: unterminated $'")),
+            Some('\'') => break,
+            Some('\\') if index + 1 < chars.len() => {
+                index += 1;
+                let mut escaped = String::new();
+                if !ansi_c_escape(chars, &mut index, &mut escaped) {
+                    ended = true;
+                }
+                if !ended {
+                    value.push_str(&escaped);
+                }
+            }
+            Some(&c) => {
+                if !ended {
+                    value.push(c);
+                }
+                index += 1;
+            }
+        }
+    }
+
+    // escaped bytes which form UTF-8 characters become the characters
+    let value = osstr::from_bytes(&osstr::to_bytes(&value));
+    word.push('\'');
+    word.push_str(&value.replace('\'', "'\\''"));
+    word.push('\'');
+    Ok(index + 1)
+}
+
+// read ((...)) of arithmetic command or $((...)) expansion starting at the
+// first parenthesis, operators like > are parts of the expression
+fn read_arithmetic(chars: &[char], start: usize, word: &mut String) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut index = start;
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            '\'' | '"' => {
+                index = read_quoted(chars, index, word)?;
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        word.push(c);
+        index += 1;
+        if depth == 0 {
+            return Ok(index);
+        }
+    }
+
+    Err(String::from("syntax error: unterminated (("))
+}
+
+// read (...) of compound assignment NAME=(a b), it may contain spaces
+fn read_compound(chars: &[char], start: usize, word: &mut String) -> Result<usize, String> {
+    word.push('(');
+    let mut index = start + 1;
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            '\'' | '"' => {
+                index = read_quoted(chars, index, word)?;
+                continue;
+            }
+            '\\' if index + 1 < chars.len() => {
+                word.push(c);
+                index += 1;
+                word.push(chars[index]);
+            }
+            ')' => {
+                word.push(c);
+                return Ok(index + 1);
+            }
+            _ => word.push(c),
+        }
+        index += 1;
+    }
+
+    Err(String::from("syntax error: unterminated ("))
+}
+
+fn open_parentheses(word: &str) -> bool {
+    let opened = word.matches('(').count();
+    let closed = word.matches(')').count();
+    opened > closed
+}
+
+fn is_descriptor(word: &str) -> bool {
+    match word
+        .strip_prefix('{')
+        .and_then(|word| word.strip_suffix('}'))
+    {
+        Some(name) => vars::is_name(name),
+        None => word.chars().all(|c| c.is_ascii_digit()),
+    }
+}
+
+fn read_operator(chars: &[char], start: usize, prefix: String) -> (Token, usize) {
+    let mut op = prefix;
+    let next = chars.get(start + 1).copied();
+    let len = match (chars[start], next) {
+        ('>', Some('>' | '|' | '&'))
+        | ('<', Some('&' | '>'))
+        | ('&', Some('&'))
+        | ('|', Some('|')) => 2,
+        _ => 1,
+    };
+    op.extend(&chars[start..start + len]);
+    (Token::Op(op), start + len)
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            '\n' => {
+                if in_word {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                    in_word = false;
+                }
+                tokens.push(Token::Op(String::from("\n")));
+                index += 1;
+            }
+            ' ' | '\t' | '\r' => {
+                if in_word {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                    in_word = false;
+                }
+                index += 1;
+            }
+            // the comment ends at the end of the line
+            '#' if !in_word => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+            }
+            '\'' | '"' => {
+                index = read_quoted(&chars, index, &mut word)?;
+                in_word = true;
+            }
+            '\\' => {
+                word.push(c);
+                if let Some(&next) = chars.get(index + 1) {
+                    word.push(next);
+                }
+                index += 2;
+                in_word = true;
+            }
+            '(' if in_word && word.ends_with('=') && vars::assignment(&word).is_some() => {
+                index = read_compound(&chars, index, &mut word)?;
+            }
+            '$' if chars.get(index + 1) == Some(&'\'') => {
+                index = read_ansi_c(&chars, index, &mut word)?;
+                in_word = true;
+            }
+            '$' if chars.get(index + 1) == Some(&'{') => {
+                index = read_braced(&chars, index, &mut word)?;
+                in_word = true;
+            }
+            '$' if chars.get(index + 1) == Some(&'(') && chars.get(index + 2) == Some(&'(') => {
+                word.push(c);
+                index = read_arithmetic(&chars, index + 1, &mut word)?;
+                in_word = true;
+            }
+            '(' if !in_word && chars.get(index + 1) == Some(&'(') => {
+                index = read_arithmetic(&chars, index, &mut word)?;
+                in_word = true;
+            }
+            _ if is_substitution(&chars, index) => return Err(substitution_error()),
+            // | inside parentheses of patterns like @(a|b) is not a pipe
+            '|' if in_word && open_parentheses(&word) => {
+                word.push(c);
+                index += 1;
+            }
+            _ if is_operator_start(c) => {
+                // digits or {name} right before redirection are file descriptor
+                let prefix = if matches!(c, '>' | '<') && in_word && is_descriptor(&word) {
+                    in_word = false;
+                    std::mem::take(&mut word)
+                } else {
+                    String::new()
+                };
+                if in_word {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                    in_word = false;
+                }
+                let (token, next) = read_operator(&chars, index, prefix);
+                tokens.push(token);
+                index = next;
+            }
+            _ => {
+                word.push(c);
+                in_word = true;
+                index += 1;
+            }
+        }
+    }
+    if in_word {
+        tokens.push(Token::Word(word));
+    }
+
+    Ok(tokens)
+}
+
+fn starts_command(command: &[Token]) -> bool {
+    match command.last() {
+        None => true,
+        Some(Token::Op(op)) => op == "&&" || op == "||",
+        Some(Token::Word(word)) => word == "!",
+    }
+}
+
+// split tokens into commands separated by ;, & and newlines, the flag tells
+// whether the command runs in background; && and || stay in the command,
+// separators inside [[ ... ]] too
+pub fn split_commands(tokens: Vec<Token>) -> Result<Vec<(Vec<Token>, bool)>, String> {
+    let mut commands = vec![];
+    let mut command = vec![];
+    let mut conditional = false;
+
+    for token in tokens {
+        match token {
+            // [[ is recognised only at the beginning of the command
+            Token::Word(ref word) if word == "[[" && !conditional && starts_command(&command) => {
+                conditional = true;
+                command.push(token);
+            }
+            Token::Word(ref word) if word == "]]" && conditional => {
+                conditional = false;
+                command.push(token);
+            }
+            _ if conditional => command.push(token),
+            // empty lines are allowed
+            Token::Op(ref op) if op == "\n" => {
+                if !command.is_empty() {
+                    commands.push((std::mem::take(&mut command), false));
+                }
+            }
+            Token::Op(ref op) if op == ";" || op == "&" => {
+                if command.is_empty() {
+                    return Err(format!("syntax error near unexpected token `{}'", op));
+                }
+                commands.push((std::mem::take(&mut command), op == "&"));
+            }
+            _ => command.push(token),
+        }
+    }
+    if !command.is_empty() {
+        commands.push((command, false));
+    }
+
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str) -> Token {
+        Token::Word(String::from(text))
+    }
+
+    fn op(text: &str) -> Token {
+        Token::Op(String::from(text))
+    }
+
+    #[test]
+    fn words_keep_their_quotes() {
+        assert_eq!(
+            tokenize("echo 'a b' \"c\\\" d\" e\\ f ${x}").unwrap(),
+            [
+                word("echo"),
+                word("'a b'"),
+                word("\"c\\\" d\""),
+                word("e\\ f"),
+                word("${x}")
+            ]
+        );
+        assert_eq!(
+            tokenize("echo a # comment").unwrap(),
+            [word("echo"), word("a")]
+        );
+        assert_eq!(tokenize("echo a#b").unwrap(), [word("echo"), word("a#b")]);
+        assert!(tokenize("echo 'a").is_err());
+        assert_eq!(
+            tokenize("a # comment\nb").unwrap(),
+            [word("a"), op("\n"), word("b")]
+        );
+    }
+
+    #[test]
+    fn quotes_inside_braces_stay_in_the_word() {
+        assert_eq!(
+            tokenize("echo \"${y:-\"q r\"}\" ${x/a/\"z }\"} ${y:-\"a b\"}").unwrap(),
+            [
+                word("echo"),
+                word("\"${y:-\"q r\"}\""),
+                word("${x/a/\"z }\"}"),
+                word("${y:-\"a b\"}")
+            ]
+        );
+        assert!(tokenize("echo \"${y:-\"a}\"").is_err());
+    }
+
+    #[test]
+    fn command_substitution_is_an_error() {
+        for input in [
+            "echo $(date)",
+            "echo `date`",
+            "echo \"a $(date)\"",
+            "echo ${x:-`date`}",
+        ] {
+            assert_eq!(
+                tokenize(input).unwrap_err(),
+                "syntax error: command substitution is not supported"
+            );
+        }
+        assert_eq!(
+            tokenize("echo '$(a)' \\$(b \"\\`c\" $((1))").unwrap(),
+            [
+                word("echo"),
+                word("'$(a)'"),
+                word("\\$(b"),
+                word("\"\\`c\""),
+                word("$((1))")
+            ]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            tokenize("a>b 2>>c >| d; e &").unwrap(),
+            [
+                word("a"),
+                op(">"),
+                word("b"),
+                op("2>>"),
+                word("c"),
+                op(">|"),
+                word("d"),
+                op(";"),
+                word("e"),
+                op("&")
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_and_patterns() {
+        assert_eq!(
+            tokenize("a&&b || c").unwrap(),
+            [word("a"), op("&&"), word("b"), op("||"), word("c")]
+        );
+        assert_eq!(tokenize("@(a|b)").unwrap(), [word("@(a|b)")]);
+        let commands = split_commands(tokenize("[[ a; b ]]; c").unwrap()).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].0.len(), 5);
+    }
+
+    #[test]
+    fn arithmetic_is_one_word() {
+        assert_eq!(
+            tokenize("((a > 1 && (b < 2)))").unwrap(),
+            [word("((a > 1 && (b < 2)))")]
+        );
+        assert_eq!(
+            tokenize("echo $((1 > 0)) a").unwrap(),
+            [word("echo"), word("$((1 > 0))"), word("a")]
+        );
+        assert_eq!(
+            tokenize("((1").unwrap_err(),
+            "syntax error: unterminated (("
+        );
+    }
+
+    #[test]
+    fn ansi_c_quoting() {
+        assert_eq!(tokenize("$'a\\tb'").unwrap(), [word("'a\tb'")]);
+        assert_eq!(
+            tokenize("x$'\\x41\\101\\u00e9\\cA\\q'").unwrap(),
+            [word("x'AAé\x01\\q'")]
+        );
+        assert_eq!(tokenize("$'it\\'s'").unwrap(), [word("'it'\\''s'")]);
+        assert_eq!(tokenize("$'a\\0b'c").unwrap(), [word("'a'c")]);
+        // escaped bytes form UTF-8 characters, others stay bytes
+        assert_eq!(tokenize("$'\\xc3\\xa9'").unwrap(), [word("'é'")]);
+        let byte = tokenize("$'\\377'").unwrap();
+        assert_eq!(byte, [Token::Word(format!("'{}'", osstr::from_byte(0xff)))]);
+        let private = osstr::from_bytes("\u{10ff41}".as_bytes());
+        assert_eq!(
+            tokenize("$'\\U0010ff41'").unwrap(),
+            [Token::Word(format!("'{}'", private))]
+        );
+        assert_eq!(
+            tokenize("$'a").unwrap_err(),
+            "syntax error: unterminated $'"
+        );
+    }
+
+    #[test]
+    fn commands_are_split() {
+        let commands = split_commands(tokenize("a 1; b & c").unwrap()).unwrap();
+        assert_eq!(
+            commands,
+            [
+                (vec![word("a"), word("1")], false),
+                (vec![word("b")], true),
+                (vec![word("c")], false)
+            ]
+        );
+        assert_eq!(
+            split_commands(tokenize("\na\n\nb\n").unwrap()).unwrap(),
+            [(vec![word("a")], false), (vec![word("b")], false)]
+        );
+        assert_eq!(
+            split_commands(tokenize("; a").unwrap()).unwrap_err(),
+            "syntax error near unexpected token `;'"
+        );
+    }
+}
@@ -0,0 +1,2572 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+#[allow(unused_imports)]
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+mod arith;
+mod async_shell;
+mod cancel;
+mod complete;
+mod compound;
+mod conditional;
+mod config;
+mod editor;
+mod expand;
+mod frequency;
+mod glob;
+mod history;
+mod jobs;
+mod keymap;
+mod lexer;
+mod options;
+pub mod osstr;
+mod printf;
+mod priority;
+mod prompt;
+mod record;
+mod redirect;
+mod regex;
+mod resolve;
+mod rusage;
+mod shell;
+mod signals;
+mod spell;
+mod theme;
+mod vars;
+
+pub use async_shell::{AsyncShell, Eval, Event, NextEvent};
+pub use cancel::CancelToken;
+pub use resolve::{PathResolver, Resolver};
+pub use shell::{ExecResult, HistoryBackend, Shell, ShellBuilder};
+
+use jobs::Jobs;
+use options::Options;
+use vars::Variables;
+
+type CommandFn<C> = Rc<dyn Fn(&[&str], &mut C) -> Result<Command, String>>;
+struct CommandEnv {
+    commands: Vec<(String, CommandFn<Self>)>,
+    options: Options,
+    jobs: Jobs,
+    vars: Variables,
+    login: bool,
+    interactive: bool,
+    // standard input of the running command is redirected
+    stdin: bool,
+    // the script and the sourced files being run, the current one is the last
+    frames: Vec<Frame>,
+    // priority of programs run by lowprio
+    priority: Option<priority::Priority>,
+    // log of the entered commands started by record builtin
+    recorder: Option<record::Recorder>,
+    // keys of the line editor, changed by bind builtin and ~/.inputrc
+    bindings: Rc<RefCell<keymap::Bindings>>,
+    // entered commands, shared with the line editor
+    history: Rc<RefCell<history::History>>,
+    // commands run in each directory, shared with the completion
+    frequency: Rc<RefCell<frequency::Frequency>>,
+    // PATH, HOME and working directory the line editor completes names in
+    completion: Rc<RefCell<complete::Context>>,
+    history_backend: HistoryBackend,
+    // writers of the embedding program for the output and errors of commands
+    handles: Option<Handles>,
+    // writers the output and errors of the running command go to, they
+    // follow its redirections
+    routes: [redirect::Route; 2],
+    // commands are skipped after the embedding program cancels them
+    cancel: CancelToken,
+    // finds the programs for the commands which aren't builtins
+    resolver: Rc<dyn Resolver>,
+}
+
+struct Handles {
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+}
+
+struct Frame {
+    name: String,
+    file: String,
+    // line of the command being run
+    line: usize,
+}
+
+impl CommandEnv {
+    fn push(&mut self, name: String, cmdfn: CommandFn<Self>) {
+        self.commands.push((name, cmdfn));
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.commands.iter().map(|(name, _)| &name[..])
+    }
+
+    // interactive shells and the ones given a history by the embedding program
+    fn keeps_history(&self) -> bool {
+        self.interactive || !matches!(self.history_backend, HistoryBackend::Default)
+    }
+
+    fn find(&self, name: &str) -> Option<CommandFn<Self>> {
+        self.commands
+            .iter()
+            .find(|(command_name, _)| command_name == name)
+            .map(|(_, cmdfn)| Rc::clone(cmdfn))
+    }
+
+    // the program is found in PATH of the shell
+    fn find_program(&self, name: &str) -> Result<Option<String>, String> {
+        self.resolver.find(name, self.vars.get("PATH").as_deref())
+    }
+
+    // the command gets the environment and the working directory of the shell
+    fn program(&self, path: &str) -> process::Command {
+        let mut command = self.resolver.command(path);
+        self.vars.prepare(&mut command);
+        command
+    }
+}
+
+const RUN_INTERNAL: &str = "__r_u_n__";
+
+enum Command {
+    Exit(i32),
+    Echo(String),
+    Type(String),
+    Pwd(String),
+    // standard output and standard error of the program
+    Run(String, String),
+    Job(String),
+    Set(String),
+    Repeat,
+    Disown,
+    Cd(String),
+    Assign,
+    // exit status without output, like [[ ... ]]
+    Status(i32),
+    // break and continue with the number of loops
+    Break(usize),
+    Continue(usize),
+}
+
+// what to do after the command, break and continue leave the enclosing loops
+enum Flow {
+    Next,
+    Break(usize),
+    Continue(usize),
+    // exit or errexit, the commands are left up to the caller of the shell
+    Exit(i32),
+}
+
+fn init() -> CommandEnv {
+    let options = Options::new();
+    let mut command_env = CommandEnv {
+        commands: vec![],
+        jobs: Jobs::new(&options),
+        vars: Variables::new(&options),
+        options,
+        login: false,
+        interactive: false,
+        stdin: false,
+        frames: vec![],
+        priority: None,
+        recorder: None,
+        bindings: Rc::new(RefCell::new(keymap::Bindings::new())),
+        history: Rc::new(RefCell::new(history::History::new())),
+        frequency: Rc::new(RefCell::new(frequency::Frequency::new())),
+        completion: Rc::new(RefCell::new(complete::Context::default())),
+        history_backend: HistoryBackend::Default,
+        handles: None,
+        routes: redirect::routes(&[]),
+        cancel: CancelToken::new(),
+        resolver: Rc::new(PathResolver),
+    };
+
+    // the first token in command_tokens is always a command name
+    command_env.push(
+        String::from("exit"),
+        Rc::new(|command_tokens, _| {
+            if command_tokens.len() == 2 {
+                match command_tokens[1].trim().parse() {
+                    // the history is saved when the shell is dropped
+                    Ok(code) => Ok(Command::Exit(code)),
+                    Err(_) => Err(String::from("invalid error code")),
+                }
+            } else {
+                Err(String::from("invalid exit command: exit <error_code>"))
+            }
+        }),
+    );
+
+    command_env.push(
+        String::from("echo"),
+        Rc::new(|command_tokens, _| Ok(Command::Echo(command_tokens[1..].join(" ")))),
+    );
+
+    command_env.push(
+        String::from("type"),
+        Rc::new(|command_tokens: &[&str], command_env| {
+            if command_tokens.len() != 2 {
+                return Err(String::from("invalid type command: type <command>"));
+            }
+
+            let typed_command_name = command_tokens[1].trim();
+            if command_env.names().any(|name| name == typed_command_name) {
+                Ok(Command::Type(format!(
+                    "{} is a shell builtin",
+                    String::from(typed_command_name)
+                )))
+            } else {
+                // try to find this command in user system folders
+                match command_env.find_program(typed_command_name) {
+                    Ok(Some(path)) => Ok(Command::Type(format!(
+                        "{} is {}",
+                        String::from(typed_command_name),
+                        path
+                    ))),
+                    Ok(None) => Ok(Command::Type(format!(
+                        "{}: not found",
+                        String::from(typed_command_name)
+                    ))),
+                    Err(err) => Err(format!("type: {}: {}", typed_command_name, err)),
+                }
+            }
+        }),
+    );
+
+    command_env.push(
+        String::from("pwd"),
+        Rc::new(|_, command_env| {
+            Ok(Command::Pwd(osstr::from_os(
+                command_env.vars.cwd.as_os_str(),
+            )))
+        }),
+    );
+
+    command_env.push(
+        String::from("cd"),
+        Rc::new(|command_tokens, command_env| {
+            let (directory, print) = match command_tokens[1..] {
+                [] => match command_env.vars.get("HOME") {
+                    Some(home) => (home, false),
+                    None => return Err(String::from("cd: HOME not set")),
+                },
+                ["-"] => match command_env.vars.get("OLDPWD") {
+                    Some(oldpwd) => (oldpwd, true),
+                    None => return Err(String::from("cd: OLDPWD not set")),
+                },
+                [directory] => (String::from(directory), false),
+                _ => return Err(String::from("invalid cd command: cd [<directory>]")),
+            };
+
+            let oldpwd = osstr::from_os(command_env.vars.cwd.as_os_str());
+            let mut output = String::new();
+            if change_dir(&directory, &mut command_env.vars).is_err() {
+                let error = format!("cd: {}: No such file or directory", directory);
+                let corrections = spell::corrections(&command_env.vars.cwd, &directory);
+                match corrections[..] {
+                    [] => return Err(error),
+                    // the corrected name is printed like in bash
+                    [ref corrected] if command_env.options.get("cdspell") => {
+                        if change_dir(corrected, &mut command_env.vars).is_err() {
+                            return Err(error);
+                        }
+                        output = corrected.clone();
+                    }
+                    _ => {
+                        return Err(format!(
+                            "{}\ndid you mean {}?",
+                            error,
+                            corrections.join(" or ")
+                        ))
+                    }
+                }
+            }
+            // they are exported like the ones the shell got from its parent
+            command_env.vars.set_environment("OLDPWD", Some(&oldpwd));
+            let pwd = osstr::from_os(command_env.vars.cwd.as_os_str());
+            command_env.vars.set_environment("PWD", Some(&pwd));
+
+            if print {
+                output = pwd;
+            }
+            Ok(Command::Cd(output))
+        }),
+    );
+
+    command_env.push(
+        String::from("set"),
+        Rc::new(|command_tokens, command_env| match command_tokens[1..] {
+            [] | ["-o"] => Ok(Command::Set(command_env.options.list())),
+            _ => {
+                // options are followed by positional parameters: set -o name -- a b
+                let args = &command_tokens[1..];
+                // the name after -o and +o is a part of the options
+                let split = (0..args.len()).position(|index| {
+                    let arg = args[index];
+                    let named = index > 0 && matches!(args[index - 1], "-o" | "+o");
+                    !named && (!arg.starts_with(['-', '+']) || arg == "--")
+                });
+                let (options, positional) = match split {
+                    Some(index) if args[index] == "--" => {
+                        (&args[..index], Some(&args[index + 1..]))
+                    }
+                    Some(index) => (&args[..index], Some(&args[index..])),
+                    None => (args, None),
+                };
+
+                command_env.options.apply(options)?;
+                if let Some(positional) = positional {
+                    command_env.vars.positional =
+                        positional.iter().map(|arg| String::from(*arg)).collect();
+                }
+                Ok(Command::Set(String::new()))
+            }
+        }),
+    );
+
+    command_env.push(String::from("declare"), Rc::new(declare));
+    command_env.push(String::from("typeset"), Rc::new(declare));
+
+    // readonly [-aAp] [<name>[=<value>]...] is declare -r, without names it
+    // lists readonly variables
+    command_env.push(
+        String::from("readonly"),
+        Rc::new(|command_tokens, command_env| {
+            let mut args = vec!["declare", "-r"];
+            for arg in &command_tokens[1..] {
+                match *arg {
+                    "-a" | "-A" => args.push(arg),
+                    // names are printed with -p only when there are no names
+                    "-p" => {}
+                    _ if arg.starts_with('-') => {
+                        return Err(format!("readonly: {}: invalid option", arg))
+                    }
+                    _ => args.push(arg),
+                }
+            }
+            declare(&args, command_env)
+        }),
+    );
+
+    command_env.push(
+        String::from("unset"),
+        Rc::new(|command_tokens, command_env| {
+            let names = match command_tokens[1..] {
+                ["-v", ref names @ ..] => names,
+                ["-f", ..] => return Err(String::from("unset: -f: functions are not supported")),
+                ref names => names,
+            };
+            for name in names {
+                if let Some((name, key)) = name.strip_suffix(']').and_then(|n| n.split_once('[')) {
+                    if vars::is_name(name) {
+                        let key = vars::subscript(name, key, &mut command_env.vars)?;
+                        command_env.vars.unset_element(name, &key)?;
+                        continue;
+                    }
+                }
+                if !vars::is_name(name) {
+                    return Err(format!("unset: `{}': not a valid identifier", name));
+                }
+                command_env
+                    .vars
+                    .unset(name)
+                    .map_err(|err| format!("unset: {}", err))?;
+            }
+            Ok(Command::Set(String::new()))
+        }),
+    );
+
+    command_env.push(
+        String::from("break"),
+        Rc::new(|command_tokens, _command_env| {
+            Ok(Command::Break(loop_count("break", command_tokens)?))
+        }),
+    );
+
+    command_env.push(
+        String::from("continue"),
+        Rc::new(|command_tokens, _command_env| {
+            Ok(Command::Continue(loop_count("continue", command_tokens)?))
+        }),
+    );
+
+    command_env.push(
+        String::from("shift"),
+        Rc::new(|command_tokens, command_env| {
+            let count = match command_tokens[1..] {
+                [] => 1,
+                [count] => match count.parse::<usize>() {
+                    Ok(count) => count,
+                    Err(_) => return Err(format!("shift: {}: numeric argument required", count)),
+                },
+                _ => return Err(String::from("invalid shift command: shift [<count>]")),
+            };
+
+            let positional = &mut command_env.vars.positional;
+            if count > positional.len() {
+                return Err(format!("shift: {}: shift count out of range", count));
+            }
+            positional.drain(..count);
+            Ok(Command::Set(String::new()))
+        }),
+    );
+
+    // the same options as set -o, for compatibility with bash scripts
+    command_env.push(
+        String::from("shopt"),
+        Rc::new(|command_tokens, command_env| {
+            let value = match command_tokens[1..] {
+                [] => return Ok(Command::Set(command_env.options.list())),
+                ["-s", ..] => true,
+                ["-u", ..] => false,
+                _ => {
+                    return Err(String::from(
+                        "invalid shopt command: shopt [-s|-u] <option>...",
+                    ))
+                }
+            };
+            for name in &command_tokens[2..] {
+                command_env.options.set(name, value)?;
+            }
+            Ok(Command::Set(String::new()))
+        }),
+    );
+
+    command_env.push(
+        String::from("disown"),
+        Rc::new(|command_tokens, command_env| {
+            // -h keeps jobs in the table but they won't be hung up on exit
+            let mut keep = false;
+            let mut all = false;
+            let mut args = &command_tokens[1..];
+            while let Some(flags) = args.first().and_then(|arg| arg.strip_prefix('-')) {
+                if flags.is_empty() || flags.starts_with('%') {
+                    break;
+                }
+                for flag in flags.chars() {
+                    match flag {
+                        'h' => keep = true,
+                        'a' => all = true,
+                        _ => return Err(format!("disown: -{}: invalid option", flag)),
+                    }
+                }
+                args = &args[1..];
+            }
+
+            match args {
+                _ if all => command_env.jobs.disown_all(keep),
+                [] => command_env.jobs.disown(None, keep)?,
+                _ => {
+                    for spec in args {
+                        command_env.jobs.disown(Some(spec), keep)?;
+                    }
+                }
+            }
+            Ok(Command::Disown)
+        }),
+    );
+
+    command_env.push(
+        String::from("suspend"),
+        Rc::new(|command_tokens, command_env| {
+            let force = match command_tokens[1..] {
+                [] => false,
+                ["-f"] => true,
+                _ => return Err(String::from("suspend: usage: suspend [-f]")),
+            };
+            // a login shell has no parent shell to resume it
+            if command_env.login && !force {
+                return Err(String::from("suspend: cannot suspend a login shell"));
+            }
+            signals::suspend();
+            Ok(Command::Set(String::new()))
+        }),
+    );
+
+    command_env.push(
+        String::from("times"),
+        Rc::new(|_, _| {
+            let lines: Vec<String> = [rusage::shell(), rusage::children()]
+                .iter()
+                .map(|times| {
+                    format!(
+                        "{} {}",
+                        rusage::format(times.user),
+                        rusage::format(times.system)
+                    )
+                })
+                .collect();
+            Ok(Command::Set(lines.join("\n")))
+        }),
+    );
+
+    command_env.push(
+        String::from("caller"),
+        Rc::new(|command_tokens, command_env| {
+            let level = match command_tokens[1..] {
+                [] => None,
+                [level] => Some(
+                    level
+                        .parse::<usize>()
+                        .map_err(|_| format!("caller: {}: invalid number", level))?,
+                ),
+                _ => return Err(String::from("caller: usage: caller [n]")),
+            };
+
+            // the frame which called the current one, or n frames above it
+            let frames = &command_env.frames;
+            let index = frames
+                .len()
+                .checked_sub(2 + level.unwrap_or(0))
+                .map(|index| &frames[index]);
+            match (index, level) {
+                (Some(frame), None) => Ok(Command::Echo(format!("{} {}", frame.line, frame.file))),
+                (Some(frame), Some(_)) => Ok(Command::Echo(format!(
+                    "{} {} {}",
+                    frame.line, frame.name, frame.file
+                ))),
+                (None, _) => Ok(Command::Status(1)),
+            }
+        }),
+    );
+
+    let source: CommandFn<CommandEnv> = Rc::new(|command_tokens, command_env| {
+        let name = match command_tokens.get(1) {
+            Some(name) => *name,
+            None => return Err(String::from("source: filename argument required")),
+        };
+        // like in bash, a name without slashes is looked up in PATH first
+        let path = match name.contains('/') {
+            true => None,
+            false => resolve::find_in_path(name, command_env.vars.get("PATH").as_deref())
+                .ok()
+                .flatten()
+                .map(|path| PathBuf::from(osstr::to_os(&path)))
+                .filter(|path| path.is_file()),
+        }
+        .unwrap_or_else(|| PathBuf::from(osstr::to_os(name)));
+
+        // arguments replace the positional parameters while the file runs
+        let positional = match command_tokens.len() > 2 {
+            true => Some(std::mem::replace(
+                &mut command_env.vars.positional,
+                command_tokens[2..]
+                    .iter()
+                    .map(|arg| String::from(*arg))
+                    .collect(),
+            )),
+            false => None,
+        };
+        command_env.vars.status = 0;
+        let result = source_file(&path, command_env);
+        if let Some(positional) = positional {
+            command_env.vars.positional = positional;
+        }
+
+        result.map(|exited| match exited {
+            Some(code) => Command::Exit(code),
+            None => Command::Status(command_env.vars.status),
+        })
+    });
+    command_env.push(String::from("source"), Rc::clone(&source));
+    command_env.push(String::from("."), source);
+
+    command_env.push(
+        String::from("exec"),
+        Rc::new(|command_tokens, command_env| {
+            // without a command only the redirections are applied, they stay
+            let command_name = match command_tokens.get(1) {
+                Some(command_name) => *command_name,
+                None => return Ok(Command::Status(0)),
+            };
+            match command_env.find_program(command_name)? {
+                Some(path) => {
+                    let _ = io::stdout().flush();
+                    let err = priority::lower(
+                        signals::unblock_in_child(&mut command_env.program(&path)),
+                        command_env.priority,
+                    )
+                    .args(command_tokens[2..].iter().map(|arg| osstr::to_os(arg)))
+                    .exec();
+                    Err(format!("exec: {}: {}", command_name, err))
+                }
+                None => {
+                    command_env.vars.status = 127;
+                    Ok(Command::Run(
+                        String::new(),
+                        format!("exec: {}: not found\n", command_name),
+                    ))
+                }
+            }
+        }),
+    );
+
+    command_env.push(
+        String::from("printf"),
+        Rc::new(|command_tokens, command_env| {
+            // printf [-v var] format [arguments...]
+            let (target, args) = match command_tokens[1..] {
+                ["-v", target, ref args @ ..] => (Some(target), args),
+                ["-v"] => return Err(String::from("printf: -v: option requires an argument")),
+                ["--", ref args @ ..] => (None, args),
+                ref args => (None, args),
+            };
+            let (format, args) = match args.split_first() {
+                Some((format, args)) => (*format, args),
+                None => {
+                    return Err(String::from(
+                        "printf: usage: printf [-v var] format [arguments]",
+                    ))
+                }
+            };
+
+            let output = printf::format(format, args)?;
+            let mut stderr = String::new();
+            for err in &output.errors {
+                stderr.push_str(&format!("{}\n", err));
+            }
+            command_env.vars.status = if output.errors.is_empty() { 0 } else { 1 };
+            match target {
+                Some(target) => {
+                    vars::set_target(target, &output.text, &mut command_env.vars)
+                        .map_err(|err| format!("printf: {}", err))?;
+                    Ok(Command::Run(String::new(), stderr))
+                }
+                None => Ok(Command::Run(output.text, stderr)),
+            }
+        }),
+    );
+
+    command_env.push(String::from("read"), Rc::new(read));
+
+    command_env.push(
+        String::from("let"),
+        Rc::new(|command_tokens, command_env| match command_tokens.len() {
+            1 => Err(String::from("let: expression expected")),
+            // arguments are expanded already, so they are evaluated as is
+            _ => {
+                let mut value = 0;
+                for expression in &command_tokens[1..] {
+                    value = arith::evaluate(expression, &mut command_env.vars)?;
+                }
+                Ok(arithmetic_status(value))
+            }
+        }),
+    );
+
+    command_env.push(String::from("mapfile"), Rc::new(mapfile));
+    command_env.push(String::from("readarray"), Rc::new(mapfile));
+    command_env.push(String::from("foreach"), Rc::new(foreach));
+
+    command_env.push(
+        String::from("jobs"),
+        Rc::new(|command_tokens, command_env| {
+            let long = match command_tokens[1..] {
+                [] => false,
+                ["-l"] => true,
+                _ => return Err(String::from("jobs: usage: jobs [-l]")),
+            };
+            let mut stdout = String::new();
+            command_env.vars.status = 0;
+            for line in command_env.jobs.list(long) {
+                stdout.push_str(&line);
+                stdout.push('\n');
+            }
+            Ok(Command::Run(stdout, String::new()))
+        }),
+    );
+
+    command_env.push(
+        String::from("lowprio"),
+        Rc::new(|command_tokens, command_env| {
+            const USAGE: &str =
+                "lowprio: usage: lowprio [-n <nice>] [-c <class>] [-l <level>] [-C] <command>";
+
+            let mut priority = priority::Priority::default();
+            let mut args = &command_tokens[1..];
+            loop {
+                match args {
+                    ["-n", nice, rest @ ..] => {
+                        priority.nice = match nice.parse() {
+                            Ok(nice) if (-20..=19).contains(&nice) => nice,
+                            _ => return Err(format!("lowprio: {}: invalid nice level", nice)),
+                        };
+                        args = rest;
+                    }
+                    ["-c", class, rest @ ..] => {
+                        let level = priority.io.map_or(7, |(_, level)| level);
+                        priority.io = Some((priority::io_class(class)?, level));
+                        args = rest;
+                    }
+                    ["-l", level, rest @ ..] => {
+                        let level = match level.parse() {
+                            Ok(level) if (0..=7).contains(&level) => level,
+                            _ => return Err(format!("lowprio: {}: invalid I/O level", level)),
+                        };
+                        priority.io = Some((priority.io.map_or(2, |(class, _)| class), level));
+                        args = rest;
+                    }
+                    // only the nice level, the I/O priority is inherited
+                    ["-C", rest @ ..] => {
+                        priority.io = None;
+                        args = rest;
+                    }
+                    ["--", rest @ ..] => {
+                        args = rest;
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            if args.is_empty() || args[0].starts_with('-') {
+                return Err(String::from(USAGE));
+            }
+
+            // builtins run in the shell itself, only programs get the priority
+            let previous = command_env.priority.replace(priority);
+            let result = run_tokens(args, command_env, false);
+            command_env.priority = previous;
+            result
+        }),
+    );
+
+    command_env.push(String::from("bind"), Rc::new(bind));
+
+    command_env.push(String::from("history"), Rc::new(history));
+
+    command_env.push(String::from("record"), Rc::new(record));
+
+    command_env.push(
+        String::from("repeat"),
+        Rc::new(|command_tokens, command_env| {
+            const USAGE: &str = "invalid repeat command: repeat [-n <seconds>] [--] <command>";
+
+            let mut args = &command_tokens[1..];
+            let mut interval = 2.0;
+            if let ["-n", value, rest @ ..] = args {
+                interval = match value.trim().parse::<f64>() {
+                    Ok(interval) if interval > 0.0 => interval,
+                    _ => return Err(format!("invalid repeat interval: {}", value)),
+                };
+                args = rest;
+            }
+            if let ["--", rest @ ..] = args {
+                args = rest;
+            }
+            if args.is_empty() {
+                return Err(String::from(USAGE));
+            }
+
+            // run until Ctrl-C, the shell itself must survive it
+            signals::catch_interrupt();
+            let stopped = |command_env: &CommandEnv| {
+                signals::interrupted() || command_env.cancel.is_cancelled()
+            };
+            while !stopped(command_env) {
+                let header = format!("Every {:.1}s: {}\n\n", interval, args.join(" "));
+                print_text(format!("\x1b[H\x1b[2J{}", header), false, command_env);
+                let result = run_tokens(args, command_env, false);
+                if let Flow::Exit(code) = flow(result, command_env, &mut io::stdout()) {
+                    signals::release_interrupt();
+                    return Ok(Command::Exit(code));
+                }
+
+                let until = Instant::now() + Duration::from_secs_f64(interval);
+                while !stopped(command_env) && Instant::now() < until {
+                    thread::sleep(Duration::from_millis(50));
+                }
+            }
+            signals::release_interrupt();
+
+            Ok(Command::Repeat)
+        }),
+    );
+
+    // internal command, not for using from shell, this command must be last, see handle None branch to understand it
+    command_env.push(
+        String::from(RUN_INTERNAL),
+        Rc::new(|command_tokens, command_env| {
+            let command_name = command_tokens[0].trim();
+            match command_env.find_program(command_name) {
+                Ok(Some(path)) => {
+                    let args = &command_tokens[1..];
+
+                    // the shell forwards SIGHUP, SIGTERM and SIGQUIT to the program while waiting
+                    let started = Instant::now();
+                    let mut program = command_env.program(&path);
+                    // the interactive shell gives the terminal to the program,
+                    // so Ctrl-C goes to the programs it starts as well; they
+                    // are in the group also for cancel, which kills all of them
+                    let terminal = signals::terminal().filter(|_| command_env.interactive);
+                    let group = terminal.is_some() || command_env.cancel.is_shared();
+                    if group {
+                        program.process_group(0);
+                    }
+                    if terminal.is_some() {
+                        signals::ignore_stop_in_child(&mut program);
+                    }
+                    let result = priority::lower(
+                        signals::unblock_in_child(&mut program),
+                        command_env.priority,
+                    )
+                    .args(args.iter().map(|arg| osstr::to_os(arg)))
+                    .stdout(process::Stdio::piped())
+                    .stderr(process::Stdio::piped())
+                    .spawn()
+                    .and_then(|child| {
+                        let pid = child.id();
+                        if let Some(fd) = terminal {
+                            signals::give_terminal(fd, pid);
+                        }
+                        signals::set_foreground(pid, group);
+                        command_env.cancel.watch(pid);
+                        // output is written as it comes, embedding programs
+                        // get it while the program runs
+                        let output =
+                            rusage::wait_with_output(child, started, &mut |bytes, error| {
+                                print_chunk(bytes, error, command_env)
+                            });
+                        command_env.cancel.unwatch();
+                        signals::clear_foreground();
+                        if let Some(fd) = terminal {
+                            signals::take_terminal(fd);
+                        }
+                        output.map(|(status, stats)| (status, stats, pid))
+                    });
+
+                    match result {
+                        Ok((status, stats, pid)) => {
+                            // repeat stops when the program got Ctrl-C
+                            if status.signal() == Some(signals::SIGINT) {
+                                signals::interrupt();
+                            }
+                            command_env.vars.status = exit_status(status);
+                            let command = command_tokens.join(" ");
+                            set_job_stats(&command, pid, &stats, &mut command_env.vars);
+                            Ok(Command::Run(String::new(), String::new()))
+                        }
+                        Err(err) => Err(format!("failed to execute program: {}", err)),
+                    }
+                }
+                Ok(None) => {
+                    command_env.vars.status = 127;
+                    Ok(Command::Run(
+                        String::new(),
+                        format!("{}: not found\n", String::from(command_name)),
+                    ))
+                }
+                Err(err) => Err(format!("{}: {}", command_name, err)),
+            }
+        }),
+    );
+
+    command_env
+}
+
+// the working directory of the shell becomes the directory, the one of the
+// process doesn't change; symbolic links are resolved like by chdir
+fn change_dir(directory: &str, vars: &mut Variables) -> io::Result<()> {
+    let path = fs::canonicalize(vars.path(directory))?;
+    // the directory must be searchable
+    fs::metadata(path.join("."))?;
+    vars.cwd = path;
+    Ok(())
+}
+
+// exit code of the program, 128 + signal number if it was killed
+fn exit_status(status: process::ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+// the resources used by the last program the shell waited for go to the
+// LAST_JOB_STATS associative array: command, pid, status, real, user and sys
+// in seconds, maxrss in kilobytes, read and written in bytes
+fn set_job_stats(command: &str, pid: u32, stats: &rusage::Stats, vars: &mut Variables) {
+    const NAME: &str = "LAST_JOB_STATS";
+    let seconds = |time: Duration| format!("{:.3}", time.as_secs_f64());
+    let status = vars.status.to_string();
+    let _ = vars.unset(NAME);
+    if vars.declare_assoc(NAME).is_err() {
+        return;
+    }
+    for (key, value) in [
+        ("command", String::from(command)),
+        ("pid", pid.to_string()),
+        ("status", status),
+        ("real", seconds(stats.real)),
+        ("user", seconds(stats.user)),
+        ("sys", seconds(stats.system)),
+        ("maxrss", stats.max_rss.to_string()),
+        ("read", stats.read.to_string()),
+        ("written", stats.written.to_string()),
+    ] {
+        let _ = vars.set_element(NAME, key, &value);
+    }
+}
+
+// format duration in a short human-readable form: 850ms, 3.2s, 1m 5.0s
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 1.0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else {
+        format!("{}m {:.1}s", duration.as_secs() / 60, secs % 60.0)
+    }
+}
+
+// save duration of the last command to CMD_DURATION (in seconds), a shell
+// variable which programs don't get unless it's exported, and report it if it
+// exceeds REPORTTIME threshold (in seconds, like in zsh)
+fn report_duration(duration: Duration, command_env: &mut CommandEnv) {
+    let seconds = format!("{:.3}", duration.as_secs_f64());
+    let vars = &mut command_env.vars;
+    let _ = vars.set("CMD_DURATION", &seconds);
+
+    if let Some(value) = vars.get("REPORTTIME") {
+        let report = match value.trim().parse::<f64>() {
+            Ok(threshold) if threshold >= 0.0 => {
+                if duration.as_secs_f64() < threshold {
+                    return;
+                }
+                format!("took {}\n", format_duration(duration))
+            }
+            _ => format!("invalid REPORTTIME value: {}\n", value),
+        };
+        print_text(report, true, command_env);
+    }
+}
+
+// keymap of the current editing mode
+fn editing_keymap(options: &Options) -> keymap::Keymap {
+    match options.get("vi") {
+        true => keymap::Keymap::ViInsert,
+        false => keymap::Keymap::Emacs,
+    }
+}
+
+// bind [-m keymap] [-l] [-p] [-f file] [-r keyseq] [binding...]: bindings are
+// lines like in ~/.inputrc, "\C-t": transpose-chars or "\C-o": "macro text";
+// -l lists the function names, -p the bindings of the keymap
+fn bind(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    const USAGE: &str =
+        "bind: usage: bind [-m keymap] [-l] [-p] [-f file] [-r keyseq] [keyseq:function-name ...]";
+
+    let mut keymap = editing_keymap(&command_env.options);
+    let mut lines = vec![];
+    let mut args = &command_tokens[1..];
+    while let [option, rest @ ..] = args {
+        let mut bindings = command_env.bindings.borrow_mut();
+        args = match (*option, rest) {
+            ("-m", [name, rest @ ..]) => {
+                keymap = keymap::Keymap::parse(name).map_err(|err| format!("bind: {}", err))?;
+                rest
+            }
+            ("-l", _) => {
+                lines.extend(keymap::action_names().into_iter().map(String::from));
+                rest
+            }
+            ("-p", _) => {
+                lines.extend(bindings.list(keymap));
+                rest
+            }
+            ("-f", [path, rest @ ..]) => {
+                let path = osstr::from_os(command_env.vars.path(path).as_os_str());
+                keymap::read_file(&mut bindings, &path, keymap, &command_env.options)
+                    .map_err(|err| format!("bind: {}", err))?;
+                rest
+            }
+            ("-r", [sequence, rest @ ..]) => {
+                bindings
+                    .remove(keymap, sequence)
+                    .map_err(|err| format!("bind: {}", err))?;
+                rest
+            }
+            ("--", rest) => {
+                args = rest;
+                break;
+            }
+            (option, _) if option.starts_with('-') => return Err(String::from(USAGE)),
+            _ => break,
+        };
+    }
+
+    for line in args {
+        let mut bindings = command_env.bindings.borrow_mut();
+        keymap = keymap::parse_line(&mut bindings, line, keymap, &command_env.options)
+            .map_err(|err| format!("bind: {}", err))?;
+    }
+    match lines.is_empty() {
+        true => Ok(Command::Status(0)),
+        false => Ok(Command::Echo(lines.join("\n"))),
+    }
+}
+
+// HISTFILE, ~/.shell_history by default; history isn't saved when it's empty
+// or when the embedding program keeps it in memory
+fn history_file(command_env: &CommandEnv) -> Option<String> {
+    let vars = &command_env.vars;
+    match &command_env.history_backend {
+        HistoryBackend::Default => {}
+        HistoryBackend::Memory => return None,
+        HistoryBackend::File(path) => return Some(osstr::from_os(path.as_os_str())),
+    }
+    match vars.get("HISTFILE") {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(osstr::from_os(vars.path(&path).as_os_str())),
+        None => vars
+            .get("HOME")
+            .map(|home| format!("{}/.shell_history", home.trim_end_matches('/'))),
+    }
+}
+
+// $XDG_STATE_HOME/shell/frequency or ~/.local/state/shell/frequency
+fn frequency_file(vars: &Variables) -> Option<String> {
+    match (vars.get("XDG_STATE_HOME"), vars.get("HOME")) {
+        (Some(state), _) if !state.is_empty() => Some(format!("{}/shell/frequency", state)),
+        (_, Some(home)) => Some(format!(
+            "{}/.local/state/shell/frequency",
+            home.trim_end_matches('/')
+        )),
+        _ => None,
+    }
+}
+
+// names of the commands of the line which can be completed, the words after
+// redirections and assignments are skipped
+fn command_names(item: &compound::Item, names: &mut Vec<String>) {
+    let mut add = |tokens: &[lexer::Token]| {
+        let mut tokens = tokens.iter();
+        while let Some(token) = tokens.next() {
+            match token {
+                lexer::Token::Op(_) => {
+                    tokens.next();
+                }
+                lexer::Token::Word(word) if vars::assignment(word).is_some() => {}
+                lexer::Token::Word(word) => {
+                    if !word.is_empty() && !word.contains(|c| "$'\"\\/()`".contains(c)) {
+                        names.push(word.clone());
+                    }
+                    break;
+                }
+            }
+        }
+    };
+    match item {
+        compound::Item::Simple(tokens, _) => add(tokens),
+        compound::Item::And(first, second) | compound::Item::Or(first, second) => {
+            command_names(first, names);
+            command_names(second, names);
+        }
+        compound::Item::Not(inner) => command_names(inner, names),
+        compound::Item::Select { body, .. } | compound::Item::For { body, .. } => {
+            for item in body {
+                command_names(item, names);
+            }
+        }
+        compound::Item::Coproc { commands, .. } => {
+            for (tokens, _) in commands {
+                add(tokens);
+            }
+        }
+    }
+}
+
+// commands entered in the interactive shell are counted in the current
+// directory
+fn count_commands(input: &str, command_env: &mut CommandEnv) {
+    if !command_env.interactive {
+        return;
+    }
+    let items = match compound::parse(input) {
+        Ok(items) => items,
+        Err(_) => return,
+    };
+    let directory = osstr::from_os(command_env.vars.cwd.as_os_str());
+    let mut names = vec![];
+    for item in &items {
+        command_names(item, &mut names);
+    }
+    let mut frequency = command_env.frequency.borrow_mut();
+    for name in names {
+        frequency.add(&directory, &name);
+    }
+}
+
+fn save_frequency(command_env: &mut CommandEnv) {
+    if !command_env.interactive {
+        return;
+    }
+    if let Some(path) = frequency_file(&command_env.vars) {
+        if let Err(err) = command_env.frequency.borrow_mut().save(&path) {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+// entries kept in memory, HISTSIZE or 500
+fn history_size(vars: &Variables) -> usize {
+    vars.get("HISTSIZE")
+        .and_then(|size| size.trim().parse().ok())
+        .unwrap_or(500)
+}
+
+// the command is added to the history of the interactive shell with secrets
+// masked by the HISTREDACT patterns, unless HISTIGNORE or HISTCONTROL leave
+// it out; with histappend it's appended to the history file right away, so
+// other sessions can read it with history -n
+fn add_history(input: &str, command_env: &mut CommandEnv) {
+    if !command_env.keeps_history() {
+        return;
+    }
+    let vars = &command_env.vars;
+    let extglob = command_env.options.get("extglob");
+    let secrets = vars
+        .get("HISTREDACT")
+        .unwrap_or_else(|| String::from(history::SECRETS));
+    let entry = history::redact(input.trim_end_matches('\n'), &secrets, extglob);
+    let ignore = vars.get("HISTIGNORE").unwrap_or_default();
+    let control = vars.get("HISTCONTROL").unwrap_or_default();
+    let previous = command_env.history.borrow().entries().last().cloned();
+    if history::ignored(&entry, previous.as_deref(), &ignore, &control, extglob) {
+        return;
+    }
+    let limit = history_size(vars);
+    command_env.history.borrow_mut().add(&entry, limit);
+    if command_env.options.get("histappend") {
+        if let Some(path) = history_file(command_env) {
+            let appended = command_env.history.borrow_mut().append(&path);
+            if let Err(err) = appended {
+                print_output(Err(err), command_env, &mut io::stdout());
+            }
+        }
+    }
+}
+
+// when the interactive shell exits the new entries are appended to the file
+// with histappend, otherwise the file is replaced with the history
+fn save_history(command_env: &mut CommandEnv) {
+    if !command_env.keeps_history() {
+        return;
+    }
+    if let Some(path) = history_file(command_env) {
+        let mut history = command_env.history.borrow_mut();
+        let result = match command_env.options.get("histappend") {
+            true => history.append(&path),
+            false => history.write(&path),
+        };
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+// history [n] lists the last n entries, history -c clears them; -a appends
+// the new ones to the history file, -n reads the ones other sessions added
+// to it, -r reads the whole file and -w replaces it with the history
+fn history(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    const USAGE: &str = "history: usage: history [n] | -c | -a | -n | -r | -w [file]";
+
+    let limit = history_size(&command_env.vars);
+    // the file given after the option or HISTFILE
+    let file = command_tokens
+        .get(2)
+        .map(|path| osstr::from_os(command_env.vars.path(path).as_os_str()))
+        .or_else(|| history_file(command_env))
+        .ok_or_else(|| String::from("history: HISTFILE is empty"));
+    let mut history = command_env.history.borrow_mut();
+    let count = match command_tokens[1..] {
+        ["-c"] => {
+            history.clear();
+            return Ok(Command::Status(0));
+        }
+        ["-a"] | ["-a", _] => return history.append(&file?).map(|_| Command::Status(0)),
+        ["-n"] | ["-n", _] => return history.read_new(&file?, limit).map(|_| Command::Status(0)),
+        ["-r"] | ["-r", _] => return history.read(&file?, limit).map(|_| Command::Status(0)),
+        ["-w"] | ["-w", _] => return history.write(&file?).map(|_| Command::Status(0)),
+        [] => history.entries().len(),
+        [count] if !count.starts_with('-') => count
+            .parse()
+            .map_err(|_| format!("history: {}: numeric argument required", count))?,
+        _ => return Err(String::from(USAGE)),
+    };
+
+    let entries = history.entries();
+    let lines: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .skip(entries.len().saturating_sub(count))
+        .map(|(index, entry)| format!("{:5}  {}", index + 1, entry.replace('\n', "\n       ")))
+        .collect();
+    Ok(match lines.is_empty() {
+        true => Command::Status(0),
+        false => Command::Echo(lines.join("\n")),
+    })
+}
+
+// record start [-o] file, record stop, record show file and
+// record replay [-t] file: commands entered while recording are saved with
+// their time, duration, status and with -o their output; replay runs them
+// again, with -t keeping the pauses between them
+fn record(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    const USAGE: &str =
+        "record: usage: record start [-o] <file> | stop | show <file> | replay [-t] <file>";
+
+    // the file is in the working directory of the shell
+    let in_cwd = |path: &str| osstr::from_os(command_env.vars.path(path).as_os_str());
+    match command_tokens[1..] {
+        ["start", path] | ["start", "-o", path] => {
+            let path = &in_cwd(path);
+            if let Some(recorder) = &command_env.recorder {
+                return Err(format!("record: already recording to {}", recorder.path));
+            }
+            let output = command_tokens.len() == 4;
+            command_env.recorder = Some(record::Recorder::start(path, output)?);
+            Ok(Command::Status(0))
+        }
+        ["stop"] => match command_env.recorder.take() {
+            Some(_) => Ok(Command::Status(0)),
+            None => Err(String::from("record: not recording")),
+        },
+        ["show", path] => {
+            let text = record::show(&record::load(&in_cwd(path))?);
+            Ok(Command::Echo(String::from(text.trim_end_matches('\n'))))
+        }
+        ["replay", path] | ["replay", "-t", path] => {
+            let timed = command_tokens.len() == 4;
+            let entries = record::load(&in_cwd(path))?;
+            let mut previous: Option<f64> = None;
+            for entry in &entries {
+                if let (true, Some(previous)) = (timed, previous) {
+                    thread::sleep(Duration::from_secs_f64((entry.time - previous).max(0.0)));
+                }
+                previous = Some(entry.time);
+
+                let shown = format!("$ {}\n", entry.command.replace('\n', "\n> "));
+                print_text(shown, false, command_env);
+                if let Flow::Exit(code) = handle_input(&format!("{}\n", entry.command), command_env)
+                {
+                    return Ok(Command::Exit(code));
+                }
+            }
+            Ok(Command::Status(command_env.vars.status))
+        }
+        _ => Err(String::from(USAGE)),
+    }
+}
+
+// status of ((...)) and let is 0 if the value isn't 0
+fn arithmetic_status(value: i64) -> Command {
+    Command::Status(if value != 0 { 0 } else { 1 })
+}
+
+// read [-r] [-a array] [-d delim] [-p prompt] [name...] reads a line of the
+// standard input and splits it by IFS into the variables, REPLY by default;
+// without -r backslash escapes the next character and joins lines
+fn read(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    let mut raw = false;
+    let mut array = None;
+    let mut delimiter = b'\n';
+    let mut prompt = None;
+    let mut args = command_tokens[1..].iter();
+    let mut names = vec![];
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| {
+            args.next()
+                .copied()
+                .ok_or_else(|| format!("read: {}: option requires an argument", option))
+        };
+        match *arg {
+            "-r" => raw = true,
+            "-a" => array = Some(value("-a")?),
+            // empty delimiter is NUL like in bash
+            "-d" => delimiter = value("-d")?.bytes().next().unwrap_or(0),
+            "-p" => prompt = Some(value("-p")?),
+            flag if flag.starts_with('-') && flag.len() > 1 && names.is_empty() => {
+                return Err(format!("read: {}: invalid option", flag))
+            }
+            name if vars::is_name(name) => names.push(name),
+            name => return Err(format!("read: `{}': not a valid identifier", name)),
+        }
+    }
+    for name in array.iter().chain(&names) {
+        command_env.vars.check_writable(name)?;
+    }
+
+    // the prompt is shown only when the input is the terminal
+    if let Some(prompt) = prompt {
+        if !command_env.stdin && io::stdin().is_terminal() {
+            print_text(String::from(prompt), true, command_env);
+        }
+    }
+
+    // redirected input is read byte by byte, so the rest of it stays unread
+    let mut file = match command_env.stdin {
+        true => Some(redirect::stdin()?),
+        false => None,
+    };
+    let mut read_record = |record: &mut Vec<u8>| -> io::Result<bool> {
+        match file.as_mut() {
+            Some(file) => {
+                let mut byte = [0];
+                loop {
+                    if io::Read::read(file, &mut byte)? == 0 {
+                        return Ok(false);
+                    }
+                    if byte[0] == delimiter {
+                        return Ok(true);
+                    }
+                    record.push(byte[0]);
+                }
+            }
+            None => {
+                io::stdin().lock().read_until(delimiter, record)?;
+                let complete = record.last() == Some(&delimiter);
+                if complete {
+                    record.pop();
+                }
+                Ok(complete)
+            }
+        }
+    };
+
+    let mut chars: Vec<(char, bool)> = vec![];
+    let complete = loop {
+        let mut record = vec![];
+        let complete = read_record(&mut record).map_err(|err| format!("read: {}", err))?;
+        let text = osstr::from_bytes(&record);
+        if raw {
+            chars.extend(text.chars().map(|c| (c, false)));
+            break complete;
+        }
+
+        let mut escaped = false;
+        for c in text.chars() {
+            match (c, escaped) {
+                ('\\', false) => escaped = true,
+                (c, escaped_now) => {
+                    chars.push((c, escaped_now));
+                    escaped = false;
+                }
+            }
+        }
+        // backslash at the end joins the next line
+        if !(escaped && complete) {
+            break complete;
+        }
+    };
+
+    let ifs = expand::ifs(&command_env.vars);
+    if let Some(array) = array {
+        let fields = expand::split_read(&chars, &ifs, None);
+        command_env.vars.set_array(array, fields);
+    } else if names.is_empty() {
+        let line: String = chars.iter().map(|(c, _)| c).collect();
+        command_env.vars.set("REPLY", &line)?;
+    } else {
+        let mut fields = expand::split_read(&chars, &ifs, Some(names.len())).into_iter();
+        for name in names {
+            command_env
+                .vars
+                .set(name, &fields.next().unwrap_or_default())?;
+        }
+    }
+
+    // the status tells that the input ended before the delimiter
+    Ok(Command::Status(if complete { 0 } else { 1 }))
+}
+
+// mapfile [-t] [-n count] [-s count] [-d delim] [array] reads lines of the
+// standard input into the array, MAPFILE by default
+fn mapfile(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    let command_name = command_tokens[0];
+    let number = |option: &str, value: Option<&&str>| match value.map(|value| value.parse()) {
+        Some(Ok(number)) => Ok(number),
+        Some(Err(_)) => Err(format!(
+            "{}: {}: invalid number",
+            command_name,
+            value.unwrap()
+        )),
+        None => Err(format!(
+            "{}: {}: option requires an argument",
+            command_name, option
+        )),
+    };
+
+    let mut trim = false;
+    // 0 means all the lines
+    let mut count = 0;
+    let mut skip = 0;
+    let mut delimiter = b'\n';
+    let mut name = "MAPFILE";
+    let mut args = command_tokens[1..].iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "-t" => trim = true,
+            "-n" => count = number("-n", args.next())?,
+            "-s" => skip = number("-s", args.next())?,
+            // empty delimiter is NUL like in bash
+            "-d" => match args.next() {
+                Some(value) => delimiter = value.bytes().next().unwrap_or(0),
+                None => return Err(format!("{}: -d: option requires an argument", command_name)),
+            },
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("{}: {}: invalid option", command_name, flag))
+            }
+            _ if vars::is_name(arg) && args.len() == 0 => name = arg,
+            _ => {
+                return Err(format!(
+                    "{}: `{}': not a valid identifier",
+                    command_name, arg
+                ))
+            }
+        }
+    }
+    command_env.vars.check_writable(name)?;
+
+    let mut reader: Box<dyn BufRead> = match command_env.stdin {
+        true => Box::new(io::BufReader::new(redirect::stdin()?)),
+        false => Box::new(io::stdin().lock()),
+    };
+    let mut lines = vec![];
+    let mut line = vec![];
+    let mut read = 0;
+    while count == 0 || lines.len() < count {
+        line.clear();
+        match reader.read_until(delimiter, &mut line) {
+            Ok(0) => break,
+            Ok(_) => read += 1,
+            Err(err) => return Err(format!("{}: {}", command_name, err)),
+        }
+        if read <= skip {
+            continue;
+        }
+        if trim && line.last() == Some(&delimiter) {
+            line.pop();
+        }
+        lines.push(osstr::from_bytes(&line));
+    }
+    command_env.vars.set_array(name, lines);
+
+    Ok(Command::Assign)
+}
+
+// foreach [-d delim] [-n count] [--] command [args...] runs the command with
+// the items of the input as its last arguments, one item or up to count at a
+// time. Items end with NUL unless -d gives another delimiter, they are never
+// split or expanded, so any file name is passed as it is. The shell has no
+// pipelines, so the items come from a redirection or the input of the shell:
+// find . -print0 > list; foreach rm -- < list. The status is the one of the
+// last failed command, 0 if all of them succeeded
+fn foreach(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    const USAGE: &str = "foreach: usage: foreach [-d delim] [-n count] [--] command [args...]";
+
+    let mut delimiter = 0;
+    let mut count = 1;
+    let mut args = &command_tokens[1..];
+    loop {
+        match args {
+            ["-d", value, rest @ ..] => {
+                delimiter = value.bytes().next().unwrap_or(0);
+                args = rest;
+            }
+            ["-n", value, rest @ ..] => {
+                count = match value.parse() {
+                    Ok(count) if count > 0 => count,
+                    _ => return Err(format!("foreach: {}: invalid count", value)),
+                };
+                args = rest;
+            }
+            ["--", rest @ ..] => {
+                args = rest;
+                break;
+            }
+            [flag, ..] if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("foreach: {}: invalid option", flag))
+            }
+            _ => break,
+        }
+    }
+    if args.is_empty() {
+        return Err(String::from(USAGE));
+    }
+
+    // the whole input is read first, so the commands don't get its rest
+    let mut reader: Box<dyn BufRead> = match command_env.stdin {
+        true => Box::new(io::BufReader::new(redirect::stdin()?)),
+        false => Box::new(io::stdin().lock()),
+    };
+    let mut items = vec![];
+    let mut item = vec![];
+    loop {
+        item.clear();
+        match reader.read_until(delimiter, &mut item) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => return Err(format!("foreach: {}", err)),
+        }
+        if item.last() == Some(&delimiter) {
+            item.pop();
+        }
+        items.push(osstr::from_bytes(&item));
+    }
+    drop(reader);
+
+    let mut status = 0;
+    for batch in items.chunks(count) {
+        if command_env.cancel.is_cancelled() {
+            break;
+        }
+        let mut words: Vec<&str> = args.to_vec();
+        words.extend(batch.iter().map(|item| &item[..]));
+        let result = run_tokens(&words, command_env, false);
+        if let Flow::Exit(code) = flow(result, command_env, &mut io::stdout()) {
+            return Ok(Command::Exit(code));
+        }
+        if command_env.vars.status != 0 {
+            status = command_env.vars.status;
+        }
+    }
+
+    Ok(Command::Status(status))
+}
+
+// [1] 1234 line of the started job is printed only by the interactive shell
+fn job_started(job: String, command_env: &CommandEnv) -> Command {
+    match command_env.interactive {
+        true => Command::Job(job),
+        false => Command::Status(0),
+    }
+}
+
+// run system program in background, the shell doesn't wait for it
+fn run_background(
+    command_tokens: &[&str],
+    command_env: &mut CommandEnv,
+) -> Result<Command, String> {
+    let command_name = command_tokens[0].trim();
+    match command_env.find_program(command_name) {
+        Ok(Some(path)) => {
+            let job = command_env.jobs.spawn(
+                command_env.program(&path),
+                &command_tokens[1..],
+                &command_tokens.join(" "),
+                match command_env.stdin {
+                    true => Some(redirect::stdin()?),
+                    false => None,
+                },
+            )?;
+            Ok(job_started(job, command_env))
+        }
+        Ok(None) => Ok(Command::Run(
+            String::new(),
+            format!("{}: not found\n", command_name),
+        )),
+        Err(err) => Err(err),
+    }
+}
+
+// declare [-aAirx] [+irx] [-p] [<name>[=<value>]...] sets attributes and values
+// of the variables, without names or with -p prints definitions
+fn declare(command_tokens: &[&str], command_env: &mut CommandEnv) -> Result<Command, String> {
+    let mut enabled = String::new();
+    let mut disabled = String::new();
+    let mut names = vec![];
+    // -- ends the options, names after it may start with - or +
+    let mut options = true;
+    for arg in &command_tokens[1..] {
+        match arg.chars().next() {
+            _ if options && *arg == "--" => options = false,
+            Some(sign @ ('-' | '+')) if options && arg.len() > 1 => {
+                for flag in arg[1..].chars() {
+                    if !"aAiprx".contains(flag) {
+                        return Err(format!("declare: {}{}: invalid option", sign, flag));
+                    }
+                    match sign {
+                        '-' => enabled.push(flag),
+                        _ => disabled.push(flag),
+                    }
+                }
+            }
+            _ => {
+                options = false;
+                names.push(*arg);
+            }
+        }
+    }
+    if enabled.contains('a') && enabled.contains('A') {
+        return Err(String::from("declare: -a and -A can't be used together"));
+    }
+
+    let vars = &mut command_env.vars;
+    if names.is_empty() {
+        // only variables with all the given attributes are listed
+        let listed = |name: &String| {
+            let attributes = vars.attributes(name);
+            enabled.chars().all(|flag| match flag {
+                'a' => vars.is_indexed(name),
+                'A' => vars.is_assoc(name),
+                'i' => attributes.integer,
+                'r' => attributes.readonly,
+                'x' => vars.is_exported(name),
+                _ => true,
+            })
+        };
+        let declarations: Vec<String> = vars
+            .names()
+            .iter()
+            .filter(|name| listed(name))
+            .filter_map(|name| vars.declaration(name))
+            .collect();
+        return Ok(Command::Set(declarations.join("\n")));
+    }
+
+    if enabled.contains('p') {
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        for name in names {
+            match vars.declaration(name) {
+                Some(declaration) => stdout.push_str(&format!("{}\n", declaration)),
+                None => stderr.push_str(&format!("declare: {}: not found\n", name)),
+            }
+        }
+        vars.status = if stderr.is_empty() { 0 } else { 1 };
+        return Ok(Command::Run(stdout, stderr));
+    }
+
+    for arg in names {
+        let assignment = vars::assignment(arg);
+        let name = assignment.map_or(arg, |(name, _, _)| name);
+        if !vars::is_name(name) {
+            return Err(format!("declare: `{}': not a valid identifier", arg));
+        }
+
+        let mut attributes = command_env.vars.attributes(name);
+        if attributes.readonly && (disabled.contains('r') || disabled.contains('i')) {
+            return Err(format!("declare: {}: readonly variable", name));
+        }
+        if enabled.contains('A') {
+            command_env.vars.declare_assoc(name)?;
+        }
+        if enabled.contains('a') {
+            command_env.vars.declare_indexed(name)?;
+        }
+        attributes.integer =
+            (attributes.integer || enabled.contains('i')) && !disabled.contains('i');
+        command_env.vars.set_attributes(name, attributes);
+
+        if assignment.is_some() {
+            vars::assign(arg, &mut command_env.vars, &command_env.options)?;
+        }
+        if enabled.contains('x') {
+            command_env.vars.export(name)?;
+        }
+        if disabled.contains('x') {
+            command_env.vars.unexport(name);
+        }
+        // readonly is set after the assignment: declare -r NAME=value
+        if enabled.contains('r') {
+            attributes.readonly = true;
+            command_env.vars.set_attributes(name, attributes);
+        }
+    }
+
+    Ok(Command::Set(String::new()))
+}
+
+// number of loops for break and continue, 1 by default
+fn loop_count(name: &str, command_tokens: &[&str]) -> Result<usize, String> {
+    match command_tokens[1..] {
+        [] => Ok(1),
+        [count] => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(format!("{}: {}: loop count out of range", name, count)),
+        },
+        _ => Err(format!("invalid {} command: {} [<count>]", name, name)),
+    }
+}
+
+// run the command line and print output of its commands
+fn handle_input(input: &str, command_env: &mut CommandEnv) -> Flow {
+    match compound::parse(input) {
+        // break outside of loops is ignored
+        Ok(items) => match run_items(&items, command_env) {
+            Flow::Exit(code) => Flow::Exit(code),
+            _ => Flow::Next,
+        },
+        Err(err) => syntax_error(err, command_env),
+    }
+}
+
+// the status of a syntax error is 2 like in bash, a non-interactive shell exits
+fn syntax_error(err: String, command_env: &mut CommandEnv) -> Flow {
+    print_output(Err(err), command_env, &mut io::stdout());
+    command_env.vars.status = 2;
+    match command_env.interactive {
+        true => Flow::Next,
+        false => Flow::Exit(2),
+    }
+}
+
+fn is_syntax_error(err: &str) -> bool {
+    err.starts_with("syntax error")
+}
+
+fn run_items(items: &[compound::Item], command_env: &mut CommandEnv) -> Flow {
+    for item in items {
+        if command_env.cancel.is_cancelled() {
+            break;
+        }
+        let flow = run_item(item, command_env, true);
+        if !matches!(flow, Flow::Next) {
+            return flow;
+        }
+    }
+
+    Flow::Next
+}
+
+// failures of checked commands exit the shell with errexit option, commands
+// on the left of && and || are not checked
+fn run_item(item: &compound::Item, command_env: &mut CommandEnv, checked: bool) -> Flow {
+    match item {
+        compound::Item::Simple(tokens, background) => {
+            let flow = run_command(tokens.clone(), *background, command_env);
+            let failed = command_env.vars.status != 0 && !command_env.cancel.is_cancelled();
+            // exit of a sourced file is already an exit
+            let exited = matches!(flow, Flow::Exit(_));
+            if checked && failed && !exited && command_env.options.get("errexit") {
+                return exit_on_error(command_env);
+            }
+            flow
+        }
+        compound::Item::And(first, second) => {
+            let flow = run_item(first, command_env, false);
+            match command_env.vars.status {
+                0 if matches!(flow, Flow::Next) => run_item(second, command_env, checked),
+                _ => flow,
+            }
+        }
+        compound::Item::Or(first, second) => {
+            let flow = run_item(first, command_env, false);
+            match command_env.vars.status {
+                0 => flow,
+                _ if matches!(flow, Flow::Next) => run_item(second, command_env, checked),
+                _ => flow,
+            }
+        }
+        compound::Item::Select { name, words, body } => {
+            run_select(name, words.as_deref(), body, command_env)
+        }
+        compound::Item::For { name, words, body } => {
+            run_for(name, words.as_deref(), body, command_env)
+        }
+        // negated commands don't exit the shell with errexit option
+        compound::Item::Not(inner) => {
+            let flow = run_item(inner, command_env, false);
+            command_env.vars.status = match command_env.vars.status {
+                0 => 1,
+                _ => 0,
+            };
+            flow
+        }
+        compound::Item::Coproc { name, commands } => {
+            let result = run_coproc(name, commands, command_env);
+            flow(result, command_env, &mut io::stdout())
+        }
+    }
+}
+
+// start the coprocess: NAME gets the descriptors to read its output from and
+// to write its input to, NAME_PID gets its pid. A single program is run
+// directly, other commands are run by a child shell
+fn run_coproc(
+    name: &str,
+    commands: &[(Vec<lexer::Token>, bool)],
+    command_env: &mut CommandEnv,
+) -> Result<Command, String> {
+    command_env.vars.check_writable(name)?;
+    let text = commands
+        .iter()
+        .map(|(tokens, background)| {
+            let command: Vec<&str> = tokens
+                .iter()
+                .map(|token| match token {
+                    lexer::Token::Word(word) | lexer::Token::Op(word) => &word[..],
+                })
+                .collect();
+            format!(
+                "{}{}",
+                command.join(" "),
+                if *background { " &" } else { "" }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    if command_env.options.get("dryrun") {
+        command_env.vars.status = 0;
+        return Ok(Command::Run(
+            format!("coproc {} {}\n", name, text),
+            String::new(),
+        ));
+    }
+
+    let mut program = None;
+    if let [(tokens, _)] = commands {
+        // redirections need the child shell
+        let (words, redirects) = parse_command(tokens.clone(), command_env)?;
+        if let (Words::Command(words), true) = (words, redirects.is_empty()) {
+            if let Some(name) = words
+                .first()
+                .filter(|name| command_env.find(name).is_none())
+            {
+                program = command_env
+                    .find_program(name)?
+                    .map(|path| (command_env.program(&path), words.clone()));
+            }
+        }
+    }
+    let (program, args) = match program {
+        Some((program, words)) => (program, words[1..].to_vec()),
+        None => {
+            let shell = env::current_exe()
+                .map_err(|err| format!("coproc: failed to find the shell: {}", err))?;
+            let mut program = process::Command::new(shell);
+            command_env.vars.prepare(&mut program);
+            (program, vec![String::from("-c"), text.clone()])
+        }
+    };
+
+    let args: Vec<&str> = args.iter().map(|arg| &arg[..]).collect();
+    let (job, pid, read, write) = command_env.jobs.coproc(program, &args, &text)?;
+    command_env
+        .vars
+        .set_array(name, vec![read.to_string(), write.to_string()]);
+    command_env
+        .vars
+        .set(&format!("{}_PID", name), &pid.to_string())?;
+
+    Ok(job_started(job, command_env))
+}
+
+// exit because of errexit, inside sourced files print where it happened:
+//   errexit: exit status 1
+//     at lib.sh:3 (source)
+//     at script.sh:10 (main)
+fn exit_on_error(command_env: &mut CommandEnv) -> Flow {
+    let status = command_env.vars.status;
+    if command_env.frames.len() > 1 {
+        let mut trace = format!("errexit: exit status {}\n", status);
+        for frame in command_env.frames.iter().rev() {
+            trace.push_str(&format!(
+                "  at {}:{} ({})\n",
+                frame.file, frame.line, frame.name
+            ));
+        }
+        print_text(trace, true, command_env);
+    }
+    Flow::Exit(status)
+}
+
+// flow after the body of the loop, None means the loop is finished
+fn loop_flow(flow: Flow) -> Option<Flow> {
+    match flow {
+        Flow::Next | Flow::Continue(1) => Some(Flow::Next),
+        Flow::Break(1) => None,
+        Flow::Break(count) => Some(Flow::Break(count - 1)),
+        Flow::Continue(count) => Some(Flow::Continue(count - 1)),
+        Flow::Exit(code) => Some(Flow::Exit(code)),
+    }
+}
+
+// words of select and for loops, "$@" without in
+fn loop_words(
+    words: Option<&[lexer::Token]>,
+    command_env: &mut CommandEnv,
+) -> Result<Vec<String>, String> {
+    match words {
+        Some(words) => match parse_command(words.to_vec(), command_env)? {
+            (Words::Command(words), _) => Ok(words),
+            _ => Ok(vec![]),
+        },
+        None => Ok(command_env.vars.positional.clone()),
+    }
+}
+
+// run the body for each word stored in the variable, the status is the one of
+// the last command of the body, 0 without words
+fn run_for(
+    name: &str,
+    words: Option<&[lexer::Token]>,
+    body: &[compound::Item],
+    command_env: &mut CommandEnv,
+) -> Flow {
+    let words = match loop_words(words, command_env) {
+        Ok(words) => words,
+        Err(err) => return flow(Err(err), command_env, &mut io::stdout()),
+    };
+
+    command_env.vars.status = 0;
+    for word in words {
+        if command_env.cancel.is_cancelled() {
+            break;
+        }
+        if let Err(err) = command_env.vars.set(name, &word) {
+            return flow(Err(err), command_env, &mut io::stdout());
+        }
+        match loop_flow(run_items(body, command_env)) {
+            Some(Flow::Next) => {}
+            Some(flow) => return flow,
+            None => return Flow::Next,
+        }
+    }
+    Flow::Next
+}
+
+fn print_menu(words: &[String], command_env: &mut CommandEnv) {
+    let menu: String = words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| format!("{}) {}\n", index + 1, word))
+        .collect();
+    print_text(menu, true, command_env);
+}
+
+// print numbered menu of the words on stderr and run the body for each choice
+// read from stdin until break or end of input, the line is stored in REPLY and
+// the chosen word in the variable (empty if the choice is invalid)
+fn run_select(
+    name: &str,
+    words: Option<&[lexer::Token]>,
+    body: &[compound::Item],
+    command_env: &mut CommandEnv,
+) -> Flow {
+    let words = match loop_words(words, command_env) {
+        Ok(words) => words,
+        Err(err) => return flow(Err(err), command_env, &mut io::stdout()),
+    };
+    if words.is_empty() {
+        return Flow::Next;
+    }
+
+    print_menu(&words, command_env);
+    while !command_env.cancel.is_cancelled() {
+        let prompt = command_env
+            .vars
+            .get("PS3")
+            .unwrap_or_else(|| String::from("#? "));
+        print_text(prompt, true, command_env);
+
+        let mut reply = String::new();
+        match io::stdin().read_line(&mut reply) {
+            Ok(0) | Err(_) => {
+                print_text(String::from("\n"), true, command_env);
+                return Flow::Next;
+            }
+            Ok(_) => {}
+        }
+        let reply = reply.trim_end_matches(['\n', '\r']);
+        if let Err(err) = command_env.vars.set("REPLY", reply) {
+            print_output(Err(err), command_env, &mut io::stdout());
+            return Flow::Next;
+        }
+        // empty line prints the menu again
+        if reply.trim().is_empty() {
+            print_menu(&words, command_env);
+            continue;
+        }
+
+        let choice = reply
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|choice| words.get(choice.wrapping_sub(1)))
+            .cloned()
+            .unwrap_or_default();
+        if let Err(err) = command_env.vars.set(name, &choice) {
+            print_output(Err(err), command_env, &mut io::stdout());
+            return Flow::Next;
+        }
+
+        match loop_flow(run_items(body, command_env)) {
+            Some(Flow::Next) => {}
+            Some(flow) => return flow,
+            None => return Flow::Next,
+        }
+    }
+    Flow::Next
+}
+
+fn run_command(tokens: Vec<lexer::Token>, background: bool, command_env: &mut CommandEnv) -> Flow {
+    // the rest of a && or || list
+    if command_env.cancel.is_cancelled() {
+        return Flow::Next;
+    }
+    let mut stdout = io::stdout();
+    if matches!(tokens.first(), Some(lexer::Token::Word(word)) if word == "[[") {
+        let result = conditional::evaluate(&tokens, &mut command_env.vars, &command_env.options);
+        let result = match result {
+            Ok(true) => Ok(Command::Status(0)),
+            Ok(false) => Ok(Command::Status(1)),
+            Err(err) if is_syntax_error(&err) => Err(err),
+            Err(err) => {
+                print_text(format!("{}\n", err), true, command_env);
+                Ok(Command::Status(2))
+            }
+        };
+        return flow(result, command_env, &mut stdout);
+    }
+    if let [lexer::Token::Word(word)] = &tokens[..] {
+        if let Some(expression) = word
+            .strip_prefix("((")
+            .and_then(|word| word.strip_suffix("))"))
+        {
+            let result =
+                expand::arithmetic(expression, &mut command_env.vars).map(arithmetic_status);
+            return flow(result, command_env, &mut stdout);
+        }
+    }
+
+    let (words, redirects) = match parse_command(tokens, command_env) {
+        Ok(parsed) => parsed,
+        Err(err) => return flow(Err(err), command_env, &mut stdout),
+    };
+
+    // with dryrun option the redirections of printed commands are not applied,
+    // they would create the files
+    if let Words::Command(words) = &words {
+        if words.first().is_some_and(|name| dry(name, command_env)) {
+            let words: Vec<&str> = words.iter().map(|word| &word[..]).collect();
+            let result = dry_run(&words, &redirects, background, command_env);
+            return flow(Ok(result), command_env, &mut stdout);
+        }
+    }
+
+    // output of builtins goes to the redirected descriptors as well, so the
+    // pending output must be written before they change
+    let _ = stdout.flush();
+    let noclobber = command_env.options.get("noclobber");
+    let saved = match redirect::apply(&redirects, &mut command_env.vars, noclobber) {
+        Ok(saved) => saved,
+        Err(err) => return flow(Err(err), command_env, &mut stdout),
+    };
+    // exec without a command keeps its redirections
+    let keep = matches!(&words, Words::Command(words) if words.len() == 1 && words[0] == "exec");
+
+    command_env.stdin = redirects.iter().any(|redirect| redirect.is_stdin());
+    command_env.routes = redirect::routes(&redirects);
+    let result = run_words(words, command_env, background);
+    command_env.stdin = false;
+    let next = flow(result, command_env, &mut stdout);
+    command_env.routes = redirect::routes(&[]);
+    if keep {
+        saved.keep();
+    } else {
+        saved.restore();
+    }
+    next
+}
+
+// print the result of the command and remember its exit status, programs
+// store their status themselves; fatal expansion errors exit a
+// non-interactive shell
+fn flow(
+    result: Result<Command, String>,
+    command_env: &mut CommandEnv,
+    out: &mut dyn Write,
+) -> Flow {
+    if std::mem::take(&mut command_env.vars.fatal) && !command_env.interactive {
+        print_output(result, command_env, out);
+        command_env.vars.status = 1;
+        return Flow::Exit(1);
+    }
+    match result {
+        Err(err) if is_syntax_error(&err) => return syntax_error(err, command_env),
+        Ok(Command::Run(..)) => {}
+        Ok(Command::Status(status) | Command::Exit(status)) => command_env.vars.status = status,
+        Ok(_) => command_env.vars.status = 0,
+        Err(_) => command_env.vars.status = 1,
+    }
+
+    match result {
+        Ok(Command::Break(count)) => Flow::Break(count),
+        Ok(Command::Continue(count)) => Flow::Continue(count),
+        Ok(Command::Exit(code)) => Flow::Exit(code),
+        result => {
+            print_output(result, command_env, out);
+            Flow::Next
+        }
+    }
+}
+
+// the result goes to the writers of the embedding program unless the
+// descriptor is redirected elsewhere, and to the log of record -o
+fn print_output(
+    result: Result<Command, String>,
+    command_env: &mut CommandEnv,
+    out: &mut dyn Write,
+) {
+    let mut stderr = io::stderr();
+    let handles = match command_env.handles.take() {
+        Some(handles) => RefCell::new(handles),
+        None => {
+            match &mut command_env.recorder {
+                Some(recorder) if recorder.output => {
+                    print_result(result, &mut recorder.tee(out), &mut stderr)
+                }
+                _ => print_result(result, out, &mut stderr),
+            }
+            return;
+        }
+    };
+    // with 2>&1 both descriptors go to the same writer
+    let [out_route, err_route] = command_env.routes;
+    let mut routed_out = Routed {
+        handles: &handles,
+        error: out_route == redirect::Route::Stderr,
+    };
+    let mut routed_err = Routed {
+        handles: &handles,
+        error: err_route == redirect::Route::Stderr,
+    };
+    let out: &mut dyn Write = match out_route {
+        redirect::Route::Fd => out,
+        _ => &mut routed_out,
+    };
+    let err: &mut dyn Write = match err_route {
+        redirect::Route::Fd => &mut stderr,
+        _ => &mut routed_err,
+    };
+    match &mut command_env.recorder {
+        Some(recorder) if recorder.output => print_result(result, &mut recorder.tee(out), err),
+        _ => print_result(result, out, err),
+    }
+    command_env.handles = Some(handles.into_inner());
+}
+
+// one of the writers of the embedding program
+struct Routed<'a> {
+    handles: &'a RefCell<Handles>,
+    error: bool,
+}
+
+impl Write for Routed<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut handles = self.handles.borrow_mut();
+        match self.error {
+            true => handles.stderr.write(buf),
+            false => handles.stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut handles = self.handles.borrow_mut();
+        match self.error {
+            true => handles.stderr.flush(),
+            false => handles.stdout.flush(),
+        }
+    }
+}
+
+// text printed by the shell itself goes where the output or the errors of
+// the command do, so Shell::eval captures it as well
+fn print_text(text: String, error: bool, command_env: &mut CommandEnv) {
+    let result = match error {
+        true => Command::Run(String::new(), text),
+        false => Command::Run(text, String::new()),
+    };
+    print_output(Ok(result), command_env, &mut io::stdout());
+}
+
+// part of the output of a program, it goes where the output of the command does
+fn print_chunk(bytes: &[u8], error: bool, command_env: &mut CommandEnv) {
+    print_text(osstr::from_bytes(bytes), error, command_env);
+}
+
+enum Words {
+    Command(Vec<String>),
+    // NAME=value words without a command, not expanded yet
+    Assignments(Vec<String>),
+}
+
+// expand words of the command and its redirections
+fn parse_command(
+    tokens: Vec<lexer::Token>,
+    command_env: &mut CommandEnv,
+) -> Result<(Words, Vec<redirect::Redirect>), String> {
+    let (words, redirect) = redirect::parse(tokens)?;
+    let redirect = redirect
+        .into_iter()
+        .map(|redirect| redirect.expand(&mut command_env.vars))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // values of assignments are neither split nor globbed
+    if words.iter().all(|word| vars::assignment(word).is_some()) {
+        return Ok((Words::Assignments(words), redirect));
+    }
+
+    // declaration builtins get their assignment arguments as is and assign them
+    let declaration = matches!(
+        words.first().map(|word| &word[..]),
+        Some("declare" | "typeset" | "readonly")
+    );
+    let mut fields = vec![];
+    for word in &words {
+        match vars::assignment(word) {
+            Some(_) if declaration => fields.push(expand::Field::literal(word)),
+            _ => fields.extend(expand::expand_word(word, &mut command_env.vars)?),
+        }
+    }
+    let words = expand::expand_patterns(fields, &command_env.vars.cwd, &command_env.options)?;
+
+    Ok((Words::Command(words), redirect))
+}
+
+fn run_words(
+    words: Words,
+    command_env: &mut CommandEnv,
+    background: bool,
+) -> Result<Command, String> {
+    let words = match words {
+        Words::Command(words) => words,
+        Words::Assignments(assignments) => {
+            for word in assignments {
+                vars::assign(&word, &mut command_env.vars, &command_env.options)?;
+            }
+            return Ok(Command::Assign);
+        }
+    };
+
+    let command_tokens: Vec<&str> = words.iter().map(|word| &word[..]).collect();
+    run_tokens(&command_tokens, command_env, background)
+}
+
+// builtins which act outside of the shell are printed like programs with
+// dryrun option
+const DRY_BUILTINS: [&str; 3] = ["exec", "record", "suspend"];
+
+// commands which are not found still fail like without the option
+fn dry(name: &str, command_env: &CommandEnv) -> bool {
+    command_env.options.get("dryrun")
+        && match command_env.find(name) {
+            Some(_) => DRY_BUILTINS.contains(&name),
+            None => matches!(command_env.find_program(name), Ok(Some(_))),
+        }
+}
+
+// the command which isn't run with dryrun option, its words are quoted so the
+// shell reads them back the same
+fn dry_run(
+    command_tokens: &[&str],
+    redirects: &[redirect::Redirect],
+    background: bool,
+    command_env: &mut CommandEnv,
+) -> Command {
+    let mut words: Vec<String> = command_tokens
+        .iter()
+        .map(|word| printf::quote(word))
+        .collect();
+    words.extend(redirects.iter().map(|redirect| redirect.text()));
+    if background {
+        words.push(String::from("&"));
+    }
+    command_env.vars.status = 0;
+    Command::Run(format!("{}\n", words.join(" ")), String::new())
+}
+
+fn run_tokens(
+    command_tokens: &[&str],
+    command_env: &mut CommandEnv,
+    background: bool,
+) -> Result<Command, String> {
+    if !command_tokens.is_empty() {
+        // builtins like lowprio and foreach run their commands here
+        if dry(command_tokens[0], command_env) {
+            return Ok(dry_run(command_tokens, &[], background, command_env));
+        }
+        match command_env.find(command_tokens[0]) {
+            Some(cmdfn) => cmdfn(command_tokens, command_env),
+            None if background => run_background(command_tokens, command_env),
+            None => {
+                // try to run find command in system folder (using PATH) and run it
+                let command_run = command_env.find(RUN_INTERNAL).unwrap();
+                command_run(command_tokens, command_env)
+            }
+        }
+    } else {
+        Err(String::from("command not specified"))
+    }
+}
+
+// bytes kept from file names and program output are written back as they were
+fn write_text(out: &mut dyn Write, text: &str) -> io::Result<()> {
+    out.write_all(&osstr::to_bytes(text))
+}
+
+fn print_result(result: Result<Command, String>, out: &mut dyn Write, err: &mut dyn Write) {
+    let written = match result {
+        Ok(command) => match command {
+            Command::Echo(output) => write_text(out, &format!("{}\n", output)),
+            Command::Type(command) | Command::Pwd(command) | Command::Job(command) => {
+                write_text(out, &format!("{}\n", command))
+            }
+            Command::Run(stdout, stderr) => {
+                let _ = write_text(err, &stderr);
+                write_text(out, &stdout)
+            }
+            Command::Set(output) | Command::Cd(output) => {
+                if !output.is_empty() {
+                    write_text(out, &format!("{}\n", output))
+                } else {
+                    Ok(())
+                }
+            }
+            Command::Repeat
+            | Command::Exit(_)
+            | Command::Disown
+            | Command::Assign
+            | Command::Status(_)
+            | Command::Break(_)
+            | Command::Continue(_) => Ok(()),
+        },
+        Err(desc) => write_text(err, &format!("{}\n", desc)).and_then(|_| err.flush()),
+    };
+
+    if let Err(err) = written.and_then(|_| out.flush()) {
+        eprintln!("failed to write output: {}", err);
+    }
+}
+
+fn run_line(input: &str, command_env: &mut CommandEnv) -> Flow {
+    let started = Instant::now();
+    let time = SystemTime::now();
+    // record start and record stop themselves are not saved
+    let recording = command_env.recorder.is_some();
+    let flow = handle_input(input, command_env);
+    let duration = started.elapsed();
+
+    if let (true, Some(recorder)) = (recording, &mut command_env.recorder) {
+        if let Err(err) = recorder.write(input, time, duration, command_env.vars.status) {
+            command_env.recorder = None;
+            print_output(Err(err), command_env, &mut io::stdout());
+        }
+    }
+    report_duration(duration, command_env);
+    flow
+}
+
+// run commands from PROMPT_COMMAND before the primary prompt, each element of
+// the array in turn; they affect neither $? nor CMD_DURATION of the last
+// command entered by user
+fn run_prompt_command(command_env: &mut CommandEnv) -> Option<i32> {
+    let status = command_env.vars.status;
+    for prompt_command in command_env.vars.elements("PROMPT_COMMAND") {
+        for line in prompt_command.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Flow::Exit(code) = handle_input(line, command_env) {
+                return Some(code);
+            }
+        }
+    }
+    command_env.vars.status = status;
+    None
+}
+
+// run commands line by line, the prompt is given to the reader only in
+// interactive mode; lines are read by the function, so stdin isn't locked
+// while commands run. The exit status is returned if the commands exited
+fn run_lines(
+    read_line: &mut dyn FnMut(&mut String, Option<&prompt::Prompt>) -> io::Result<usize>,
+    command_env: &mut CommandEnv,
+    interactive: bool,
+) -> Option<i32> {
+    let mut input = String::new();
+    // lines read so far and the first line of the current command
+    let mut lines = 0;
+    let mut start = 1;
+
+    while !command_env.cancel.is_cancelled() {
+        if input.is_empty() {
+            start = lines + 1;
+        }
+        let prompt = match interactive {
+            true if input.is_empty() => {
+                for line in command_env.jobs.finished() {
+                    println!("{}", line);
+                }
+                if let Some(code) = run_prompt_command(command_env) {
+                    return Some(code);
+                }
+                Some(prompt::primary(
+                    &mut command_env.vars,
+                    command_env.jobs.running(),
+                ))
+            }
+            // continuation of compound command
+            true => Some(prompt::continuation(&mut command_env.vars)),
+            false => None,
+        };
+
+        if interactive {
+            *command_env.completion.borrow_mut() = complete::Context {
+                path: command_env.vars.get("PATH"),
+                home: command_env.vars.get("HOME"),
+                cwd: command_env.vars.cwd.clone(),
+            };
+        }
+        match read_line(&mut input, prompt.as_ref()) {
+            Ok(0) => {
+                if interactive {
+                    println!();
+                }
+                if !input.trim().is_empty() {
+                    add_history(&input, command_env);
+                    count_commands(&input, command_env);
+                    set_line(start, command_env);
+                    if let Flow::Exit(code) = run_line(&input, command_env) {
+                        return Some(code);
+                    }
+                }
+                return None;
+            }
+            Ok(_) => {
+                lines += 1;
+                if compound::is_incomplete(&input) {
+                    continue;
+                }
+                if !input.trim().is_empty() {
+                    add_history(&input, command_env);
+                    count_commands(&input, command_env);
+                    set_line(start, command_env);
+                    if let Flow::Exit(code) = run_line(&input, command_env) {
+                        return Some(code);
+                    }
+                }
+            }
+            // Ctrl-C in the line editor drops the command being entered
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                eprintln!("failed to read input: {}", err);
+                return None;
+            }
+        }
+
+        input.clear();
+    }
+    None
+}
+
+// line of the running file shown by caller and the errexit trace
+fn set_line(line: usize, command_env: &mut CommandEnv) {
+    if let Some(frame) = command_env.frames.last_mut() {
+        frame.line = line;
+    }
+}
+
+// run commands from the file, missing startup files are silently skipped
+fn run_file(path: &PathBuf, command_env: &mut CommandEnv) -> Result<Option<i32>, String> {
+    let file = fs::File::open(command_env.vars.cwd.join(path))
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut reader = io::BufReader::new(file);
+    Ok(run_lines(
+        &mut |line, _| reader.read_line(line),
+        command_env,
+        false,
+    ))
+}
+
+// run the file in its own frame
+fn source_file(path: &PathBuf, command_env: &mut CommandEnv) -> Result<Option<i32>, String> {
+    command_env.frames.push(Frame {
+        name: String::from("source"),
+        file: path.display().to_string(),
+        line: 0,
+    });
+    let result = run_file(path, command_env);
+    command_env.frames.pop();
+    result
+}
+
+// the exit status if the file exited
+fn source_startup_file(name: &str, command_env: &mut CommandEnv) -> Option<i32> {
+    let home = command_env.vars.get("HOME")?;
+    let path = PathBuf::from(osstr::to_os(&home)).join(name);
+    if !path.is_file() {
+        return None;
+    }
+    match source_file(&path, command_env) {
+        Ok(exited) => exited,
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        }
+    }
+}
+
+// key bindings from INPUTRC or ~/.inputrc, like readline does
+fn read_inputrc(command_env: &mut CommandEnv) {
+    let vars = &command_env.vars;
+    let path = match (vars.get("INPUTRC"), vars.get("HOME")) {
+        (Some(path), _) => vars.path(&path),
+        (None, Some(home)) => PathBuf::from(osstr::to_os(&home)).join(".inputrc"),
+        (None, None) => return,
+    };
+    if !path.is_file() {
+        return;
+    }
+    let keymap = editing_keymap(&command_env.options);
+    let mut bindings = command_env.bindings.borrow_mut();
+    if let Err(err) = keymap::read_file(
+        &mut bindings,
+        &path.to_string_lossy(),
+        keymap,
+        &command_env.options,
+    ) {
+        eprintln!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_command_keeps_the_status() {
+        let mut command_env = init();
+        command_env.vars.set("PROMPT_COMMAND", "false").unwrap();
+        command_env.vars.status = 3;
+        assert_eq!(run_prompt_command(&mut command_env), None);
+        assert_eq!(command_env.vars.status, 3);
+    }
+
+    #[test]
+    fn prompt_command_array() {
+        let mut command_env = init();
+        command_env.vars.set_array(
+            "PROMPT_COMMAND",
+            vec![String::from("first=1"), String::from("second=2")],
+        );
+        run_prompt_command(&mut command_env);
+        assert_eq!(command_env.vars.get("first").as_deref(), Some("1"));
+        assert_eq!(command_env.vars.get("second").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn prompt_command_exit() {
+        let mut command_env = init();
+        command_env.vars.set("PROMPT_COMMAND", "exit 4").unwrap();
+        assert_eq!(run_prompt_command(&mut command_env), Some(4));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// shell options changed by the set builtin, flags are shared with threads
+// (job waiters, signal handling), that's why they are atomic
+pub struct Options(Vec<(&'static str, Option<char>, Arc<AtomicBool>)>);
+
+impl Options {
+    pub fn new() -> Self {
+        let options = [
+            // report finished background jobs immediately
+            ("notify", Some('b')),
+            // exit the shell when a command fails outside of && and || conditions
+            ("errexit", Some('e')),
+            // send SIGHUP to background jobs when the shell exits or is hung up
+            ("huponexit", None),
+            // don't overwrite existing files with > redirection, >| still does it
+            ("noclobber", Some('C')),
+            // ** in patterns matches directories recursively
+            ("globstar", None),
+            // ksh-style extended patterns: @(a|b), !(a|b), +(a|b), ?(a|b), *(a|b)
+            ("extglob", None),
+            // patterns without matches expand to nothing instead of themselves
+            ("nullglob", None),
+            // patterns without matches are errors, the command is not run
+            ("failglob", None),
+            // cd goes to the only directory close to a misspelled one
+            ("cdspell", None),
+            // the history file is shared by the sessions: new commands are
+            // appended to it as they are entered instead of replacing it at exit
+            ("histappend", None),
+            // programs are printed with their expanded arguments instead of
+            // being run, builtins which only use the shell state still run
+            ("dryrun", None),
+            // editing mode of the line editor, only one of them is on
+            ("emacs", None),
+            ("vi", None),
+        ];
+
+        Options(
+            options
+                .into_iter()
+                .map(|(name, flag)| (name, flag, Arc::new(AtomicBool::new(name == "emacs"))))
+                .collect(),
+        )
+    }
+
+    pub fn flag(&self, name: &str) -> Arc<AtomicBool> {
+        self.0
+            .iter()
+            .find(|(option, _, _)| *option == name)
+            .map(|(_, _, value)| Arc::clone(value))
+            .unwrap_or_else(|| panic!("unknown shell option: {}", name))
+    }
+
+    pub fn get(&self, name: &str) -> bool {
+        self.flag(name).load(Ordering::Relaxed)
+    }
+
+    // None if there is no such option
+    pub fn lookup(&self, name: &str) -> Option<bool> {
+        self.0
+            .iter()
+            .find(|(option, _, _)| *option == name)
+            .map(|(_, _, value)| value.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, name: &str, value: bool) -> Result<(), String> {
+        match self.0.iter().find(|(option, _, _)| *option == name) {
+            Some((_, _, flag)) => {
+                flag.store(value, Ordering::Relaxed);
+                match (name, value) {
+                    ("emacs", true) => self.set("vi", false),
+                    ("vi", true) => self.set("emacs", false),
+                    _ => Ok(()),
+                }
+            }
+            None => Err(format!("invalid option name: {}", name)),
+        }
+    }
+
+    fn set_short(&self, short: char, value: bool) -> Result<(), String> {
+        match self.0.iter().find(|(_, flag, _)| *flag == Some(short)) {
+            Some((name, _, _)) => self.set(name, value),
+            None => Err(format!("invalid option: {}", short)),
+        }
+    }
+
+    // options in the form of `set -o` output
+    pub fn list(&self) -> String {
+        self.0
+            .iter()
+            .map(|(name, _, value)| {
+                let state = if value.load(Ordering::Relaxed) {
+                    "on"
+                } else {
+                    "off"
+                };
+                format!("{:<15}\t{}", name, state)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // apply arguments of the set builtin: -o name, +o name, -b, +b
+    pub fn apply(&self, args: &[&str]) -> Result<(), String> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => return Err(format!("invalid set argument: {}", arg)),
+            };
+
+            if arg[1..] == *"o" {
+                match args.next() {
+                    Some(name) => self.set(name, value)?,
+                    None => return Err(format!("{}o: option name required", &arg[..1])),
+                }
+            } else {
+                for short in arg[1..].chars() {
+                    self.set_short(short, value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_arguments() {
+        let options = Options::new();
+        options.apply(&["-b", "-o", "huponexit"]).unwrap();
+        assert!(options.flag("notify").load(Ordering::Relaxed));
+        assert!(options.flag("huponexit").load(Ordering::Relaxed));
+        options.apply(&["+b"]).unwrap();
+        assert!(!options.flag("notify").load(Ordering::Relaxed));
+        assert!(options.list().contains(&format!("{:<15}\ton", "huponexit")));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let options = Options::new();
+        assert_eq!(options.apply(&["-z"]).unwrap_err(), "invalid option: z");
+        assert_eq!(
+            options.apply(&["-o", "nope"]).unwrap_err(),
+            "invalid option name: nope"
+        );
+        assert_eq!(
+            options.apply(&["-o"]).unwrap_err(),
+            "-o: option name required"
+        );
+        assert_eq!(
+            options.apply(&["b"]).unwrap_err(),
+            "invalid set argument: b"
+        );
+    }
+}
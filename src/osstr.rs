@@ -0,0 +1,97 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+// words of the shell are strings, so bytes of file names and program output
+// which are not valid UTF-8 are kept in them as characters of the private use
+// area U+10FF00..U+10FFFF and turned back into the same bytes at the end
+const ESCAPED: u32 = 0x10ff00;
+
+fn is_escaped(c: char) -> bool {
+    c as u32 >= ESCAPED
+}
+
+// \xHH and \NNN escapes give bytes, not characters
+pub fn from_byte(byte: u8) -> char {
+    match byte.is_ascii() {
+        true => char::from(byte),
+        false => char::from_u32(ESCAPED + byte as u32).unwrap(),
+    }
+}
+
+// characters of the escaped range itself are kept as their bytes, so they
+// don't turn into other bytes
+pub fn push_char(text: &mut String, c: char) {
+    match is_escaped(c) {
+        true => text.extend(c.encode_utf8(&mut [0; 4]).bytes().map(from_byte)),
+        false => text.push(c),
+    }
+}
+
+pub fn from_bytes(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            push_char(&mut text, c);
+        }
+        for &byte in chunk.invalid() {
+            text.push(from_byte(byte));
+        }
+    }
+    text
+}
+
+pub fn to_bytes(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match (c as u32).checked_sub(ESCAPED) {
+            Some(byte) => bytes.push(byte as u8),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bytes
+}
+
+pub fn from_os(name: &OsStr) -> String {
+    from_bytes(name.as_bytes())
+}
+
+pub fn to_os(text: &str) -> OsString {
+    OsString::from_vec(to_bytes(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_bytes_are_kept() {
+        let bytes = b"a\xffb\xc3";
+        let text = from_bytes(bytes);
+        assert_eq!(text.chars().count(), 4);
+        assert_eq!(to_bytes(&text), bytes);
+        assert_eq!(to_os(&from_os(OsStr::from_bytes(bytes))).as_bytes(), bytes);
+    }
+
+    #[test]
+    fn valid_text_is_unchanged() {
+        assert_eq!(from_bytes("héllo".as_bytes()), "héllo");
+        assert_eq!(to_bytes("héllo"), "héllo".as_bytes());
+    }
+
+    #[test]
+    fn characters_of_the_escaped_range_are_kept() {
+        let bytes = "a\u{10ff41}\u{10ffff}".as_bytes();
+        let text = from_bytes(bytes);
+        assert_eq!(text.chars().count(), 9);
+        assert_eq!(to_bytes(&text), bytes);
+        let mut text = String::new();
+        push_char(&mut text, '\u{10ff41}');
+        assert_eq!(to_bytes(&text), "\u{10ff41}".as_bytes());
+    }
+
+    #[test]
+    fn escaped_bytes() {
+        assert_eq!(from_byte(b'a'), 'a');
+        assert_eq!(to_bytes(&from_byte(0xe9).to_string()), [0xe9]);
+    }
+}
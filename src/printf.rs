@@ -0,0 +1,607 @@
+// formatting of the printf builtin: %[flags][width][.precision]conversion
+// with d i u o x X c s b q f F e E g G and %%, the format is reused while
+// there are arguments left
+
+use crate::osstr;
+
+pub struct Output {
+    pub text: String,
+    // invalid numbers are reported, they are taken as 0 like in bash
+    pub errors: Vec<String>,
+}
+
+// larger widths and precisions are errors instead of huge allocations
+const MAX_WIDTH: usize = 1 << 20;
+
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+struct Formatter<'a> {
+    args: &'a [&'a str],
+    next: usize,
+    errors: Vec<String>,
+    // \c in %b argument stops all the output
+    stopped: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn arg(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.next).copied();
+        self.next += 1;
+        arg
+    }
+
+    fn integer(&mut self) -> i64 {
+        let arg = self.arg().unwrap_or("");
+        match parse_integer(arg) {
+            Some(value) => value,
+            None => {
+                self.errors.push(format!("printf: {}: invalid number", arg));
+                0
+            }
+        }
+    }
+
+    fn float(&mut self) -> f64 {
+        let arg = self.arg().unwrap_or("");
+        let trimmed = arg.trim();
+        if trimmed.is_empty() {
+            return 0.0;
+        }
+        match trimmed.parse::<f64>() {
+            Ok(value) => value,
+            Err(_) => match parse_integer(arg) {
+                Some(value) => value as f64,
+                None => {
+                    self.errors.push(format!("printf: {}: invalid number", arg));
+                    0.0
+                }
+            },
+        }
+    }
+
+    // width or precision given as * is taken from the arguments
+    fn number(
+        &mut self,
+        chars: &[char],
+        index: &mut usize,
+        name: &str,
+    ) -> Result<Option<usize>, String> {
+        let number = if chars.get(*index) == Some(&'*') {
+            *index += 1;
+            Some(self.integer().max(0) as u64)
+        } else {
+            let start = *index;
+            while chars.get(*index).is_some_and(|c| c.is_ascii_digit()) {
+                *index += 1;
+            }
+            let digits: String = chars[start..*index].iter().collect();
+            match digits.is_empty() {
+                true => None,
+                false => Some(digits.parse().unwrap_or(u64::MAX)),
+            }
+        };
+        match number {
+            Some(number) if number > MAX_WIDTH as u64 => Err(format!("printf: invalid {}", name)),
+            number => Ok(number.map(|number| number as usize)),
+        }
+    }
+
+    fn conversion(&mut self, chars: &[char], index: &mut usize) -> Result<String, String> {
+        let start = *index - 1;
+        let mut spec = Spec {
+            left: false,
+            plus: false,
+            space: false,
+            alternate: false,
+            zero: false,
+            width: None,
+            precision: None,
+        };
+        while let Some(&flag) = chars.get(*index) {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            *index += 1;
+        }
+        spec.width = self.number(chars, index, "width")?;
+        if chars.get(*index) == Some(&'.') {
+            *index += 1;
+            spec.precision = Some(self.number(chars, index, "precision")?.unwrap_or(0));
+        }
+
+        let conversion = match chars.get(*index) {
+            Some(&conversion) => conversion,
+            None => return Err(String::from("printf: missing format character")),
+        };
+        *index += 1;
+
+        let text = match conversion {
+            'd' | 'i' => {
+                let value = self.integer();
+                let sign = if value < 0 {
+                    "-"
+                } else if spec.plus {
+                    "+"
+                } else if spec.space {
+                    " "
+                } else {
+                    ""
+                };
+                return Ok(pad_number(sign, &value.unsigned_abs().to_string(), &spec));
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                let value = self.integer() as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (value.to_string(), ""),
+                    'o' => (
+                        format!("{:o}", value),
+                        if spec.alternate { "0" } else { "" },
+                    ),
+                    'x' => (
+                        format!("{:x}", value),
+                        if spec.alternate { "0x" } else { "" },
+                    ),
+                    _ => (
+                        format!("{:X}", value),
+                        if spec.alternate { "0X" } else { "" },
+                    ),
+                };
+                let prefix = if value == 0 { "" } else { prefix };
+                return Ok(pad_number(prefix, &digits, &spec));
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.float();
+                let sign = if value.is_sign_negative() {
+                    "-"
+                } else if spec.plus {
+                    "+"
+                } else if spec.space {
+                    " "
+                } else {
+                    ""
+                };
+                let digits = float(value.abs(), conversion, &spec);
+                let spec = Spec {
+                    precision: None,
+                    ..spec
+                };
+                return Ok(pad_number(sign, &digits, &spec));
+            }
+            'c' => self
+                .arg()
+                .and_then(|arg| arg.chars().next())
+                .map_or(String::new(), String::from),
+            's' => truncate(self.arg().unwrap_or(""), spec.precision),
+            'b' => {
+                let arg = self.arg().unwrap_or("");
+                let (text, stopped) = escapes(arg, true);
+                self.stopped = stopped;
+                truncate(&text, spec.precision)
+            }
+            'q' => quote(self.arg().unwrap_or("")),
+            '%' if *index - start == 2 => return Ok(String::from("%")),
+            _ => {
+                let spec: String = chars[start..*index].iter().collect();
+                return Err(format!("printf: {}: invalid format character", spec));
+            }
+        };
+
+        Ok(pad(&text, &spec))
+    }
+
+    // one pass over the format
+    fn format(&mut self, chars: &[char], result: &mut String) -> Result<(), String> {
+        let mut index = 0;
+        while index < chars.len() && !self.stopped {
+            match chars[index] {
+                '%' => {
+                    index += 1;
+                    let text = self.conversion(chars, &mut index)?;
+                    result.push_str(&text);
+                }
+                '\\' => {
+                    let end = escape_end(chars, index);
+                    let escape: String = chars[index..end].iter().collect();
+                    let (text, stopped) = escapes(&escape, false);
+                    result.push_str(&text);
+                    self.stopped = stopped;
+                    index = end;
+                }
+                c => {
+                    result.push(c);
+                    index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// decimal, 0x hexadecimal and 0 octal numbers, 'c and "c give the character
+fn parse_integer(text: &str) -> Option<i64> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix(['\'', '"']) {
+        return Some(rest.chars().next().map_or(0, |c| c as i64));
+    }
+    if text.is_empty() {
+        return Some(0);
+    }
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse::<u64>().ok()?
+    } as i64;
+
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+fn pad(text: &str, spec: &Spec) -> String {
+    let width = spec.width.unwrap_or(0);
+    let len = text.chars().count();
+    if len >= width {
+        return String::from(text);
+    }
+    let padding = " ".repeat(width - len);
+    match spec.left {
+        true => format!("{}{}", text, padding),
+        false => format!("{}{}", padding, text),
+    }
+}
+
+// precision is the minimal number of digits, zeros fill the width after the
+// sign unless the number is aligned to the left or has precision
+fn pad_number(sign: &str, digits: &str, spec: &Spec) -> String {
+    let digits = match spec.precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(precision) if digits.len() < precision => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        _ => String::from(digits),
+    };
+
+    let width = spec.width.unwrap_or(0);
+    let len = sign.len() + digits.len();
+    if spec.zero && !spec.left && spec.precision.is_none() && len < width {
+        return format!("{}{}{}", sign, "0".repeat(width - len), digits);
+    }
+    pad(&format!("{}{}", sign, digits), spec)
+}
+
+// 1.500000e+02 like in C
+fn exponent_form(value: f64, precision: usize, upper: bool) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    let text = format!("{}e{}{:02}", mantissa, sign, exponent.abs());
+    match upper {
+        true => text.to_uppercase(),
+        false => text,
+    }
+}
+
+fn float(value: f64, conversion: char, spec: &Spec) -> String {
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return match conversion.is_ascii_uppercase() {
+            true => text.to_uppercase(),
+            false => String::from(text),
+        };
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    match conversion {
+        'f' | 'F' => format!("{:.*}", precision, value),
+        'e' | 'E' => exponent_form(value, precision, conversion == 'E'),
+        _ => {
+            // %g uses the shorter form and drops trailing zeros
+            let precision = precision.max(1);
+            let exponent_text = exponent_form(value, precision - 1, false);
+            let exponent: i32 = exponent_text.split_once('e').unwrap().1.parse().unwrap();
+            let text = if exponent < -4 || exponent >= precision as i32 {
+                exponent_form(value, precision - 1, conversion == 'G')
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+            };
+            if spec.alternate {
+                return text;
+            }
+            match text.split_once(['e', 'E']) {
+                Some((mantissa, exponent)) => {
+                    let e = if conversion == 'G' { 'E' } else { 'e' };
+                    format!("{}{}{}", trim_zeros(mantissa), e, exponent)
+                }
+                None => trim_zeros(&text),
+            }
+        }
+    }
+}
+
+fn trim_zeros(text: &str) -> String {
+    match text.contains('.') {
+        true => String::from(text.trim_end_matches('0').trim_end_matches('.')),
+        false => String::from(text),
+    }
+}
+
+fn truncate(text: &str, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => String::from(text),
+    }
+}
+
+// end of the escape sequence starting at the backslash
+fn escape_end(chars: &[char], start: usize) -> usize {
+    let mut end = start + 2;
+    match chars.get(start + 1) {
+        Some('0'..='7') => {
+            while end < chars.len() && end < start + 4 && ('0'..='7').contains(&chars[end]) {
+                end += 1;
+            }
+        }
+        Some('x') => {
+            while end < chars.len() && end < start + 4 && chars[end].is_ascii_hexdigit() {
+                end += 1;
+            }
+        }
+        None => end = start + 1,
+        _ => {}
+    }
+    end.min(chars.len())
+}
+
+// interpret backslash escapes, %b arguments have octal as \0NNN; the flag
+// tells that \c stopped the output
+pub fn escapes(text: &str, argument: bool) -> (String, bool) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        if chars[index] != '\\' || index + 1 == chars.len() {
+            result.push(chars[index]);
+            index += 1;
+            continue;
+        }
+
+        let c = chars[index + 1];
+        index += 2;
+        match c {
+            'n' => result.push('\n'),
+            't' => result.push('\t'),
+            'r' => result.push('\r'),
+            'a' => result.push('\x07'),
+            'b' => result.push('\x08'),
+            'f' => result.push('\x0c'),
+            'v' => result.push('\x0b'),
+            'e' | 'E' => result.push('\x1b'),
+            '\\' => result.push('\\'),
+            'c' if argument => return (osstr::from_bytes(&osstr::to_bytes(&result)), true),
+            '0'..='7' => {
+                // \NNN in the format, \0NNN in %b arguments
+                let max = if argument && c == '0' { 3 } else { 2 };
+                let mut value = if argument && c == '0' {
+                    0
+                } else {
+                    c.to_digit(8).unwrap()
+                };
+                let mut count = 0;
+                while count < max && index < chars.len() && ('0'..='7').contains(&chars[index]) {
+                    value = value * 8 + chars[index].to_digit(8).unwrap();
+                    index += 1;
+                    count += 1;
+                }
+                result.push(osstr::from_byte(value as u8));
+            }
+            'x' => {
+                let mut value = 0;
+                let mut count = 0;
+                while count < 2 && index < chars.len() && chars[index].is_ascii_hexdigit() {
+                    value = value * 16 + chars[index].to_digit(16).unwrap();
+                    index += 1;
+                    count += 1;
+                }
+                match count {
+                    0 => result.push_str("\\x"),
+                    _ => result.push(osstr::from_byte(value as u8)),
+                }
+            }
+            '"' | '\'' if !argument => result.push(c),
+            c => {
+                result.push('\\');
+                result.push(c);
+            }
+        }
+    }
+
+    (osstr::from_bytes(&osstr::to_bytes(&result)), false)
+}
+
+// quote the text so the shell reads it back as one word: a\ b, ''
+pub(crate) fn quote(text: &str) -> String {
+    if text.is_empty() {
+        return String::from("''");
+    }
+    if text.chars().any(|c| c.is_control()) {
+        let mut quoted = String::from("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\'' => quoted.push_str("\\'"),
+                '\\' => quoted.push_str("\\\\"),
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+
+    let mut quoted = String::new();
+    for c in text.chars() {
+        if !c.is_alphanumeric() && !"_./,:=@%^+-".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+pub fn format(format: &str, args: &[&str]) -> Result<Output, String> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut formatter = Formatter {
+        args,
+        next: 0,
+        errors: vec![],
+        stopped: false,
+    };
+    let mut text = String::new();
+    loop {
+        let before = formatter.next;
+        formatter.format(&chars, &mut text)?;
+        // the format without conversions is printed once
+        if formatter.stopped || formatter.next >= args.len() || formatter.next == before {
+            break;
+        }
+    }
+
+    Ok(Output {
+        text,
+        errors: formatter.errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printed(format_text: &str, args: &[&str]) -> String {
+        format(format_text, args).unwrap().text
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(
+            printed("%d|%5d|%-5d|%05d", &["1", "2", "3", "-4"]),
+            "1|    2|3    |-0004"
+        );
+        assert_eq!(printed("%+d|% d|%.3d", &["5", "6", "7"]), "+5| 6|007");
+        assert_eq!(
+            printed("%x|%#X|%#o|%u", &["255", "255", "8", "9"]),
+            "ff|0XFF|010|9"
+        );
+        assert_eq!(printed("%d %d %d", &["0x10", "010", "'A"]), "16 8 65");
+        assert_eq!(printed("%*d", &["4", "1"]), "   1");
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(
+            printed("%f|%.2f|%8.3f", &["1.5", "2", "-3.14159"]),
+            "1.500000|2.00|  -3.142"
+        );
+        assert_eq!(printed("%e|%.1E", &["150", "0.05"]), "1.500000e+02|5.0E-02");
+        assert_eq!(
+            printed("%g|%g|%g", &["100000", "1000000", "0.0001"]),
+            "100000|1e+06|0.0001"
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            printed("[%s][%5s][%-5s][%.2s]", &["a", "b", "c", "def"]),
+            "[a][    b][c    ][de]"
+        );
+        assert_eq!(printed("%c%c", &["xyz", ""]), "x");
+        assert_eq!(
+            printed("%q %q %q", &["a b", "", "x\ny"]),
+            "a\\ b '' $'x\\ny'"
+        );
+        assert_eq!(printed("100%%\\n", &[]), "100%\n");
+    }
+
+    #[test]
+    fn format_is_reused() {
+        assert_eq!(printed("%s=%s;", &["a", "1", "b"]), "a=1;b=;");
+        assert_eq!(printed("text\\n", &["ignored"]), "text\n");
+    }
+
+    #[test]
+    fn escapes_of_b_arguments() {
+        assert_eq!(printed("%b|", &["a\\tb\\0101"]), "a\tbA|");
+        assert_eq!(printed("%s %b %s", &["a", "b\\cc", "d"]), "a b");
+        assert_eq!(
+            escapes("\\x41\\101\\e", false),
+            (String::from("AA\x1b"), false)
+        );
+        let bytes = |text: &str| osstr::to_bytes(&printed(text, &[]));
+        assert_eq!(bytes("\\xe2\\x82\\xac"), "€".as_bytes());
+        assert_eq!(bytes("\\xff\\377"), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn errors() {
+        let output = format("%d %d", &["1x", "2"]).unwrap();
+        assert_eq!(output.text, "0 2");
+        assert_eq!(output.errors, ["printf: 1x: invalid number"]);
+        assert_eq!(
+            format("%z", &[]).err().unwrap(),
+            "printf: %z: invalid format character"
+        );
+        assert_eq!(
+            format("%5", &[]).err().unwrap(),
+            "printf: missing format character"
+        );
+    }
+
+    #[test]
+    fn huge_widths_are_errors() {
+        assert_eq!(
+            format("%.99999999999d", &["1"]).err().unwrap(),
+            "printf: invalid precision"
+        );
+        assert_eq!(
+            format("%10000000000s", &["a"]).err().unwrap(),
+            "printf: invalid width"
+        );
+        assert_eq!(
+            format("%*s", &["99999999", "a"]).err().unwrap(),
+            "printf: invalid width"
+        );
+        assert_eq!(
+            format("%99999999999999999999999f", &["1"]).err().unwrap(),
+            "printf: invalid width"
+        );
+        assert_eq!(printed("%.3000f", &["1"]).len(), 3002);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+use std::os::raw::{c_int, c_long};
+use std::os::unix::process::CommandExt;
+use std::process;
+
+extern "C" {
+    fn setpriority(which: c_int, who: c_int, prio: c_int) -> c_int;
+    #[cfg(target_os = "linux")]
+    fn syscall(number: c_long, ...) -> c_long;
+}
+
+const PRIO_PROCESS: c_int = 0;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYS_IOPRIO_SET: c_long = 251;
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+const SYS_IOPRIO_SET: c_long = 289;
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+const SYS_IOPRIO_SET: c_long = 314;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "aarch64", target_arch = "riscv64")
+))]
+const SYS_IOPRIO_SET: c_long = 30;
+
+const IOPRIO_WHO_PROCESS: c_int = 1;
+const IOPRIO_CLASS_SHIFT: c_int = 13;
+
+// priority of programs started by lowprio: nice level and I/O scheduling
+// class with its level, like nice -n 10 ionice -c 2 -n 7
+#[derive(Clone, Copy)]
+pub struct Priority {
+    pub nice: i32,
+    pub io: Option<(i32, i32)>,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority {
+            nice: 10,
+            io: Some((2, 7)),
+        }
+    }
+}
+
+// realtime, best-effort and idle, by name or number like in ionice
+pub fn io_class(name: &str) -> Result<i32, String> {
+    match name {
+        "1" | "realtime" => Ok(1),
+        "2" | "best-effort" => Ok(2),
+        "3" | "idle" => Ok(3),
+        _ => Err(format!("{}: unknown I/O scheduling class", name)),
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+fn set_io_priority(class: i32, level: i32) -> io::Result<()> {
+    let value = (class << IOPRIO_CLASS_SHIFT) | level;
+    match unsafe { syscall(SYS_IOPRIO_SET, IOPRIO_WHO_PROCESS, 0 as c_int, value) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+// other systems have no I/O priorities, only the nice level is set
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+)))]
+fn set_io_priority(_class: i32, _level: i32) -> io::Result<()> {
+    let _ = (IOPRIO_WHO_PROCESS, IOPRIO_CLASS_SHIFT);
+    Ok(())
+}
+
+// set the priority in the child before exec, the shell keeps its own
+pub fn lower(command: &mut process::Command, priority: Option<Priority>) -> &mut process::Command {
+    let priority = match priority {
+        Some(priority) => priority,
+        None => return command,
+    };
+    unsafe {
+        command.pre_exec(move || {
+            if setpriority(PRIO_PROCESS, 0, priority.nice) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            match priority.io {
+                Some((class, level)) => set_io_priority(class, level),
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_classes() {
+        assert_eq!(io_class("idle"), Ok(3));
+        assert_eq!(io_class("2"), Ok(2));
+        assert_eq!(
+            io_class("4"),
+            Err(String::from("4: unknown I/O scheduling class"))
+        );
+    }
+
+    #[test]
+    fn programs_get_the_nice_level() {
+        let priority = Priority {
+            nice: 5,
+            io: Some((3, 0)),
+        };
+        let output = lower(&mut process::Command::new("/bin/sh"), Some(priority))
+            .args(["-c", "cut -d' ' -f19 /proc/self/stat"])
+            .output()
+            .unwrap();
+        let nice: i32 = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(nice >= 5);
+    }
+}
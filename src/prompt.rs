@@ -0,0 +1,393 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::os::raw::{c_char, c_int, c_long};
+use std::path::Path;
+use std::ptr;
+
+use crate::expand;
+use crate::osstr;
+use crate::theme;
+use crate::vars::Variables;
+
+// the primary prompt and the right one, which is shown at the end of the
+// first line of the input while the typed text doesn't reach it; the
+// transient one replaces both of them once the line is entered
+pub struct Prompt {
+    pub left: String,
+    pub right: String,
+    pub transient: Option<String>,
+}
+
+// \[ and \] around escape sequences, they take no space on the screen
+pub const START_IGNORE: char = '\x01';
+pub const END_IGNORE: char = '\x02';
+
+// the default PS1, the failure of the last command is shown before the $
+const DEFAULT_PS1: &str = "\\f$ ";
+
+// struct tm of glibc on Linux
+#[cfg(not(target_os = "linux"))]
+compile_error!("the time of the prompt is only written for Linux");
+
+#[repr(C)]
+struct Tm {
+    sec: c_int,
+    min: c_int,
+    hour: c_int,
+    mday: c_int,
+    mon: c_int,
+    year: c_int,
+    wday: c_int,
+    yday: c_int,
+    isdst: c_int,
+    gmtoff: c_long,
+    zone: *const c_char,
+}
+
+extern "C" {
+    fn time(time: *mut c_long) -> c_long;
+    fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+    fn geteuid() -> u32;
+}
+
+fn local_time() -> Tm {
+    let mut tm = Tm {
+        sec: 0,
+        min: 0,
+        hour: 0,
+        mday: 1,
+        mon: 0,
+        year: 70,
+        wday: 4,
+        yday: 0,
+        isdst: 0,
+        gmtoff: 0,
+        zone: ptr::null(),
+    };
+    unsafe {
+        let now = time(ptr::null_mut());
+        localtime_r(&now, &mut tm);
+    }
+    tm
+}
+
+// the directory with the home replaced by ~
+fn directory(vars: &Variables) -> String {
+    let pwd = osstr::from_os(vars.cwd.as_os_str());
+    match vars.get("HOME") {
+        Some(home) if !home.is_empty() && home != "/" && pwd.starts_with(&home) => {
+            match &pwd[home.len()..] {
+                rest if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+                _ => pwd,
+            }
+        }
+        _ => pwd,
+    }
+}
+
+// branch of the git repository the current directory is in, the short commit
+// id for a detached HEAD
+pub fn git_branch(cwd: &Path) -> Option<String> {
+    let mut directory = cwd.to_path_buf();
+    loop {
+        let git = directory.join(".git");
+        let head = match git.is_file() {
+            // worktrees and submodules have "gitdir: path" in .git file
+            true => fs::read_to_string(&git).ok().and_then(|text| {
+                let path = text.trim().strip_prefix("gitdir: ")?.to_string();
+                fs::read_to_string(directory.join(path).join("HEAD")).ok()
+            }),
+            false => fs::read_to_string(git.join("HEAD")).ok(),
+        };
+        if let Some(head) = head {
+            let head = head.trim();
+            return Some(match head.strip_prefix("ref: refs/heads/") {
+                Some(branch) => String::from(branch),
+                None => head.chars().take(7).collect(),
+            });
+        }
+        if !directory.pop() {
+            return None;
+        }
+    }
+}
+
+// $name, ${...}, $((...)) and special parameters like $? in the prompt
+fn parameter_end(chars: &[char], start: usize) -> usize {
+    let closing = |open: char, close: char, from: usize| {
+        let mut depth = 0;
+        for (index, &c) in chars.iter().enumerate().skip(from) {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return index + 1;
+                }
+            }
+        }
+        chars.len()
+    };
+
+    match chars.get(start + 1) {
+        Some('{') => closing('{', '}', start + 1),
+        Some('(') => closing('(', ')', start + 1),
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            let mut end = start + 1;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            end
+        }
+        Some(_) => start + 2,
+        None => start + 1,
+    }
+}
+
+// "✗ 1 " in red after a failed command, nothing after a successful one; the
+// color is left out when the output isn't a terminal
+fn failure(status: i32) -> String {
+    match (status, io::stdout().is_terminal()) {
+        (0, _) => String::new(),
+        (status, true) => format!(
+            "{}\x1b[31m{}✗ {}{}\x1b[0m{} ",
+            START_IGNORE, END_IGNORE, status, START_IGNORE, END_IGNORE
+        ),
+        (status, false) => format!("✗ {} ", status),
+    }
+}
+
+// bash prompt escapes and parameter expansions, escapes are replaced first
+// so their results are never expanded: \u \h \H \w \W \t \T \@ \A \d \$ \n
+// \r \e \a \\ \[ \], \g for the git branch, \? for the status of the last
+// command and \f for the mark of its failure
+pub fn expand(template: &str, vars: &mut Variables) -> String {
+    let chars: Vec<char> = template.chars().collect();
+    let mut result = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' if index + 1 < chars.len() => {
+                match chars[index + 1] {
+                    'u' => result.push_str(&vars.get("USER").unwrap_or_default()),
+                    'h' => {
+                        let host = expand::hostname().unwrap_or_default();
+                        result.push_str(host.split('.').next().unwrap_or_default());
+                    }
+                    'H' => result.push_str(&expand::hostname().unwrap_or_default()),
+                    'w' => result.push_str(&directory(vars)),
+                    'W' => {
+                        let directory = directory(vars);
+                        let name = match directory.as_str() {
+                            "/" | "~" => &directory[..],
+                            _ => Path::new(&directory)
+                                .file_name()
+                                .and_then(|name| name.to_str())
+                                .unwrap_or(&directory),
+                        };
+                        result.push_str(name);
+                    }
+                    't' => {
+                        let tm = local_time();
+                        result.push_str(&format!("{:02}:{:02}:{:02}", tm.hour, tm.min, tm.sec));
+                    }
+                    'T' | '@' => {
+                        let tm = local_time();
+                        let hour = if tm.hour % 12 == 0 { 12 } else { tm.hour % 12 };
+                        match chars[index + 1] {
+                            'T' => {
+                                result.push_str(&format!("{:02}:{:02}:{:02}", hour, tm.min, tm.sec))
+                            }
+                            _ => result.push_str(&format!(
+                                "{:02}:{:02} {}",
+                                hour,
+                                tm.min,
+                                if tm.hour < 12 { "AM" } else { "PM" }
+                            )),
+                        }
+                    }
+                    'A' => {
+                        let tm = local_time();
+                        result.push_str(&format!("{:02}:{:02}", tm.hour, tm.min));
+                    }
+                    'd' => {
+                        const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+                        const MONTHS: [&str; 12] = [
+                            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+                            "Nov", "Dec",
+                        ];
+                        let tm = local_time();
+                        result.push_str(&format!(
+                            "{} {} {:02}",
+                            DAYS[tm.wday.rem_euclid(7) as usize],
+                            MONTHS[tm.mon.rem_euclid(12) as usize],
+                            tm.mday
+                        ));
+                    }
+                    'g' => result.push_str(&git_branch(&vars.cwd).unwrap_or_default()),
+                    '?' => result.push_str(&vars.status.to_string()),
+                    'f' => result.push_str(&failure(vars.status)),
+                    '$' => {
+                        let root = unsafe { geteuid() } == 0;
+                        result.push(if root { '#' } else { '$' });
+                    }
+                    'n' => result.push('\n'),
+                    'r' => result.push('\r'),
+                    'e' => result.push('\x1b'),
+                    'a' => result.push('\x07'),
+                    '\\' => result.push('\\'),
+                    '[' => result.push(START_IGNORE),
+                    ']' => result.push(END_IGNORE),
+                    c => {
+                        result.push('\\');
+                        result.push(c);
+                    }
+                }
+                index += 2;
+            }
+            '$' => {
+                let end = parameter_end(&chars, index);
+                let parameter: String = chars[index..end].iter().collect();
+                let quoted = format!("\"{}\"", parameter);
+                result.push_str(&expand::expand_string(&quoted, vars).unwrap_or(parameter));
+                index = end;
+            }
+            c => {
+                result.push(c);
+                index += 1;
+            }
+        }
+    }
+    result
+}
+
+// columns taken by the text on the screen: escape sequences and the parts
+// between \[ and \] are not counted
+pub fn width(text: &str) -> usize {
+    let mut width = 0;
+    let mut ignored = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            START_IGNORE => ignored = true,
+            END_IGNORE => ignored = false,
+            // CSI sequences end with a letter
+            '\x1b' => {
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() || c == '~' {
+                            break;
+                        }
+                    }
+                }
+            }
+            c if ignored || c.is_control() => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+// the text to write to the terminal, without \[ and \] markers
+pub fn printable(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c != START_IGNORE && c != END_IGNORE)
+        .collect()
+}
+
+// PS1 or the theme when it isn't set, jobs is the number of running jobs
+pub fn primary(vars: &mut Variables, jobs: usize) -> Prompt {
+    let ps1 = vars.get("PS1");
+    let themed = match ps1 {
+        Some(_) => None,
+        None => theme::render(vars, jobs),
+    };
+    let (left, themed_right) = match (ps1, themed) {
+        (Some(ps1), _) => (expand(&ps1, vars), String::new()),
+        (None, Some((left, right))) => (left, right),
+        (None, None) => (expand(DEFAULT_PS1, vars), String::new()),
+    };
+    let right = match vars.get("RPROMPT") {
+        Some(rprompt) => expand(&rprompt, vars),
+        None => themed_right,
+    };
+    // TRANSIENT_PROMPT keeps the scrollback short with long prompts
+    let transient = vars
+        .get("TRANSIENT_PROMPT")
+        .map(|transient| expand(&transient, vars));
+    Prompt {
+        left,
+        right,
+        transient,
+    }
+}
+
+pub fn continuation(vars: &mut Variables) -> Prompt {
+    let left = match vars.get("PS2") {
+        Some(ps2) => expand(&ps2, vars),
+        None => String::from("> "),
+    };
+    Prompt {
+        left,
+        right: String::new(),
+        transient: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn escapes_are_replaced() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("PROMPT_TEST_NAME", "$x \\n").unwrap();
+        assert_eq!(
+            expand("a\\nb\\\\\\[\\e[1m\\]\\q", &mut vars),
+            "a\nb\\\x01\x1b[1m\x02\\q"
+        );
+        // results of expansions are not escapes again
+        assert_eq!(
+            expand(
+                "$PROMPT_TEST_NAME:${PROMPT_TEST_NAME}:$((1 + 2))",
+                &mut vars
+            ),
+            "$x \\n:$x \\n:3"
+        );
+        assert_eq!(expand("${PROMPT_TEST_UNSET}>", &mut vars), ">");
+    }
+
+    #[test]
+    fn invisible_text_takes_no_columns() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("\x1b[1;31mab\x1b[0m"), 2);
+        assert_eq!(width("\x01\x1b]0;title\x07\x02$ "), 2);
+        assert_eq!(width("\u{e9}\t"), 1);
+        assert_eq!(printable("\x01\x1b[1m\x02$ "), "\x1b[1m$ ");
+    }
+
+    #[test]
+    fn status_is_shown() {
+        let mut vars = Variables::new(&Options::new());
+        vars.status = 3;
+        assert_eq!(expand("\\?", &mut vars), "3");
+        assert_eq!(width(&expand("\\f$ ", &mut vars)), 6);
+        vars.status = 0;
+        assert_eq!(expand("\\f$ ", &mut vars), "$ ");
+    }
+
+    #[test]
+    fn prompts_are_expanded() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("PS1", "a\\\\ ").unwrap();
+        vars.set("RPROMPT", "$((2 * 3))").unwrap();
+        vars.set("TRANSIENT_PROMPT", "> ").unwrap();
+        let prompt = primary(&mut vars, 0);
+        assert_eq!(prompt.left, "a\\ ");
+        assert_eq!(prompt.right, "6");
+        assert_eq!(prompt.transient.as_deref(), Some("> "));
+        assert!(continuation(&mut vars).transient.is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::osstr;
+
+// first line of the log, entries follow one per line:
+// time<TAB>duration<TAB>status<TAB>command[<TAB>output]
+// with seconds since the epoch, seconds the command took and escaped text
+const HEADER: &str = "# shell session v1";
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next)) => {
+                chars.next();
+                unescaped.push(match next {
+                    't' => '\t',
+                    'n' => '\n',
+                    'r' => '\r',
+                    c => c,
+                });
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// commands entered while recording, written to the log as they finish
+pub struct Recorder {
+    file: File,
+    pub path: String,
+    // output of the commands is saved too
+    pub output: bool,
+    captured: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn start(path: &str, output: bool) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(osstr::to_os(path))
+            .map_err(|err| format!("record: {}: {}", path, err))?;
+        writeln!(file, "{}", HEADER).map_err(|err| format!("record: {}: {}", path, err))?;
+
+        Ok(Recorder {
+            file,
+            path: String::from(path),
+            output,
+            captured: vec![],
+        })
+    }
+
+    // writer which copies the output of the command for the log
+    pub fn tee<'a>(&'a mut self, out: &'a mut dyn Write) -> Tee<'a> {
+        Tee {
+            out,
+            captured: &mut self.captured,
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        command: &str,
+        started: SystemTime,
+        duration: Duration,
+        status: i32,
+    ) -> Result<(), String> {
+        let time = started
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut line = format!(
+            "{:.3}\t{:.3}\t{}\t{}",
+            time,
+            duration.as_secs_f64(),
+            status,
+            escape(command.trim_end_matches('\n'))
+        );
+        if self.output {
+            line.push('\t');
+            line.push_str(&escape(&osstr::from_bytes(&self.captured)));
+        }
+        self.captured.clear();
+        line.push('\n');
+
+        self.file
+            .write_all(&osstr::to_bytes(&line))
+            .map_err(|err| format!("record: {}: {}", self.path, err))
+    }
+}
+
+pub struct Tee<'a> {
+    out: &'a mut dyn Write,
+    captured: &'a mut Vec<u8>,
+}
+
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.captured.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+pub struct Entry {
+    pub time: f64,
+    pub duration: f64,
+    pub status: i32,
+    pub command: String,
+    pub output: Option<String>,
+}
+
+pub fn load(path: &str) -> Result<Vec<Entry>, String> {
+    let bytes = std::fs::read(osstr::to_os(path)).map_err(|err| format!("{}: {}", path, err))?;
+    let text = osstr::from_bytes(&bytes);
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(format!("{}: not a recorded session", path));
+    }
+
+    let mut entries = vec![];
+    for (number, line) in lines.enumerate() {
+        let invalid = || format!("{}:{}: invalid entry", path, number + 2);
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            return Err(invalid());
+        }
+        entries.push(Entry {
+            time: fields[0].parse().map_err(|_| invalid())?,
+            duration: fields[1].parse().map_err(|_| invalid())?,
+            status: fields[2].parse().map_err(|_| invalid())?,
+            command: unescape(fields[3]),
+            output: fields.get(4).map(|output| unescape(output)),
+        });
+    }
+    Ok(entries)
+}
+
+// 2026-10-14 12:00:01 UTC
+fn format_time(time: f64) -> String {
+    let secs = time as i64;
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // civil date from days since the epoch
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// commands with the time since the start of the session, their output if it
+// was recorded and failed statuses
+pub fn show(entries: &[Entry]) -> String {
+    let start = match entries.first() {
+        Some(entry) => entry.time,
+        None => return String::from("empty session\n"),
+    };
+
+    let mut text = format!("session recorded {}\n", format_time(start));
+    for entry in entries {
+        let command = entry.command.replace('\n', "\n> ");
+        text.push_str(&format!("[+{:.3}s] $ {}\n", entry.time - start, command));
+        if let Some(output) = &entry.output {
+            text.push_str(output);
+            if !output.is_empty() && !output.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        if entry.status != 0 {
+            text.push_str(&format!(
+                "[exit status {}, took {:.3}s]\n",
+                entry.status, entry.duration
+            ));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn escapes_are_reversible() {
+        let text = "a\tb\\n\nc\r";
+        assert_eq!(escape(text), "a\\tb\\\\n\\nc\\r");
+        assert_eq!(unescape(&escape(text)), text);
+    }
+
+    #[test]
+    fn times_are_formatted() {
+        assert_eq!(format_time(0.0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(1_791_979_201.5), "2026-10-14 12:00:01 UTC");
+        assert_eq!(format_time(951_782_400.0), "2000-02-29 00:00:00 UTC");
+    }
+
+    #[test]
+    fn sessions_are_saved_and_loaded() {
+        let path = env::temp_dir().join(format!("record-{}", std::process::id()));
+        let path = path.display().to_string();
+        let mut recorder = Recorder::start(&path, true).unwrap();
+        let started = UNIX_EPOCH + Duration::from_secs(100);
+        let mut out = vec![];
+        recorder.tee(&mut out).write_all(b"a\tb\n").unwrap();
+        recorder
+            .write("echo 'a\tb'\n", started, Duration::from_millis(5), 0)
+            .unwrap();
+        recorder
+            .write("false", started + Duration::from_secs(2), Duration::ZERO, 1)
+            .unwrap();
+        assert_eq!(out, b"a\tb\n");
+
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "echo 'a\tb'");
+        assert_eq!(entries[0].output.as_deref(), Some("a\tb\n"));
+        assert_eq!(entries[1].output.as_deref(), Some(""));
+        assert_eq!(
+            show(&entries),
+            "session recorded 1970-01-01 00:01:40 UTC\n\
+             [+0.000s] $ echo 'a\tb'\n\
+             a\tb\n\
+             [+2.000s] $ false\n\
+             [exit status 1, took 0.000s]\n"
+        );
+
+        std::fs::write(&path, "text\n").unwrap();
+        assert_eq!(
+            load(&path).err().unwrap(),
+            format!("{}: not a recorded session", path)
+        );
+        std::fs::write(&path, format!("{}\n1\t2\n", HEADER)).unwrap();
+        assert_eq!(
+            load(&path).err().unwrap(),
+            format!("{}:2: invalid entry", path)
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(show(&[]), "empty session\n");
+    }
+}
@@ -0,0 +1,685 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+
+use crate::expand;
+use crate::lexer::Token;
+use crate::printf;
+use crate::vars::{self, Variables};
+
+// the fcntl commands have the values of Linux
+#[cfg(not(target_os = "linux"))]
+compile_error!("redirections are only written for Linux");
+
+extern "C" {
+    fn dup2(oldfd: c_int, newfd: c_int) -> c_int;
+    fn close(fd: c_int) -> c_int;
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+const F_DUPFD: c_int = 0;
+const F_GETFD: c_int = 1;
+const F_DUPFD_CLOEXEC: c_int = 1030;
+
+// descriptors saved while the command runs and allocated by {name}>file
+// start here, so they don't take the ones used by scripts
+const FIRST_FREE: c_int = 10;
+
+enum Mode {
+    // >, refuses to overwrite existing file with noclobber option
+    Truncate,
+    // >|, always overwrites
+    Clobber,
+    // >>
+    Append,
+    // <
+    Read,
+    // <>
+    ReadWrite,
+}
+
+enum Target {
+    File(String, Mode),
+    // >&word and <&word, the word is a descriptor or - to close it
+    Duplicate(String),
+}
+
+// the redirected descriptor: a number or {name}, which allocates a new
+// descriptor and stores it in the variable
+enum Fd {
+    Number(c_int),
+    Variable(String),
+}
+
+pub struct Redirect {
+    fd: Fd,
+    target: Target,
+}
+
+impl Redirect {
+    pub fn expand(self, vars: &mut Variables) -> Result<Self, String> {
+        let target = match self.target {
+            Target::File(path, mode) => Target::File(expand::expand_string(&path, vars)?, mode),
+            Target::Duplicate(word) => Target::Duplicate(expand::expand_string(&word, vars)?),
+        };
+        Ok(Redirect {
+            fd: self.fd,
+            target,
+        })
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        matches!(self.fd, Fd::Number(0))
+    }
+
+    // the redirection as it's written in the command, the target is quoted
+    pub fn text(&self) -> String {
+        let fd = match &self.fd {
+            Fd::Number(0) if matches!(self.target, Target::File(_, Mode::Read)) => String::new(),
+            Fd::Number(1) if !matches!(self.target, Target::File(_, Mode::Read)) => String::new(),
+            Fd::Number(fd) => fd.to_string(),
+            Fd::Variable(name) => format!("{{{}}}", name),
+        };
+        let (operator, word) = match &self.target {
+            Target::File(path, mode) => (
+                match mode {
+                    Mode::Truncate => ">",
+                    Mode::Clobber => ">|",
+                    Mode::Append => ">>",
+                    Mode::Read => "<",
+                    Mode::ReadWrite => "<>",
+                },
+                path,
+            ),
+            Target::Duplicate(word) => (
+                match self.fd {
+                    Fd::Number(0) => "<&",
+                    _ => ">&",
+                },
+                word,
+            ),
+        };
+        format!("{}{}{}", fd, operator, printf::quote(word))
+    }
+}
+
+// /dev/tcp/host/port and /dev/udp/host/port connect a socket like in bash
+fn connect(path: &str) -> Option<Result<File, String>> {
+    let (protocol, address) = path
+        .strip_prefix("/dev/tcp/")
+        .map(|address| ("tcp", address))
+        .or_else(|| {
+            path.strip_prefix("/dev/udp/")
+                .map(|address| ("udp", address))
+        })?;
+
+    let connected = || -> Result<OwnedFd, String> {
+        let (host, port) = address
+            .rsplit_once('/')
+            .ok_or_else(|| String::from("missing port"))?;
+        let port: u16 = port
+            .parse()
+            .map_err(|_| format!("{}: invalid port", port))?;
+        let addresses = (host, port)
+            .to_socket_addrs()
+            .map_err(|err| format!("{}: {}", host, err))?;
+
+        let mut last_error = format!("{}: host not found", host);
+        for address in addresses {
+            let socket = match protocol {
+                "tcp" => TcpStream::connect(address).map(OwnedFd::from),
+                _ => {
+                    let local = match address {
+                        SocketAddr::V4(_) => "0.0.0.0:0",
+                        SocketAddr::V6(_) => "[::]:0",
+                    };
+                    UdpSocket::bind(local)
+                        .and_then(|socket| socket.connect(address).map(|_| socket))
+                        .map(OwnedFd::from)
+                }
+            };
+            match socket {
+                Ok(socket) => return Ok(socket),
+                Err(err) => last_error = err.to_string(),
+            }
+        }
+        Err(last_error)
+    };
+
+    Some(
+        connected()
+            .map(File::from)
+            .map_err(|err| format!("{}: {}", path, err)),
+    )
+}
+
+// relative paths are in the working directory of the shell
+fn open(path: &str, mode: &Mode, vars: &Variables, noclobber: bool) -> Result<File, String> {
+    if let Some(socket) = connect(path) {
+        return socket;
+    }
+    let name = path;
+    let path = vars.path(path);
+
+    let mut options = OpenOptions::new();
+    match mode {
+        Mode::Truncate | Mode::Clobber => options.write(true).create(true).truncate(true),
+        Mode::Append => options.append(true).create(true),
+        Mode::Read => options.read(true),
+        Mode::ReadWrite => options.read(true).write(true).create(true),
+    };
+
+    if matches!(mode, Mode::Truncate) && noclobber {
+        // only regular files are protected, so > /dev/null still works
+        if fs::metadata(&path).is_ok_and(|metadata| metadata.is_file()) {
+            return Err(format!("{}: cannot overwrite existing file", name));
+        }
+    }
+
+    options
+        .open(&path)
+        .map_err(|err| format!("{}: {}", name, err))
+}
+
+fn os_error(name: &str) -> String {
+    let err = io::Error::last_os_error();
+    // "Bad file descriptor (os error 9)" -> "Bad file descriptor"
+    let message = err.to_string();
+    let message = message.split(" (os error").next().unwrap_or_default();
+    format!("{}: {}", name, message)
+}
+
+// copy of the descriptor which is closed on exec
+fn save(fd: c_int) -> Option<c_int> {
+    match unsafe { fcntl(fd, F_DUPFD_CLOEXEC, FIRST_FREE) } {
+        -1 => None,
+        saved => Some(saved),
+    }
+}
+
+// descriptors replaced by redirections of the command and their saved copies,
+// None if the descriptor was closed
+#[derive(Default)]
+pub struct Saved(Vec<(c_int, Option<c_int>)>);
+
+impl Saved {
+    // put back the descriptors in the reverse order, so the one redirected
+    // twice gets its first copy
+    pub fn restore(self) {
+        for (fd, saved) in self.0.into_iter().rev() {
+            unsafe {
+                match saved {
+                    Some(saved) => {
+                        dup2(saved, fd);
+                        close(saved);
+                    }
+                    None => {
+                        close(fd);
+                    }
+                }
+            }
+        }
+    }
+
+    // the redirections stay, like with exec without a command
+    pub fn keep(self) {
+        for (_, saved) in self.0 {
+            if let Some(saved) = saved {
+                unsafe {
+                    close(saved);
+                }
+            }
+        }
+    }
+
+    fn replace(&mut self, fd: c_int, source: c_int) -> Result<(), String> {
+        // checked first, the saved copy could take the closed descriptor
+        if unsafe { fcntl(source, F_GETFD) } == -1 {
+            return Err(os_error(&source.to_string()));
+        }
+        self.0.push((fd, save(fd)));
+        if unsafe { dup2(source, fd) } == -1 {
+            return Err(os_error(&source.to_string()));
+        }
+        Ok(())
+    }
+
+    fn replace_with_file(
+        &mut self,
+        fd: c_int,
+        open: impl Fn() -> Result<File, String>,
+    ) -> Result<(), String> {
+        // saved before opening, the file may get the descriptor if it's closed
+        self.0.push((fd, save(fd)));
+        let file = open()?;
+        if file.as_raw_fd() == fd {
+            // the descriptor stays open without the file
+            let _ = file.into_raw_fd();
+        } else if unsafe { dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(os_error(&fd.to_string()));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, fd: c_int) {
+        self.0.push((fd, save(fd)));
+        unsafe {
+            close(fd);
+        }
+    }
+
+    fn apply(
+        &mut self,
+        redirect: &Redirect,
+        vars: &mut Variables,
+        noclobber: bool,
+    ) -> Result<(), String> {
+        match (&redirect.fd, &redirect.target) {
+            (Fd::Number(fd), Target::File(path, mode)) => {
+                self.replace_with_file(*fd, || open(path, mode, vars, noclobber))
+            }
+            (Fd::Number(fd), Target::Duplicate(word)) if word == "-" => {
+                self.close(*fd);
+                Ok(())
+            }
+            (Fd::Number(fd), Target::Duplicate(word)) => match word.parse::<c_int>() {
+                Ok(source) if source == *fd => Ok(()),
+                Ok(source) => self.replace(*fd, source),
+                Err(_) => Err(format!("{}: ambiguous redirect", word)),
+            },
+            // {name}>&- closes the descriptor stored in the variable
+            (Fd::Variable(name), Target::Duplicate(word)) if word == "-" => {
+                match vars.get(name).and_then(|fd| fd.parse::<c_int>().ok()) {
+                    Some(fd) => {
+                        unsafe {
+                            close(fd);
+                        }
+                        Ok(())
+                    }
+                    None => Err(format!("{}: ambiguous redirect", name)),
+                }
+            }
+            (Fd::Variable(name), target) => {
+                let source = match target {
+                    Target::File(path, mode) => open(path, mode, vars, noclobber)?.into_raw_fd(),
+                    Target::Duplicate(word) => word
+                        .parse::<c_int>()
+                        .map_err(|_| format!("{}: ambiguous redirect", word))?,
+                };
+                // allocated descriptors stay open after the command
+                let fd = unsafe { fcntl(source, F_DUPFD, FIRST_FREE) };
+                if matches!(target, Target::File(..)) {
+                    unsafe {
+                        close(source);
+                    }
+                }
+                if fd == -1 {
+                    return Err(os_error(&source.to_string()));
+                }
+                vars.set(name, &fd.to_string())
+            }
+        }
+    }
+}
+
+// apply the redirections to the shell descriptors from left to right, the
+// command run after it inherits them; on error already applied ones are
+// restored
+pub fn apply(
+    redirects: &[Redirect],
+    vars: &mut Variables,
+    noclobber: bool,
+) -> Result<Saved, String> {
+    let mut saved = Saved::default();
+    for redirect in redirects {
+        if let Err(err) = saved.apply(redirect, vars, noclobber) {
+            saved.restore();
+            return Err(err);
+        }
+    }
+    Ok(saved)
+}
+
+// where output of descriptor 1 or 2 goes when Shell::eval captures it: the
+// writer of the output, the one of the errors, or the descriptor itself
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Route {
+    Stdout,
+    Stderr,
+    Fd,
+}
+
+// routes of descriptors 1 and 2 after the redirections, which are followed
+// from left to right: 2>&1 >file sends the errors to the writer of the output
+pub fn routes(redirects: &[Redirect]) -> [Route; 2] {
+    let mut routes = [Route::Stdout, Route::Stderr];
+    for redirect in redirects {
+        let fd = match redirect.fd {
+            Fd::Number(fd @ (1 | 2)) => fd as usize,
+            _ => continue,
+        };
+        routes[fd - 1] = match &redirect.target {
+            Target::Duplicate(word) if word == "1" => routes[0],
+            Target::Duplicate(word) if word == "2" => routes[1],
+            _ => Route::Fd,
+        };
+    }
+    routes
+}
+
+// standard input descriptor as a file for builtins reading it, the shell's
+// own buffered stdin may already hold input which isn't for the command
+pub fn stdin() -> Result<File, String> {
+    match unsafe { fcntl(0, F_DUPFD_CLOEXEC, FIRST_FREE) } {
+        -1 => Err(os_error("0")),
+        fd => Ok(unsafe { File::from_raw_fd(fd) }),
+    }
+}
+
+// redirection operator with optional descriptor: 2>, 2>&, >>, <, <>, {fd}>
+fn operator(op: &str) -> Result<(Fd, Target), String> {
+    let start = op.find(['<', '>']).unwrap_or(op.len());
+    let (prefix, op) = op.split_at(start);
+    let (default, target) = match op {
+        ">|" => (1, Target::File(String::new(), Mode::Clobber)),
+        ">>" => (1, Target::File(String::new(), Mode::Append)),
+        ">" => (1, Target::File(String::new(), Mode::Truncate)),
+        "<" => (0, Target::File(String::new(), Mode::Read)),
+        "<>" => (0, Target::File(String::new(), Mode::ReadWrite)),
+        ">&" => (1, Target::Duplicate(String::new())),
+        "<&" => (0, Target::Duplicate(String::new())),
+        _ => return Err(format!("unsupported redirection: {}{}", prefix, op)),
+    };
+
+    let fd = match prefix {
+        "" => Fd::Number(default),
+        _ if prefix.starts_with('{') && prefix.ends_with('}') => {
+            let name = &prefix[1..prefix.len() - 1];
+            if !vars::is_name(name) {
+                return Err(format!("{}: invalid variable name for redirection", prefix));
+            }
+            Fd::Variable(String::from(name))
+        }
+        _ => Fd::Number(
+            prefix
+                .parse()
+                .map_err(|_| format!("{}: bad file descriptor", prefix))?,
+        ),
+    };
+    Ok((fd, target))
+}
+
+// remove redirections (N> file, N>> file, N>| file, N< file, N<> file,
+// N>&M, N<&M, N>&- and {name}> file) from the command tokens, they are applied
+// from left to right; file names are not expanded yet
+pub fn parse(tokens: Vec<Token>) -> Result<(Vec<String>, Vec<Redirect>), String> {
+    let mut words = vec![];
+    let mut redirects = vec![];
+
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => words.push(word),
+            Token::Op(op) => {
+                let (fd, target) = operator(&op)?;
+                let word = match tokens.next() {
+                    Some(Token::Word(word)) => word,
+                    _ => return Err(String::from("syntax error: redirection without file")),
+                };
+                let target = match target {
+                    Target::File(_, mode) => Target::File(word, mode),
+                    Target::Duplicate(_) => Target::Duplicate(word),
+                };
+                redirects.push(Redirect { fd, target });
+            }
+        }
+    }
+
+    Ok((words, redirects))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::options::Options;
+    use std::env;
+    use std::io::Write;
+    use std::mem::ManuallyDrop;
+
+    fn parsed(line: &str) -> Result<(Vec<String>, Vec<Redirect>), String> {
+        parse(lexer::tokenize(line).unwrap())
+    }
+
+    // apply redirections of the line, descriptors used by the tests are high
+    // so they don't touch the standard ones of the test harness
+    fn applied(line: &str, vars: &mut Variables, noclobber: bool) -> Result<Saved, String> {
+        let (_, redirects) = parsed(line).unwrap();
+        let redirects = redirects
+            .into_iter()
+            .map(|redirect| redirect.expand(vars))
+            .collect::<Result<Vec<_>, _>>()?;
+        apply(&redirects, vars, noclobber)
+    }
+
+    fn write_fd(fd: c_int, text: &str) {
+        let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn is_open(fd: c_int) -> bool {
+        unsafe { fcntl(fd, F_GETFD) != -1 }
+    }
+
+    // a file name in the temporary directory unique to the test
+    fn temporary(name: &str) -> String {
+        let path = env::temp_dir().join(format!("redirect-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path.display().to_string()
+    }
+
+    #[test]
+    fn routes_follow_the_duplications() {
+        let routes = |line: &str| routes(&parsed(line).unwrap().1);
+        assert_eq!(routes("x"), [Route::Stdout, Route::Stderr]);
+        assert_eq!(routes("x >&2"), [Route::Stderr, Route::Stderr]);
+        assert_eq!(routes("x 2>&1"), [Route::Stdout, Route::Stdout]);
+        assert_eq!(routes("x 2>&1 >f"), [Route::Fd, Route::Stdout]);
+        assert_eq!(routes("x >f 2>&1"), [Route::Fd, Route::Fd]);
+        assert_eq!(routes("x 1>&2 2>&1"), [Route::Stderr, Route::Stderr]);
+        assert_eq!(routes("x 2>&3 3>f"), [Route::Stdout, Route::Fd]);
+    }
+
+    #[test]
+    fn redirections_are_written_back() {
+        let line = "x >out 2>>'a b' <in <>rw 3<&0 >&2 {REDIRECT_TEST_FD}>|f";
+        let (_, redirects) = parsed(line).unwrap();
+        let texts: Vec<String> = redirects
+            .into_iter()
+            .map(|redirect| {
+                redirect
+                    .expand(&mut Variables::new(&Options::new()))
+                    .unwrap()
+                    .text()
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                ">out",
+                "2>>a\\ b",
+                "<in",
+                "0<>rw",
+                "3>&0",
+                ">&2",
+                "{REDIRECT_TEST_FD}>|f"
+            ]
+        );
+    }
+
+    #[test]
+    fn redirections_are_removed() {
+        let (command, redirects) = parsed("echo a > out b 2>&1 <in").unwrap();
+        assert_eq!(command, ["echo", "a", "b"]);
+        assert!(matches!(
+            &redirects[0],
+            Redirect { fd: Fd::Number(1), target: Target::File(path, Mode::Truncate) } if path == "out"
+        ));
+        assert!(matches!(
+            &redirects[1],
+            Redirect { fd: Fd::Number(2), target: Target::Duplicate(word) } if word == "1"
+        ));
+        assert!(redirects[2].is_stdin());
+
+        let (_, redirects) = parsed("echo >>a 3<>b >|c {log}>d 4<&-").unwrap();
+        assert!(matches!(redirects[0].target, Target::File(_, Mode::Append)));
+        assert!(matches!(
+            redirects[1],
+            Redirect {
+                fd: Fd::Number(3),
+                target: Target::File(_, Mode::ReadWrite)
+            }
+        ));
+        assert!(matches!(
+            redirects[2].target,
+            Target::File(_, Mode::Clobber)
+        ));
+        assert!(matches!(&redirects[3].fd, Fd::Variable(name) if name == "log"));
+        assert!(matches!(&redirects[4].target, Target::Duplicate(word) if word == "-"));
+
+        assert!(parsed("echo a >").is_err());
+        // not a name, so it's an argument
+        let (command, redirects) = parsed("echo {1x}>a").unwrap();
+        assert_eq!(command, ["echo", "{1x}"]);
+        assert!(matches!(redirects[0].fd, Fd::Number(1)));
+    }
+
+    #[test]
+    fn descriptors_are_restored() {
+        let path = temporary("restore");
+        let mut vars = Variables::new(&Options::new());
+        let saved = applied(&format!("60>{} 61>&60", path), &mut vars, false).unwrap();
+        write_fd(60, "a");
+        write_fd(61, "b");
+        saved.restore();
+        assert!(!is_open(60) && !is_open(61));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ab");
+
+        let saved = applied(&format!("62>>{}", path), &mut vars, false).unwrap();
+        write_fd(62, "c");
+        saved.keep();
+        assert!(is_open(62));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc");
+        applied("62>&-", &mut vars, false).unwrap().keep();
+        assert!(!is_open(62));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn variables_get_new_descriptors() {
+        let path = temporary("variable");
+        let mut vars = Variables::new(&Options::new());
+        applied(&format!("{{REDIRECT_TEST_FD}}>{}", path), &mut vars, false)
+            .unwrap()
+            .restore();
+        let fd: c_int = vars.get("REDIRECT_TEST_FD").unwrap().parse().unwrap();
+        assert!(fd >= FIRST_FREE);
+        write_fd(fd, "kept");
+        applied("{REDIRECT_TEST_FD}>&-", &mut vars, false)
+            .unwrap()
+            .restore();
+        assert!(!is_open(fd));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "kept");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn errors_restore_applied_redirections() {
+        let path = temporary("errors");
+        let mut vars = Variables::new(&Options::new());
+        assert_eq!(
+            applied(&format!("63>{} 64>&65", path), &mut vars, false)
+                .err()
+                .unwrap(),
+            "65: Bad file descriptor"
+        );
+        assert!(!is_open(63));
+        assert_eq!(
+            applied("64>&x", &mut vars, false).err().unwrap(),
+            "x: ambiguous redirect"
+        );
+        assert_eq!(
+            applied(&format!("66>{}", path), &mut vars, true)
+                .err()
+                .unwrap(),
+            format!("{}: cannot overwrite existing file", path)
+        );
+        applied(&format!("66>|{}", path), &mut vars, true)
+            .unwrap()
+            .restore();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sockets_are_connected() {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let mut text = String::new();
+            listener
+                .accept()
+                .unwrap()
+                .0
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        });
+
+        let path = format!("/dev/tcp/127.0.0.1/{}", port);
+        let mut socket = open(
+            &path,
+            &Mode::ReadWrite,
+            &Variables::new(&Options::new()),
+            false,
+        )
+        .unwrap();
+        socket.write_all(b"hello").unwrap();
+        drop(socket);
+        assert_eq!(server.join().unwrap(), "hello");
+
+        assert!(open(
+            "/dev/udp/127.0.0.1/9",
+            &Mode::Truncate,
+            &Variables::new(&Options::new()),
+            false
+        )
+        .is_ok());
+        assert_eq!(
+            open(
+                "/dev/tcp/localhost/x",
+                &Mode::Read,
+                &Variables::new(&Options::new()),
+                false
+            )
+            .err()
+            .unwrap(),
+            "/dev/tcp/localhost/x: x: invalid port"
+        );
+        assert_eq!(
+            open(
+                "/dev/tcp/localhost",
+                &Mode::Read,
+                &Variables::new(&Options::new()),
+                false
+            )
+            .err()
+            .unwrap(),
+            "/dev/tcp/localhost: missing port"
+        );
+    }
+}
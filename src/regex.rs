@@ -0,0 +1,462 @@
+// small matcher of POSIX extended regular expressions used by
+// [[ text =~ regex ]], the leftmost match wins and quantifiers are greedy
+
+enum Node {
+    Char(char),
+    Any,
+    // ranges of [...] and the negation flag
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    // the group and its number for captures
+    Group(Box<Node>, usize),
+    Alternation(Vec<Node>),
+    Sequence(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+// ranges of [:name:] character class
+fn named_class(name: &str) -> Option<Vec<(char, char)>> {
+    Some(match name {
+        "alpha" => vec![('a', 'z'), ('A', 'Z')],
+        "digit" => vec![('0', '9')],
+        "alnum" => vec![('a', 'z'), ('A', 'Z'), ('0', '9')],
+        "upper" => vec![('A', 'Z')],
+        "lower" => vec![('a', 'z')],
+        "xdigit" => vec![('0', '9'), ('a', 'f'), ('A', 'F')],
+        "space" => vec![(' ', ' '), ('\t', '\r')],
+        "blank" => vec![(' ', ' '), ('\t', '\t')],
+        "punct" => vec![('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+        "cntrl" => vec![('\0', '\x1f'), ('\x7f', '\x7f')],
+        "print" => vec![(' ', '~')],
+        "graph" => vec![('!', '~')],
+        _ => return None,
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.index += 1;
+            branches.push(self.sequence()?);
+        }
+
+        Ok(match branches.len() {
+            1 => branches.pop().unwrap(),
+            _ => Node::Alternation(branches),
+        })
+    }
+
+    fn sequence(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+
+        Ok(Node::Sequence(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars[self.index];
+        self.index += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => self.class()?,
+            '(' => {
+                self.groups += 1;
+                let group = self.groups;
+                let inner = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(String::from("parentheses not balanced"));
+                }
+                self.index += 1;
+                Node::Group(Box::new(inner), group)
+            }
+            '\\' => match self.peek() {
+                Some(c) => {
+                    self.index += 1;
+                    Node::Char(c)
+                }
+                None => return Err(String::from("trailing backslash")),
+            },
+            '*' | '+' | '?' => return Err(String::from("invalid preceding regular expression")),
+            c => Node::Char(c),
+        })
+    }
+
+    // {m}, {m,} and {m,n}, None if it's not a valid interval
+    fn interval(&mut self) -> Option<(usize, Option<usize>)> {
+        let close = self.index + self.chars[self.index..].iter().position(|&c| c == '}')?;
+        let inner: String = self.chars[self.index + 1..close].iter().collect();
+        let (min, max) = match inner.split_once(',') {
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+            None => {
+                let count = inner.parse().ok()?;
+                (count, Some(count))
+            }
+        };
+        self.index = close + 1;
+        Some((min, max))
+    }
+
+    fn quantified(&mut self, mut atom: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.interval() {
+                    Some((min, max))
+                        if max.is_some_and(|max| max < min) || max.unwrap_or(min) > MAX_REPEAT =>
+                    {
+                        return Err(String::from("invalid content of \\{\\}"))
+                    }
+                    Some((min, max)) => {
+                        atom = Node::Repeat(Box::new(atom), min, max);
+                        continue;
+                    }
+                    // not an interval, { is literal
+                    None => return Ok(atom),
+                },
+                _ => return Ok(atom),
+            };
+            self.index += 1;
+            atom = Node::Repeat(Box::new(atom), min, max);
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negate = self.peek() == Some('^');
+        if negate {
+            self.index += 1;
+        }
+
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(String::from("brackets ([ ]) not balanced")),
+            };
+            self.index += 1;
+            if c == ']' && !first {
+                return Ok(Node::Class(ranges, negate));
+            }
+            first = false;
+
+            if c == '[' && self.peek() == Some(':') {
+                let rest: String = self.chars[self.index..].iter().collect();
+                if let Some(end) = rest.find(":]") {
+                    let name = &rest[1..end];
+                    ranges.extend(
+                        named_class(name).ok_or_else(|| String::from("invalid character class"))?,
+                    );
+                    self.index += rest[..end + 2].chars().count();
+                    continue;
+                }
+            }
+
+            match (self.peek(), self.chars.get(self.index + 1)) {
+                (Some('-'), Some(&end)) if end != ']' => {
+                    if end < c {
+                        return Err(String::from("invalid range end"));
+                    }
+                    ranges.push((c, end));
+                    self.index += 2;
+                }
+                _ => ranges.push((c, c)),
+            }
+        }
+    }
+}
+
+// the node compiled to the instructions of a Pike VM: the threads go through
+// the text together and at most one is at each instruction, so the time
+// doesn't grow exponentially with nested quantifiers
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    // the position is stored in the slot, two for each group
+    Save(usize),
+    // both ways are followed, the first one is preferred
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+// POSIX RE_DUP_MAX, larger intervals would make huge programs
+const MAX_REPEAT: usize = 255;
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(ranges, negate) => program.push(Inst::Class(ranges.clone(), *negate)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(inner, group) => {
+            program.push(Inst::Save(2 * group));
+            compile(inner, program);
+            program.push(Inst::Save(2 * group + 1));
+        }
+        Node::Alternation(branches) => {
+            let mut jumps = vec![];
+            for (index, branch) in branches.iter().enumerate() {
+                if index + 1 == branches.len() {
+                    compile(branch, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(branch, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        }
+        Node::Sequence(nodes) => {
+            for node in nodes {
+                compile(node, program);
+            }
+        }
+        Node::Repeat(inner, min, max) => {
+            for _ in 0..*min {
+                compile(inner, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(inner, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    // each optional copy is skipped with the rest of them
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(inner, program);
+                    }
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, program.len());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// a thread at an instruction with the positions stored in its slots
+type Thread = (usize, Vec<Option<usize>>);
+
+struct Machine<'a> {
+    program: &'a [Inst],
+    text: &'a [char],
+}
+
+impl Machine<'_> {
+    // follow the jumps, splits, saves and anchors at the position, the
+    // threads are added in the order of preference
+    fn add(
+        &self,
+        threads: &mut Vec<Thread>,
+        seen: &mut [bool],
+        pc: usize,
+        pos: usize,
+        mut slots: Vec<Option<usize>>,
+    ) {
+        if seen[pc] {
+            return;
+        }
+        seen[pc] = true;
+        match &self.program[pc] {
+            Inst::Jump(to) => self.add(threads, seen, *to, pos, slots),
+            Inst::Split(first, second) => {
+                self.add(threads, seen, *first, pos, slots.clone());
+                self.add(threads, seen, *second, pos, slots);
+            }
+            Inst::Save(slot) => {
+                slots[*slot] = Some(pos);
+                self.add(threads, seen, pc + 1, pos, slots);
+            }
+            Inst::Start if pos == 0 => self.add(threads, seen, pc + 1, pos, slots),
+            Inst::End if pos == self.text.len() => self.add(threads, seen, pc + 1, pos, slots),
+            Inst::Start | Inst::End => {}
+            _ => threads.push((pc, slots)),
+        }
+    }
+
+    // the slots of the leftmost match, a thread which is preferred over the
+    // matched one may still find a longer match
+    fn run(&self, slots: usize) -> Option<Vec<Option<usize>>> {
+        let mut matched = None;
+        let mut current = vec![];
+        let mut seen = vec![false; self.program.len()];
+        for pos in 0..=self.text.len() {
+            // until there is a match a new one may start at each position
+            if matched.is_none() {
+                self.add(&mut current, &mut seen, 0, pos, vec![None; slots]);
+            }
+            if current.is_empty() && matched.is_some() {
+                break;
+            }
+            let mut next = vec![];
+            let mut next_seen = vec![false; self.program.len()];
+            for (pc, slots) in current {
+                let c = self.text.get(pos).copied();
+                let step = match (&self.program[pc], c) {
+                    (Inst::Match, _) => {
+                        matched = Some(slots);
+                        // the remaining threads are less preferred
+                        break;
+                    }
+                    (_, None) => false,
+                    (Inst::Char(expected), Some(c)) => *expected == c,
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class(ranges, negate), Some(c)) => {
+                        ranges.iter().any(|&(start, end)| start <= c && c <= end) != *negate
+                    }
+                    _ => false,
+                };
+                if step {
+                    self.add(&mut next, &mut next_seen, pc + 1, pos + 1, slots);
+                }
+            }
+            current = next;
+            seen = next_seen;
+        }
+        matched
+    }
+}
+
+// find the leftmost match of the regex in the text, the result has the whole
+// match and the groups, groups which didn't participate are empty
+pub fn captures(regex: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+    let mut parser = Parser {
+        chars: regex.chars().collect(),
+        index: 0,
+        groups: 0,
+    };
+    let root = parser.alternation()?;
+    if parser.index < parser.chars.len() {
+        return Err(String::from("parentheses not balanced"));
+    }
+
+    let mut program = vec![Inst::Save(0)];
+    compile(&root, &mut program);
+    program.push(Inst::Save(1));
+    program.push(Inst::Match);
+
+    let text: Vec<char> = text.chars().collect();
+    let machine = Machine {
+        program: &program,
+        text: &text,
+    };
+    let slots = match machine.run(2 * (parser.groups + 1)) {
+        Some(slots) => slots,
+        None => return Ok(None),
+    };
+    let captures = slots
+        .chunks(2)
+        .map(|slot| match slot {
+            [Some(start), Some(end)] => text[*start..*end].iter().collect(),
+            _ => String::new(),
+        })
+        .collect();
+    Ok(Some(captures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched(regex: &str, text: &str) -> Option<Vec<String>> {
+        captures(regex, text).unwrap()
+    }
+
+    #[test]
+    fn leftmost_greedy_match() {
+        assert_eq!(matched("b+", "abbbc").unwrap(), ["bbb"]);
+        assert_eq!(matched("a|ab", "xab").unwrap(), ["a"]);
+        assert_eq!(matched("^a.c$", "abc").unwrap(), ["abc"]);
+        assert_eq!(matched("^b", "abc"), None);
+        assert_eq!(matched("x?", "abc").unwrap(), [""]);
+    }
+
+    #[test]
+    fn groups_are_captured() {
+        assert_eq!(
+            matched("([a-z]+)-([0-9]{2,3})", "id: abc-1234").unwrap(),
+            ["abc-123", "abc", "123"]
+        );
+        assert_eq!(matched("(a)|(b)", "b").unwrap(), ["b", "", "b"]);
+        assert_eq!(matched("(a|b)*", "abba").unwrap(), ["abba", "a"]);
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(matched("[[:digit:]]+", "a42").unwrap(), ["42"]);
+        assert_eq!(matched("[^a-c]", "abcd").unwrap(), ["d"]);
+        assert_eq!(matched("[]x]", "]").unwrap(), ["]"]);
+        assert_eq!(matched("a\\.b", "a.b").unwrap(), ["a.b"]);
+        assert_eq!(matched("a{x", "a{x").unwrap(), ["a{x"]);
+    }
+
+    #[test]
+    fn nested_quantifiers_take_linear_time() {
+        let text = "a".repeat(5000);
+        assert_eq!(matched("^(a*)*c$", &text), None);
+        assert_eq!(matched("^(a|aa)+$", &text).unwrap()[0], text);
+        assert_eq!(matched("(a*)*b", "aab").unwrap(), ["aab", "aa"]);
+        assert_eq!(matched("a{2}(b{1,2})?", "aabbb").unwrap(), ["aabb", "bb"]);
+    }
+
+    #[test]
+    fn invalid_regexes() {
+        assert_eq!(captures("(a", "").unwrap_err(), "parentheses not balanced");
+        assert_eq!(captures("a)", "").unwrap_err(), "parentheses not balanced");
+        assert_eq!(
+            captures("[a", "").unwrap_err(),
+            "brackets ([ ]) not balanced"
+        );
+        assert_eq!(captures("[z-a]", "").unwrap_err(), "invalid range end");
+        assert_eq!(
+            captures("*a", "").unwrap_err(),
+            "invalid preceding regular expression"
+        );
+        assert_eq!(
+            captures("a{3,1}", "").unwrap_err(),
+            "invalid content of \\{\\}"
+        );
+        assert_eq!(
+            captures("a{1000}", "").unwrap_err(),
+            "invalid content of \\{\\}"
+        );
+    }
+}
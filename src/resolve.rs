@@ -0,0 +1,56 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use crate::osstr;
+
+// finds the programs run for the commands which aren't builtins. The shell
+// uses PathResolver, a program embedding it may give its own one, e.g. to run
+// the commands in a container or on another machine; it can wrap PathResolver
+// to fall back to the local programs
+pub trait Resolver {
+    // the program for the name, type shows it; None if there's no such program.
+    // path is PATH of the shell, which programs get in their environment
+    fn find(&self, name: &str, path: Option<&str>) -> Result<Option<String>, String>;
+
+    // the command which runs the program found by find, the shell adds the
+    // arguments and the standard streams
+    fn command(&self, program: &str) -> process::Command {
+        process::Command::new(osstr::to_os(program))
+    }
+}
+
+// programs in the directories of PATH
+pub struct PathResolver;
+
+impl Resolver for PathResolver {
+    fn find(&self, name: &str, path: Option<&str>) -> Result<Option<String>, String> {
+        find_in_path(name, path)
+    }
+}
+
+pub fn find_in_path(command_name: &str, path: Option<&str>) -> Result<Option<String>, String> {
+    match path {
+        Some(value) => {
+            // names are compared as bytes, so programs with non UTF-8 names are
+            // found too; directories which can't be read are skipped
+            let command_name = osstr::to_os(command_name);
+            for directory in env::split_paths(&osstr::to_os(value)) {
+                let dir_entries = match fs::read_dir(&directory) {
+                    Ok(dir_entries) => dir_entries,
+                    Err(_) => continue,
+                };
+                for dir_entry in dir_entries.flatten() {
+                    if dir_entry.file_name() == command_name {
+                        return Ok(Some(osstr::from_os(dir_entry.path().as_os_str())));
+                    }
+                }
+            }
+
+            Ok(None)
+        }
+        None => Err(String::from(
+            "failed to get PATH variable to find commands in system folders",
+        )),
+    }
+}
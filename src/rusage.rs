@@ -0,0 +1,250 @@
+use std::io::{self, Read};
+use std::os::raw::{c_int, c_long};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{self, ExitStatus};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RUSAGE_SELF: c_int = 0;
+const RUSAGE_CHILDREN: c_int = -1;
+
+// struct rusage and wait4 of glibc on Linux
+#[cfg(not(target_os = "linux"))]
+compile_error!("resource usage is only written for Linux");
+
+#[repr(C)]
+struct TimeVal {
+    sec: c_long,
+    usec: c_long,
+}
+
+// linux struct rusage, the other counters are not used here
+#[repr(C)]
+struct RUsage {
+    utime: TimeVal,
+    stime: TimeVal,
+    // kilobytes
+    maxrss: c_long,
+    memory: [c_long; 6],
+    // 512-byte blocks read and written by the file system
+    inblock: c_long,
+    oublock: c_long,
+    counters: [c_long; 5],
+}
+
+impl RUsage {
+    fn new() -> Self {
+        RUsage {
+            utime: TimeVal { sec: 0, usec: 0 },
+            stime: TimeVal { sec: 0, usec: 0 },
+            maxrss: 0,
+            memory: [0; 6],
+            inblock: 0,
+            oublock: 0,
+            counters: [0; 5],
+        }
+    }
+}
+
+extern "C" {
+    fn getrusage(who: c_int, usage: *mut RUsage) -> c_int;
+    fn wait4(pid: c_int, status: *mut c_int, options: c_int, usage: *mut RUsage) -> c_int;
+}
+
+// user and system CPU time
+pub struct Times {
+    pub user: Duration,
+    pub system: Duration,
+}
+
+fn duration(time: &TimeVal) -> Duration {
+    Duration::from_secs(time.sec as u64) + Duration::from_micros(time.usec as u64)
+}
+
+fn times(who: c_int) -> Times {
+    let mut usage = RUsage::new();
+    unsafe {
+        getrusage(who, &mut usage);
+    }
+
+    Times {
+        user: duration(&usage.utime),
+        system: duration(&usage.stime),
+    }
+}
+
+// CPU time of the shell itself
+pub fn shell() -> Times {
+    times(RUSAGE_SELF)
+}
+
+// CPU time of the waited children
+pub fn children() -> Times {
+    times(RUSAGE_CHILDREN)
+}
+
+// format the time like the times builtin: 0m1.250s
+pub fn format(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}m{}.{:03}s", secs / 60, secs % 60, time.subsec_millis())
+}
+
+// resources used by a finished program
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub real: Duration,
+    pub user: Duration,
+    pub system: Duration,
+    // kilobytes
+    pub max_rss: u64,
+    // bytes read and written by the file system, not by pipes or terminals
+    pub read: u64,
+    pub written: u64,
+}
+
+// wait for the program instead of child.wait(), the resources are given by
+// the kernel only when it's reaped; started is when the program was spawned
+pub fn wait(child: &process::Child, started: Instant) -> io::Result<(ExitStatus, Stats)> {
+    let mut status = 0;
+    let mut usage = RUsage::new();
+    loop {
+        if unsafe { wait4(child.id() as c_int, &mut status, 0, &mut usage) } >= 0 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    let stats = Stats {
+        real: started.elapsed(),
+        user: duration(&usage.utime),
+        system: duration(&usage.stime),
+        max_rss: usage.maxrss.max(0) as u64,
+        read: usage.inblock.max(0) as u64 * 512,
+        written: usage.oublock.max(0) as u64 * 512,
+    };
+    Ok((ExitStatus::from_raw(status), stats))
+}
+
+// both pipes are read at once so the program doesn't block on a full one,
+// the output is given to the function as it comes, true for the error one
+pub fn wait_with_output(
+    mut child: process::Child,
+    started: Instant,
+    output: &mut dyn FnMut(&[u8], bool),
+) -> io::Result<(ExitStatus, Stats)> {
+    let (sender, chunks) = mpsc::channel();
+    let readers: Vec<_> = [
+        child
+            .stdout
+            .take()
+            .map(|pipe| (Box::new(pipe) as Box<dyn Read + Send>, false)),
+        child
+            .stderr
+            .take()
+            .map(|pipe| (Box::new(pipe) as Box<dyn Read + Send>, true)),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|(mut pipe, error)| {
+        let sender = sender.clone();
+        thread::spawn(move || -> io::Result<()> {
+            let mut buffer = [0; 8192];
+            loop {
+                match pipe.read(&mut buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(read) => {
+                        let _ = sender.send((buffer[..read].to_vec(), error));
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        })
+    })
+    .collect();
+    drop(sender);
+    for (bytes, error) in chunks {
+        output(&bytes, error);
+    }
+    for reader in readers {
+        match reader.join() {
+            Ok(result) => result?,
+            Err(_) => return Err(io::Error::other("failed to read the output")),
+        }
+    }
+
+    wait(&child, started)
+}
+
+// kilobytes in a short form: 512K, 120.5M, 2.0G
+pub fn format_size(kilobytes: u64) -> String {
+    match kilobytes {
+        0..=1023 => format!("{}K", kilobytes),
+        1024..=1048575 => format!("{:.1}M", kilobytes as f64 / 1024.0),
+        _ => format!("{:.1}G", kilobytes as f64 / 1048576.0),
+    }
+}
+
+// shown by jobs -l: real 3.2s, user 2.9s, sys 300ms, max rss 120.5M, read 0K, written 1.2M
+pub fn summary(stats: &Stats) -> String {
+    format!(
+        "real {}, user {}, sys {}, max rss {}, read {}, written {}",
+        crate::format_duration(stats.real),
+        crate::format_duration(stats.user),
+        crate::format_duration(stats.system),
+        format_size(stats.max_rss),
+        format_size(stats.read / 1024),
+        format_size(stats.written / 1024)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_are_formatted() {
+        assert_eq!(format(Duration::from_millis(1250)), "0m1.250s");
+        assert_eq!(format(Duration::from_millis(125_007)), "2m5.007s");
+    }
+
+    #[test]
+    fn shell_time_grows() {
+        let before = shell();
+        let started = std::time::Instant::now();
+        while started.elapsed() < Duration::from_millis(50) {}
+        let after = shell();
+        assert!(after.user + after.system > before.user + before.system);
+    }
+
+    #[test]
+    fn sizes_are_formatted() {
+        assert_eq!(format_size(512), "512K");
+        assert_eq!(format_size(123_392), "120.5M");
+        assert_eq!(format_size(2 * 1_048_576), "2.0G");
+    }
+
+    #[test]
+    fn programs_are_waited_for() {
+        let started = Instant::now();
+        let child = process::Command::new("/bin/sh")
+            .args(["-c", "echo out; echo err >&2; exit 3"])
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let (status, stats) = wait_with_output(child, started, &mut |bytes, error| match error {
+            true => stderr.extend_from_slice(bytes),
+            false => stdout.extend_from_slice(bytes),
+        })
+        .unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!((&stdout[..], &stderr[..]), (&b"out\n"[..], &b"err\n"[..]));
+        assert!(stats.max_rss > 0 && stats.real <= started.elapsed());
+    }
+}
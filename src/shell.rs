@@ -0,0 +1,906 @@
+use std::cell::RefCell;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::{complete, editor, osstr, prompt, signals};
+use crate::{init, CancelToken, CommandEnv, Frame, Handles, Resolver, RUN_INTERNAL};
+
+// status of the commands stopped with the cancel token, like after Ctrl-C
+pub(crate) const CANCELLED: i32 = 130;
+
+// what the commands given to Shell::eval did; output of the redirected
+// descriptors isn't there, it went to the files
+#[derive(Debug)]
+pub struct ExecResult {
+    pub status: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub duration: Duration,
+}
+
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// where the history of entered commands is kept
+pub enum HistoryBackend {
+    // HISTFILE or ~/.shell_history, only interactive shells keep the history
+    Default,
+    // in memory only, it's lost with the shell
+    Memory,
+    // the file, whether the shell is interactive or not
+    File(PathBuf),
+}
+
+// configuration of the shell for programs embedding it; by default the shell
+// has all the builtins and the environment, directory and standard streams of
+// the process, and it reads no startup files
+pub struct ShellBuilder {
+    builtins: Option<Vec<String>>,
+    excluded: Vec<String>,
+    // variables set, or removed with None
+    env: Vec<(String, Option<String>)>,
+    env_clear: bool,
+    current_dir: Option<PathBuf>,
+    history: HistoryBackend,
+    stdout: Option<Box<dyn Write>>,
+    stderr: Option<Box<dyn Write>>,
+    options: Vec<(String, bool)>,
+    name: Option<String>,
+    positional: Vec<String>,
+    login: bool,
+    interactive: bool,
+    rc_file: bool,
+    signals: bool,
+    resolver: Option<Rc<dyn Resolver>>,
+}
+
+impl ShellBuilder {
+    pub fn new() -> Self {
+        ShellBuilder {
+            builtins: None,
+            excluded: vec![],
+            env: vec![],
+            env_clear: false,
+            current_dir: None,
+            history: HistoryBackend::Default,
+            stdout: None,
+            stderr: None,
+            options: vec![],
+            name: None,
+            positional: vec![],
+            login: false,
+            interactive: false,
+            rc_file: false,
+            signals: false,
+            resolver: None,
+        }
+    }
+
+    // only these builtins are registered, other names are run as programs
+    pub fn builtins<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.builtins = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn without_builtin(mut self, name: impl Into<String>) -> Self {
+        self.excluded.push(name.into());
+        self
+    }
+
+    // the environment starts as a copy of the one of the process, programs
+    // run by the shell get it
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((name.into(), Some(value.into())));
+        self
+    }
+
+    pub fn env_remove(mut self, name: impl Into<String>) -> Self {
+        self.env.push((name.into(), None));
+        self
+    }
+
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    pub fn current_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(path.into());
+        self
+    }
+
+    pub fn history(mut self, backend: HistoryBackend) -> Self {
+        self.history = backend;
+        self
+    }
+
+    // output and errors of commands which aren't redirected go to the writers
+    pub fn stdout(mut self, writer: impl Write + 'static) -> Self {
+        self.stdout = Some(Box::new(writer));
+        self
+    }
+
+    pub fn stderr(mut self, writer: impl Write + 'static) -> Self {
+        self.stderr = Some(Box::new(writer));
+        self
+    }
+
+    // the same names as set -o takes
+    pub fn option(mut self, name: impl Into<String>, value: bool) -> Self {
+        self.options.push((name.into(), value));
+        self
+    }
+
+    // $0 and the positional parameters
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn positional(mut self, args: Vec<String>) -> Self {
+        self.positional = args;
+        self
+    }
+
+    // a login shell reads ~/.shell_profile
+    pub fn login(mut self, login: bool) -> Self {
+        self.login = login;
+        self
+    }
+
+    // an interactive shell shows prompts, reads ~/.inputrc and keeps history
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    // ~/.shellrc is read by an interactive shell
+    pub fn rc_file(mut self, read: bool) -> Self {
+        self.rc_file = read;
+        self
+    }
+
+    // SIGHUP, SIGTERM and SIGQUIT are forwarded to the running programs, it
+    // changes the handlers of the whole process
+    pub fn handle_signals(mut self, handle: bool) -> Self {
+        self.signals = handle;
+        self
+    }
+
+    // programs for the commands which aren't builtins are found by the
+    // resolver instead of being looked up in PATH
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Rc::new(resolver));
+        self
+    }
+
+    pub fn build(self) -> Result<Shell, String> {
+        let mut command_env = init();
+        let registered: Vec<String> = command_env.names().map(String::from).collect();
+        let chosen = self.builtins.iter().flatten();
+        if let Some(name) = chosen
+            .chain(&self.excluded)
+            .find(|name| !registered.contains(name) || *name == RUN_INTERNAL)
+        {
+            return Err(format!("{}: no such builtin", name));
+        }
+        command_env.commands.retain(|(name, _)| {
+            name == RUN_INTERNAL
+                || (self
+                    .builtins
+                    .as_ref()
+                    .map_or(true, |names| names.contains(name))
+                    && !self.excluded.contains(name))
+        });
+        for (name, value) in &self.options {
+            command_env.options.set(name, *value)?;
+        }
+
+        // the environment and the directory are the shell's own, the ones of
+        // the process don't change
+        let vars = &mut command_env.vars;
+        if self.env_clear {
+            vars.clear_environment();
+        }
+        for (name, value) in &self.env {
+            vars.set_environment(name, value.as_deref());
+        }
+        if let Some(path) = &self.current_dir {
+            vars.cwd = vars
+                .cwd
+                .join(path)
+                .canonicalize()
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            let pwd = osstr::from_os(vars.cwd.as_os_str());
+            vars.set_environment("PWD", Some(&pwd));
+        }
+
+        if let Some(name) = self.name {
+            command_env.vars.name = name;
+        }
+        command_env.vars.positional = self.positional;
+        command_env.login = self.login;
+        command_env.interactive = self.interactive;
+        command_env.history_backend = self.history;
+        if let Some(resolver) = self.resolver {
+            command_env.resolver = resolver;
+        }
+        if self.stdout.is_some() || self.stderr.is_some() {
+            command_env.handles = Some(Handles {
+                stdout: self.stdout.unwrap_or_else(|| Box::new(io::stdout())),
+                stderr: self.stderr.unwrap_or_else(|| Box::new(io::stderr())),
+            });
+        }
+        command_env.frames.push(Frame {
+            name: String::from("main"),
+            file: command_env.vars.name.clone(),
+            line: 0,
+        });
+        if self.signals {
+            let jobs = command_env.jobs.clone();
+            signals::forward_signals(move || jobs.hangup(), self.interactive);
+            if self.interactive {
+                signals::catch_terminal_signals();
+            }
+        }
+
+        // a startup file may exit the shell before it runs any command
+        let mut exited = None;
+        if self.login {
+            exited = crate::source_startup_file(".shell_profile", &mut command_env);
+        }
+        if self.interactive {
+            crate::read_inputrc(&mut command_env);
+        }
+        if self.interactive && self.rc_file {
+            exited = exited.or_else(|| crate::source_startup_file(".shellrc", &mut command_env));
+        }
+        // the history of the previous sessions, HISTFILE may be set in .shellrc
+        if let (true, Some(path)) = (
+            command_env.keeps_history(),
+            crate::history_file(&command_env),
+        ) {
+            let limit = crate::history_size(&command_env.vars);
+            if let Err(err) = command_env.history.borrow_mut().read(&path, limit) {
+                eprintln!("{}", err);
+            }
+        }
+        if let (true, Some(path)) = (self.interactive, crate::frequency_file(&command_env.vars)) {
+            if let Err(err) = command_env.frequency.borrow_mut().read(&path) {
+                eprintln!("{}", err);
+            }
+        }
+
+        Ok(Shell {
+            command_env,
+            exited,
+        })
+    }
+}
+
+impl Default for ShellBuilder {
+    fn default() -> Self {
+        ShellBuilder::new()
+    }
+}
+
+// the shell run by a program: commands are evaluated one text at a time, the
+// variables, options and jobs are kept between them. When it's dropped, the
+// history is saved and background jobs are hung up like at exit
+pub struct Shell {
+    command_env: CommandEnv,
+    // the status given to exit, no more commands are run then
+    exited: Option<i32>,
+}
+
+impl Shell {
+    pub fn builder() -> ShellBuilder {
+        ShellBuilder::new()
+    }
+
+    // the lines of the text are run like by sh -c, their output is kept
+    // apart from the one of the other commands
+    pub fn eval(&mut self, input: &str) -> ExecResult {
+        let stdout = Rc::new(RefCell::new(vec![]));
+        let stderr = Rc::new(RefCell::new(vec![]));
+        let handles = self.command_env.handles.replace(Handles {
+            stdout: Box::new(Capture(Rc::clone(&stdout))),
+            stderr: Box::new(Capture(Rc::clone(&stderr))),
+        });
+        let started = Instant::now();
+        let status = self.run(input);
+        let duration = started.elapsed();
+        self.command_env.handles = handles;
+        ExecResult {
+            status,
+            stdout: stdout.take(),
+            stderr: stderr.take(),
+            duration,
+        }
+    }
+
+    // like eval, but the output goes to the writers of the builder, the
+    // status of the last command is returned
+    pub fn run(&mut self, input: &str) -> i32 {
+        if let Some(code) = self.exited {
+            return code;
+        }
+        // lines of compound commands are joined like in scripts
+        let mut lines = input.split_inclusive('\n');
+        self.exited = crate::run_lines(
+            &mut |line, _| match lines.next() {
+                Some(text) => {
+                    line.push_str(text);
+                    Ok(text.len())
+                }
+                None => Ok(0),
+            },
+            &mut self.command_env,
+            false,
+        );
+        self.finish()
+    }
+
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<i32, String> {
+        if let Some(code) = self.exited {
+            return Ok(code);
+        }
+        let result = crate::run_file(&path.as_ref().to_path_buf(), &mut self.command_env);
+        if let Ok(exited) = result {
+            self.exited = exited;
+        }
+        let status = self.finish();
+        result.map(|_| status)
+    }
+
+    // commands are read from the standard input until its end, the input of
+    // the terminal is edited by the line editor
+    pub fn run_stdin(&mut self) -> i32 {
+        if let Some(code) = self.exited {
+            return code;
+        }
+        let command_env = &mut self.command_env;
+        let interactive = command_env.interactive;
+        let terminal = io::stdin().is_terminal() && io::stdout().is_terminal();
+        let builtins = command_env
+            .names()
+            .filter(|name| *name != RUN_INTERNAL)
+            .map(String::from)
+            .collect();
+        let mut editor = editor::Editor::new(
+            Rc::clone(&command_env.bindings),
+            command_env.options.flag("vi"),
+            complete::Completer::new(
+                builtins,
+                Rc::clone(&command_env.frequency),
+                Rc::clone(&command_env.completion),
+            ),
+            command_env.jobs.clone(),
+            Rc::clone(&command_env.history),
+        );
+        self.exited = crate::run_lines(
+            &mut |line, prompt| match prompt {
+                Some(prompt) if terminal => editor.read_line(prompt, line),
+                Some(prompt) => {
+                    print!("{}", prompt::printable(&prompt.left));
+                    io::stdout().flush()?;
+                    io::stdin().read_line(line)
+                }
+                None => io::stdin().read_line(line),
+            },
+            command_env,
+            interactive,
+        );
+        self.finish()
+    }
+
+    // the token may be sent to another thread, cancelling it stops the
+    // commands running now or the next ones run
+    pub fn cancel_token(&self) -> CancelToken {
+        self.command_env.cancel.share()
+    }
+
+    // output of the commands goes to the writers instead of the ones given
+    // to the builder
+    pub(crate) fn set_handles(&mut self, stdout: Box<dyn Write>, stderr: Box<dyn Write>) {
+        self.command_env.handles = Some(Handles { stdout, stderr });
+    }
+
+    fn finish(&mut self) -> i32 {
+        if self.command_env.cancel.reset() {
+            self.command_env.vars.status = CANCELLED;
+        }
+        self.command_env.vars.status
+    }
+
+    // the status given to exit or the one of the failed command with errexit
+    // option; the shell doesn't run commands after it
+    pub fn exited(&self) -> Option<i32> {
+        self.exited
+    }
+
+    // exit status of the last command
+    pub fn status(&self) -> i32 {
+        self.command_env.vars.status
+    }
+
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.command_env.vars.get(name)
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.command_env.vars.set(name, value)
+    }
+
+    pub fn current_dir(&self) -> Option<String> {
+        Some(osstr::from_os(self.command_env.vars.cwd.as_os_str()))
+    }
+}
+
+impl Drop for Shell {
+    fn drop(&mut self) {
+        crate::save_history(&mut self.command_env);
+        crate::save_frequency(&mut self.command_env);
+        self.command_env.jobs.hangup();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // output shared with the test after the shell takes the writer
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn take(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    #[test]
+    fn status_of_the_last_command() {
+        let mut shell = Shell::builder().build().unwrap();
+        assert_eq!(shell.run("true"), 0);
+        assert_eq!(shell.run("false"), 1);
+        assert_eq!(shell.run("false; true"), 0);
+        assert_eq!(shell.run("true\nfalse"), 1);
+        assert_eq!(shell.status(), 1);
+    }
+
+    #[test]
+    fn variables_are_kept_between_evaluations() {
+        let mut shell = Shell::builder()
+            .name("embedded")
+            .positional(vec![String::from("a"), String::from("b")])
+            .build()
+            .unwrap();
+        shell.set_var("SHELL_TEST_GREETING", "hi").unwrap();
+        shell.run("SHELL_TEST_ANSWER=\"$SHELL_TEST_GREETING $0 $2\"");
+        assert_eq!(
+            shell.var("SHELL_TEST_ANSWER").as_deref(),
+            Some("hi embedded b")
+        );
+        assert!(shell.var("SHELL_TEST_UNSET").is_none());
+    }
+
+    #[test]
+    fn output_goes_to_the_writers() {
+        let (stdout, stderr) = (Buffer::default(), Buffer::default());
+        let mut shell = Shell::builder()
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build()
+            .unwrap();
+        shell.run("echo one\nprintf '%s-' a b");
+        assert_eq!(stdout.take(), "one\na-b-");
+        shell.run("cd /nonexistent/directory");
+        assert!(stderr.take().contains("/nonexistent/directory"));
+    }
+
+    #[test]
+    fn builtins_are_chosen() {
+        let mut shell = Shell::builder()
+            .builtins(["echo", "type"])
+            .stdout(Buffer::default())
+            .build()
+            .unwrap();
+        assert_eq!(shell.run("type echo"), 0);
+        assert_eq!(shell.run("pushd /"), 127);
+
+        let mut shell = Shell::builder()
+            .without_builtin("cd")
+            .stdout(Buffer::default())
+            .stderr(Buffer::default())
+            .build()
+            .unwrap();
+        assert_eq!(shell.run("cd /"), 127);
+
+        let error = Shell::builder().without_builtin("fly").build().err();
+        assert_eq!(error.as_deref(), Some("fly: no such builtin"));
+        let error = Shell::builder().option("nosuchoption", true).build().err();
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn cancelled_commands_are_skipped() {
+        let mut shell = Shell::builder().build().unwrap();
+        shell.cancel_token().cancel();
+        assert_eq!(shell.run("SHELL_TEST_CANCELLED=1"), CANCELLED);
+        assert!(shell.var("SHELL_TEST_CANCELLED").is_none());
+        // the token is reset for the next commands
+        assert_eq!(shell.run("true"), 0);
+    }
+
+    #[test]
+    fn running_programs_are_killed() {
+        let mut shell = Shell::builder().build().unwrap();
+        let token = shell.cancel_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            token.cancel();
+        });
+        let started = std::time::Instant::now();
+        assert_eq!(shell.run("sleep 5; SHELL_TEST_AFTER=1"), CANCELLED);
+        assert!(started.elapsed().as_secs() < 5);
+        assert!(shell.var("SHELL_TEST_AFTER").is_none());
+        canceller.join().unwrap();
+
+        // the programs started by the program are killed with it, otherwise
+        // sleep would keep the output open
+        let token = shell.cancel_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            token.cancel();
+        });
+        let started = std::time::Instant::now();
+        assert_eq!(shell.eval("sh -c 'sleep 5; true'").status, CANCELLED);
+        assert!(started.elapsed().as_secs() < 5);
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn output_is_captured() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("echo out; cd /nonexistent/directory; true");
+        assert_eq!(result.status, 0);
+        assert_eq!(result.stdout, b"out\n");
+        assert!(result.stderr.starts_with(b"cd: "));
+        // the result of each text is its own
+        assert_eq!(shell.eval("printf x").stdout, b"x");
+    }
+
+    // every name runs echo with the name of the program before the arguments
+    struct EchoResolver;
+
+    impl Resolver for EchoResolver {
+        fn find(&self, name: &str, _path: Option<&str>) -> Result<Option<String>, String> {
+            if name == "broken" {
+                return Err(String::from("no connection"));
+            }
+            Ok((name != "missing").then(|| format!("remote/{}", name)))
+        }
+
+        fn command(&self, program: &str) -> std::process::Command {
+            let mut command = std::process::Command::new("/bin/echo");
+            command.arg(program);
+            command
+        }
+    }
+
+    #[test]
+    fn programs_are_found_by_the_resolver() {
+        let mut shell = Shell::builder().resolver(EchoResolver).build().unwrap();
+        let result = shell.eval("ls -l; type ls; missing");
+        assert_eq!(result.stdout, b"remote/ls -l\nls is remote/ls\n");
+        assert_eq!(result.status, 127);
+        // errors of the resolver are reported as they are
+        assert!(shell
+            .eval("broken")
+            .stderr
+            .starts_with(b"broken: no connection"));
+        assert!(shell
+            .eval("type broken")
+            .stderr
+            .starts_with(b"type: broken: no connection"));
+    }
+
+    #[test]
+    fn resources_of_the_last_program_are_kept() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result =
+            shell.eval("sh -c 'exit 4'; echo ${LAST_JOB_STATS[command]} ${LAST_JOB_STATS[status]}");
+        assert_eq!(result.stdout, b"sh -c exit 4 4\n");
+        assert!(shell.eval("jobs -x").stderr.starts_with(b"jobs: usage"));
+    }
+
+    #[test]
+    fn foreach_runs_a_command_per_item() {
+        let path = std::env::temp_dir().join(format!("foreach-test-{}", std::process::id()));
+        std::fs::write(&path, "a b\0c\0d\0").unwrap();
+        let path = path.display().to_string();
+        let mut shell = Shell::builder().build().unwrap();
+
+        let result = shell.eval(&format!("foreach printf '<%s>' < {}", path));
+        assert_eq!(result.stdout, b"<a b><c><d>");
+        let result = shell.eval(&format!("foreach -n 2 -- printf '%s,' < {}", path));
+        assert_eq!(result.stdout, b"a b,c,d,");
+        let result = shell.eval(&format!("foreach -d ' ' echo < {}", path));
+        assert_eq!(result.stdout, b"a\nb\0c\0d\0\n");
+        // the status of a failed command is kept, not the one of the last
+        let result = shell.eval(&format!("foreach sh -c 'test \"$0\" != c' < {}", path));
+        assert_eq!(result.status, 1);
+
+        assert!(shell
+            .eval("foreach -n 0 echo")
+            .stderr
+            .starts_with(b"foreach: 0: invalid count"));
+        assert!(shell
+            .eval("foreach -x echo")
+            .stderr
+            .starts_with(b"foreach: -x: invalid option"));
+        assert!(shell.eval("foreach").stderr.starts_with(b"foreach: usage"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn programs_are_printed_with_dryrun() {
+        let path = std::env::temp_dir().join(format!("dryrun-test-{}", std::process::id()));
+        let mut shell = Shell::builder().option("dryrun", true).build().unwrap();
+        let result = shell.eval(&format!(
+            "x='a b'; ls \"$x\" > {} & echo $((1 + 2)); nosuchprogram",
+            path.display()
+        ));
+        assert_eq!(
+            String::from_utf8(result.stdout).unwrap(),
+            format!("ls a\\ b >{} &\n3\n", path.display())
+        );
+        assert_eq!(result.status, 127);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn lines_of_compound_commands_are_joined() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("x=1\nselect item in\ndo\necho never\ndone\necho $x");
+        assert_eq!(result.stdout, b"1\n");
+        assert_eq!(result.stderr, b"");
+    }
+
+    #[test]
+    fn exit_stops_the_shell() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("echo a; exit 5; echo b");
+        assert_eq!(result.status, 5);
+        assert_eq!(result.stdout, b"a\n");
+        assert_eq!(shell.exited(), Some(5));
+        assert_eq!(shell.eval("echo c").stdout, b"");
+    }
+
+    #[test]
+    fn errexit_returns_the_status() {
+        let mut shell = Shell::builder().option("errexit", true).build().unwrap();
+        let result = shell.eval("false\necho no");
+        assert_eq!(result.status, 1);
+        assert_eq!(result.stdout, b"");
+        assert_eq!(shell.exited(), Some(1));
+    }
+
+    #[test]
+    fn environment_and_directory_of_each_shell() {
+        let mut first = Shell::builder()
+            .current_dir("/")
+            .env("SHELL_TEST_NAME", "first")
+            .build()
+            .unwrap();
+        let mut second = Shell::builder().build().unwrap();
+        assert_eq!(
+            first.eval("pwd; echo $SHELL_TEST_NAME").stdout,
+            b"/\nfirst\n"
+        );
+        assert_eq!(second.eval("echo $SHELL_TEST_NAME").stdout, b"\n");
+        // cd changes the directory of the shell, not the one of the process
+        let before = std::env::current_dir().unwrap();
+        first.eval("cd /tmp");
+        assert_eq!(first.current_dir().as_deref(), Some("/tmp"));
+        assert_eq!(std::env::current_dir().unwrap(), before);
+    }
+
+    #[test]
+    fn read_keeps_the_bytes() {
+        let path = std::env::temp_dir().join(format!("read-test-{}", std::process::id()));
+        std::fs::write(&path, b"a\xffb\nc\xfe\n").unwrap();
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval(&format!("read -r x < {}; printf %s \"$x\"", path.display()));
+        assert_eq!(result.stdout, b"a\xffb");
+        let result = shell.eval(&format!(
+            "mapfile -t lines < {}; printf '%s,' \"${{lines[@]}}\"",
+            path.display()
+        ));
+        assert_eq!(result.stdout, b"a\xffb,c\xfe,");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_required_parameters_stop_the_shell() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("echo ${SHELL_TEST_UNSET:?gone}; echo after");
+        assert_eq!(result.stdout, b"");
+        assert!(result.stderr.starts_with(b"SHELL_TEST_UNSET: gone"));
+        assert_eq!(result.status, 1);
+        assert_eq!(shell.exited(), Some(1));
+    }
+
+    #[test]
+    fn misspelled_directories_are_corrected() {
+        let root = std::env::temp_dir().join(format!("cdspell-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("documents")).unwrap();
+        let mut shell = Shell::builder().current_dir(&root).build().unwrap();
+        let result = shell.eval("cd documnets");
+        assert!(String::from_utf8(result.stderr)
+            .unwrap()
+            .ends_with("did you mean documents?\n"));
+        assert_eq!(result.status, 1);
+
+        let mut shell = Shell::builder()
+            .current_dir(&root)
+            .option("cdspell", true)
+            .build()
+            .unwrap();
+        let result = shell.eval("cd documnets");
+        assert_eq!(result.stdout, b"documents\n");
+        assert_eq!(result.status, 0);
+        assert_eq!(
+            shell.current_dir(),
+            Some(root.join("documents").display().to_string())
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn duplicated_descriptors_are_captured() {
+        let path = std::env::temp_dir().join(format!("routes-test-{}", std::process::id()));
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("printf x >&2");
+        assert_eq!((result.stdout, result.stderr), (vec![], b"x".to_vec()));
+        let result = shell.eval("sh -c 'echo e >&2' 2>&1");
+        assert_eq!((result.stdout, result.stderr), (b"e\n".to_vec(), vec![]));
+        let result = shell.eval("cd /nonexistent 2>&1");
+        assert!(result.stdout.starts_with(b"cd: /nonexistent"));
+        // 2>&1 >file sends only the output to the file
+        let result = shell.eval(&format!(
+            "sh -c 'echo o; echo e >&2' 2>&1 >{}",
+            path.display()
+        ));
+        assert_eq!(result.stdout, b"e\n");
+        assert_eq!(std::fs::read(&path).unwrap(), b"o\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn messages_of_the_shell_are_captured() {
+        let path = std::env::temp_dir().join(format!("messages-test-{}", std::process::id()));
+        std::fs::write(&path, "false\necho no\n").unwrap();
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("[[ a =~ [z-a] ]]");
+        assert_eq!(result.stderr, b"[z-a]: invalid range end\n");
+        assert_eq!(result.status, 2);
+        let result = shell.eval("REPORTTIME=0; true");
+        assert!(result.stderr.starts_with(b"took "));
+
+        let mut shell = Shell::builder().option("errexit", true).build().unwrap();
+        let result = shell.eval(&format!("source {}", path.display()));
+        assert!(result.stderr.starts_with(b"errexit: exit status 1\n  at "));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn options_of_declare_end_with_dashes() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("declare -i -- n=1+2; echo $n; declare -p -- n");
+        assert_eq!(result.stdout, b"3\ndeclare -i n=\"3\"\n");
+        let result = shell.eval("declare -- -x");
+        assert!(result
+            .stderr
+            .starts_with(b"declare: `-x': not a valid identifier"));
+        assert_eq!(result.status, 1);
+    }
+
+    #[test]
+    fn syntax_errors_stop_the_shell() {
+        for text in ["echo a; done; echo b", "echo >", "[[ ( ]]; echo b"] {
+            let mut shell = Shell::builder().build().unwrap();
+            let result = shell.eval(text);
+            assert!(result.stderr.starts_with(b"syntax error"), "{}", text);
+            assert_eq!(result.stdout, b"", "{}", text);
+            assert_eq!(result.status, 2);
+            assert_eq!(shell.exited(), Some(2));
+        }
+        // the history and the startup files are in the temporary home
+        let home = std::env::temp_dir().join(format!("syntax-test-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        let mut shell = Shell::builder()
+            .interactive(true)
+            .env("HOME", home.to_str().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(shell.eval("echo >").status, 2);
+        assert_eq!(shell.eval("echo $?").stdout, b"2\n");
+        drop(shell);
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn for_loops_run_the_body_for_each_word() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell
+            .eval("set -- p q\nfor x in a 'b c'; do echo \"[$x]\"; done\nfor x\ndo\necho $x\ndone");
+        assert_eq!(result.stdout, b"[a]\n[b c]\np\nq\n");
+        let result = shell.eval(
+            "for a in 1 2 3; do for b in x y; do [[ $b == y ]] && continue 2; [[ $a == 3 ]] && break 2; echo $a$b; done; done",
+        );
+        assert_eq!(result.stdout, b"1x\n2x\n");
+        assert_eq!(shell.eval("false; for x in; do :; done").status, 0);
+        let result = shell.eval("readonly for_r=1; for for_r in 2; do echo no; done");
+        assert_eq!(result.stdout, b"");
+        assert_eq!(result.status, 1);
+    }
+
+    #[test]
+    fn durations_are_not_exported() {
+        let mut shell = Shell::builder().build().unwrap();
+        shell.eval("sleep 0.1");
+        let result = shell.eval("echo ${CMD_DURATION:0:2}; sh -c 'echo \"[$CMD_DURATION]\"'");
+        assert_eq!(result.stdout, b"0.\n[]\n");
+    }
+
+    #[test]
+    fn jobs_are_announced_only_when_interactive() {
+        let mut shell = Shell::builder().build().unwrap();
+        let result = shell.eval("true &\ncoproc cat\necho after");
+        assert_eq!(result.stdout, b"after\n");
+        assert_eq!(result.status, 0);
+
+        let home = std::env::temp_dir().join(format!("jobs-test-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        let mut shell = Shell::builder()
+            .interactive(true)
+            .env("HOME", home.to_str().unwrap())
+            .build()
+            .unwrap();
+        let result = shell.eval("true &");
+        assert!(result.stdout.starts_with(b"[1] "), "{:?}", result.stdout);
+        drop(shell);
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+pub const SIGHUP: c_int = 1;
+pub const SIGINT: c_int = 2;
+pub const SIGQUIT: c_int = 3;
+pub const SIGKILL: c_int = 9;
+pub const SIGTERM: c_int = 15;
+pub const SIGTSTP: c_int = 20;
+const SIGTTOU: c_int = 22;
+const SIGWINCH: c_int = 28;
+
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+const SIG_BLOCK: c_int = 0;
+const SIG_UNBLOCK: c_int = 1;
+
+// the constants and structures are the ones of glibc on Linux, there is no
+// libc crate to take them from
+#[cfg(not(target_os = "linux"))]
+compile_error!("signal handling is only written for Linux");
+
+// glibc sigset_t, 1024 bits
+#[repr(C)]
+struct SigSet([u64; 16]);
+
+#[repr(C)]
+struct SigAction {
+    handler: usize,
+    mask: SigSet,
+    flags: c_int,
+    restorer: usize,
+}
+
+extern "C" {
+    fn signal(signum: c_int, handler: usize) -> usize;
+    fn sigemptyset(set: *mut SigSet) -> c_int;
+    fn sigaddset(set: *mut SigSet, signum: c_int) -> c_int;
+    fn pthread_sigmask(how: c_int, set: *const SigSet, oldset: *mut SigSet) -> c_int;
+    fn sigwait(set: *const SigSet, signum: *mut c_int) -> c_int;
+    fn kill(pid: c_int, signum: c_int) -> c_int;
+    fn sigaction(signum: c_int, action: *const SigAction, old: *mut SigAction) -> c_int;
+    fn pthread_self() -> c_ulong;
+    fn pthread_kill(thread: c_ulong, signum: c_int) -> c_int;
+    fn isatty(fd: c_int) -> c_int;
+    fn tcsetpgrp(fd: c_int, pgrp: c_int) -> c_int;
+    fn getpgrp() -> c_int;
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// SIGINT handler of the shell outside of catch_interrupt
+static INTERRUPT_HANDLER: AtomicUsize = AtomicUsize::new(SIG_DFL);
+
+// the program in its own process group doesn't get Ctrl-C when it hasn't the
+// terminal, so the shell passes it on
+extern "C" fn on_interrupt(signum: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    let foreground = FOREGROUND.load(Ordering::SeqCst);
+    if foreground < 0 {
+        unsafe {
+            kill(foreground, signum);
+        }
+    }
+}
+
+fn interrupt_handler() -> usize {
+    on_interrupt as extern "C" fn(c_int) as usize
+}
+
+// catch SIGINT instead of terminating the shell, check it with interrupted()
+pub fn catch_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    unsafe {
+        signal(SIGINT, interrupt_handler());
+    }
+}
+
+// restore SIGINT behavior of the shell, default unless it's interactive
+pub fn release_interrupt() {
+    unsafe {
+        signal(SIGINT, INTERRUPT_HANDLER.load(Ordering::SeqCst));
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// the foreground program was killed by Ctrl-C, the shell acts as if it got it
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Ctrl-C doesn't terminate the interactive shell, it's caught and passed to
+// the foreground program; SIGTTOU is ignored, so the shell takes the terminal
+// back from the program
+pub fn catch_terminal_signals() {
+    INTERRUPT_HANDLER.store(interrupt_handler(), Ordering::SeqCst);
+    unsafe {
+        signal(SIGINT, interrupt_handler());
+        signal(SIGTTOU, SIG_IGN);
+    }
+}
+
+// a standard descriptor of the shell which is the terminal
+pub fn terminal() -> Option<c_int> {
+    (0..3).find(|&fd| unsafe { isatty(fd) } == 1)
+}
+
+// Ctrl-C and Ctrl-Z go to the process group, which may read the terminal
+pub fn give_terminal(fd: c_int, pgid: u32) {
+    unsafe {
+        tcsetpgrp(fd, pgid as c_int);
+    }
+}
+
+pub fn take_terminal(fd: c_int) {
+    unsafe {
+        tcsetpgrp(fd, getpgrp());
+    }
+}
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+static RESIZE_THREAD: AtomicU64 = AtomicU64::new(0);
+
+// any thread may get the signal, it's passed to the one waiting for input so
+// its read is interrupted
+extern "C" fn on_resize(signum: c_int) {
+    let thread = RESIZE_THREAD.load(Ordering::SeqCst) as c_ulong;
+    if unsafe { pthread_self() } != thread {
+        unsafe {
+            pthread_kill(thread, signum);
+        }
+        return;
+    }
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+fn set_resize_handler(handler: usize) {
+    let action = SigAction {
+        handler,
+        mask: SigSet([0; 16]),
+        // no SA_RESTART, reading the terminal fails with EINTR on resize
+        flags: 0,
+        restorer: 0,
+    };
+    unsafe {
+        sigaction(SIGWINCH, &action, ptr::null_mut());
+    }
+}
+
+// catch SIGWINCH while the calling thread reads the terminal, check it with
+// resized(); other reads would be interrupted too, so it's released after
+pub fn catch_resize() {
+    RESIZE_THREAD.store(unsafe { pthread_self() } as u64, Ordering::SeqCst);
+    RESIZED.store(false, Ordering::SeqCst);
+    set_resize_handler(on_resize as extern "C" fn(c_int) as usize);
+}
+
+pub fn release_resize() {
+    set_resize_handler(SIG_DFL);
+}
+
+// the terminal was resized since the last check
+pub fn resized() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
+static NOTIFIED: AtomicBool = AtomicBool::new(false);
+
+// interrupt the read of the terminal like a resize does, so the line editor
+// prints the finished jobs; check it with notified(). while the terminal isn't
+// read SIGWINCH is ignored
+pub fn notify_reader() {
+    NOTIFIED.store(true, Ordering::SeqCst);
+    let thread = RESIZE_THREAD.load(Ordering::SeqCst) as c_ulong;
+    if thread != 0 {
+        unsafe {
+            pthread_kill(thread, SIGWINCH);
+        }
+    }
+}
+
+// a job finished since the last check
+pub fn notified() -> bool {
+    NOTIFIED.swap(false, Ordering::SeqCst)
+}
+
+// pid of the program the shell is waiting for, minus it when the program is
+// in its own process group, 0 if there is no such program
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+pub fn set_foreground(pid: u32, group: bool) {
+    let pid = pid as i32;
+    FOREGROUND.store(if group { -pid } else { pid }, Ordering::SeqCst);
+}
+
+pub fn clear_foreground() {
+    FOREGROUND.store(0, Ordering::SeqCst);
+}
+
+pub fn send(pid: u32, signum: c_int) {
+    unsafe {
+        kill(pid as c_int, signum);
+    }
+}
+
+// send the signal to the process group, its id is the pid of the leader
+pub fn send_group(pgid: u32, signum: c_int) {
+    unsafe {
+        kill(-(pgid as c_int), signum);
+    }
+}
+
+// stop the shell itself until it gets SIGCONT from the parent shell
+pub fn suspend() {
+    send(process::id(), SIGTSTP);
+}
+
+fn forwarded_set() -> SigSet {
+    let mut set = SigSet([0; 16]);
+    unsafe {
+        sigemptyset(&mut set);
+        for signum in [SIGHUP, SIGQUIT, SIGTERM] {
+            sigaddset(&mut set, signum);
+        }
+    }
+    set
+}
+
+// spawned programs inherit the blocked signal mask and the ignored signals, so
+// unblock forwarded signals and reset the ones of the interactive shell in the
+// child before exec
+pub fn unblock_in_child(command: &mut process::Command) -> &mut process::Command {
+    let set = forwarded_set();
+    unsafe {
+        command.pre_exec(move || {
+            pthread_sigmask(SIG_UNBLOCK, &set, ptr::null_mut());
+            signal(SIGINT, SIG_DFL);
+            signal(SIGTTOU, SIG_DFL);
+            Ok(())
+        })
+    }
+}
+
+// the shell has no job control, a stopped program would never be continued;
+// programs may still catch SIGTSTP themselves
+pub fn ignore_stop_in_child(command: &mut process::Command) -> &mut process::Command {
+    unsafe {
+        command.pre_exec(|| {
+            signal(SIGTSTP, SIG_IGN);
+            Ok(())
+        })
+    }
+}
+
+// handle SIGHUP, SIGTERM and SIGQUIT in a dedicated thread: they are forwarded to
+// the foreground program, on SIGHUP the shell calls on_hangup and exits, SIGTERM
+// terminates only a non-interactive shell. Must be called before any other thread
+// is spawned, so that all threads inherit the blocked signal mask
+pub fn forward_signals(on_hangup: impl Fn() + Send + 'static, interactive: bool) {
+    let set = forwarded_set();
+    unsafe {
+        pthread_sigmask(SIG_BLOCK, &set, ptr::null_mut());
+    }
+
+    thread::spawn(move || loop {
+        let mut signum = 0;
+        if unsafe { sigwait(&set, &mut signum) } != 0 {
+            continue;
+        }
+
+        // the whole group of a program in its own process group gets them
+        let foreground = FOREGROUND.load(Ordering::SeqCst);
+        if foreground != 0 {
+            unsafe {
+                kill(foreground, signum);
+            }
+        }
+
+        match signum {
+            SIGHUP => {
+                on_hangup();
+                process::exit(128 + SIGHUP);
+            }
+            SIGTERM if foreground == 0 && !interactive => process::exit(128 + SIGTERM),
+            _ => {}
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn ignored_interrupt_is_reset_in_the_child() {
+        unsafe {
+            signal(SIGINT, SIG_IGN);
+        }
+        let status = unblock_in_child(&mut process::Command::new("/bin/sh"))
+            .args(["-c", "kill -INT $$; exit 3"])
+            .status()
+            .unwrap();
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+        }
+        assert_eq!(status.signal(), Some(SIGINT));
+    }
+
+    #[test]
+    fn stop_is_ignored_in_the_child() {
+        let mut command = process::Command::new("/bin/sh");
+        let status = ignore_stop_in_child(&mut command)
+            .args(["-c", "kill -TSTP $$; exit 3"])
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+}
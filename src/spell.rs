@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::Path;
+
+use crate::osstr;
+
+// most corrections to look for, one component may have several close names
+const MAX_CORRECTIONS: usize = 5;
+
+// edits turning one name into the other: a character added, removed,
+// replaced or two neighbouring ones swapped
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+// relative paths are in the directory
+fn is_dir(cwd: &Path, path: &str) -> bool {
+    fs::metadata(cwd.join(osstr::to_os(path))).is_ok_and(|metadata| metadata.is_dir())
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    match prefix {
+        "" => String::from(name),
+        _ if prefix.ends_with('/') => format!("{}{}", prefix, name),
+        _ => format!("{}/{}", prefix, name),
+    }
+}
+
+// directories in the prefix with names closest to the misspelled one, long
+// names may have two mistakes
+fn closest(cwd: &Path, prefix: &str, name: &str) -> Vec<String> {
+    let directory = if prefix.is_empty() { "." } else { prefix };
+    let entries = match fs::read_dir(cwd.join(osstr::to_os(directory))) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let name: Vec<char> = name.chars().collect();
+    let allowed = if name.len() >= 6 { 2 } else { 1 };
+    let mut best = allowed + 1;
+    let mut names = vec![];
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let candidate = osstr::from_os(&entry.file_name());
+        if candidate.starts_with('.') && name.first() != Some(&'.') {
+            continue;
+        }
+        let found = distance(&name, &candidate.chars().collect::<Vec<_>>());
+        if found > best || !is_dir(cwd, &join(prefix, &candidate)) {
+            continue;
+        }
+        if found < best {
+            best = found;
+            names.clear();
+        }
+        names.push(candidate);
+    }
+    names.sort();
+    names
+}
+
+// existing directories close to the path which doesn't exist, every
+// misspelled component is corrected; relative paths are in cwd
+pub fn corrections(cwd: &Path, path: &str) -> Vec<String> {
+    let mut paths = vec![String::from(if path.starts_with('/') { "/" } else { "" })];
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let mut next = vec![];
+        for prefix in &paths {
+            let exact = join(prefix, component);
+            if component == "." || component == ".." || is_dir(cwd, &exact) {
+                next.push(exact);
+                continue;
+            }
+            for name in closest(cwd, prefix, component) {
+                next.push(join(prefix, &name));
+            }
+        }
+        next.truncate(MAX_CORRECTIONS);
+        paths = next;
+    }
+    paths.retain(|corrected| !corrected.is_empty() && corrected != path);
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(distance(&chars("src"), &chars("src")), 0);
+        assert_eq!(distance(&chars("scr"), &chars("src")), 1);
+        assert_eq!(distance(&chars("sr"), &chars("src")), 1);
+        assert_eq!(distance(&chars("srcc"), &chars("src")), 1);
+        assert_eq!(distance(&chars("abc"), &chars("xyz")), 3);
+    }
+
+    #[test]
+    fn directories_are_corrected() {
+        let root = env::temp_dir().join(format!("spell-{}", std::process::id()));
+        for directory in ["src/nested", "docs", "dogs", "documents"] {
+            fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        fs::write(root.join("srcs"), "").unwrap();
+        let corrected = |path: &str| corrections(&root, path);
+
+        assert_eq!(corrected("scr/nestde"), ["src/nested"]);
+        assert_eq!(corrected("dos"), ["docs", "dogs"]);
+        assert_eq!(corrected("./documnet"), ["./documents"]);
+        assert!(corrected("src").is_empty());
+        assert!(corrected("xyz").is_empty());
+        // absolute paths don't depend on the directory
+        let absolute = format!("{}/dcos", root.display());
+        assert_eq!(
+            corrections(Path::new("/"), &absolute),
+            [format!("{}/docs", root.display())]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
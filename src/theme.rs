@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, IsTerminal};
+use std::process::{self, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{self, Config};
+use crate::prompt::{self, END_IGNORE, START_IGNORE};
+use crate::signals;
+use crate::vars::Variables;
+
+// milliseconds the prompt waits for the command segments
+const TIMEOUT: u64 = 100;
+
+// powerline separator, the fonts for it have the branch symbol too
+const ARROW: &str = "\u{e0b0}";
+
+// a bundled theme: segments of the prompt and of the right prompt, the
+// symbol after them, and the colors and formats of the segments; with blocks
+// the segments have background colors and arrows between them
+struct Theme {
+    name: &'static str,
+    left: &'static [&'static str],
+    right: &'static [&'static str],
+    symbol: &'static str,
+    blocks: bool,
+    // segment, foreground, background and format, "" for the default ones
+    styles: &'static [(&'static str, &'static str, &'static str, &'static str)],
+}
+
+const THEMES: [Theme; 4] = [
+    Theme {
+        name: "default",
+        left: &["status", "cwd", "git"],
+        right: &[],
+        symbol: "$",
+        blocks: false,
+        styles: &[
+            ("status", "red", "", ""),
+            ("cwd", "blue", "", ""),
+            ("git", "magenta", "", "({})"),
+        ],
+    },
+    Theme {
+        name: "minimal",
+        left: &["status", "cwd"],
+        right: &[],
+        symbol: "❯",
+        blocks: false,
+        styles: &[("status", "red", "", "{}"), ("cwd", "cyan", "", "")],
+    },
+    Theme {
+        name: "powerline",
+        left: &["status", "cwd", "git", "jobs"],
+        right: &[],
+        symbol: "$",
+        blocks: true,
+        styles: &[
+            ("status", "white", "red", ""),
+            ("cwd", "black", "blue", ""),
+            ("git", "black", "yellow", "\u{e0a0} {}"),
+            ("jobs", "black", "cyan", ""),
+        ],
+    },
+    Theme {
+        name: "informative",
+        left: &["user", "cwd", "git", "jobs", "status"],
+        right: &["time"],
+        symbol: "$",
+        blocks: false,
+        styles: &[
+            ("user", "green", "", "{}@"),
+            ("cwd", "blue", "", ""),
+            ("git", "magenta", "", "on {}"),
+            ("jobs", "yellow", "", ""),
+            ("status", "red", "", ""),
+            ("time", "bright-black", "", ""),
+        ],
+    },
+];
+
+// output of the command segments by segment and directory; a slow command
+// keeps running after the prompt is shown, the next prompts show its result
+static CACHE: Mutex<BTreeMap<(String, String), String>> = Mutex::new(BTreeMap::new());
+static RUNNING: Mutex<BTreeSet<(String, String)>> = Mutex::new(BTreeSet::new());
+
+struct Segment {
+    text: String,
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+}
+
+// names of the 8 colors and their bright- variants, 0-255 or #rrggbb
+fn color(name: &str, background: bool) -> Option<String> {
+    const NAMES: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let offset = if background { 10 } else { 0 };
+    let (bright, base) = match name.strip_prefix("bright-") {
+        Some(base) => (true, base),
+        None => (false, name),
+    };
+    if let Some(index) = NAMES.iter().position(|&color| color == base) {
+        let first = if bright { 90 } else { 30 };
+        return Some((first + offset + index).to_string());
+    }
+    let kind = if background { 48 } else { 38 };
+    if let Some(hex) = name.strip_prefix('#') {
+        let channel = |at: usize| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok();
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Some(format!("{};2;{};{};{}", kind, r, g, b)),
+            _ => None,
+        };
+    }
+    name.parse::<u8>()
+        .ok()
+        .map(|number| format!("{};5;{}", kind, number))
+}
+
+// the escape sequence of the colors, marked as taking no space
+fn sgr(codes: &[String]) -> String {
+    match codes.is_empty() || !io::stdout().is_terminal() {
+        true => String::new(),
+        false => format!("{}\x1b[{}m{}", START_IGNORE, codes.join(";"), END_IGNORE),
+    }
+}
+
+fn reset() -> String {
+    sgr(&[String::from("0")])
+}
+
+fn style(segment: &Segment) -> String {
+    let mut codes = vec![];
+    if segment.bold {
+        codes.push(String::from("1"));
+    }
+    codes.extend(segment.fg.iter().filter_map(|fg| color(fg, false)));
+    codes.extend(segment.bg.iter().filter_map(|bg| color(bg, true)));
+    sgr(&codes)
+}
+
+fn current_dir(vars: &Variables) -> String {
+    vars.cwd.display().to_string()
+}
+
+// first line of the output of sh -c command, run in the background with the
+// environment of the shell and the signals of other programs; the cached
+// result is used if it doesn't finish in time
+fn start_command(name: &str, command: &str, vars: &Variables, done: &mpsc::Sender<()>) -> bool {
+    let key = (String::from(name), current_dir(vars));
+    if !RUNNING.lock().unwrap().insert(key.clone()) {
+        return false;
+    }
+    let mut program = process::Command::new("sh");
+    vars.prepare(
+        signals::unblock_in_child(&mut program)
+            .arg("-c")
+            .arg(command),
+    );
+    let done = done.clone();
+    thread::spawn(move || {
+        let output = program.stdin(Stdio::null()).stderr(Stdio::null()).output();
+        let text = match output {
+            Ok(output) => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            Err(_) => String::new(),
+        };
+        CACHE.lock().unwrap().insert(key.clone(), text);
+        RUNNING.lock().unwrap().remove(&key);
+        let _ = done.send(());
+    });
+    true
+}
+
+fn cached(name: &str, vars: &Variables) -> String {
+    let key = (String::from(name), current_dir(vars));
+    CACHE.lock().unwrap().get(&key).cloned().unwrap_or_default()
+}
+
+// text of the builtin segments, None for unknown ones
+fn builtin(name: &str, vars: &mut Variables, jobs: usize) -> Option<String> {
+    let text = match name {
+        "cwd" => prompt::expand("\\w", vars),
+        "git" => prompt::git_branch(&vars.cwd).unwrap_or_default(),
+        "time" => prompt::expand("\\t", vars),
+        "user" => prompt::expand("\\u", vars),
+        "host" => prompt::expand("\\h", vars),
+        "status" => match vars.status {
+            0 => String::new(),
+            status => status.to_string(),
+        },
+        "jobs" => match jobs {
+            0 => String::new(),
+            jobs => jobs.to_string(),
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+fn default_format(name: &str) -> &'static str {
+    match name {
+        "status" => "✗ {}",
+        "jobs" => "⚙ {}",
+        _ => "{}",
+    }
+}
+
+struct Settings<'a> {
+    config: Option<&'a Config>,
+    theme: &'a Theme,
+}
+
+impl Settings<'_> {
+    fn prompt(&self, key: &str) -> Option<&config::Value> {
+        self.config?.get("prompt", key)
+    }
+
+    fn segment(&self, name: &str, key: &str) -> Option<&config::Value> {
+        self.config?.get(&format!("segments.{}", name), key)
+    }
+
+    fn theme_style(&self, name: &str) -> Option<(&str, &str, &str)> {
+        self.theme
+            .styles
+            .iter()
+            .find(|style| style.0 == name)
+            .map(|style| (style.1, style.2, style.3))
+    }
+
+    // the command segments are run at once, the prompt waits for them only
+    // until the timeout
+    fn evaluate(&self, names: &[String], vars: &Variables) {
+        let timeout = self
+            .prompt("timeout")
+            .and_then(|value| value.as_integer())
+            .map_or(TIMEOUT, |timeout| timeout.max(0) as u64);
+        let (done, finished) = mpsc::channel();
+        let mut started = 0;
+        for name in names {
+            if let Some(command) = self
+                .segment(name, "command")
+                .and_then(|value| value.as_str())
+            {
+                started += start_command(name, command, vars, &done) as usize;
+            }
+        }
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+        while started > 0 {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if finished.recv_timeout(left).is_err() {
+                break;
+            }
+            started -= 1;
+        }
+    }
+
+    // the segments which have text
+    fn segments(&self, names: &[String], vars: &mut Variables, jobs: usize) -> Vec<Segment> {
+        let mut segments = vec![];
+        for name in names {
+            let text = match self.segment(name, "command") {
+                Some(_) => cached(name, vars),
+                None => match builtin(name, vars, jobs) {
+                    Some(text) => text,
+                    None => {
+                        eprintln!("prompt: {}: unknown segment", name);
+                        continue;
+                    }
+                },
+            };
+            if text.is_empty() {
+                continue;
+            }
+            let theme = self.theme_style(name);
+            let configured = |key| {
+                self.segment(name, key)
+                    .and_then(|value| value.as_str())
+                    .map(String::from)
+            };
+            let from_theme =
+                |value: Option<&str>| value.filter(|value| !value.is_empty()).map(String::from);
+            let format = configured("format")
+                .or_else(|| from_theme(theme.map(|style| style.2)))
+                .unwrap_or_else(|| String::from(default_format(name)));
+            segments.push(Segment {
+                text: format.replace("{}", &text),
+                fg: configured("fg").or_else(|| from_theme(theme.map(|style| style.0))),
+                bg: configured("bg").or_else(|| from_theme(theme.map(|style| style.1))),
+                bold: self
+                    .segment(name, "bold")
+                    .and_then(|value| value.as_bool())
+                    .unwrap_or(false),
+            });
+        }
+        segments
+    }
+}
+
+fn join(segments: &[Segment], separator: &str) -> String {
+    segments
+        .iter()
+        .map(|segment| format!("{}{}{}", style(segment), segment.text, reset()))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+// blocks with background colors, the arrow after each block has its color on
+// the background of the next one
+fn blocks(segments: &[Segment]) -> String {
+    let mut text = String::new();
+    for (index, segment) in segments.iter().enumerate() {
+        text.push_str(&style(segment));
+        text.push_str(&format!(" {} ", segment.text));
+        let arrow = Segment {
+            text: String::new(),
+            fg: segment.bg.clone(),
+            bg: segments.get(index + 1).and_then(|next| next.bg.clone()),
+            bold: false,
+        };
+        text.push_str(&reset());
+        text.push_str(&style(&arrow));
+        text.push_str(ARROW);
+    }
+    text.push_str(&reset());
+    text
+}
+
+// the prompt and the right prompt of the theme: PROMPT_THEME or theme in the
+// [prompt] table of the configuration, where the segments, their colors and
+// the command segments are set too. None when neither of them chooses one
+pub fn render(vars: &mut Variables, jobs: usize) -> Option<(String, String)> {
+    let config = match config::load(vars) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        }
+    };
+    let chosen = vars.get("PROMPT_THEME").or_else(|| {
+        config
+            .as_ref()?
+            .get("prompt", "theme")?
+            .as_str()
+            .map(String::from)
+    });
+    if chosen.is_none()
+        && !config
+            .as_ref()
+            .is_some_and(|config| config.has_table("prompt"))
+    {
+        return None;
+    }
+    let name = chosen.unwrap_or_else(|| String::from("default"));
+    let theme = match THEMES.iter().find(|theme| theme.name == name) {
+        Some(theme) => theme,
+        None => {
+            let names: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+            eprintln!(
+                "prompt: {}: no such theme, the themes are {}",
+                name,
+                names.join(", ")
+            );
+            &THEMES[0]
+        }
+    };
+    let settings = Settings {
+        config: config.as_deref(),
+        theme,
+    };
+
+    let names = |key: &str, default: &[&str]| {
+        settings
+            .prompt(key)
+            .and_then(|value| value.strings())
+            .unwrap_or_else(|| default.iter().map(|name| String::from(*name)).collect())
+    };
+    let left_names = names("segments", theme.left);
+    let right_names = names("right", theme.right);
+    settings.evaluate(&[&left_names[..], &right_names[..]].concat(), vars);
+    let segments = settings.segments(&left_names, vars, jobs);
+    let right_segments = settings.segments(&right_names, vars, jobs);
+
+    let string = |key: &str| {
+        settings
+            .prompt(key)
+            .and_then(|value| value.as_str())
+            .map(String::from)
+    };
+    let separator = string("separator").unwrap_or_else(|| String::from(" "));
+    let symbol = string("symbol").unwrap_or_else(|| String::from(theme.symbol));
+    let blocks_style = settings
+        .prompt("blocks")
+        .and_then(|value| value.as_bool())
+        .unwrap_or(theme.blocks);
+
+    let mut left = match blocks_style {
+        true => blocks(&segments),
+        false => join(&segments, &separator),
+    };
+    if !segments.is_empty() {
+        left.push(' ');
+    }
+    left.push_str(&symbol);
+    left.push(' ');
+    Some((left, join(&right_segments, &separator)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    fn texts(segments: &[Segment]) -> Vec<&str> {
+        segments.iter().map(|segment| &segment.text[..]).collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| String::from(*name)).collect()
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(color("red", false).as_deref(), Some("31"));
+        assert_eq!(color("bright-blue", true).as_deref(), Some("104"));
+        assert_eq!(color("#ff0080", false).as_deref(), Some("38;2;255;0;128"));
+        assert_eq!(color("208", true).as_deref(), Some("48;5;208"));
+        assert_eq!(color("#12", false), None);
+        assert_eq!(color("purple", false), None);
+    }
+
+    #[test]
+    fn segments_are_styled_by_the_config_and_the_theme() {
+        let config = config::parse(
+            "[segments.status]\n\
+             format = \"!{}\"\n\
+             fg = \"green\"\n\
+             bold = true\n",
+        )
+        .unwrap();
+        let settings = Settings {
+            config: Some(&config),
+            theme: &THEMES[0],
+        };
+        let mut vars = Variables::new(&Options::new());
+        vars.status = 2;
+        let segments = settings.segments(&names(&["status", "jobs", "nosuch"]), &mut vars, 3);
+        assert_eq!(texts(&segments), ["!2", "⚙ 3"]);
+        assert_eq!(segments[0].fg.as_deref(), Some("green"));
+        assert!(segments[0].bold && !segments[1].bold);
+        assert!(segments[1].fg.is_none());
+
+        // empty segments are left out
+        vars.status = 0;
+        assert!(settings
+            .segments(&names(&["status", "jobs"]), &mut vars, 0)
+            .is_empty());
+    }
+
+    #[test]
+    fn command_segments_show_the_first_line() {
+        let config = config::parse(
+            "[segments.theme_test]\n\
+             command = \"echo '  first  '; echo second\"\n",
+        )
+        .unwrap();
+        let settings = Settings {
+            config: Some(&config),
+            theme: &THEMES[0],
+        };
+        let names = names(&["theme_test"]);
+        let mut vars = Variables::new(&Options::new());
+        let started = Instant::now();
+        while cached("theme_test", &vars).is_empty() && started.elapsed() < Duration::from_secs(5) {
+            settings.evaluate(&names, &vars);
+        }
+        let segments = settings.segments(&names, &mut vars, 0);
+        assert_eq!(texts(&segments), ["first"]);
+    }
+
+    #[test]
+    fn styles_take_no_columns() {
+        let segment = |text: &str, bg: &str| Segment {
+            text: String::from(text),
+            fg: Some(String::from("black")),
+            bg: Some(String::from(bg)),
+            bold: false,
+        };
+        let segments = [segment("a", "red"), segment("bc", "blue")];
+        assert_eq!(prompt::width(&join(&segments, " | ")), 6);
+        // " a " and " bc " with an arrow after each of them
+        assert_eq!(prompt::width(&blocks(&segments)), 9);
+    }
+}
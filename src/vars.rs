@@ -0,0 +1,766 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::arith;
+use crate::expand;
+use crate::lexer::{self, Token};
+use crate::options::Options;
+use crate::osstr;
+
+enum Value {
+    Scalar(String),
+    // declare -a, it may have gaps
+    Indexed(BTreeMap<usize, String>),
+    // declare -A, keys are kept sorted
+    Assoc(BTreeMap<String, String>),
+}
+
+// attributes set by declare, exported variables are the ones in the environment
+// of programs
+#[derive(Clone, Copy, Default)]
+pub struct Attributes {
+    // values of assignments are arithmetic expressions
+    pub integer: bool,
+    pub readonly: bool,
+}
+
+// shell variables, exported variables are kept apart in the environment given
+// to programs; it starts as a copy of the one of the process, which the shell
+// never changes, so several shells can live in one process
+pub struct Variables {
+    values: HashMap<String, Value>,
+    environment: BTreeMap<String, String>,
+    // working directory of the shell, relative paths are resolved against it
+    pub cwd: PathBuf,
+    attributes: HashMap<String, Attributes>,
+    // $0 and $1, $2...
+    pub name: String,
+    pub positional: Vec<String>,
+    // exit status of the last command, $?
+    pub status: i32,
+    // an expansion failed with ${NAME:?word}, which exits a non-interactive shell
+    pub fatal: bool,
+    // extglob option for the patterns of ${NAME#pattern} and ${NAME/pattern/string}
+    extglob: Arc<AtomicBool>,
+    // SECONDS counts from this moment (shell start or the last assignment)
+    seconds_base: (u64, Instant),
+    random_state: u32,
+}
+
+impl Variables {
+    pub fn new(options: &Options) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0)
+            ^ process::id();
+
+        Variables {
+            values: HashMap::new(),
+            environment: env::vars_os()
+                .map(|(name, value)| (osstr::from_os(&name), osstr::from_os(&value)))
+                .collect(),
+            cwd: env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+            attributes: HashMap::new(),
+            name: env::args_os()
+                .next()
+                .map(|arg0| osstr::from_os(&arg0))
+                .unwrap_or_default(),
+            positional: vec![],
+            status: 0,
+            fatal: false,
+            extglob: options.flag("extglob"),
+            seconds_base: (0, Instant::now()),
+            random_state: seed | 1,
+        }
+    }
+
+    // scalar value, an array gives its element with key 0 like in bash
+    pub fn get(&self, name: &str) -> Option<String> {
+        match self.values.get(name) {
+            Some(Value::Scalar(value)) => Some(value.clone()),
+            Some(Value::Indexed(array)) => array.get(&0).cloned(),
+            Some(Value::Assoc(map)) => map.get("0").cloned(),
+            None => self.environment.get(name).cloned(),
+        }
+    }
+
+    pub fn attributes(&self, name: &str) -> Attributes {
+        self.attributes.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn set_attributes(&mut self, name: &str, attributes: Attributes) {
+        self.attributes.insert(String::from(name), attributes);
+    }
+
+    pub fn check_writable(&self, name: &str) -> Result<(), String> {
+        if self.attributes(name).readonly {
+            return Err(format!("{}: readonly variable", name));
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.check_writable(name)?;
+        match name {
+            "SECONDS" => self.seconds_base = (value.trim().parse().unwrap_or(0), Instant::now()),
+            "RANDOM" => self.random_state = value.trim().parse::<u32>().unwrap_or(0) | 1,
+            _ => match self.values.get_mut(name) {
+                Some(Value::Indexed(array)) => {
+                    array.insert(0, String::from(value));
+                }
+                Some(Value::Assoc(map)) => {
+                    map.insert(String::from("0"), String::from(value));
+                }
+                _ if self.environment.contains_key(name) => {
+                    self.environment
+                        .insert(String::from(name), String::from(value));
+                }
+                _ => {
+                    self.values
+                        .insert(String::from(name), Value::Scalar(String::from(value)));
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), String> {
+        if self.attributes(name).readonly {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        self.values.remove(name);
+        self.attributes.remove(name);
+        self.environment.remove(name);
+        Ok(())
+    }
+
+    // unset NAME[key] removes one element, the variable stays even if it's empty
+    pub fn unset_element(&mut self, name: &str, key: &str) -> Result<(), String> {
+        if self.attributes(name).readonly {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        match self.values.get_mut(name) {
+            Some(Value::Indexed(array)) => {
+                array.remove(&index(key)?);
+            }
+            Some(Value::Assoc(map)) => {
+                map.remove(key);
+            }
+            _ if key == "0" => self.unset(name)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_assoc(&self, name: &str) -> bool {
+        matches!(self.values.get(name), Some(Value::Assoc(_)))
+    }
+
+    pub fn is_indexed(&self, name: &str) -> bool {
+        matches!(self.values.get(name), Some(Value::Indexed(_)))
+    }
+
+    pub fn is_exported(&self, name: &str) -> bool {
+        !self.values.contains_key(name) && self.environment.contains_key(name)
+    }
+
+    // declare -A, existing scalar becomes the element with key 0
+    pub fn declare_assoc(&mut self, name: &str) -> Result<(), String> {
+        match self.values.get(name) {
+            Some(Value::Assoc(_)) => return Ok(()),
+            Some(Value::Indexed(_)) => {
+                return Err(format!(
+                    "{}: cannot convert indexed to associative array",
+                    name
+                ))
+            }
+            _ => {}
+        }
+        self.check_writable(name)?;
+
+        let mut map = BTreeMap::new();
+        if let Some(value) = self.get(name) {
+            map.insert(String::from("0"), value);
+        }
+        self.environment.remove(name);
+        self.values.insert(String::from(name), Value::Assoc(map));
+        Ok(())
+    }
+
+    // declare -a, existing scalar becomes the element with index 0
+    pub fn declare_indexed(&mut self, name: &str) -> Result<(), String> {
+        match self.values.get(name) {
+            Some(Value::Indexed(_)) => return Ok(()),
+            Some(Value::Assoc(_)) => {
+                return Err(format!(
+                    "{}: cannot convert associative to indexed array",
+                    name
+                ))
+            }
+            _ => {}
+        }
+        self.check_writable(name)?;
+
+        let mut array = BTreeMap::new();
+        if let Some(value) = self.get(name) {
+            array.insert(0, value);
+        }
+        self.environment.remove(name);
+        self.values
+            .insert(String::from(name), Value::Indexed(array));
+        Ok(())
+    }
+
+    // declare -x moves the variable to the environment of programs,
+    // arrays can't be exported
+    pub fn export(&mut self, name: &str) -> Result<(), String> {
+        match self.values.get(name) {
+            Some(Value::Scalar(value)) => {
+                self.environment.insert(String::from(name), value.clone());
+                self.values.remove(name);
+                Ok(())
+            }
+            Some(_) => Err(format!("{}: arrays can't be exported", name)),
+            // declare -x NAME without value exports an empty variable
+            None if !self.environment.contains_key(name) => {
+                self.environment.insert(String::from(name), String::new());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // declare +x keeps the variable in the shell only
+    pub fn unexport(&mut self, name: &str) {
+        if let Some(value) = self.environment.remove(name) {
+            self.values
+                .entry(String::from(name))
+                .or_insert(Value::Scalar(value));
+        }
+    }
+
+    // replace the variable by indexed array of the values
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) {
+        self.environment.remove(name);
+        self.values.insert(
+            String::from(name),
+            Value::Indexed(values.into_iter().enumerate().collect()),
+        );
+    }
+
+    pub fn element(&self, name: &str, key: &str) -> Option<String> {
+        match self.values.get(name) {
+            Some(Value::Indexed(array)) => array.get(&index(key).ok()?).cloned(),
+            Some(Value::Assoc(map)) => map.get(key).cloned(),
+            _ if key == "0" => self.get(name),
+            _ => None,
+        }
+    }
+
+    // assignment to element of scalar makes it indexed array like in bash
+    pub fn set_element(&mut self, name: &str, key: &str, value: &str) -> Result<(), String> {
+        self.check_writable(name)?;
+        if !self.is_assoc(name) && !self.is_indexed(name) {
+            self.declare_indexed(name)?;
+        }
+
+        match self.values.get_mut(name) {
+            Some(Value::Indexed(array)) => {
+                array.insert(index(key)?, String::from(value));
+            }
+            Some(Value::Assoc(map)) => {
+                map.insert(String::from(key), String::from(value));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn keys(&self, name: &str) -> Vec<String> {
+        match self.values.get(name) {
+            Some(Value::Indexed(array)) => array.keys().map(|key| key.to_string()).collect(),
+            Some(Value::Assoc(map)) => map.keys().cloned().collect(),
+            _ => self
+                .get(name)
+                .map(|_| String::from("0"))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    pub fn elements(&self, name: &str) -> Vec<String> {
+        match self.values.get(name) {
+            Some(Value::Indexed(array)) => array.values().cloned().collect(),
+            Some(Value::Assoc(map)) => map.values().cloned().collect(),
+            _ => self.get(name).into_iter().collect(),
+        }
+    }
+
+    // names of shell and exported variables, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: BTreeSet<String> = self.values.keys().cloned().collect();
+        names.extend(self.attributes.keys().cloned());
+        names.extend(self.environment.keys().cloned());
+        names.into_iter().collect()
+    }
+
+    // definition of the variable in declare -p format which can be sourced
+    pub fn declaration(&self, name: &str) -> Option<String> {
+        let (kind, value) = match self.values.get(name) {
+            Some(Value::Scalar(value)) => ("", quote(value)),
+            Some(Value::Indexed(array)) => {
+                let elements: Vec<String> = array
+                    .iter()
+                    .map(|(index, value)| format!("[{}]={}", index, quote(value)))
+                    .collect();
+                ("a", format!("({})", elements.join(" ")))
+            }
+            Some(Value::Assoc(map)) => {
+                let elements: Vec<String> = map
+                    .iter()
+                    .map(|(key, value)| format!("[{}]={}", quote(key), quote(value)))
+                    .collect();
+                ("A", format!("({})", elements.join(" ")))
+            }
+            None => match self.environment.get(name) {
+                Some(value) => ("", quote(value)),
+                // declared without value: readonly NAME
+                None if self.attributes.contains_key(name) => ("", String::new()),
+                None => return None,
+            },
+        };
+
+        let attributes = self.attributes(name);
+        let mut flags = String::from(kind);
+        if attributes.integer {
+            flags.push('i');
+        }
+        if attributes.readonly {
+            flags.push('r');
+        }
+        if self.is_exported(name) {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('-');
+        }
+
+        match value.is_empty() {
+            true => Some(format!("declare -{} {}", flags, name)),
+            false => Some(format!("declare -{} {}={}", flags, name, value)),
+        }
+    }
+
+    pub fn extglob(&self) -> bool {
+        self.extglob.load(Ordering::Relaxed)
+    }
+
+    // exported variables are set and removed without touching the shell ones,
+    // like for the environment given to a builder
+    pub fn set_environment(&mut self, name: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self
+                .environment
+                .insert(String::from(name), String::from(value)),
+            None => self.environment.remove(name),
+        };
+    }
+
+    pub fn clear_environment(&mut self) {
+        self.environment.clear();
+    }
+
+    // the path in the working directory of the shell
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.cwd.join(osstr::to_os(path))
+    }
+
+    // programs get the exported variables and start in the working directory
+    // of the shell, not the ones of the process
+    pub fn prepare<'a>(&self, command: &'a mut process::Command) -> &'a mut process::Command {
+        command
+            .env_clear()
+            .envs(
+                self.environment
+                    .iter()
+                    .map(|(name, value)| (osstr::to_os(name), osstr::to_os(value))),
+            )
+            .current_dir(&self.cwd)
+    }
+
+    pub fn seconds(&self) -> u64 {
+        let (base, since) = self.seconds_base;
+        base + since.elapsed().as_secs()
+    }
+
+    // xorshift generator, values are in range 0..32767 like in bash
+    pub fn random(&mut self) -> u32 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state = x;
+        (x >> 16) & 0x7fff
+    }
+}
+
+// value in double quotes which gives the same string when it's read back
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// subscript of indexed array
+fn index(key: &str) -> Result<usize, String> {
+    key.trim()
+        .parse()
+        .map_err(|_| format!("{}: bad array subscript", key))
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// split NAME=value or NAME[key]=value assignment, the key is not expanded yet
+pub fn assignment(word: &str) -> Option<(&str, Option<&str>, &str)> {
+    let (target, value) = word.split_once('=')?;
+    if is_name(target) {
+        return Some((target, None, value));
+    }
+
+    // the key may contain =, so look for ]= after [
+    let open = target.find('[')?;
+    let name = &word[..open];
+    let close = open + word[open..].find("]=")?;
+    if is_name(name) {
+        Some((name, Some(&word[open + 1..close]), &word[close + 2..]))
+    } else {
+        None
+    }
+}
+
+// set NAME or NAME[key] to the value produced by a builtin like printf -v,
+// the value is taken as is
+pub fn set_target(target: &str, value: &str, vars: &mut Variables) -> Result<(), String> {
+    if let Some((name, key)) = target
+        .strip_suffix(']')
+        .and_then(|target| target.split_once('['))
+    {
+        if is_name(name) {
+            let key = subscript(name, key, vars)?;
+            return vars.set_element(name, &key, value);
+        }
+    }
+    if !is_name(target) {
+        return Err(format!("`{}': not a valid identifier", target));
+    }
+    vars.set(target, value)
+}
+
+// value of integer variable is evaluated as arithmetic expression
+fn assigned_value(name: &str, value: &str, vars: &mut Variables) -> Result<String, String> {
+    let value = expand::expand_string(value, vars)?;
+    if vars.attributes(name).integer {
+        return arith::evaluate(&value, vars).map(|value| value.to_string());
+    }
+    Ok(value)
+}
+
+// subscript of indexed array is arithmetic expression
+pub fn subscript(name: &str, key: &str, vars: &mut Variables) -> Result<String, String> {
+    let key = expand::expand_string(key, vars)?;
+    if vars.is_assoc(name) {
+        return Ok(key);
+    }
+    arith::evaluate(&key, vars).map(|index| index.to_string())
+}
+
+// assign NAME=value, NAME[key]=value or NAME=(...) word expanding its parts,
+// values are neither split nor globbed except elements of indexed arrays
+pub fn assign(word: &str, vars: &mut Variables, options: &Options) -> Result<(), String> {
+    let (name, key, value) = match assignment(word) {
+        Some(assignment) => assignment,
+        None => return Err(format!("{}: not a valid assignment", word)),
+    };
+
+    match key {
+        Some(key) => {
+            let key = subscript(name, key, vars)?;
+            let value = assigned_value(name, value, vars)?;
+            vars.set_element(name, &key, &value)
+        }
+        None if value.starts_with('(') && value.ends_with(')') && value.len() > 1 => {
+            vars.check_writable(name)?;
+            let elements = &value[1..value.len() - 1];
+            if vars.is_assoc(name) {
+                assign_assoc(name, elements, vars)
+            } else {
+                assign_indexed(name, elements, vars, options)
+            }
+        }
+        None => {
+            let value = assigned_value(name, value, vars)?;
+            vars.set(name, &value)
+        }
+    }
+}
+
+fn element_words(elements: &str) -> Result<Vec<String>, String> {
+    lexer::tokenize(elements)?
+        .into_iter()
+        .filter(|token| *token != Token::Op(String::from("\n")))
+        .map(|token| match token {
+            Token::Word(element) => Ok(element),
+            Token::Op(op) => Err(format!("syntax error near unexpected token `{}'", op)),
+        })
+        .collect()
+}
+
+// NAME=([key]=value ...) replaces all elements of the associative array
+fn assign_assoc(name: &str, elements: &str, vars: &mut Variables) -> Result<(), String> {
+    let mut map = BTreeMap::new();
+    for element in element_words(elements)? {
+        let (key, value) = element
+            .strip_prefix('[')
+            .and_then(|element| element.split_once("]="))
+            .ok_or_else(|| format!("{}: {}: must use subscript when assigning", name, element))?;
+        map.insert(
+            expand::expand_string(key, vars)?,
+            assigned_value(name, value, vars)?,
+        );
+    }
+    vars.values.insert(String::from(name), Value::Assoc(map));
+
+    Ok(())
+}
+
+// NAME=(a b [5]=c d) gives elements 0, 1, 5 and 6, words without subscripts
+// are split and globbed like command arguments
+fn assign_indexed(
+    name: &str,
+    elements: &str,
+    vars: &mut Variables,
+    options: &Options,
+) -> Result<(), String> {
+    let mut array = BTreeMap::new();
+    let mut next = 0;
+    for element in element_words(elements)? {
+        if let Some((key, value)) = element
+            .strip_prefix('[')
+            .and_then(|element| element.split_once("]="))
+        {
+            next = index(&subscript(name, key, vars)?)?;
+            array.insert(next, assigned_value(name, value, vars)?);
+            next += 1;
+            continue;
+        }
+
+        let fields = expand::expand_word(&element, vars)?;
+        for value in expand::expand_patterns(fields, &vars.cwd, options)? {
+            let value = match vars.attributes(name).integer {
+                true => arith::evaluate(&value, vars)?.to_string(),
+                false => value,
+            };
+            array.insert(next, value);
+            next += 1;
+        }
+    }
+    vars.environment.remove(name);
+    vars.values
+        .insert(String::from(name), Value::Indexed(array));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assigned(word: &str, vars: &mut Variables) -> Result<(), String> {
+        assign(word, vars, &Options::new())
+    }
+
+    #[test]
+    fn names_and_assignments() {
+        assert!(is_name("_a1"));
+        assert!(!is_name("1a"));
+        assert!(!is_name("a-b"));
+        assert_eq!(assignment("x=1=2"), Some(("x", None, "1=2")));
+        assert_eq!(assignment("x[a=b]=c"), Some(("x", Some("a=b"), "c")));
+        assert_eq!(assignment("x-y=1"), None);
+        assert_eq!(assignment("echo"), None);
+    }
+
+    #[test]
+    fn special_values() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("SECONDS", "100").unwrap();
+        assert!((100..102).contains(&vars.seconds()));
+        vars.set("RANDOM", "7").unwrap();
+        let first = vars.random();
+        vars.set("RANDOM", "7").unwrap();
+        assert_eq!(vars.random(), first);
+        assert!((0..32768).all(|_| vars.random() < 32768));
+    }
+
+    #[test]
+    fn shell_variables() {
+        let mut vars = Variables::new(&Options::new());
+        assert_eq!(vars.get("VARS_TEST_VALUE"), None);
+        vars.set("VARS_TEST_VALUE", "a b").unwrap();
+        assert_eq!(vars.get("VARS_TEST_VALUE").as_deref(), Some("a b"));
+    }
+
+    #[test]
+    fn associative_arrays() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("VARS_TEST_ASSOC", "zero").unwrap();
+        vars.declare_assoc("VARS_TEST_ASSOC").unwrap();
+        assert!(vars.is_assoc("VARS_TEST_ASSOC"));
+        assigned("VARS_TEST_ASSOC[b]=2", &mut vars).unwrap();
+        assigned("VARS_TEST_ASSOC[a]=1", &mut vars).unwrap();
+        assert_eq!(vars.keys("VARS_TEST_ASSOC"), ["0", "a", "b"]);
+        assert_eq!(vars.elements("VARS_TEST_ASSOC"), ["zero", "1", "2"]);
+        assert_eq!(vars.element("VARS_TEST_ASSOC", "a").as_deref(), Some("1"));
+
+        assigned("VARS_TEST_ASSOC=([k]=v [x y]=z)", &mut vars).unwrap_err();
+        assigned("VARS_TEST_ASSOC=([k]=v ['x y']=z)", &mut vars).unwrap();
+        assert_eq!(vars.keys("VARS_TEST_ASSOC"), ["k", "x y"]);
+        assert_eq!(
+            assigned("VARS_TEST_ASSOC=(v)", &mut vars).unwrap_err(),
+            "VARS_TEST_ASSOC: v: must use subscript when assigning"
+        );
+    }
+
+    #[test]
+    fn indexed_arrays() {
+        let mut vars = Variables::new(&Options::new());
+        assigned("VARS_TEST_INDEXED[1+1]=a", &mut vars).unwrap();
+        assert!(vars.is_indexed("VARS_TEST_INDEXED"));
+        assert_eq!(vars.keys("VARS_TEST_INDEXED"), ["2"]);
+        assigned("VARS_TEST_INDEXED=(x 'y z' [5]=five six)", &mut vars).unwrap();
+        assert_eq!(vars.keys("VARS_TEST_INDEXED"), ["0", "1", "5", "6"]);
+        assert_eq!(vars.get("VARS_TEST_INDEXED").as_deref(), Some("x"));
+        assert_eq!(
+            vars.element("VARS_TEST_INDEXED", "1").as_deref(),
+            Some("y z")
+        );
+        assert_eq!(
+            vars.declare_assoc("VARS_TEST_INDEXED").unwrap_err(),
+            "VARS_TEST_INDEXED: cannot convert indexed to associative array"
+        );
+        assert_eq!(
+            vars.export("VARS_TEST_INDEXED").unwrap_err(),
+            "VARS_TEST_INDEXED: arrays can't be exported"
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set_attributes(
+            "VARS_TEST_INTEGER",
+            Attributes {
+                integer: true,
+                readonly: false,
+            },
+        );
+        assigned("VARS_TEST_INTEGER=2*3+1", &mut vars).unwrap();
+        assert_eq!(vars.get("VARS_TEST_INTEGER").as_deref(), Some("7"));
+
+        vars.set("VARS_TEST_READONLY", "a").unwrap();
+        vars.set_attributes(
+            "VARS_TEST_READONLY",
+            Attributes {
+                integer: false,
+                readonly: true,
+            },
+        );
+        assert_eq!(
+            assigned("VARS_TEST_READONLY=b", &mut vars).unwrap_err(),
+            "VARS_TEST_READONLY: readonly variable"
+        );
+        assert!(vars.declare_indexed("VARS_TEST_READONLY").is_err());
+        assert_eq!(vars.get("VARS_TEST_READONLY").as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn declarations() {
+        let mut vars = Variables::new(&Options::new());
+        vars.set("VARS_TEST_DECLARED", "a \"$b\"").unwrap();
+        assert_eq!(
+            vars.declaration("VARS_TEST_DECLARED").unwrap(),
+            "declare -- VARS_TEST_DECLARED=\"a \\\"\\$b\\\"\""
+        );
+        vars.declare_assoc("VARS_TEST_DECLARED_MAP").unwrap();
+        assigned("VARS_TEST_DECLARED_MAP[k]=v", &mut vars).unwrap();
+        vars.set_attributes(
+            "VARS_TEST_DECLARED_MAP",
+            Attributes {
+                integer: false,
+                readonly: true,
+            },
+        );
+        assert_eq!(
+            vars.declaration("VARS_TEST_DECLARED_MAP").unwrap(),
+            "declare -Ar VARS_TEST_DECLARED_MAP=([\"k\"]=\"v\")"
+        );
+        assert!(vars.names().contains(&String::from("VARS_TEST_DECLARED")));
+        assert_eq!(vars.declaration("VARS_TEST_UNDECLARED"), None);
+    }
+
+    #[test]
+    fn variables_are_unset() {
+        let mut vars = Variables::new(&Options::new());
+        assigned("VARS_TEST_UNSET=(a b c)", &mut vars).unwrap();
+        vars.unset_element("VARS_TEST_UNSET", "1").unwrap();
+        assert_eq!(vars.elements("VARS_TEST_UNSET"), ["a", "c"]);
+        vars.unset("VARS_TEST_UNSET").unwrap();
+        assert_eq!(vars.get("VARS_TEST_UNSET"), None);
+
+        vars.set_attributes(
+            "VARS_TEST_UNSET_READONLY",
+            Attributes {
+                integer: false,
+                readonly: true,
+            },
+        );
+        assert_eq!(
+            vars.declaration("VARS_TEST_UNSET_READONLY").unwrap(),
+            "declare -r VARS_TEST_UNSET_READONLY"
+        );
+        assert_eq!(
+            vars.unset("VARS_TEST_UNSET_READONLY").unwrap_err(),
+            "VARS_TEST_UNSET_READONLY: cannot unset: readonly variable"
+        );
+    }
+
+    #[test]
+    fn targets_of_builtins() {
+        let mut vars = Variables::new(&Options::new());
+        set_target("VARS_TEST_TARGET", "$x", &mut vars).unwrap();
+        assert_eq!(vars.get("VARS_TEST_TARGET").as_deref(), Some("$x"));
+        set_target("VARS_TEST_TARGETS[1+1]", "b", &mut vars).unwrap();
+        assert_eq!(vars.element("VARS_TEST_TARGETS", "2").as_deref(), Some("b"));
+        assert_eq!(
+            set_target("1x", "", &mut vars).unwrap_err(),
+            "`1x': not a valid identifier"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn shell(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn exit_status_of_command_string() {
+    assert_eq!(shell(&["-c", "false"], "").status.code(), Some(1));
+    assert_eq!(shell(&["-c", "true"], "").status.code(), Some(0));
+}
+
+#[test]
+fn exit_status_of_standard_input() {
+    let output = shell(&[], "echo a\nfalse\n");
+    assert_eq!(output.stdout, b"a\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn missing_script() {
+    let output = shell(&["/nonexistent/script.sh"], "");
+    assert_eq!(output.status.code(), Some(127));
+}
+
+#[test]
+fn multi_line_command_string() {
+    let output = shell(
+        &[
+            "-c",
+            "select x in one two\ndo\necho got $x\nbreak\ndone\necho after",
+        ],
+        "2\n",
+    );
+    assert_eq!(output.stdout, b"got two\nafter\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn exit_ends_the_command_string() {
+    let output = shell(&["-c", "echo a; exit 7; echo no"], "");
+    assert_eq!(output.stdout, b"a\n");
+    assert_eq!(output.status.code(), Some(7));
+}